@@ -44,8 +44,25 @@ fn main() {
                 .unwrap(),
             proto_path.join("merkle.proto").to_str().unwrap(),
             proto_path.join("command.proto").to_str().unwrap(),
+            #[cfg(feature = "context-recorder")]
+            proto_path.join("context_trace.proto").to_str().unwrap(),
+            #[cfg(feature = "wasm-adapter")]
+            proto_path.join("contract_registry.proto").to_str().unwrap(),
+            #[cfg(feature = "evm-adapter")]
+            proto_path.join("evm.proto").to_str().unwrap(),
+            #[cfg(feature = "event-bus")]
+            proto_path.join("event_subscription.proto").to_str().unwrap(),
+            #[cfg(feature = "genesis")]
+            proto_path.join("genesis.proto").to_str().unwrap(),
             #[cfg(feature = "key-value-state")]
             proto_path.join("key_value_state.proto").to_str().unwrap(),
+            #[cfg(feature = "receipt-store")]
+            proto_path.join("receipt_store.proto").to_str().unwrap(),
+            #[cfg(feature = "socket-adapter")]
+            proto_path
+                .join("external_execution.proto")
+                .to_str()
+                .unwrap(),
         ],
         includes: &[proto_path.to_str().unwrap()],
         customize: Customize::default(),
@@ -55,6 +72,6 @@ fn main() {
     // Create mod.rs accordingly
     let mut mod_file = File::create(dest_path.join("mod.rs")).unwrap();
     mod_file
-        .write_all(b"pub mod batch;\npub mod events;\n#[cfg(feature = \"key-value-state\")]\npub mod key_value_state;\npub mod transaction;\npub mod transaction_receipt;\npub mod merkle;\npub mod command;\n")
+        .write_all(b"pub mod batch;\npub mod events;\n#[cfg(feature = \"key-value-state\")]\npub mod key_value_state;\npub mod transaction;\npub mod transaction_receipt;\npub mod merkle;\npub mod command;\n#[cfg(feature = \"context-recorder\")]\npub mod context_trace;\n#[cfg(feature = \"wasm-adapter\")]\npub mod contract_registry;\n#[cfg(feature = \"evm-adapter\")]\npub mod evm;\n#[cfg(feature = \"event-bus\")]\npub mod event_subscription;\n#[cfg(feature = \"genesis\")]\npub mod genesis;\n#[cfg(feature = \"receipt-store\")]\npub mod receipt_store;\n#[cfg(feature = \"socket-adapter\")]\npub mod external_execution;\n")
         .unwrap();
 }
@@ -39,8 +39,9 @@ impl ContextLifecycle for ContextManager {
         *new_context.id()
     }
 
-    fn drop_context(&mut self, _context_id: ContextId) {
-        unimplemented!();
+    /// Drops the Context; Contexts which depend on it can no longer read its state changes.
+    fn drop_context(&mut self, context_id: ContextId) {
+        self.contexts.remove(&context_id);
     }
 
     /// Generates a valid `TransactionReceipt` based on the information available within the
@@ -307,6 +308,16 @@ mod tests {
         assert_eq!(manager.contexts.len(), 2);
     }
 
+    #[test]
+    fn drop_contexts() {
+        let (mut manager, state_id) = make_manager(None);
+        let first_context_id = manager.create_context(&[], &state_id);
+        let second_context_id = manager.create_context(&[], &state_id);
+        manager.drop_context(first_context_id);
+        assert!(!manager.contexts.contains_key(&first_context_id));
+        assert!(manager.contexts.contains_key(&second_context_id));
+    }
+
     #[test]
     fn add_context_event() {
         let (mut manager, state_id) = make_manager(None);
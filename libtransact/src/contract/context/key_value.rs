@@ -340,6 +340,9 @@ where
         key: &K,
         values: HashMap<String, ValueType>,
     ) -> Result<StateEntry, ContractContextError> {
+        // Values are ordered by key, so that the same values are always serialized the same way
+        let mut values = values.into_iter().collect::<Vec<_>>();
+        values.sort_by(|(a, _), (b, _)| a.cmp(b));
         let state_values: Vec<StateEntryValue> = values
             .iter()
             .map(|(key, value)| {
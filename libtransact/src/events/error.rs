@@ -0,0 +1,98 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::protos::ProtoConversionError;
+
+use super::EventPosition;
+
+#[derive(Debug)]
+pub enum EventBusError {
+    /// A filter could not be created, such as from an invalid regular expression.
+    InvalidFilter(String),
+    /// A subscription started from a state root which no retained commit produced.
+    UnknownStateRoot(String),
+    /// A subscription started after a position whose following events are no longer retained.
+    PositionUnavailable(EventPosition),
+    /// A subscription was rejected by the event socket server.
+    SubscriptionRejected(String),
+    /// A message could not be converted to or from its protobuf form.
+    ProtoConversionError(ProtoConversionError),
+    /// Reading from or writing to a socket failed.
+    IoError(io::Error),
+    /// A socket message was malformed or unexpected.
+    ProtocolError(String),
+    /// The bus's internal state is unusable, such as after a thread panicked while holding its
+    /// lock.
+    Internal(String),
+}
+
+impl Error for EventBusError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EventBusError::InvalidFilter(_) => None,
+            EventBusError::UnknownStateRoot(_) => None,
+            EventBusError::PositionUnavailable(_) => None,
+            EventBusError::SubscriptionRejected(_) => None,
+            EventBusError::ProtoConversionError(err) => Some(err),
+            EventBusError::IoError(err) => Some(err),
+            EventBusError::ProtocolError(_) => None,
+            EventBusError::Internal(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for EventBusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventBusError::InvalidFilter(msg) => write!(f, "invalid event filter: {}", msg),
+            EventBusError::UnknownStateRoot(state_root) => {
+                write!(f, "no retained commit produced state root {}", state_root)
+            }
+            EventBusError::PositionUnavailable(position) => write!(
+                f,
+                "events after position {}:{} are no longer retained",
+                position.sequence(),
+                position.index()
+            ),
+            EventBusError::SubscriptionRejected(msg) => {
+                write!(f, "subscription rejected: {}", msg)
+            }
+            EventBusError::ProtoConversionError(err) => {
+                write!(f, "unable to convert event message: {}", err)
+            }
+            EventBusError::IoError(err) => write!(f, "event socket I/O failed: {}", err),
+            EventBusError::ProtocolError(msg) => write!(f, "event protocol error: {}", msg),
+            EventBusError::Internal(msg) => write!(f, "event bus internal error: {}", msg),
+        }
+    }
+}
+
+impl From<ProtoConversionError> for EventBusError {
+    fn from(err: ProtoConversionError) -> Self {
+        EventBusError::ProtoConversionError(err)
+    }
+}
+
+impl From<io::Error> for EventBusError {
+    fn from(err: io::Error) -> Self {
+        EventBusError::IoError(err)
+    }
+}
@@ -0,0 +1,199 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use regex::Regex;
+
+use crate::protocol::receipt::Event;
+use crate::protos;
+use crate::protos::{FromNative, FromProto, IntoNative, IntoProto, ProtoConversionError};
+
+use super::EventBusError;
+
+/// A rule which an event type or attribute value must match.
+#[derive(Clone, Debug)]
+pub enum MatchRule {
+    /// The value must equal the string.
+    Exact(String),
+    /// The value must start with the string.
+    Prefix(String),
+    /// The value must match the regular expression somewhere; anchor it to match the whole value.
+    Regex(Regex),
+}
+
+impl MatchRule {
+    /// Creates a `Regex` rule from the pattern.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EventBusError::InvalidFilter` if the pattern is not a valid regular
+    /// expression.
+    pub fn regex(pattern: &str) -> Result<Self, EventBusError> {
+        Regex::new(pattern)
+            .map(MatchRule::Regex)
+            .map_err(|err| EventBusError::InvalidFilter(format!("{}: {}", pattern, err)))
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            MatchRule::Exact(expected) => value == expected,
+            MatchRule::Prefix(prefix) => value.starts_with(prefix.as_str()),
+            MatchRule::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+/// Selects events by their type and attributes.
+///
+/// An event matches the filter if its type matches the event type rule, when one is given, and if
+/// for each attribute rule it has an attribute with the rule's key whose value matches the rule.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    event_type: Option<MatchRule>,
+    attributes: Vec<(String, MatchRule)>,
+}
+
+impl EventFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_event_type(mut self, rule: MatchRule) -> Self {
+        self.event_type = Some(rule);
+        self
+    }
+
+    pub fn with_attribute(mut self, key: String, rule: MatchRule) -> Self {
+        self.attributes.push((key, rule));
+        self
+    }
+
+    pub fn event_type(&self) -> Option<&MatchRule> {
+        self.event_type.as_ref()
+    }
+
+    pub fn attributes(&self) -> &[(String, MatchRule)] {
+        &self.attributes
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        self.event_type
+            .as_ref()
+            .map(|rule| rule.matches(&event.event_type))
+            .unwrap_or(true)
+            && self.attributes.iter().all(|(key, rule)| {
+                event
+                    .attributes
+                    .iter()
+                    .any(|(attr_key, value)| attr_key == key && rule.matches(value))
+            })
+    }
+}
+
+impl FromProto<protos::event_subscription::EventMatchRule> for MatchRule {
+    fn from_proto(
+        mut rule: protos::event_subscription::EventMatchRule,
+    ) -> Result<Self, ProtoConversionError> {
+        use protos::event_subscription::EventMatchRule_Type;
+
+        match rule.get_rule_type() {
+            EventMatchRule_Type::EXACT => Ok(MatchRule::Exact(rule.take_pattern())),
+            EventMatchRule_Type::PREFIX => Ok(MatchRule::Prefix(rule.take_pattern())),
+            EventMatchRule_Type::REGEX => MatchRule::regex(rule.get_pattern())
+                .map_err(|err| ProtoConversionError::DeserializationError(err.to_string())),
+            EventMatchRule_Type::TYPE_UNSET => Err(ProtoConversionError::InvalidTypeError(
+                "Cannot convert EventMatchRule with type unset".into(),
+            )),
+        }
+    }
+}
+
+impl FromNative<MatchRule> for protos::event_subscription::EventMatchRule {
+    fn from_native(rule: MatchRule) -> Result<Self, ProtoConversionError> {
+        use protos::event_subscription::EventMatchRule_Type;
+
+        let mut proto_rule = protos::event_subscription::EventMatchRule::new();
+        match rule {
+            MatchRule::Exact(pattern) => {
+                proto_rule.set_rule_type(EventMatchRule_Type::EXACT);
+                proto_rule.set_pattern(pattern);
+            }
+            MatchRule::Prefix(pattern) => {
+                proto_rule.set_rule_type(EventMatchRule_Type::PREFIX);
+                proto_rule.set_pattern(pattern);
+            }
+            MatchRule::Regex(regex) => {
+                proto_rule.set_rule_type(EventMatchRule_Type::REGEX);
+                proto_rule.set_pattern(regex.as_str().into());
+            }
+        }
+        Ok(proto_rule)
+    }
+}
+
+impl IntoProto<protos::event_subscription::EventMatchRule> for MatchRule {}
+impl IntoNative<MatchRule> for protos::event_subscription::EventMatchRule {}
+
+impl FromProto<protos::event_subscription::EventSubscriptionFilter> for EventFilter {
+    fn from_proto(
+        mut filter: protos::event_subscription::EventSubscriptionFilter,
+    ) -> Result<Self, ProtoConversionError> {
+        let event_type = if filter.has_event_type() {
+            Some(MatchRule::from_proto(filter.take_event_type())?)
+        } else {
+            None
+        };
+        let attributes = filter
+            .take_attributes()
+            .into_iter()
+            .map(|mut attribute| {
+                Ok((
+                    attribute.take_key(),
+                    MatchRule::from_proto(attribute.take_rule())?,
+                ))
+            })
+            .collect::<Result<_, ProtoConversionError>>()?;
+        Ok(EventFilter {
+            event_type,
+            attributes,
+        })
+    }
+}
+
+impl FromNative<EventFilter> for protos::event_subscription::EventSubscriptionFilter {
+    fn from_native(filter: EventFilter) -> Result<Self, ProtoConversionError> {
+        let mut proto_filter = protos::event_subscription::EventSubscriptionFilter::new();
+        if let Some(rule) = filter.event_type {
+            proto_filter.set_event_type(rule.into_proto()?);
+        }
+        proto_filter.set_attributes(
+            filter
+                .attributes
+                .into_iter()
+                .map(|(key, rule)| {
+                    let mut attribute = protos::event_subscription::EventAttributeFilter::new();
+                    attribute.set_key(key);
+                    attribute.set_rule(rule.into_proto()?);
+                    Ok(attribute)
+                })
+                .collect::<Result<_, ProtoConversionError>>()?,
+        );
+        Ok(proto_filter)
+    }
+}
+
+impl IntoProto<protos::event_subscription::EventSubscriptionFilter> for EventFilter {}
+impl IntoNative<EventFilter> for protos::event_subscription::EventSubscriptionFilter {}
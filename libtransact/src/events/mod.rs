@@ -0,0 +1,696 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Delivery of the events of committed transactions to subscribers.
+//!
+//! An `EventBus` is given the receipts of each commit, in commit order, along with the state root
+//! the commit produced. It numbers the commits, and gives each event an `EventPosition`: the
+//! sequence number of its commit and its index among that commit's events. Subscribers select
+//! events with `EventFilter`s and receive the matching events in order. A subscription can start
+//! after the commit which produced a given state root, or resume after the position of the last
+//! event it received, as long as the bus still retains the commits which follow it. By default, a
+//! bus retains the `DEFAULT_RETENTION` most recent commits; retaining every commit must be asked
+//! for with `EventBus::new_unbounded`.
+//!
+//! With the `"socket-adapter"` feature, an `EventSocketServer` also delivers events to
+//! subscribers connected over a local socket with a `SocketEventSubscriber`.
+//!
+//! Note, to use this module, the Transact library must have the `"event-bus"` feature enabled.
+
+mod error;
+mod filter;
+#[cfg(feature = "socket-adapter")]
+mod socket;
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::protocol::receipt::{Event, TransactionReceipt, TransactionResult};
+use crate::protos;
+use crate::protos::{FromNative, FromProto, IntoNative, IntoProto, ProtoConversionError};
+
+pub use self::error::EventBusError;
+pub use self::filter::{EventFilter, MatchRule};
+#[cfg(feature = "socket-adapter")]
+pub use self::socket::{EventSocketServer, SocketEventSubscriber};
+
+/// The position of an event among all of the events published to a bus.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct EventPosition {
+    sequence: u64,
+    index: u32,
+}
+
+impl EventPosition {
+    pub fn new(sequence: u64, index: u32) -> Self {
+        EventPosition { sequence, index }
+    }
+
+    /// The sequence number of the commit the event was published with; the first commit is 1.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// The index of the event among the events of its commit.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+/// An event of a committed transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommittedEvent {
+    position: EventPosition,
+    state_root: String,
+    transaction_id: String,
+    event: Event,
+}
+
+impl CommittedEvent {
+    pub fn position(&self) -> EventPosition {
+        self.position
+    }
+
+    /// The state root produced by the commit the event was published with.
+    pub fn state_root(&self) -> &str {
+        &self.state_root
+    }
+
+    pub fn transaction_id(&self) -> &str {
+        &self.transaction_id
+    }
+
+    pub fn event(&self) -> &Event {
+        &self.event
+    }
+}
+
+/// Where a subscription starts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StartFrom {
+    /// With the next commit published.
+    Latest,
+    /// After the most recent retained commit which produced the state root.
+    StateRoot(String),
+    /// After the event at the position, such as the last event a previous subscription received.
+    After(EventPosition),
+}
+
+impl Default for StartFrom {
+    fn default() -> Self {
+        StartFrom::Latest
+    }
+}
+
+/// The number of most recent commits an `EventBus` retains by default.
+pub const DEFAULT_RETENTION: usize = 1_000;
+
+/// Publishes the events of committed transactions to subscribers.
+///
+/// Clones of a bus share its commits and subscribers.
+#[derive(Clone)]
+pub struct EventBus {
+    state: Arc<Mutex<EventBusState>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new_with_retention(DEFAULT_RETENTION)
+    }
+}
+
+impl EventBus {
+    /// Creates a bus which retains the `DEFAULT_RETENTION` most recent commits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a bus which retains only the given number of most recent commits.
+    pub fn new_with_retention(commits: usize) -> Self {
+        Self::with_retention(Some(commits))
+    }
+
+    /// Creates a bus which retains every commit, so that subscriptions can start anywhere.
+    ///
+    /// The bus's memory grows with every commit published to it, so this is only suitable when
+    /// the number of commits is bounded by other means.
+    pub fn new_unbounded() -> Self {
+        Self::with_retention(None)
+    }
+
+    fn with_retention(retention: Option<usize>) -> Self {
+        EventBus {
+            state: Arc::new(Mutex::new(EventBusState {
+                commits: VecDeque::new(),
+                retention,
+                next_sequence: 1,
+                last_dropped: None,
+                subscribers: vec![],
+            })),
+        }
+    }
+
+    /// Publishes the events of the receipts of a commit, which produced `state_root`, to the
+    /// subscribers whose filters they match; the receipts must be given in commit order. Returns
+    /// the sequence number of the commit.
+    pub fn publish(
+        &self,
+        state_root: &str,
+        receipts: &[TransactionReceipt],
+    ) -> Result<u64, EventBusError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| EventBusError::Internal("event bus lock poisoned".into()))?;
+
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+
+        let events = receipts
+            .iter()
+            .flat_map(|receipt| match &receipt.transaction_result {
+                TransactionResult::Valid { events, .. } => events
+                    .iter()
+                    .map(|event| (receipt.transaction_id.clone(), event.clone()))
+                    .collect::<Vec<_>>(),
+                TransactionResult::Invalid { .. } => vec![],
+            })
+            .enumerate()
+            .map(|(index, (transaction_id, event))| CommittedEvent {
+                position: EventPosition::new(sequence, index as u32),
+                state_root: state_root.into(),
+                transaction_id,
+                event,
+            })
+            .collect::<Vec<_>>();
+
+        state
+            .subscribers
+            .retain(|subscriber| subscriber.send_all(&events));
+
+        state.commits.push_back(Commit {
+            state_root: state_root.into(),
+            events,
+        });
+        while state
+            .retention
+            .map(|retention| state.commits.len() > retention)
+            .unwrap_or(false)
+        {
+            if let Some(commit) = state.commits.pop_front() {
+                if let Some(last) = commit.events.last() {
+                    state.last_dropped = Some(last.position);
+                }
+            }
+        }
+
+        Ok(sequence)
+    }
+
+    /// Subscribes to the events matching any of the filters, or to all events if there are none.
+    ///
+    /// The retained events after the start are delivered first, followed by those of each commit
+    /// published from then on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EventBusError` if the start is a state root which no retained commit produced,
+    /// or a position after which events are no longer retained.
+    pub fn subscribe(
+        &self,
+        filters: Vec<EventFilter>,
+        start: StartFrom,
+    ) -> Result<EventSubscription, EventBusError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| EventBusError::Internal("event bus lock poisoned".into()))?;
+
+        let after = match start {
+            StartFrom::Latest => None,
+            StartFrom::StateRoot(state_root) => {
+                let commit = state
+                    .commits
+                    .iter()
+                    .rposition(|commit| commit.state_root == state_root)
+                    .ok_or_else(|| EventBusError::UnknownStateRoot(state_root))?;
+                let sequence = state.next_sequence - (state.commits.len() - commit) as u64;
+                // Every event of a later commit comes after this position
+                Some(EventPosition::new(sequence, u32::max_value()))
+            }
+            StartFrom::After(position) => {
+                if state
+                    .last_dropped
+                    .map(|last_dropped| position < last_dropped)
+                    .unwrap_or(false)
+                {
+                    return Err(EventBusError::PositionUnavailable(position));
+                }
+                Some(position)
+            }
+        };
+
+        let (sender, receiver) = channel();
+        let subscriber = Subscriber { filters, sender };
+        if let Some(after) = after {
+            for commit in &state.commits {
+                let backlog = commit
+                    .events
+                    .iter()
+                    .filter(|event| event.position > after)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                subscriber.send_all(&backlog);
+            }
+        }
+        state.subscribers.push(subscriber);
+
+        Ok(EventSubscription {
+            receiver,
+            position: None,
+        })
+    }
+}
+
+struct EventBusState {
+    commits: VecDeque<Commit>,
+    retention: Option<usize>,
+    next_sequence: u64,
+    // The position of the last event of the most recent commit which was no longer retained
+    last_dropped: Option<EventPosition>,
+    subscribers: Vec<Subscriber>,
+}
+
+struct Commit {
+    state_root: String,
+    events: Vec<CommittedEvent>,
+}
+
+struct Subscriber {
+    filters: Vec<EventFilter>,
+    sender: Sender<CommittedEvent>,
+}
+
+impl Subscriber {
+    /// Sends the events which match the subscriber's filters, returning false if the
+    /// subscription has been dropped.
+    fn send_all(&self, events: &[CommittedEvent]) -> bool {
+        events
+            .iter()
+            .filter(|event| {
+                self.filters.is_empty()
+                    || self
+                        .filters
+                        .iter()
+                        .any(|filter| filter.matches(&event.event))
+            })
+            .all(|event| self.sender.send(event.clone()).is_ok())
+    }
+}
+
+/// Receives the events of a subscription, in order.
+///
+/// The subscription ends when it is dropped, or when every clone of its bus has been dropped.
+pub struct EventSubscription {
+    receiver: Receiver<CommittedEvent>,
+    position: Option<EventPosition>,
+}
+
+impl EventSubscription {
+    /// Waits for the next event, returning `None` if the bus has been dropped.
+    pub fn recv(&mut self) -> Option<CommittedEvent> {
+        let event = self.receiver.recv().ok();
+        self.record(event)
+    }
+
+    /// Waits at most `timeout` for the next event.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<CommittedEvent> {
+        let event = self.receiver.recv_timeout(timeout).ok();
+        self.record(event)
+    }
+
+    /// Returns the next event, if one has already been delivered.
+    pub fn try_recv(&mut self) -> Option<CommittedEvent> {
+        let event = self.receiver.try_recv().ok();
+        self.record(event)
+    }
+
+    /// The position of the last event received; a new subscription which starts after it
+    /// resumes where this one left off.
+    pub fn position(&self) -> Option<EventPosition> {
+        self.position
+    }
+
+    fn record(&mut self, event: Option<CommittedEvent>) -> Option<CommittedEvent> {
+        if let Some(event) = &event {
+            self.position = Some(event.position);
+        }
+        event
+    }
+}
+
+impl Iterator for EventSubscription {
+    type Item = CommittedEvent;
+
+    fn next(&mut self) -> Option<CommittedEvent> {
+        self.recv()
+    }
+}
+
+impl FromProto<protos::event_subscription::EventPosition> for EventPosition {
+    fn from_proto(
+        position: protos::event_subscription::EventPosition,
+    ) -> Result<Self, ProtoConversionError> {
+        Ok(EventPosition::new(
+            position.get_sequence(),
+            position.get_index(),
+        ))
+    }
+}
+
+impl FromNative<EventPosition> for protos::event_subscription::EventPosition {
+    fn from_native(position: EventPosition) -> Result<Self, ProtoConversionError> {
+        let mut proto_position = protos::event_subscription::EventPosition::new();
+        proto_position.set_sequence(position.sequence);
+        proto_position.set_index(position.index);
+        Ok(proto_position)
+    }
+}
+
+impl IntoProto<protos::event_subscription::EventPosition> for EventPosition {}
+impl IntoNative<EventPosition> for protos::event_subscription::EventPosition {}
+
+impl FromProto<protos::event_subscription::CommittedEvent> for CommittedEvent {
+    fn from_proto(
+        mut event: protos::event_subscription::CommittedEvent,
+    ) -> Result<Self, ProtoConversionError> {
+        Ok(CommittedEvent {
+            position: EventPosition::from_proto(event.take_position())?,
+            state_root: event.take_state_root(),
+            transaction_id: event.take_transaction_id(),
+            event: Event::from_proto(event.take_event())?,
+        })
+    }
+}
+
+impl FromNative<CommittedEvent> for protos::event_subscription::CommittedEvent {
+    fn from_native(event: CommittedEvent) -> Result<Self, ProtoConversionError> {
+        let mut proto_event = protos::event_subscription::CommittedEvent::new();
+        proto_event.set_position(event.position.into_proto()?);
+        proto_event.set_state_root(event.state_root);
+        proto_event.set_transaction_id(event.transaction_id);
+        proto_event.set_event(event.event.into_proto()?);
+        Ok(proto_event)
+    }
+}
+
+impl IntoProto<protos::event_subscription::CommittedEvent> for CommittedEvent {}
+impl IntoNative<CommittedEvent> for protos::event_subscription::CommittedEvent {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::receipt::TransactionReceiptBuilder;
+
+    fn make_event(event_type: &str, name: &str) -> Event {
+        Event {
+            event_type: event_type.into(),
+            attributes: vec![("name".into(), name.into())],
+            data: vec![],
+        }
+    }
+
+    fn make_receipt(transaction_id: &str, events: Vec<Event>) -> TransactionReceipt {
+        TransactionReceiptBuilder::new()
+            .valid()
+            .with_events(events)
+            .with_transaction_id(transaction_id.into())
+            .build()
+            .expect("Unable to build receipt")
+    }
+
+    fn received(subscription: &mut EventSubscription) -> Vec<(EventPosition, String)> {
+        std::iter::from_fn(|| subscription.try_recv())
+            .map(|event| (event.position(), event.event().event_type.clone()))
+            .collect()
+    }
+
+    /// Tests that subscribers receive the events of valid transactions which match their filters,
+    /// in commit order.
+    #[test]
+    fn event_bus_filters() {
+        let bus = EventBus::new();
+
+        let mut all = bus
+            .subscribe(vec![], StartFrom::Latest)
+            .expect("Unable to subscribe");
+        let mut exact = bus
+            .subscribe(
+                vec![EventFilter::new().with_event_type(MatchRule::Exact("xo/create".into()))],
+                StartFrom::Latest,
+            )
+            .expect("Unable to subscribe");
+        let mut prefix_or_regex = bus
+            .subscribe(
+                vec![
+                    EventFilter::new().with_event_type(MatchRule::Prefix("intkey/".into())),
+                    EventFilter::new().with_attribute(
+                        "name".into(),
+                        MatchRule::regex("^game-[0-9]+$").expect("Invalid regex"),
+                    ),
+                ],
+                StartFrom::Latest,
+            )
+            .expect("Unable to subscribe");
+
+        assert!(MatchRule::regex("game-(").is_err());
+
+        let invalid = TransactionReceiptBuilder::new()
+            .invalid()
+            .with_transaction_id("txn2".into())
+            .build()
+            .expect("Unable to build receipt");
+        assert_eq!(
+            bus.publish(
+                "root1",
+                &[
+                    make_receipt(
+                        "txn1",
+                        vec![
+                            make_event("xo/create", "game-1"),
+                            make_event("xo/take", "game-x"),
+                        ]
+                    ),
+                    invalid,
+                ],
+            )
+            .expect("Unable to publish"),
+            1
+        );
+        assert_eq!(
+            bus.publish(
+                "root2",
+                &[make_receipt("txn3", vec![make_event("intkey/set", "a")])],
+            )
+            .expect("Unable to publish"),
+            2
+        );
+
+        assert_eq!(
+            received(&mut all),
+            vec![
+                (EventPosition::new(1, 0), "xo/create".into()),
+                (EventPosition::new(1, 1), "xo/take".into()),
+                (EventPosition::new(2, 0), "intkey/set".into()),
+            ]
+        );
+        assert_eq!(all.position(), Some(EventPosition::new(2, 0)));
+        assert_eq!(
+            received(&mut exact),
+            vec![(EventPosition::new(1, 0), "xo/create".into())]
+        );
+        assert_eq!(
+            received(&mut prefix_or_regex),
+            vec![
+                (EventPosition::new(1, 0), "xo/create".into()),
+                (EventPosition::new(2, 0), "intkey/set".into()),
+            ]
+        );
+
+        // A dropped subscription no longer receives events
+        drop(exact);
+        bus.publish(
+            "root3",
+            &[make_receipt(
+                "txn4",
+                vec![make_event("xo/create", "game-2")],
+            )],
+        )
+        .expect("Unable to publish");
+        assert_eq!(received(&mut all).len(), 1);
+    }
+
+    /// Tests that subscriptions can start from a state root or resume after a position, as long as
+    /// the bus retains the commits which follow.
+    #[test]
+    fn event_bus_resume() {
+        let bus = EventBus::new_with_retention(2);
+        for (i, state_root) in ["root1", "root2", "root3"].iter().enumerate() {
+            bus.publish(
+                state_root,
+                &[make_receipt(
+                    &format!("txn{}", i),
+                    vec![make_event("a", "x"), make_event("b", "x")],
+                )],
+            )
+            .expect("Unable to publish");
+        }
+
+        let mut from_root = bus
+            .subscribe(vec![], StartFrom::StateRoot("root2".into()))
+            .expect("Unable to subscribe");
+        let event = from_root.try_recv().expect("No event received");
+        assert_eq!(event.position(), EventPosition::new(3, 0));
+        assert_eq!(event.state_root(), "root3");
+        assert_eq!(event.transaction_id(), "txn2");
+
+        let mut resumed = bus
+            .subscribe(vec![], StartFrom::After(EventPosition::new(2, 0)))
+            .expect("Unable to subscribe");
+        bus.publish("root4", &[make_receipt("txn3", vec![make_event("c", "x")])])
+            .expect("Unable to publish");
+        assert_eq!(
+            received(&mut resumed),
+            vec![
+                (EventPosition::new(2, 1), "b".into()),
+                (EventPosition::new(3, 0), "a".into()),
+                (EventPosition::new(3, 1), "b".into()),
+                (EventPosition::new(4, 0), "c".into()),
+            ]
+        );
+        assert_eq!(
+            received(&mut from_root),
+            vec![
+                (EventPosition::new(3, 1), "b".into()),
+                (EventPosition::new(4, 0), "c".into()),
+            ]
+        );
+
+        // Only root3 and root4 are retained now
+        match bus.subscribe(vec![], StartFrom::StateRoot("root2".into())) {
+            Err(EventBusError::UnknownStateRoot(_)) => (),
+            res => panic!("Expected UnknownStateRoot, got {:?}", res.map(|_| ())),
+        }
+        match bus.subscribe(vec![], StartFrom::After(EventPosition::new(2, 0))) {
+            Err(EventBusError::PositionUnavailable(_)) => (),
+            res => panic!("Expected PositionUnavailable, got {:?}", res.map(|_| ())),
+        }
+        assert!(bus
+            .subscribe(vec![], StartFrom::After(EventPosition::new(2, 1)))
+            .is_ok());
+    }
+
+    /// Tests that a new bus retains only the `DEFAULT_RETENTION` most recent commits, and that an
+    /// unbounded bus retains every commit.
+    #[test]
+    fn event_bus_default_retention() {
+        let bus = EventBus::new();
+        let unbounded = EventBus::new_unbounded();
+        for i in 0..=DEFAULT_RETENTION {
+            let state_root = format!("root{}", i);
+            bus.publish(&state_root, &[]).expect("Unable to publish");
+            unbounded
+                .publish(&state_root, &[])
+                .expect("Unable to publish");
+        }
+
+        match bus.subscribe(vec![], StartFrom::StateRoot("root0".into())) {
+            Err(EventBusError::UnknownStateRoot(_)) => (),
+            res => panic!("Expected UnknownStateRoot, got {:?}", res.map(|_| ())),
+        }
+        assert!(bus
+            .subscribe(vec![], StartFrom::StateRoot("root1".into()))
+            .is_ok());
+        assert!(unbounded
+            .subscribe(vec![], StartFrom::StateRoot("root0".into()))
+            .is_ok());
+    }
+
+    /// Tests that a socket subscriber receives the matching events after its start, and that a
+    /// subscription with an unknown start is rejected.
+    #[cfg(feature = "socket-adapter")]
+    #[test]
+    fn event_socket_subscription() {
+        use crate::execution::adapter::socket::SocketAddress;
+
+        let bus = EventBus::new();
+        let server =
+            EventSocketServer::start(bus.clone(), SocketAddress::Tcp("127.0.0.1:0".into()))
+                .expect("Unable to start server");
+
+        bus.publish(
+            "root1",
+            &[make_receipt(
+                "txn1",
+                vec![make_event("xo/create", "game-1")],
+            )],
+        )
+        .expect("Unable to publish");
+
+        match SocketEventSubscriber::subscribe(
+            server.local_address(),
+            vec![],
+            StartFrom::StateRoot("unknown".into()),
+        ) {
+            Err(EventBusError::SubscriptionRejected(_)) => (),
+            res => panic!("Expected SubscriptionRejected, got {:?}", res.map(|_| ())),
+        }
+
+        let mut subscriber = SocketEventSubscriber::subscribe(
+            server.local_address(),
+            vec![EventFilter::new()
+                .with_event_type(MatchRule::regex("^xo/").expect("Invalid regex"))],
+            StartFrom::After(EventPosition::new(0, 0)),
+        )
+        .expect("Unable to subscribe");
+
+        bus.publish(
+            "root2",
+            &[make_receipt(
+                "txn2",
+                vec![
+                    make_event("intkey/set", "a"),
+                    make_event("xo/take", "game-1"),
+                ],
+            )],
+        )
+        .expect("Unable to publish");
+
+        let event = subscriber.next_event().expect("Unable to receive event");
+        assert_eq!(event.position(), EventPosition::new(1, 0));
+        assert_eq!(event.event(), &make_event("xo/create", "game-1"));
+        let event = subscriber.next_event().expect("Unable to receive event");
+        assert_eq!(event.position(), EventPosition::new(2, 1));
+        assert_eq!(event.state_root(), "root2");
+        assert_eq!(subscriber.position(), Some(EventPosition::new(2, 1)));
+
+        server.stop().expect("Unable to stop server");
+    }
+}
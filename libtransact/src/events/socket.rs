@@ -0,0 +1,333 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Delivery of events to subscribers over a local socket.
+//!
+//! Each message is a protobuf message preceded by its length as a four-byte big-endian integer.
+//! A subscriber sends an `EventSubscribeRequest`, and the server answers with an
+//! `EventSubscribeResponse`; if the subscription was accepted, it then sends each matching event
+//! as a `CommittedEvent` until either side closes the connection.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use protobuf::Message;
+
+use crate::execution::adapter::socket::{connect, Listener, SocketAddress, Stream, Writer};
+use crate::protos::event_subscription::{
+    self, EventSubscribeRequest, EventSubscribeResponse, EventSubscribeResponse_Status,
+};
+use crate::protos::{FromProto, IntoNative, IntoProto, ProtoConversionError};
+
+use super::{
+    CommittedEvent, EventBus, EventBusError, EventFilter, EventPosition, EventSubscription,
+    StartFrom,
+};
+
+/// The largest message which will be read from an event socket.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+type Connections = Arc<Mutex<HashMap<usize, Box<dyn Writer>>>>;
+
+/// Serves subscriptions to an `EventBus` over a socket.
+///
+/// Each subscriber is served by its own thread. Once the subscriber disconnects or the server is
+/// stopped, the thread ends as soon as it fails to deliver an event, or when the bus is dropped.
+pub struct EventSocketServer {
+    address: SocketAddress,
+    connections: Connections,
+    shutdown: Arc<AtomicBool>,
+    accept_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl EventSocketServer {
+    /// Binds to the given address and starts accepting subscribers to the bus.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EventBusError` if the socket cannot be bound or the accepting thread cannot be
+    /// started.
+    pub fn start(bus: EventBus, address: SocketAddress) -> Result<Self, EventBusError> {
+        let listener = Listener::bind(&address)?;
+        let address = listener.local_address()?;
+
+        let connections: Connections = Arc::new(Mutex::new(HashMap::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let thread_connections = connections.clone();
+        let thread_shutdown = shutdown.clone();
+        let accept_handle = thread::Builder::new()
+            .name("EventSocketServer".into())
+            .spawn(move || {
+                accept_subscribers(listener, bus, thread_connections, thread_shutdown)
+            })?;
+
+        Ok(EventSocketServer {
+            address,
+            connections,
+            shutdown,
+            accept_handle: Some(accept_handle),
+        })
+    }
+
+    /// The address subscribers should connect to.
+    pub fn local_address(&self) -> &SocketAddress {
+        &self.address
+    }
+
+    /// Stops accepting subscribers and closes the connections of those already subscribed.
+    pub fn stop(mut self) -> Result<(), EventBusError> {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        if let Some(accept_handle) = self.accept_handle.take() {
+            // Wake the accepting thread so it sees the shutdown flag
+            if let Err(err) = connect(&self.address) {
+                debug!("Unable to wake event socket server thread: {}", err);
+            }
+            accept_handle
+                .join()
+                .map_err(|_| EventBusError::Internal("Unable to join accepting thread.".into()))?;
+        }
+
+        let connections = self
+            .connections
+            .lock()
+            .map_err(|_| EventBusError::Internal("connections lock poisoned".into()))?;
+        for writer in connections.values() {
+            if let Err(err) = writer.close() {
+                debug!("Unable to close event subscriber connection: {}", err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn accept_subscribers(
+    listener: Listener,
+    bus: EventBus,
+    connections: Connections,
+    shutdown: Arc<AtomicBool>,
+) {
+    let mut next_id = 0;
+    loop {
+        let stream = listener.accept();
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Unable to accept event subscriber connection: {}", err);
+                continue;
+            }
+        };
+        let writer = match stream.try_clone_writer() {
+            Ok(writer) => writer,
+            Err(err) => {
+                warn!("Unable to clone event subscriber connection: {}", err);
+                continue;
+            }
+        };
+
+        let id: usize = next_id;
+        next_id += 1;
+        match connections.lock() {
+            Ok(mut connections) => {
+                connections.insert(id, writer);
+            }
+            Err(_) => {
+                error!("Event subscriber connections lock poisoned");
+                break;
+            }
+        }
+
+        let bus = bus.clone();
+        let thread_connections = connections.clone();
+        let spawned = thread::Builder::new()
+            .name(format!("EventSocketServer-{}", id))
+            .spawn(move || {
+                if let Err(err) = serve_subscriber(bus, stream) {
+                    debug!("Event subscriber connection ended: {}", err);
+                }
+                if let Ok(mut connections) = thread_connections.lock() {
+                    connections.remove(&id);
+                }
+            });
+        if let Err(err) = spawned {
+            warn!("Unable to start event subscriber thread: {}", err);
+            if let Ok(mut connections) = connections.lock() {
+                connections.remove(&id);
+            }
+        }
+    }
+}
+
+fn serve_subscriber(bus: EventBus, mut stream: Box<dyn Stream>) -> Result<(), EventBusError> {
+    let request: EventSubscribeRequest = read_message(&mut *stream)?;
+
+    let mut response = EventSubscribeResponse::new();
+    let subscribed = subscribe(&bus, request);
+    // Only the subscription is kept, so that it ends when the bus's owners drop it
+    drop(bus);
+    let subscription = match subscribed {
+        Ok(subscription) => {
+            response.set_status(EventSubscribeResponse_Status::OK);
+            subscription
+        }
+        Err(err) => {
+            let status = match err {
+                EventBusError::UnknownStateRoot(_) | EventBusError::PositionUnavailable(_) => {
+                    EventSubscribeResponse_Status::UNAVAILABLE
+                }
+                _ => EventSubscribeResponse_Status::INVALID_REQUEST,
+            };
+            response.set_status(status);
+            response.set_error_message(err.to_string());
+            return write_message(&mut *stream, &response);
+        }
+    };
+    write_message(&mut *stream, &response)?;
+
+    for event in subscription {
+        let event: event_subscription::CommittedEvent = event.into_proto()?;
+        write_message(&mut *stream, &event)?;
+    }
+
+    Ok(())
+}
+
+fn subscribe(
+    bus: &EventBus,
+    mut request: EventSubscribeRequest,
+) -> Result<EventSubscription, EventBusError> {
+    let filters = request
+        .take_filters()
+        .into_iter()
+        .map(EventFilter::from_proto)
+        .collect::<Result<_, _>>()?;
+    let start = if request.has_start_after() {
+        StartFrom::After(EventPosition::from_proto(request.take_start_after())?)
+    } else if !request.get_start_state_root().is_empty() {
+        StartFrom::StateRoot(request.take_start_state_root())
+    } else {
+        StartFrom::Latest
+    };
+
+    bus.subscribe(filters, start)
+}
+
+/// Receives events from an `EventSocketServer`.
+pub struct SocketEventSubscriber {
+    stream: Box<dyn Stream>,
+    position: Option<EventPosition>,
+}
+
+impl SocketEventSubscriber {
+    /// Connects to the server at the address, and subscribes to the events matching any of the
+    /// filters, or to all events if there are none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EventBusError::SubscriptionRejected` if the server rejects the subscription,
+    /// such as when the start is no longer retained by its bus.
+    pub fn subscribe(
+        address: &SocketAddress,
+        filters: Vec<EventFilter>,
+        start: StartFrom,
+    ) -> Result<Self, EventBusError> {
+        let mut request = EventSubscribeRequest::new();
+        request.set_filters(
+            filters
+                .into_iter()
+                .map(|filter| filter.into_proto())
+                .collect::<Result<_, ProtoConversionError>>()?,
+        );
+        match start {
+            StartFrom::Latest => (),
+            StartFrom::StateRoot(state_root) => request.set_start_state_root(state_root),
+            StartFrom::After(position) => request.set_start_after(position.into_proto()?),
+        }
+
+        let mut stream = connect(address)?;
+        write_message(&mut *stream, &request)?;
+        let response: EventSubscribeResponse = read_message(&mut *stream)?;
+        match response.get_status() {
+            EventSubscribeResponse_Status::OK => Ok(SocketEventSubscriber {
+                stream,
+                position: None,
+            }),
+            EventSubscribeResponse_Status::STATUS_UNSET => Err(EventBusError::ProtocolError(
+                "subscribe response status unset".into(),
+            )),
+            _ => Err(EventBusError::SubscriptionRejected(
+                response.get_error_message().into(),
+            )),
+        }
+    }
+
+    /// Waits for the next event.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EventBusError` if the connection is closed or a malformed message is received.
+    pub fn next_event(&mut self) -> Result<CommittedEvent, EventBusError> {
+        let event: event_subscription::CommittedEvent = read_message(&mut *self.stream)?;
+        let event: CommittedEvent = event.into_native()?;
+        self.position = Some(event.position());
+        Ok(event)
+    }
+
+    /// The position of the last event received; a new subscription which starts after it
+    /// resumes where this one left off.
+    pub fn position(&self) -> Option<EventPosition> {
+        self.position
+    }
+}
+
+fn read_message<M: Message, R: Read + ?Sized>(reader: &mut R) -> Result<M, EventBusError> {
+    let mut len_bytes = [0; 4];
+    reader.read_exact(&mut len_bytes)?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(EventBusError::ProtocolError(format!(
+            "message of {} bytes exceeds the maximum of {} bytes",
+            len, MAX_MESSAGE_SIZE
+        )));
+    }
+
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    protobuf::parse_from_bytes(&bytes)
+        .map_err(|err| EventBusError::ProtocolError(format!("unable to parse message: {}", err)))
+}
+
+fn write_message<W: Write + ?Sized>(
+    writer: &mut W,
+    message: &dyn Message,
+) -> Result<(), EventBusError> {
+    let bytes = message.write_to_bytes().map_err(|err| {
+        EventBusError::ProtocolError(format!("unable to serialize message: {}", err))
+    })?;
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    Ok(())
+}
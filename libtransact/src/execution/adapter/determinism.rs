@@ -0,0 +1,446 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Detection of non-deterministic transaction handlers.
+//!
+//! A `DeterminismCheck` applies a transaction twice, each time in a scratch context which reads
+//! from the transaction's context but keeps its own writes, events and receipt data. If the two
+//! runs differ, the transaction is flagged with a `NondeterminismReport` describing the
+//! differences; otherwise, the changes of the first run, if it succeeded, are made to the
+//! transaction's context.
+//!
+//! Handlers invoked by the transaction's handler run within each scratch context, so their changes
+//! are compared with the rest of the run's, and made at most once.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+
+use rand::Rng;
+
+use crate::execution::adapter::invocation::InvocationContext;
+use crate::execution::adapter::static_adapter::HandlerFamilies;
+use crate::handler::{ApplyError, ContextError, TransactionContext, TransactionHandler};
+use crate::protocol::receipt::{Event, StateChange};
+use crate::protocol::transaction::TransactionPair;
+
+type NondeterminismObserver = Box<dyn Fn(&NondeterminismReport) + Send>;
+
+/// Configures the checking of transactions for non-determinism.
+///
+/// By default, every transaction is checked, and non-deterministic transactions fail with a
+/// `NondeterminismError`.
+pub struct DeterminismCheck {
+    sample_rate: f64,
+    observer: Option<NondeterminismObserver>,
+}
+
+impl Default for DeterminismCheck {
+    fn default() -> Self {
+        DeterminismCheck {
+            sample_rate: 1.0,
+            observer: None,
+        }
+    }
+}
+
+impl DeterminismCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks a random fraction of transactions, from 0.0 (none) to 1.0 (all).
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// Reports non-deterministic transactions to the observer, instead of failing them; the
+    /// result and changes of the first run are kept.
+    pub fn with_observer(mut self, observer: NondeterminismObserver) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Whether the next transaction should be checked.
+    pub fn sample(&self) -> bool {
+        self.sample_rate >= 1.0
+            || (self.sample_rate > 0.0 && rand::thread_rng().gen::<f64>() < self.sample_rate)
+    }
+
+    /// Applies the transaction twice with the handler, and, if the runs are the same or an
+    /// observer has been given, makes the changes of the first run to the context, as long as
+    /// that run succeeded.
+    ///
+    /// The handler may invoke any of the given handlers.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NondeterminismError` if the runs differ and no observer has been given.
+    pub fn apply(
+        &self,
+        handler: &dyn TransactionHandler,
+        handlers: &[Box<dyn TransactionHandler>],
+        transaction_pair: &TransactionPair,
+        context: &mut dyn TransactionContext,
+    ) -> Result<Result<(), ApplyError>, NondeterminismError> {
+        let families = HandlerFamilies::new(handlers);
+        self.apply_with(transaction_pair, context, |context| {
+            handler.apply(
+                transaction_pair,
+                &mut InvocationContext::new(handlers, &families, transaction_pair, &*context),
+            )
+        })
+    }
+
+    /// Applies the transaction twice with the given function, as `apply` does with a handler.
+    ///
+    /// The function is given each run's scratch context, which does not support invocations; a
+    /// function applying a handler which may invoke others must give it an `InvocationContext`
+    /// over the scratch context, as `apply` does.
+    pub(crate) fn apply_with<F>(
+        &self,
+        transaction_pair: &TransactionPair,
+        context: &mut dyn TransactionContext,
+        apply: F,
+    ) -> Result<Result<(), ApplyError>, NondeterminismError>
+    where
+        F: Fn(&mut dyn TransactionContext) -> Result<(), ApplyError>,
+    {
+        let mut first = ScratchContext::new(&*context);
+        let first_result = apply(&mut first);
+        let mut second = ScratchContext::new(&*context);
+        let second_result = apply(&mut second);
+
+        let differences = compare(&first_result, &first, &second_result, &second);
+        if !differences.is_empty() {
+            let report = NondeterminismReport {
+                transaction_id: transaction_pair.transaction().header_signature().into(),
+                differences,
+            };
+            match &self.observer {
+                Some(observer) => observer(&report),
+                None => return Err(NondeterminismError(report)),
+            }
+        }
+
+        // A failed run's changes are discarded, as they would be without the check
+        if first_result.is_ok() {
+            if let Err(err) = first.commit(context) {
+                return Ok(Err(err.into()));
+            }
+        }
+        Ok(first_result)
+    }
+}
+
+/// A difference between two runs of a transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Difference {
+    /// The runs returned different results
+    Result { first: String, second: String },
+    /// The runs left an address in different states; `None` if a run did not change it
+    State {
+        address: String,
+        first: Option<StateChange>,
+        second: Option<StateChange>,
+    },
+    /// The runs added different events
+    Events {
+        first: Vec<Event>,
+        second: Vec<Event>,
+    },
+    /// The runs added different receipt data
+    ReceiptData {
+        first: Vec<Vec<u8>>,
+        second: Vec<Vec<u8>>,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Result { first, second } => {
+                write!(f, "result {} != {}", first, second)
+            }
+            Difference::State {
+                address,
+                first,
+                second,
+            } => write!(
+                f,
+                "address {}: {} != {}",
+                address,
+                describe_change(first),
+                describe_change(second)
+            ),
+            Difference::Events { first, second } => {
+                write!(f, "events {:?} != {:?}", first, second)
+            }
+            Difference::ReceiptData { first, second } => write!(
+                f,
+                "receipt data [{}] != [{}]",
+                describe_data(first),
+                describe_data(second)
+            ),
+        }
+    }
+}
+
+fn describe_change(change: &Option<StateChange>) -> String {
+    match change {
+        Some(StateChange::Set { value, .. }) => format!("set to {}", hex::encode(value)),
+        Some(StateChange::Delete { .. }) => "deleted".into(),
+        None => "unchanged".into(),
+    }
+}
+
+fn describe_data(data: &[Vec<u8>]) -> String {
+    data.iter().map(hex::encode).collect::<Vec<_>>().join(", ")
+}
+
+/// The differences between two runs of a transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NondeterminismReport {
+    transaction_id: String,
+    differences: Vec<Difference>,
+}
+
+impl NondeterminismReport {
+    pub fn transaction_id(&self) -> &str {
+        &self.transaction_id
+    }
+
+    pub fn differences(&self) -> &[Difference] {
+        &self.differences
+    }
+}
+
+impl fmt::Display for NondeterminismReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "transaction {} is non-deterministic: ",
+            self.transaction_id
+        )?;
+        for (i, difference) in self.differences.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returned when two runs of a transaction differ.
+#[derive(Debug)]
+pub struct NondeterminismError(pub NondeterminismReport);
+
+impl Error for NondeterminismError {}
+
+impl fmt::Display for NondeterminismError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn compare(
+    first_result: &Result<(), ApplyError>,
+    first: &ScratchContext,
+    second_result: &Result<(), ApplyError>,
+    second: &ScratchContext,
+) -> Vec<Difference> {
+    let mut differences = vec![];
+
+    let describe_result = |result: &Result<(), ApplyError>| match result {
+        Ok(()) => "Valid".to_string(),
+        Err(err) => err.to_string(),
+    };
+    let first_result = describe_result(first_result);
+    let second_result = describe_result(second_result);
+    if first_result != second_result {
+        differences.push(Difference::Result {
+            first: first_result,
+            second: second_result,
+        });
+    }
+
+    let first_state = first.state.borrow();
+    let second_state = second.state.borrow();
+    let addresses: BTreeSet<&String> = first_state.keys().chain(second_state.keys()).collect();
+    for address in addresses {
+        let first_change = first.state_change(address);
+        let second_change = second.state_change(address);
+        if first_change != second_change {
+            differences.push(Difference::State {
+                address: address.clone(),
+                first: first_change,
+                second: second_change,
+            });
+        }
+    }
+
+    if first.events.borrow().as_slice() != second.events.borrow().as_slice() {
+        differences.push(Difference::Events {
+            first: first.events.borrow().clone(),
+            second: second.events.borrow().clone(),
+        });
+    }
+
+    if first.data.borrow().as_slice() != second.data.borrow().as_slice() {
+        differences.push(Difference::ReceiptData {
+            first: first.data.borrow().clone(),
+            second: second.data.borrow().clone(),
+        });
+    }
+
+    differences
+}
+
+/// A `TransactionContext` which reads from another context, but keeps its writes, events and
+/// receipt data to itself until committed.
+///
+/// Invocations are not forwarded to the other context, which would make the invoked handler's
+/// changes there directly; handlers are instead given an `InvocationContext` over the scratch
+/// context, so that invoked handlers' changes are kept with the rest.
+pub(crate) struct ScratchContext<'a> {
+    context: &'a dyn TransactionContext,
+    // The value of each address changed, or None if deleted
+    state: RefCell<BTreeMap<String, Option<Vec<u8>>>>,
+    // The changes, in the order they were made
+    changes: RefCell<Vec<StateChange>>,
+    events: RefCell<Vec<Event>>,
+    data: RefCell<Vec<Vec<u8>>>,
+}
+
+impl<'a> ScratchContext<'a> {
+    pub(crate) fn new(context: &'a dyn TransactionContext) -> Self {
+        ScratchContext {
+            context,
+            state: RefCell::new(BTreeMap::new()),
+            changes: RefCell::new(vec![]),
+            events: RefCell::new(vec![]),
+            data: RefCell::new(vec![]),
+        }
+    }
+
+    fn state_change(&self, address: &str) -> Option<StateChange> {
+        self.state.borrow().get(address).map(|value| match value {
+            Some(value) => StateChange::Set {
+                key: address.into(),
+                value: value.clone(),
+            },
+            None => StateChange::Delete {
+                key: address.into(),
+            },
+        })
+    }
+
+    /// Makes the changes to the given context, in the order they were made to this one.
+    pub(crate) fn commit(self, context: &dyn TransactionContext) -> Result<(), ContextError> {
+        for change in self.changes.into_inner() {
+            match change {
+                StateChange::Set { key, value } => context.set_state_entry(key, value)?,
+                StateChange::Delete { key } => {
+                    context.delete_state_entry(&key)?;
+                }
+            }
+        }
+        for event in self.events.into_inner() {
+            context.add_event(event.event_type, event.attributes, event.data)?;
+        }
+        for data in self.data.into_inner() {
+            context.add_receipt_data(data)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> TransactionContext for ScratchContext<'a> {
+    fn get_state_entries(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        let state = self.state.borrow();
+        let unchanged = addresses
+            .iter()
+            .filter(|address| !state.contains_key(*address))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut values = if unchanged.is_empty() {
+            vec![]
+        } else {
+            self.context.get_state_entries(&unchanged)?
+        };
+        values.extend(addresses.iter().filter_map(|address| {
+            state
+                .get(address)
+                .and_then(|value| value.clone())
+                .map(|value| (address.clone(), value))
+        }));
+        Ok(values)
+    }
+
+    fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        for (address, value) in entries {
+            self.state
+                .borrow_mut()
+                .insert(address.clone(), Some(value.clone()));
+            self.changes.borrow_mut().push(StateChange::Set {
+                key: address,
+                value,
+            });
+        }
+        Ok(())
+    }
+
+    fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+        let existing = self
+            .get_state_entries(addresses)?
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect::<Vec<_>>();
+        for address in &existing {
+            self.state.borrow_mut().insert(address.clone(), None);
+            self.changes.borrow_mut().push(StateChange::Delete {
+                key: address.clone(),
+            });
+        }
+        Ok(existing)
+    }
+
+    fn add_receipt_data(&self, data: Vec<u8>) -> Result<(), ContextError> {
+        self.data.borrow_mut().push(data);
+        Ok(())
+    }
+
+    fn add_event(
+        &self,
+        event_type: String,
+        attributes: Vec<(String, String)>,
+        data: Vec<u8>,
+    ) -> Result<(), ContextError> {
+        self.events.borrow_mut().push(Event {
+            event_type,
+            attributes,
+            data,
+        });
+        Ok(())
+    }
+}
@@ -20,8 +20,10 @@ use crate::protocol::transaction::TransactionPair;
 use std::{error::Error, fmt};
 
 /// During processing of the Transaction, something unexpected happened.
-/// The `Executor` immediately retries the `TransactionPair` for all of these
-/// errors.
+///
+/// The `Executor` only retries the `TransactionPair` after a `RoutingError`. The other errors
+/// may be returned after a handler has written to the transaction's context, so the transaction
+/// is reported as invalid instead.
 #[derive(Debug)]
 pub enum ExecutionAdapterError {
     /// Executing the transaction took too much time and so abort
@@ -29,6 +31,9 @@ pub enum ExecutionAdapterError {
     /// This ExecutionAdaptor does not have the capability to process the `TransactionPair`
     /// given to it. This can happen due to a timing error in routing the `TransactionPair`
     /// to the `ExecutionAdapter`.
+    ///
+    /// An adapter must only return this error if the transaction never reached a handler, since
+    /// the transaction is then retried on the same context.
     RoutingError(Box<TransactionPair>),
 
     GeneralExecutionError(Box<dyn Error + Send>),
@@ -76,6 +81,9 @@ pub enum ExecutionOperationError {
 
     /// An error occurred on `ExecutionAdaptor.stop`
     StopError(String),
+
+    /// An error occurred while adding, replacing or removing an adapter's handlers
+    HandlerUpdateError(String),
 }
 
 impl Error for ExecutionOperationError {}
@@ -86,6 +94,9 @@ impl fmt::Display for ExecutionOperationError {
             ExecutionOperationError::StartError(s) => write!(f, "Start Error: {}", s),
             ExecutionOperationError::ExecuteError(s) => write!(f, "Execute Error: {}", s),
             ExecutionOperationError::StopError(s) => write!(f, "Execute Error: {}", s),
+            ExecutionOperationError::HandlerUpdateError(s) => {
+                write!(f, "Handler Update Error: {}", s)
+            }
         }
     }
 }
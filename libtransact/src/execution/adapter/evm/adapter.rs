@@ -0,0 +1,76 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use crate::context::manager::sync::ContextManager;
+use crate::context::ContextId;
+use crate::execution::adapter::static_adapter::StaticExecutionAdapter;
+use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError, ExecutionOperationError};
+use crate::execution::ExecutionRegistry;
+use crate::protocol::transaction::TransactionPair;
+use crate::scheduler::ExecutionTaskCompletionNotification;
+
+use super::{EvmAddresser, EvmTransactionHandler};
+
+/// An `ExecutionAdapter` which executes `evm` family transactions.
+///
+/// Transactions are executed one at a time on a background thread.
+pub struct EvmExecutionAdapter {
+    inner: StaticExecutionAdapter,
+}
+
+impl EvmExecutionAdapter {
+    /// Creates a new adapter which stores accounts at the addresses computed by `addresser`.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionAdapterError` is returned if the background thread cannot be created.
+    pub fn new(
+        addresser: EvmAddresser,
+        context_manager: ContextManager,
+    ) -> Result<Self, ExecutionAdapterError> {
+        Ok(EvmExecutionAdapter {
+            inner: StaticExecutionAdapter::new_adapter(
+                vec![Box::new(EvmTransactionHandler::new(addresser))],
+                context_manager,
+            )?,
+        })
+    }
+}
+
+impl ExecutionAdapter for EvmExecutionAdapter {
+    fn start(
+        &mut self,
+        execution_registry: Box<dyn ExecutionRegistry>,
+    ) -> Result<(), ExecutionOperationError> {
+        self.inner.start(execution_registry)
+    }
+
+    fn execute(
+        &self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: Box<
+            dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send,
+        >,
+    ) -> Result<(), ExecutionOperationError> {
+        self.inner.execute(transaction_pair, context_id, on_done)
+    }
+
+    fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
+        Box::new(self.inner).stop()
+    }
+}
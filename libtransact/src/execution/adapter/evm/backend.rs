@@ -0,0 +1,254 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Presents Transact state to the EVM interpreter as accounts.
+
+use std::cell::RefCell;
+
+use evm::backend::{Apply, Backend, Basic, Log};
+use primitive_types::{H160, H256, U256};
+use sha3::{Digest, Keccak256};
+
+use crate::contract::address::Addresser;
+use crate::handler::{ApplyError, TransactionContext};
+
+use super::{EvmAddresser, EvmKey};
+
+/// An EVM `Backend` which reads accounts from a transaction's context.
+///
+/// The `Backend` trait does not allow reads to fail, so the first error is recorded, and reported
+/// by `check_error` once the interpreter is done.
+pub(super) struct ContextBackend<'a> {
+    context: &'a dyn TransactionContext,
+    addresser: &'a EvmAddresser,
+    origin: H160,
+    error: RefCell<Option<String>>,
+}
+
+impl<'a> ContextBackend<'a> {
+    pub fn new(
+        context: &'a dyn TransactionContext,
+        addresser: &'a EvmAddresser,
+        origin: H160,
+    ) -> Self {
+        ContextBackend {
+            context,
+            addresser,
+            origin,
+            error: RefCell::new(None),
+        }
+    }
+
+    /// Returns an error if any read failed.
+    pub fn check_error(&self) -> Result<(), ApplyError> {
+        match self.error.borrow_mut().take() {
+            Some(err) => Err(ApplyError::InternalError(err)),
+            None => Ok(()),
+        }
+    }
+
+    fn record_error(&self, err: String) {
+        let mut error = self.error.borrow_mut();
+        if error.is_none() {
+            *error = Some(err);
+        }
+    }
+
+    fn read(&self, key: &EvmKey) -> Option<Vec<u8>> {
+        let result = self
+            .addresser
+            .compute(key)
+            .map_err(|err| err.to_string())
+            .and_then(|address| {
+                self.context
+                    .get_state_entry(&address)
+                    .map_err(|err| err.to_string())
+            });
+        match result {
+            Ok(value) => value,
+            Err(err) => {
+                self.record_error(format!("unable to read {:?}: {}", key, err));
+                None
+            }
+        }
+    }
+
+    /// Writes the changes made by a transaction to its context.
+    pub fn apply<A, I>(&self, values: A, logs: Vec<Log>) -> Result<(), ApplyError>
+    where
+        A: IntoIterator<Item = Apply<I>>,
+        I: IntoIterator<Item = (H256, H256)>,
+    {
+        let mut sets = vec![];
+        let mut deletes = vec![];
+        for apply in values {
+            match apply {
+                Apply::Modify {
+                    address,
+                    basic,
+                    code,
+                    storage,
+                    reset_storage: _,
+                } => {
+                    // Storage is reset when a contract is created, at an address which cannot have
+                    // held a contract, so there is no storage to clear
+                    sets.push((
+                        self.compute(&EvmKey::Account(address))?,
+                        encode_basic(&basic),
+                    ));
+                    if let Some(code) = code {
+                        sets.push((self.compute(&EvmKey::Code(address))?, code));
+                    }
+                    for (slot, value) in storage {
+                        let key = self.compute(&EvmKey::Storage(address, slot))?;
+                        if value == H256::zero() {
+                            deletes.push(key);
+                        } else {
+                            sets.push((key, value.as_bytes().to_vec()));
+                        }
+                    }
+                }
+                Apply::Delete { address } => {
+                    // Storage is not indexed by account, so is left in place; it cannot be read
+                    // unless a contract is later created at the same address
+                    deletes.push(self.compute(&EvmKey::Account(address))?);
+                    deletes.push(self.compute(&EvmKey::Code(address))?);
+                }
+            }
+        }
+
+        if !sets.is_empty() {
+            self.context.set_state_entries(sets)?;
+        }
+        if !deletes.is_empty() {
+            self.context.delete_state_entries(&deletes)?;
+        }
+
+        for log in logs {
+            let mut attributes = vec![("address".to_string(), hex::encode(log.address))];
+            for (i, topic) in log.topics.iter().enumerate() {
+                attributes.push((format!("topic{}", i), hex::encode(topic)));
+            }
+            self.context
+                .add_event("evm_log".into(), attributes, log.data)?;
+        }
+
+        Ok(())
+    }
+
+    fn compute(&self, key: &EvmKey) -> Result<String, ApplyError> {
+        self.addresser
+            .compute(key)
+            .map_err(|err| ApplyError::InternalError(err.to_string()))
+    }
+}
+
+/// Encodes an account's balance and nonce as two 32-byte big-endian integers.
+fn encode_basic(basic: &Basic) -> Vec<u8> {
+    let mut bytes = vec![0; 64];
+    basic.balance.to_big_endian(&mut bytes[..32]);
+    basic.nonce.to_big_endian(&mut bytes[32..]);
+    bytes
+}
+
+fn decode_basic(bytes: &[u8]) -> Option<Basic> {
+    if bytes.len() != 64 {
+        return None;
+    }
+    Some(Basic {
+        balance: U256::from_big_endian(&bytes[..32]),
+        nonce: U256::from_big_endian(&bytes[32..]),
+    })
+}
+
+impl<'a> Backend for ContextBackend<'a> {
+    fn gas_price(&self) -> U256 {
+        U256::zero()
+    }
+
+    fn origin(&self) -> H160 {
+        self.origin
+    }
+
+    fn block_hash(&self, _number: U256) -> H256 {
+        H256::zero()
+    }
+
+    fn block_number(&self) -> U256 {
+        U256::zero()
+    }
+
+    fn block_coinbase(&self) -> H160 {
+        H160::zero()
+    }
+
+    fn block_timestamp(&self) -> U256 {
+        U256::zero()
+    }
+
+    fn block_difficulty(&self) -> U256 {
+        U256::zero()
+    }
+
+    fn block_gas_limit(&self) -> U256 {
+        U256::max_value()
+    }
+
+    fn chain_id(&self) -> U256 {
+        U256::zero()
+    }
+
+    fn exists(&self, address: H160) -> bool {
+        self.read(&EvmKey::Account(address)).is_some()
+    }
+
+    fn basic(&self, address: H160) -> Basic {
+        match self.read(&EvmKey::Account(address)) {
+            Some(bytes) => decode_basic(&bytes).unwrap_or_else(|| {
+                self.record_error(format!("account {:x} is malformed", address));
+                Basic::default()
+            }),
+            None => Basic::default(),
+        }
+    }
+
+    fn code_hash(&self, address: H160) -> H256 {
+        H256::from_slice(Keccak256::digest(&self.code(address)).as_slice())
+    }
+
+    fn code_size(&self, address: H160) -> usize {
+        self.code(address).len()
+    }
+
+    fn code(&self, address: H160) -> Vec<u8> {
+        self.read(&EvmKey::Code(address)).unwrap_or_default()
+    }
+
+    fn storage(&self, address: H160, index: H256) -> H256 {
+        match self.read(&EvmKey::Storage(address, index)) {
+            Some(bytes) if bytes.len() == 32 => H256::from_slice(&bytes),
+            Some(_) => {
+                self.record_error(format!(
+                    "storage slot {:x} of account {:x} is malformed",
+                    index, address
+                ));
+                H256::zero()
+            }
+            None => H256::zero(),
+        }
+    }
+}
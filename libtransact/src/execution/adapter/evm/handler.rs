@@ -0,0 +1,140 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use evm::executor::StackExecutor;
+use evm::{Config, CreateScheme, ExitReason};
+use primitive_types::{H160, U256};
+use sha3::{Digest, Keccak256};
+
+use crate::handler::{ApplyError, TransactionContext, TransactionHandler};
+use crate::protocol::transaction::TransactionPair;
+use crate::protos::evm::{EvmPayload, EvmPayload_Action};
+
+use super::backend::ContextBackend;
+use super::{EvmAddresser, EVM_FAMILY_NAME, EVM_FAMILY_VERSION};
+
+/// Handles the transactions of the `evm` family.
+pub struct EvmTransactionHandler {
+    family_name: String,
+    family_versions: Vec<String>,
+    addresser: EvmAddresser,
+    config: Config,
+}
+
+impl EvmTransactionHandler {
+    /// Creates a handler which stores accounts at the addresses computed by `addresser`.
+    pub fn new(addresser: EvmAddresser) -> Self {
+        EvmTransactionHandler {
+            family_name: EVM_FAMILY_NAME.into(),
+            family_versions: vec![EVM_FAMILY_VERSION.into()],
+            addresser,
+            config: Config::istanbul(),
+        }
+    }
+}
+
+/// Returns the account of the given public key: the last 20 bytes of its Keccak-256 hash.
+fn account_address(public_key: &[u8]) -> H160 {
+    H160::from_slice(&Keccak256::digest(public_key)[12..])
+}
+
+impl TransactionHandler for EvmTransactionHandler {
+    fn family_name(&self) -> &str {
+        &self.family_name
+    }
+
+    fn family_versions(&self) -> &[String] {
+        &self.family_versions
+    }
+
+    fn apply(
+        &self,
+        transaction: &TransactionPair,
+        context: &mut dyn TransactionContext,
+    ) -> Result<(), ApplyError> {
+        let mut payload: EvmPayload =
+            protobuf::parse_from_bytes(transaction.transaction().payload()).map_err(|err| {
+                ApplyError::InvalidTransaction(format!("unable to parse payload: {}", err))
+            })?;
+        if payload.get_gas_limit() == 0 || payload.get_gas_limit() > usize::max_value() as u64 {
+            return Err(ApplyError::InvalidTransaction(format!(
+                "invalid gas limit {}",
+                payload.get_gas_limit()
+            )));
+        }
+        let gas_limit = payload.get_gas_limit() as usize;
+
+        let caller = account_address(transaction.header().signer_public_key());
+        let backend = ContextBackend::new(&*context, &self.addresser, caller);
+        let mut executor = StackExecutor::new(&backend, gas_limit, &self.config);
+
+        let (exit_reason, receipt_data) = match payload.get_action() {
+            EvmPayload_Action::DEPLOY => {
+                let address = executor.create_address(CreateScheme::Legacy { caller });
+                let exit_reason =
+                    executor.transact_create(caller, U256::zero(), payload.take_data(), gas_limit);
+                (exit_reason, address.as_bytes().to_vec())
+            }
+            EvmPayload_Action::CALL => {
+                if payload.get_to().len() != 20 {
+                    return Err(ApplyError::InvalidTransaction(
+                        "contract address must be 20 bytes".into(),
+                    ));
+                }
+                executor.transact_call(
+                    caller,
+                    H160::from_slice(payload.get_to()),
+                    U256::zero(),
+                    payload.take_data(),
+                    gas_limit,
+                )
+            }
+            EvmPayload_Action::ACTION_UNSET => {
+                return Err(ApplyError::InvalidTransaction(
+                    "payload action is not set".into(),
+                ))
+            }
+        };
+
+        match exit_reason {
+            ExitReason::Succeed(_) => (),
+            ExitReason::Revert(_) => {
+                backend.check_error()?;
+                return Err(ApplyError::InvalidTransaction(format!(
+                    "execution reverted: {}",
+                    hex::encode(receipt_data)
+                )));
+            }
+            ExitReason::Error(err) => {
+                backend.check_error()?;
+                return Err(ApplyError::InvalidTransaction(format!(
+                    "execution failed: {:?}",
+                    err
+                )));
+            }
+            ExitReason::Fatal(err) => {
+                return Err(ApplyError::InternalError(format!("EVM failed: {:?}", err)))
+            }
+        }
+        backend.check_error()?;
+
+        let (values, logs) = executor.deconstruct();
+        backend.apply(values, logs.into_iter().collect())?;
+        context.add_receipt_data(receipt_data)?;
+        Ok(())
+    }
+}
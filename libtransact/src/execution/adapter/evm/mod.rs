@@ -0,0 +1,289 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Execution of Ethereum Virtual Machine (EVM) smart contracts.
+//!
+//! The `EvmTransactionHandler` processes the transactions of the `evm` family, whose payloads are
+//! `EvmPayload` messages which either deploy a contract or call one. Contracts are run by a
+//! pure-Rust EVM interpreter against accounts held in Transact state; the `EvmExecutionAdapter`
+//! executes these transactions directly.
+//!
+//! Each EVM account is stored at addresses computed by an `EvmAddresser`:
+//!
+//! * its balance and nonce, as two 32-byte big-endian integers
+//! * its code
+//! * each non-zero storage slot, as a 32-byte value
+//!
+//! The account which sends a transaction is the last 20 bytes of the Keccak-256 hash of the
+//! transaction's signer public key. When a contract is deployed, its 20-byte address is added to
+//! the transaction receipt; when a contract is called, its output is added to the receipt. Each
+//! log is added as an event of type `"evm_log"`, with the contract's address and the log's topics
+//! as hex-encoded attributes.
+//!
+//! As Transact has no blocks, contracts see a block number, timestamp, difficulty and coinbase of
+//! zero, and gas has no price.
+//!
+//! Note, to use this module, the Transact library must have the `"evm-adapter"` feature enabled.
+
+mod adapter;
+mod backend;
+mod handler;
+
+use primitive_types::{H160, H256};
+
+use crate::contract::address::double_key_hash::DoubleKeyHashAddresser;
+use crate::contract::address::{Addresser, AddresserError};
+
+pub use self::adapter::EvmExecutionAdapter;
+pub use self::handler::EvmTransactionHandler;
+
+pub const EVM_FAMILY_NAME: &str = "evm";
+pub const EVM_FAMILY_VERSION: &str = "1.0";
+
+/// The default address prefix of EVM state.
+pub const EVM_PREFIX: &str = "a68b06";
+
+/// The natural keys of EVM state.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvmKey {
+    /// The balance and nonce of an account
+    Account(H160),
+    /// The code of an account
+    Code(H160),
+    /// A storage slot of an account
+    Storage(H160, H256),
+}
+
+/// Computes the addresses of EVM state.
+///
+/// All of an account's entries share the account's hash, which follows the prefix.
+pub struct EvmAddresser {
+    addresser: DoubleKeyHashAddresser,
+}
+
+impl EvmAddresser {
+    pub fn new(prefix: String) -> Result<Self, AddresserError> {
+        Ok(EvmAddresser {
+            addresser: DoubleKeyHashAddresser::new(prefix, None)?,
+        })
+    }
+
+    fn double_key(key: &EvmKey) -> (String, String) {
+        match key {
+            EvmKey::Account(address) => (hex::encode(address), "account".into()),
+            EvmKey::Code(address) => (hex::encode(address), "code".into()),
+            // Storage slots are 64 hex characters, so never collide with the names above
+            EvmKey::Storage(address, slot) => (hex::encode(address), hex::encode(slot)),
+        }
+    }
+}
+
+impl Addresser<EvmKey> for EvmAddresser {
+    fn compute(&self, key: &EvmKey) -> Result<String, AddresserError> {
+        self.addresser.compute(&Self::double_key(key))
+    }
+
+    fn normalize(&self, key: &EvmKey) -> String {
+        self.addresser.normalize(&Self::double_key(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use protobuf::Message;
+
+    use crate::context::manager::sync::ContextManager;
+    use crate::context::{ContextId, ContextLifecycle};
+    use crate::execution::adapter::ExecutionAdapter;
+    use crate::protocol::receipt::{Event, TransactionResult};
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::protos::evm::{EvmPayload, EvmPayload_Action};
+    use crate::scheduler::ExecutionTaskCompletionNotification;
+    use crate::signing::hash::HashSigner;
+    use crate::state::hashmap::HashMapState;
+
+    /// Init code for a contract which stores the first word of its call data in slot 0, logs it
+    /// with the topic 0x2a, and returns it.
+    const STORE_CONTRACT: &str =
+        "6016600c60003960166000f360003580600055600052602a60206000a160206000f3";
+
+    /// Executes an EVM transaction in a new context which follows the given contexts, returning
+    /// the new context and the transaction's result.
+    fn execute(
+        adapter: &EvmExecutionAdapter,
+        context_manager: &mut ContextManager,
+        dependencies: &[ContextId],
+        action: EvmPayload_Action,
+        to: &[u8],
+        data: Vec<u8>,
+        gas_limit: u64,
+    ) -> (ContextId, TransactionResult) {
+        let mut payload = EvmPayload::new();
+        payload.set_action(action);
+        payload.set_to(to.to_vec());
+        payload.set_data(data);
+        payload.set_gas_limit(gas_limit);
+
+        let txn_pair = TransactionBuilder::new()
+            .with_family_name(EVM_FAMILY_NAME.into())
+            .with_family_version(EVM_FAMILY_VERSION.into())
+            .with_inputs(vec![hex::decode(EVM_PREFIX).unwrap()])
+            .with_outputs(vec![hex::decode(EVM_PREFIX).unwrap()])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(
+                payload
+                    .write_to_bytes()
+                    .expect("Unable to serialize payload"),
+            )
+            .build_pair(&HashSigner::default())
+            .expect("Unable to build transaction");
+        let txn_id = txn_pair.transaction().header_signature().to_string();
+        let context_id =
+            context_manager.create_context(dependencies, &HashMapState::state_id(&HashMap::new()));
+
+        let (result_tx, result_rx) = channel();
+        adapter
+            .execute(
+                txn_pair,
+                context_id,
+                Box::new(move |res| result_tx.send(res).expect("Unable to send result")),
+            )
+            .expect("Unable to execute transaction");
+        match result_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Did not receive result")
+            .expect("Execution failed")
+        {
+            ExecutionTaskCompletionNotification::Valid(..) => (),
+            ExecutionTaskCompletionNotification::Invalid(_, result) => {
+                return (
+                    context_id,
+                    TransactionResult::Invalid {
+                        error_message: result.error_message,
+                        error_data: result.error_data,
+                    },
+                )
+            }
+        }
+
+        let receipt = context_manager
+            .get_transaction_receipt(&context_id, &txn_id)
+            .expect("Unable to get receipt");
+        (context_id, receipt.transaction_result)
+    }
+
+    /// Tests deploying a contract, calling it to store a value and emit a log, and that a call
+    /// with too little gas is invalid.
+    #[test]
+    fn test_evm_adapter() {
+        let state = HashMapState::new();
+        let mut context_manager = ContextManager::new(Box::new(state));
+        let addresser = EvmAddresser::new(EVM_PREFIX.into()).expect("Unable to create addresser");
+        let adapter = EvmExecutionAdapter::new(
+            EvmAddresser::new(EVM_PREFIX.into()).expect("Unable to create addresser"),
+            context_manager.clone(),
+        )
+        .expect("Unable to create adapter");
+
+        let (deploy_context, result) = execute(
+            &adapter,
+            &mut context_manager,
+            &[],
+            EvmPayload_Action::DEPLOY,
+            &[],
+            hex::decode(STORE_CONTRACT).unwrap(),
+            1_000_000,
+        );
+        let contract_address = match result {
+            TransactionResult::Valid { data, .. } => H160::from_slice(&data[0]),
+            res => panic!("Unexpected result: {:?}", res),
+        };
+        let code_address = addresser
+            .compute(&EvmKey::Code(contract_address))
+            .expect("Unable to compute address");
+        assert_eq!(
+            context_manager
+                .get(&deploy_context, &[code_address])
+                .expect("Unable to get state")
+                .len(),
+            1
+        );
+
+        let mut word = vec![0; 32];
+        word[31] = 5;
+        let (call_context, result) = execute(
+            &adapter,
+            &mut context_manager,
+            &[deploy_context],
+            EvmPayload_Action::CALL,
+            contract_address.as_bytes(),
+            word.clone(),
+            1_000_000,
+        );
+        match result {
+            TransactionResult::Valid { events, data, .. } => {
+                assert_eq!(data, vec![word.clone()]);
+                assert_eq!(
+                    events,
+                    vec![Event {
+                        event_type: "evm_log".into(),
+                        attributes: vec![
+                            ("address".into(), hex::encode(contract_address)),
+                            ("topic0".into(), hex::encode(H256::from_low_u64_be(0x2a))),
+                        ],
+                        data: word.clone(),
+                    }]
+                );
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        let slot_address = addresser
+            .compute(&EvmKey::Storage(contract_address, H256::zero()))
+            .expect("Unable to compute address");
+        assert_eq!(
+            context_manager
+                .get(&call_context, &[slot_address.clone()])
+                .expect("Unable to get state"),
+            vec![(slot_address, word.clone())]
+        );
+
+        // The gas limit does not cover the cost of the transaction
+        let (_, result) = execute(
+            &adapter,
+            &mut context_manager,
+            &[deploy_context],
+            EvmPayload_Action::CALL,
+            contract_address.as_bytes(),
+            word,
+            100,
+        );
+        match result {
+            TransactionResult::Invalid { error_message, .. } => {
+                assert_eq!(error_message, "execution failed: OutOfGas")
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        Box::new(adapter).stop().expect("Unable to stop adapter");
+    }
+}
@@ -0,0 +1,199 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Invocation of transaction handlers by other handlers, while applying a transaction.
+//!
+//! An invoked handler runs in a scratch context over the invoking handler's context, restricted
+//! to the invoked handler's namespaces. If it succeeds, its changes, events and receipt data are
+//! made to the invoking handler's context; otherwise, they are discarded. A handler which declares
+//! no namespaces may not be invoked.
+
+use std::iter;
+
+use crate::execution::adapter::determinism::ScratchContext;
+use crate::execution::adapter::static_adapter::HandlerFamilies;
+use crate::execution::TransactionFamily;
+use crate::handler::{
+    ApplyError, ContextError, Invocation, TransactionContext, TransactionHandler,
+};
+use crate::protocol::transaction::TransactionPair;
+
+/// The number of invocations which may be nested within a transaction.
+pub const MAX_INVOCATION_DEPTH: usize = 8;
+
+/// A `TransactionContext` through which a handler applying a transaction, or invoked while
+/// applying one, may invoke other handlers.
+pub(crate) struct InvocationContext<'a> {
+    handlers: &'a [Box<dyn TransactionHandler>],
+    families: &'a HandlerFamilies,
+    transaction_pair: &'a TransactionPair,
+    context: &'a dyn TransactionContext,
+    // The families being applied or invoked, outermost first
+    call_stack: Vec<String>,
+    // The address prefixes an invoked handler is restricted to; None for the handler applying the
+    // transaction, which is unrestricted
+    namespaces: Option<&'a [String]>,
+}
+
+impl<'a> InvocationContext<'a> {
+    /// Creates a context for the handler applying the transaction, through which it may invoke
+    /// the given handlers; the families must be those of the handlers.
+    pub(crate) fn new(
+        handlers: &'a [Box<dyn TransactionHandler>],
+        families: &'a HandlerFamilies,
+        transaction_pair: &'a TransactionPair,
+        context: &'a dyn TransactionContext,
+    ) -> Self {
+        InvocationContext {
+            handlers,
+            families,
+            transaction_pair,
+            context,
+            call_stack: vec![transaction_pair.header().family_name().to_string()],
+            namespaces: None,
+        }
+    }
+
+    fn check_namespaces<'b, I>(&self, addresses: I, access: &str) -> Result<(), ContextError>
+    where
+        I: IntoIterator<Item = &'b String>,
+    {
+        let namespaces = match self.namespaces {
+            Some(namespaces) => namespaces,
+            None => return Ok(()),
+        };
+        for address in addresses {
+            if !namespaces
+                .iter()
+                .any(|namespace| address.starts_with(namespace))
+            {
+                return Err(ContextError::AuthorizationError(format!(
+                    "{} may not {} address {}",
+                    self.call_stack[self.call_stack.len() - 1],
+                    access,
+                    address
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: String) -> ContextError {
+    ContextError::InvocationError(ApplyError::InvalidTransaction(message))
+}
+
+impl<'a> TransactionContext for InvocationContext<'a> {
+    fn get_state_entries(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        self.check_namespaces(addresses, "read")?;
+        self.context.get_state_entries(addresses)
+    }
+
+    fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        self.check_namespaces(entries.iter().map(|(address, _)| address), "write")?;
+        self.context.set_state_entries(entries)
+    }
+
+    fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+        self.check_namespaces(addresses, "delete")?;
+        self.context.delete_state_entries(addresses)
+    }
+
+    fn add_receipt_data(&self, data: Vec<u8>) -> Result<(), ContextError> {
+        self.context.add_receipt_data(data)
+    }
+
+    fn add_event(
+        &self,
+        event_type: String,
+        attributes: Vec<(String, String)>,
+        data: Vec<u8>,
+    ) -> Result<(), ContextError> {
+        self.context.add_event(event_type, attributes, data)
+    }
+
+    fn invoke(
+        &self,
+        family_name: &str,
+        family_version: &str,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, ContextError> {
+        let depth = self.call_stack.len();
+        if depth > MAX_INVOCATION_DEPTH {
+            return Err(invalid(format!(
+                "Unable to invoke {}: invocations may not be nested more than {} deep",
+                family_name, MAX_INVOCATION_DEPTH
+            )));
+        }
+        if self.call_stack.iter().any(|family| family == family_name) {
+            return Err(invalid(format!(
+                "Unable to invoke {}: invocation cycle {} -> {}",
+                family_name,
+                self.call_stack.join(" -> "),
+                family_name
+            )));
+        }
+        let handler = self
+            .families
+            .find_handler(
+                self.handlers,
+                &TransactionFamily::new(family_name.into(), family_version.into()),
+            )
+            .ok_or_else(|| {
+                invalid(format!(
+                    "Unable to invoke {} {}: no handler supports it",
+                    family_name, family_version
+                ))
+            })?;
+        if handler.namespaces().is_empty() {
+            return Err(invalid(format!(
+                "Unable to invoke {}: it declares no namespaces",
+                family_name
+            )));
+        }
+
+        let scratch = ScratchContext::new(self.context);
+        let result = handler.invoke(
+            &Invocation::new(
+                self.transaction_pair,
+                &self.call_stack[depth - 1],
+                payload,
+                depth,
+            ),
+            &mut InvocationContext {
+                handlers: self.handlers,
+                families: self.families,
+                transaction_pair: self.transaction_pair,
+                context: &scratch,
+                call_stack: self
+                    .call_stack
+                    .iter()
+                    .cloned()
+                    .chain(iter::once(family_name.to_string()))
+                    .collect(),
+                namespaces: Some(handler.namespaces()),
+            },
+        );
+
+        let returned = result.map_err(ContextError::InvocationError)?;
+        scratch.commit(self.context)?;
+        Ok(returned)
+    }
+}
@@ -18,15 +18,26 @@
 //! Contains execution adapter components and interfaces that proxy the `Transaction`
 //! and its associated state.
 
+pub mod determinism;
 mod error;
+#[cfg(feature = "evm-adapter")]
+pub mod evm;
+pub mod invocation;
+#[cfg(feature = "process-adapter")]
+pub mod process;
+#[cfg(feature = "socket-adapter")]
+pub mod socket;
 pub mod static_adapter;
 #[cfg(test)]
 pub mod test_adapter;
+#[cfg(feature = "wasm-adapter")]
+pub mod wasm;
 
 pub use crate::execution::adapter::error::{ExecutionAdapterError, ExecutionOperationError};
 
 use crate::context::ContextId;
-use crate::execution::ExecutionRegistry;
+use crate::execution::{ExecutionRegistry, TransactionFamily};
+use crate::handler::QueryError;
 use crate::protocol::transaction::TransactionPair;
 use crate::scheduler::ExecutionTaskCompletionNotification;
 
@@ -52,6 +63,27 @@ pub trait ExecutionAdapter: Send {
         >,
     ) -> Result<(), ExecutionOperationError>;
 
+    /// Query the handler of the transaction family at the given state root, and provide a
+    /// callback that handles the result.
+    ///
+    /// Queries are answered with a read-only context, so they cannot change state. The `on_done`
+    /// callback is fired with the bytes returned by the handler, or the error. By default,
+    /// queries are not supported.
+    fn query(
+        &self,
+        family: TransactionFamily,
+        _state_root: String,
+        _payload: Vec<u8>,
+        on_done: Box<dyn Fn(Result<Vec<u8>, QueryError>) + Send>,
+    ) -> Result<(), ExecutionOperationError> {
+        on_done(Err(QueryError::Unsupported(format!(
+            "Queries of {} {} are not supported by this adapter",
+            family.family_name(),
+            family.family_version()
+        ))));
+        Ok(())
+    }
+
     /// Stop the internal threads and the Executor will no longer call execute.
     fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError>;
 }
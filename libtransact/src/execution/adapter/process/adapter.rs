@@ -0,0 +1,435 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::context::manager::sync::ContextManager;
+use crate::context::ContextId;
+use crate::execution::adapter::socket::{HandlerConnections, SocketError, Writer};
+use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError, ExecutionOperationError};
+use crate::execution::{ExecutionRegistry, TransactionFamily};
+use crate::handler::QueryError;
+use crate::protocol::transaction::TransactionPair;
+use crate::scheduler::ExecutionTaskCompletionNotification;
+
+const DEFAULT_RESTART_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the supervisor checks on the handler process.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a handler process has to exit once its input is closed, before it is killed.
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How the handler process is started and supervised.
+#[derive(Clone)]
+struct ProcessConfig {
+    program: PathBuf,
+    args: Vec<String>,
+    restart_delay: Duration,
+    max_restarts: Option<u32>,
+    health_check_interval: Duration,
+    health_check_timeout: Duration,
+}
+
+/// Builds a `ProcessExecutionAdapter`, optionally configuring the handler's arguments, how it is
+/// restarted and how its health is checked.
+pub struct ProcessExecutionAdapterBuilder {
+    config: ProcessConfig,
+    context_manager: ContextManager,
+}
+
+impl ProcessExecutionAdapterBuilder {
+    /// Creates a builder for an adapter which runs the given handler executable.
+    pub fn new(program: PathBuf, context_manager: ContextManager) -> Self {
+        ProcessExecutionAdapterBuilder {
+            config: ProcessConfig {
+                program,
+                args: vec![],
+                restart_delay: DEFAULT_RESTART_DELAY,
+                max_restarts: None,
+                health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+                health_check_timeout: DEFAULT_HEALTH_CHECK_TIMEOUT,
+            },
+            context_manager,
+        }
+    }
+
+    /// Passes the given arguments to the handler executable.
+    pub fn with_args(mut self, args: Vec<String>) -> ProcessExecutionAdapterBuilder {
+        self.config.args = args;
+        self
+    }
+
+    /// Waits for the given time before restarting a handler process which has failed; the
+    /// default is one second.
+    pub fn with_restart_delay(mut self, restart_delay: Duration) -> ProcessExecutionAdapterBuilder {
+        self.config.restart_delay = restart_delay;
+        self
+    }
+
+    /// Restarts the handler process at most the given number of times over the adapter's
+    /// lifetime; by default, it is always restarted.
+    pub fn with_max_restarts(mut self, max_restarts: u32) -> ProcessExecutionAdapterBuilder {
+        self.config.max_restarts = Some(max_restarts);
+        self
+    }
+
+    /// Pings the handler process at the given interval, restarting it if a ping is not answered
+    /// within the given timeout; the defaults are five and thirty seconds.
+    ///
+    /// A handler only answers pings between reading messages, so the timeout must be longer than
+    /// the time taken to apply any transaction.
+    pub fn with_health_check(
+        mut self,
+        interval: Duration,
+        timeout: Duration,
+    ) -> ProcessExecutionAdapterBuilder {
+        self.config.health_check_interval = interval;
+        self.config.health_check_timeout = timeout;
+        self
+    }
+
+    /// Builds the `ProcessExecutionAdapter`; the handler process is started when the adapter is.
+    pub fn build(self) -> ProcessExecutionAdapter {
+        ProcessExecutionAdapter {
+            config: self.config,
+            connections: HandlerConnections::new(self.context_manager),
+            supervisor: None,
+        }
+    }
+}
+
+/// An `ExecutionAdapter` which executes transactions using a handler executable, which it runs
+/// as a child process.
+///
+/// The adapter sends messages to the handler over its standard input and reads the handler's
+/// messages from its standard output; the handler's standard error is inherited. The transaction
+/// families the handler registers are registered with the `ExecutionRegistry`.
+///
+/// If the handler process exits, closes its output or fails a health check, it is stopped and
+/// started again; any transactions it was processing fail, and its families are unregistered
+/// until the new process registers them.
+pub struct ProcessExecutionAdapter {
+    config: ProcessConfig,
+    connections: HandlerConnections,
+    supervisor: Option<(Sender<()>, thread::JoinHandle<()>)>,
+}
+
+impl ProcessExecutionAdapter {
+    /// Creates a new adapter which runs the given handler executable with no arguments.
+    pub fn new(program: PathBuf, context_manager: ContextManager) -> Self {
+        ProcessExecutionAdapterBuilder::new(program, context_manager).build()
+    }
+}
+
+impl ExecutionAdapter for ProcessExecutionAdapter {
+    fn start(
+        &mut self,
+        execution_registry: Box<dyn ExecutionRegistry>,
+    ) -> Result<(), ExecutionOperationError> {
+        if self.supervisor.is_some() {
+            return Err(ExecutionOperationError::StartError(
+                "process adapter already started".into(),
+            ));
+        }
+        self.connections.set_registry(execution_registry);
+
+        let process = HandlerProcess::spawn(&self.config, &self.connections).map_err(|err| {
+            ExecutionOperationError::StartError(format!(
+                "Unable to start handler process {:?}: {}",
+                self.config.program, err
+            ))
+        })?;
+
+        let (shutdown_tx, shutdown_rx) = channel();
+        let supervisor = Supervisor {
+            config: self.config.clone(),
+            connections: self.connections.clone(),
+            shutdown_rx,
+        };
+        let handle = thread::Builder::new()
+            .name("ProcessExecutionAdapter".into())
+            .spawn(move || supervisor.run(process))
+            .map_err(|err| {
+                ExecutionOperationError::StartError(format!(
+                    "Unable to start process adapter thread: {}",
+                    err
+                ))
+            })?;
+        self.supervisor = Some((shutdown_tx, handle));
+
+        Ok(())
+    }
+
+    fn execute(
+        &self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: Box<
+            dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send,
+        >,
+    ) -> Result<(), ExecutionOperationError> {
+        self.connections
+            .execute(transaction_pair, context_id, on_done)
+    }
+
+    fn query(
+        &self,
+        family: TransactionFamily,
+        state_root: String,
+        payload: Vec<u8>,
+        on_done: Box<dyn Fn(Result<Vec<u8>, QueryError>) + Send>,
+    ) -> Result<(), ExecutionOperationError> {
+        self.connections.query(family, state_root, payload, on_done)
+    }
+
+    fn stop(mut self: Box<Self>) -> Result<(), ExecutionOperationError> {
+        if let Some((shutdown_tx, handle)) = self.supervisor.take() {
+            // The supervisor may have already given up on the handler and exited
+            let _ = shutdown_tx.send(());
+            handle.join().map_err(|_| {
+                ExecutionOperationError::StopError("Unable to join supervisor thread.".into())
+            })?;
+        }
+
+        self.connections.close_all()
+    }
+}
+
+/// A running handler process, and its connection.
+struct HandlerProcess {
+    child: Child,
+    connection_id: usize,
+}
+
+impl HandlerProcess {
+    fn spawn(
+        config: &ProcessConfig,
+        connections: &HandlerConnections,
+    ) -> Result<HandlerProcess, SocketError> {
+        let mut child = Command::new(&config.program)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+
+        let pipes = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => Ok((stdin, stdout)),
+            _ => Err(SocketError::ProtocolError(
+                "handler process has no standard input or output".into(),
+            )),
+        };
+        let connection_id = pipes.and_then(|(stdin, stdout)| {
+            connections.add(
+                "ProcessExecutionAdapter",
+                stdout,
+                Box::new(ProcessInput {
+                    stdin: Mutex::new(Some(stdin)),
+                }),
+            )
+        });
+
+        match connection_id {
+            Ok(connection_id) => Ok(HandlerProcess {
+                child,
+                connection_id,
+            }),
+            Err(err) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(err)
+            }
+        }
+    }
+}
+
+/// The standard input of a handler process, which is closed to ask the handler to exit.
+struct ProcessInput {
+    stdin: Mutex<Option<ChildStdin>>,
+}
+
+impl Write for ProcessInput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stdin.get_mut() {
+            Ok(Some(stdin)) => stdin.write(buf),
+            _ => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stdin.get_mut() {
+            Ok(Some(stdin)) => stdin.flush(),
+            _ => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+}
+
+impl Writer for ProcessInput {
+    fn close(&self) -> io::Result<()> {
+        match self.stdin.lock() {
+            Ok(mut stdin) => {
+                stdin.take();
+                Ok(())
+            }
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "handler input lock poisoned",
+            )),
+        }
+    }
+}
+
+/// Watches the handler process, restarting it when it fails.
+struct Supervisor {
+    config: ProcessConfig,
+    connections: HandlerConnections,
+    shutdown_rx: Receiver<()>,
+}
+
+impl Supervisor {
+    fn run(self, process: HandlerProcess) {
+        let mut process = Some(process);
+        let mut restarts = 0;
+        loop {
+            if let Some(mut running) = process.take() {
+                let shutdown = self.supervise(&mut running);
+                self.terminate(running);
+                if shutdown {
+                    return;
+                }
+            }
+
+            if let Some(max_restarts) = self.config.max_restarts {
+                if restarts >= max_restarts {
+                    error!(
+                        "Handler process {:?} has been restarted {} times; not restarting it again",
+                        self.config.program, restarts
+                    );
+                    return;
+                }
+            }
+            restarts += 1;
+
+            match self.shutdown_rx.recv_timeout(self.config.restart_delay) {
+                Err(RecvTimeoutError::Timeout) => (),
+                _ => return,
+            }
+            match HandlerProcess::spawn(&self.config, &self.connections) {
+                Ok(running) => {
+                    info!("Restarted handler process {:?}", self.config.program);
+                    process = Some(running);
+                }
+                Err(err) => error!(
+                    "Unable to restart handler process {:?}: {}",
+                    self.config.program, err
+                ),
+            }
+        }
+    }
+
+    /// Waits until the adapter is shut down, returning `true`, or the handler process fails,
+    /// returning `false`.
+    fn supervise(&self, running: &mut HandlerProcess) -> bool {
+        let mut last_ping = Instant::now();
+        loop {
+            match self.shutdown_rx.recv_timeout(POLL_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) => (),
+                _ => return true,
+            }
+
+            match running.child.try_wait() {
+                Ok(Some(status)) => {
+                    warn!(
+                        "Handler process {:?} exited: {}",
+                        self.config.program, status
+                    );
+                    return false;
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    warn!(
+                        "Unable to check handler process {:?}: {}",
+                        self.config.program, err
+                    );
+                    return false;
+                }
+            }
+
+            if !self.connections.is_connected(running.connection_id) {
+                warn!(
+                    "Handler process {:?} closed its connection",
+                    self.config.program
+                );
+                return false;
+            }
+
+            if let Some(waited) = self.connections.unanswered_ping(running.connection_id) {
+                if waited > self.config.health_check_timeout {
+                    warn!(
+                        "Handler process {:?} has not answered a ping in {:?}",
+                        self.config.program, waited
+                    );
+                    return false;
+                }
+            } else if last_ping.elapsed() >= self.config.health_check_interval {
+                if let Err(err) = self.connections.ping(running.connection_id) {
+                    warn!(
+                        "Unable to ping handler process {:?}: {}",
+                        self.config.program, err
+                    );
+                    return false;
+                }
+                last_ping = Instant::now();
+            }
+        }
+    }
+
+    /// Stops the handler process: closing its input asks it to exit, and a handler which does
+    /// not exit in time is killed.
+    fn terminate(&self, mut running: HandlerProcess) {
+        self.connections.close(running.connection_id);
+
+        let deadline = Instant::now() + TERMINATE_TIMEOUT;
+        loop {
+            match running.child.try_wait() {
+                Ok(Some(_)) => return,
+                Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+                _ => break,
+            }
+        }
+
+        warn!(
+            "Handler process {:?} did not exit; killing it",
+            self.config.program
+        );
+        if let Err(err) = running.child.kill() {
+            warn!(
+                "Unable to kill handler process {:?}: {}",
+                self.config.program, err
+            );
+        }
+        let _ = running.child.wait();
+    }
+}
@@ -0,0 +1,62 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::io;
+
+use crate::execution::adapter::socket::{self, SocketError};
+use crate::handler::TransactionHandler;
+
+/// Serves `TransactionHandler`s to a `ProcessExecutionAdapter` over the standard input and output
+/// of the current process.
+///
+/// Nothing else may be written to standard output while the processor runs; handlers should log
+/// to standard error instead. For example, a handler executable's `main` might run:
+///
+/// ```ignore
+/// let mut processor = StdioTransactionProcessor::new();
+/// processor.add_handler(Box::new(MyTransactionHandler::new()));
+/// processor.run()?;
+/// ```
+#[derive(Default)]
+pub struct StdioTransactionProcessor {
+    handlers: Vec<Box<dyn TransactionHandler>>,
+}
+
+impl StdioTransactionProcessor {
+    pub fn new() -> Self {
+        StdioTransactionProcessor::default()
+    }
+
+    pub fn add_handler(&mut self, handler: Box<dyn TransactionHandler>) {
+        self.handlers.push(handler);
+    }
+
+    /// Registers each handler's family and processes transactions until the adapter closes the
+    /// process's standard input.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SocketError` if a registration is rejected, or reading or writing a message
+    /// fails before the adapter closes the process's input.
+    pub fn run(self) -> Result<(), SocketError> {
+        socket::serve(
+            &self.handlers,
+            Box::new(io::stdin()),
+            Box::new(io::stdout()),
+        )
+    }
+}
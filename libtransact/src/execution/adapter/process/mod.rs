@@ -0,0 +1,252 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Execution of transactions by handler executables, run as child processes.
+//!
+//! The `ProcessExecutionAdapter` starts a handler executable and exchanges messages with it over
+//! the process's standard input and output. The messages, and their framing, are those of the
+//! socket adapter: the handler registers the transaction families it supports, is sent the
+//! transactions of those families to process, and reads and writes state through requests which
+//! the adapter serves from the `ContextManager`. The adapter also pings the handler to check that
+//! it is responsive, and restarts it if it exits or stops responding.
+//!
+//! The `StdioTransactionProcessor` is the handler side of the protocol, for handlers written in
+//! Rust.
+//!
+//! Note, to use this module, the Transact library must have the `"process-adapter"` feature
+//! enabled.
+
+mod adapter;
+mod client;
+
+pub use self::adapter::{ProcessExecutionAdapter, ProcessExecutionAdapterBuilder};
+pub use self::client::StdioTransactionProcessor;
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::FromRawFd;
+    use std::sync::mpsc::{channel, Sender};
+    use std::time::Duration;
+
+    use crate::context::manager::sync::ContextManager;
+    use crate::context::ContextLifecycle;
+    use crate::execution::adapter::socket;
+    use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError};
+    use crate::execution::{ExecutionRegistry, TransactionFamily};
+    use crate::handler::{ApplyError, TransactionContext, TransactionHandler};
+    use crate::protocol::command::{
+        BytesEntry, Command, CommandPayload, ReturnInternalError, SetState,
+    };
+    use crate::protocol::transaction::TransactionPair;
+    use crate::protos::FromBytes;
+    use crate::scheduler::ExecutionTaskCompletionNotification;
+    use crate::state::hashmap::HashMapState;
+    use crate::workload::command::{make_command_transaction, CommandTransactionHandler};
+
+    /// Set in the environment of the handler processes started by these tests.
+    const HANDLER_ENV: &str = "TRANSACT_PROCESS_ADAPTER_TEST_HANDLER";
+
+    /// Reports registration changes over a channel.
+    struct ChannelRegistry {
+        sender: Sender<(bool, TransactionFamily)>,
+    }
+
+    impl ExecutionRegistry for ChannelRegistry {
+        fn register_transaction_family(&mut self, family: TransactionFamily) {
+            self.sender
+                .send((true, family))
+                .expect("Unable to send registration");
+        }
+
+        fn unregister_transaction_family(&mut self, family: &TransactionFamily) {
+            self.sender
+                .send((false, family.clone()))
+                .expect("Unable to send unregistration");
+        }
+    }
+
+    /// Handles the command family, but exits the process when asked to return an internal error,
+    /// as if it had crashed.
+    struct CrashingHandler {
+        inner: CommandTransactionHandler,
+    }
+
+    impl TransactionHandler for CrashingHandler {
+        fn family_name(&self) -> &str {
+            self.inner.family_name()
+        }
+
+        fn family_versions(&self) -> &[String] {
+            self.inner.family_versions()
+        }
+
+        fn apply(
+            &self,
+            transaction_pair: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            let payload = CommandPayload::from_bytes(transaction_pair.transaction().payload())
+                .expect("Unable to parse CommandPayload");
+            if payload.commands().iter().any(|command| match command {
+                Command::ReturnInternalError(_) => true,
+                _ => false,
+            }) {
+                std::process::exit(1);
+            }
+            self.inner.apply(transaction_pair, context)
+        }
+    }
+
+    /// Serves a `CrashingHandler` when this test binary is run as a handler process by
+    /// `test_process_adapter`, and otherwise does nothing. The test harness writes to standard
+    /// output, so the handler's messages are written to file descriptor 3 instead.
+    #[test]
+    fn handler_process() {
+        if std::env::var_os(HANDLER_ENV).is_none() {
+            return;
+        }
+        let handlers: Vec<Box<dyn TransactionHandler>> = vec![Box::new(CrashingHandler {
+            inner: CommandTransactionHandler::new(),
+        })];
+        let output = unsafe { File::from_raw_fd(3) };
+        socket::serve(&handlers, Box::new(io::stdin()), Box::new(output))
+            .expect("Handler process failed");
+    }
+
+    fn execute_set_state(
+        adapter: &ProcessExecutionAdapter,
+        context_manager: &mut ContextManager,
+        state_id: &str,
+        key: &str,
+    ) {
+        let txn_pair =
+            make_command_transaction(&[Command::SetState(SetState::new(vec![BytesEntry::new(
+                key.into(),
+                key.as_bytes().to_vec(),
+            )]))]);
+        let txn_id = txn_pair.transaction().header_signature().to_string();
+        let context_id = context_manager.create_context(&[], state_id);
+
+        let (result_tx, result_rx) = channel();
+        adapter
+            .execute(
+                txn_pair,
+                context_id,
+                Box::new(move |res| result_tx.send(res).expect("Unable to send result")),
+            )
+            .expect("Unable to execute transaction");
+        assert_eq!(
+            result_rx
+                .recv_timeout(Duration::from_secs(5))
+                .expect("Did not receive result")
+                .expect("Execution failed"),
+            ExecutionTaskCompletionNotification::Valid(context_id, txn_id)
+        );
+        assert_eq!(
+            context_manager
+                .get(&context_id, &[key.into()])
+                .expect("Unable to get state"),
+            vec![(key.to_string(), key.as_bytes().to_vec())]
+        );
+    }
+
+    /// Tests that the adapter registers the handler process's family and executes transactions
+    /// with it, and that when the process crashes, its transaction fails and the process is
+    /// restarted.
+    #[test]
+    fn test_process_adapter() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let mut context_manager = ContextManager::new(Box::new(state));
+
+        let test_binary = std::env::current_exe()
+            .expect("Unable to get test binary")
+            .to_str()
+            .expect("Test binary path is not UTF-8")
+            .to_string();
+        let mut adapter = ProcessExecutionAdapterBuilder::new("sh".into(), context_manager.clone())
+            .with_args(vec![
+                "-c".into(),
+                format!("{}=1 exec \"$0\" \"$@\" 3>&1 1>/dev/null", HANDLER_ENV),
+                test_binary,
+                "--exact".into(),
+                "execution::adapter::process::tests::handler_process".into(),
+                "--test-threads=1".into(),
+            ])
+            .with_restart_delay(Duration::from_millis(100))
+            .with_health_check(Duration::from_millis(100), Duration::from_secs(5))
+            .build();
+        let (registry_tx, registry_rx) = channel();
+        adapter
+            .start(Box::new(ChannelRegistry {
+                sender: registry_tx,
+            }))
+            .expect("Unable to start adapter");
+
+        let (registered, family) = registry_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Handler did not register");
+        assert!(registered);
+        assert_eq!(family.family_name(), "command");
+
+        execute_set_state(&adapter, &mut context_manager, &state_id, "abc");
+
+        let txn_pair = make_command_transaction(&[Command::ReturnInternalError(
+            ReturnInternalError::new("crash".into()),
+        )]);
+        let context_id = context_manager.create_context(&[], &state_id);
+        let (result_tx, result_rx) = channel();
+        adapter
+            .execute(
+                txn_pair,
+                context_id,
+                Box::new(move |res| result_tx.send(res).expect("Unable to send result")),
+            )
+            .expect("Unable to execute transaction");
+        match result_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Did not receive result")
+        {
+            Err(ExecutionAdapterError::GeneralExecutionError(_)) => (),
+            res => panic!(
+                "Unexpected result: {:?}",
+                res.map_err(|err| err.to_string())
+            ),
+        }
+
+        // The family is unregistered with the crashed process, and registered again by its
+        // replacement
+        let (registered, _) = registry_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Family was not unregistered");
+        assert!(!registered);
+        let (registered, family) = registry_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Handler was not restarted");
+        assert!(registered);
+        assert_eq!(family.family_name(), "command");
+
+        execute_set_state(&adapter, &mut context_manager, &state_id, "def");
+
+        Box::new(adapter).stop().expect("Unable to stop adapter");
+    }
+}
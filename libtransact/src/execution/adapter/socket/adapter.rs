@@ -0,0 +1,176 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::context::manager::sync::ContextManager;
+use crate::context::ContextId;
+use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError, ExecutionOperationError};
+use crate::execution::{ExecutionRegistry, TransactionFamily};
+use crate::handler::QueryError;
+use crate::protocol::transaction::TransactionPair;
+use crate::scheduler::ExecutionTaskCompletionNotification;
+
+use super::connection::{self, Listener};
+use super::connections::HandlerConnections;
+use super::SocketAddress;
+
+/// An `ExecutionAdapter` which executes transactions using handlers in other processes, which
+/// connect to it over a Unix or TCP socket.
+///
+/// Transaction families are registered with the `ExecutionRegistry` as handlers connect and
+/// register them, and unregistered once no connected handler supports them. When a handler
+/// disconnects, any transactions it was processing fail, since it may already have written to
+/// their contexts.
+pub struct SocketExecutionAdapter {
+    address: SocketAddress,
+    listener: Option<Listener>,
+    connections: HandlerConnections,
+    shutdown: Arc<AtomicBool>,
+    accept_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl SocketExecutionAdapter {
+    /// Creates a new adapter, bound to the given address.
+    ///
+    /// Handlers may connect once the adapter has been started.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionAdapterError` is returned if the socket cannot be bound.
+    pub fn new(
+        address: SocketAddress,
+        context_manager: ContextManager,
+    ) -> Result<Self, ExecutionAdapterError> {
+        let listener = Listener::bind(&address)
+            .map_err(|err| ExecutionAdapterError::GeneralExecutionError(Box::new(err)))?;
+        let address = listener
+            .local_address()
+            .map_err(|err| ExecutionAdapterError::GeneralExecutionError(Box::new(err)))?;
+
+        Ok(SocketExecutionAdapter {
+            address,
+            listener: Some(listener),
+            connections: HandlerConnections::new(context_manager),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            accept_handle: None,
+        })
+    }
+
+    /// The address handlers should connect to.
+    pub fn local_address(&self) -> &SocketAddress {
+        &self.address
+    }
+}
+
+impl ExecutionAdapter for SocketExecutionAdapter {
+    fn start(
+        &mut self,
+        execution_registry: Box<dyn ExecutionRegistry>,
+    ) -> Result<(), ExecutionOperationError> {
+        let listener = self.listener.take().ok_or_else(|| {
+            ExecutionOperationError::StartError("socket adapter already started".into())
+        })?;
+        self.connections.set_registry(execution_registry);
+
+        let connections = self.connections.clone();
+        let shutdown = self.shutdown.clone();
+        let accept_handle = thread::Builder::new()
+            .name("SocketExecutionAdapter".into())
+            .spawn(move || accept_connections(listener, connections, shutdown))
+            .map_err(|err| {
+                ExecutionOperationError::StartError(format!(
+                    "Unable to start socket adapter thread: {}",
+                    err
+                ))
+            })?;
+        self.accept_handle = Some(accept_handle);
+
+        Ok(())
+    }
+
+    fn execute(
+        &self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: Box<
+            dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send,
+        >,
+    ) -> Result<(), ExecutionOperationError> {
+        self.connections
+            .execute(transaction_pair, context_id, on_done)
+    }
+
+    fn query(
+        &self,
+        family: TransactionFamily,
+        state_root: String,
+        payload: Vec<u8>,
+        on_done: Box<dyn Fn(Result<Vec<u8>, QueryError>) + Send>,
+    ) -> Result<(), ExecutionOperationError> {
+        self.connections.query(family, state_root, payload, on_done)
+    }
+
+    fn stop(mut self: Box<Self>) -> Result<(), ExecutionOperationError> {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        if let Some(accept_handle) = self.accept_handle.take() {
+            // Wake the accepting thread so it sees the shutdown flag
+            if let Err(err) = connection::connect(&self.address) {
+                debug!("Unable to wake socket adapter thread: {}", err);
+            }
+            accept_handle.join().map_err(|_| {
+                ExecutionOperationError::StopError("Unable to join accepting thread.".into())
+            })?;
+        }
+
+        self.connections.close_all()
+    }
+}
+
+fn accept_connections(
+    listener: Listener,
+    connections: HandlerConnections,
+    shutdown: Arc<AtomicBool>,
+) {
+    loop {
+        let stream = listener.accept();
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Unable to accept handler connection: {}", err);
+                continue;
+            }
+        };
+        let writer = match stream.try_clone_writer() {
+            Ok(writer) => writer,
+            Err(err) => {
+                warn!("Unable to clone handler connection: {}", err);
+                continue;
+            }
+        };
+
+        if let Err(err) = connections.add("SocketExecutionAdapter", stream, writer) {
+            warn!("Unable to start handler connection thread: {}", err);
+        }
+    }
+}
@@ -0,0 +1,445 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+
+use protobuf::{Message, RepeatedField};
+
+use crate::execution::adapter::static_adapter::HandlerFamilies;
+use crate::execution::TransactionFamily;
+use crate::handler::{
+    ApplyError, ContextError, QueryError, ReadOnlyContext, TransactionContext, TransactionHandler,
+};
+use crate::protocol::receipt::Event;
+use crate::protocol::transaction::Transaction;
+use crate::protos::external_execution::{
+    AddEventRequest, AddReceiptDataRequest, DeleteStateRequest, DeleteStateResponse,
+    ExternalExecutionMessage, ExternalExecutionMessage_Type, GetStateRequest, GetStateResponse,
+    PingResponse, ProcessRequest, ProcessResponse, ProcessResponse_Status, QueryRequest,
+    QueryResponse, QueryResponse_Status, RegisterRequest, ResponseStatus, SetStateRequest,
+    StateEntry, StatusResponse,
+};
+use crate::protos::{FromProto, IntoProto};
+
+use super::connection;
+use super::{SocketAddress, SocketError};
+
+/// Serves `TransactionHandler`s to a `SocketExecutionAdapter` running in another process.
+///
+/// For example, a handler process might run:
+///
+/// ```ignore
+/// let mut processor = SocketTransactionProcessor::new(SocketAddress::Tcp("127.0.0.1:4004".into()));
+/// processor.add_handler(Box::new(MyTransactionHandler::new()));
+/// processor.run()?;
+/// ```
+pub struct SocketTransactionProcessor {
+    address: SocketAddress,
+    handlers: Vec<Box<dyn TransactionHandler>>,
+}
+
+impl SocketTransactionProcessor {
+    /// Creates a processor which will connect to the adapter at the given address.
+    pub fn new(address: SocketAddress) -> Self {
+        SocketTransactionProcessor {
+            address,
+            handlers: vec![],
+        }
+    }
+
+    pub fn add_handler(&mut self, handler: Box<dyn TransactionHandler>) {
+        self.handlers.push(handler);
+    }
+
+    /// Connects to the adapter, registers each handler's family and processes transactions and
+    /// queries until the adapter closes the connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SocketError` if the connection cannot be established, a registration is
+    /// rejected, or the connection fails before the adapter closes it.
+    pub fn run(self) -> Result<(), SocketError> {
+        let stream = connection::connect(&self.address)?;
+        let writer = stream.try_clone_writer()?;
+        serve(&self.handlers, Box::new(stream), Box::new(writer))
+    }
+}
+
+/// Registers each handler's family with the adapter at the other end of the given connection,
+/// and processes transactions and queries until the adapter closes it.
+pub(crate) fn serve(
+    handlers: &[Box<dyn TransactionHandler>],
+    reader: Box<dyn Read>,
+    writer: Box<dyn Write>,
+) -> Result<(), SocketError> {
+    let connection = RefCell::new(Connection::new(reader, writer));
+    let families = HandlerFamilies::new(handlers);
+
+    for handler in handlers {
+        let mut request = RegisterRequest::new();
+        request.set_family_name(handler.family_name().into());
+        request.set_family_versions(RepeatedField::from_vec(handler.family_versions().to_vec()));
+        let response: StatusResponse = connection.borrow_mut().request(
+            ExternalExecutionMessage_Type::REGISTER_REQUEST,
+            ExternalExecutionMessage_Type::REGISTER_RESPONSE,
+            &request,
+        )?;
+        if response.get_status() != ResponseStatus::OK {
+            return Err(SocketError::RegistrationError(format!(
+                "unable to register {}: {}",
+                handler.family_name(),
+                response.get_error_message()
+            )));
+        }
+    }
+
+    loop {
+        let message = match connection.borrow_mut().next_request() {
+            Ok(message) => message,
+            Err(SocketError::IoError(ref err)) if err.kind() == ErrorKind::UnexpectedEof => {
+                return Ok(())
+            }
+            Err(err) => return Err(err),
+        };
+        if message.get_message_type() == ExternalExecutionMessage_Type::QUERY_REQUEST {
+            let response = query(handlers, &families, &connection, &message)?;
+            connection.borrow_mut().send(
+                ExternalExecutionMessage_Type::QUERY_RESPONSE,
+                message.get_correlation_id().into(),
+                &response,
+            )?;
+        } else {
+            let response = process(handlers, &families, &connection, &message)?;
+            connection.borrow_mut().send(
+                ExternalExecutionMessage_Type::PROCESS_RESPONSE,
+                message.get_correlation_id().into(),
+                &response,
+            )?;
+        }
+    }
+}
+
+fn process(
+    handlers: &[Box<dyn TransactionHandler>],
+    families: &HandlerFamilies,
+    connection: &RefCell<Connection>,
+    message: &ExternalExecutionMessage,
+) -> Result<ProcessResponse, SocketError> {
+    let mut request: ProcessRequest = connection::parse_content(message)?;
+    let transaction_pair = Transaction::from_proto(request.take_transaction())
+        .map_err(|err| SocketError::ProtocolError(format!("invalid transaction: {}", err)))?
+        .into_pair()
+        .map_err(|err| SocketError::ProtocolError(format!("invalid transaction: {}", err)))?;
+
+    let mut response = ProcessResponse::new();
+    let handler =
+        match families.find_handler(handlers, &TransactionFamily::from_pair(&transaction_pair)) {
+            Some(handler) => handler,
+            None => {
+                response.set_status(ProcessResponse_Status::INTERNAL_ERROR);
+                response.set_error_message(format!(
+                    "no handler for {} {}",
+                    transaction_pair.header().family_name(),
+                    transaction_pair.header().family_version()
+                ));
+                return Ok(response);
+            }
+        };
+
+    let mut context = SocketContext {
+        connection,
+        context_id: request.take_context_id(),
+    };
+    match handler.apply(&transaction_pair, &mut context) {
+        Ok(()) => response.set_status(ProcessResponse_Status::OK),
+        Err(ApplyError::InvalidTransaction(error_message)) => {
+            response.set_status(ProcessResponse_Status::INVALID_TRANSACTION);
+            response.set_error_message(error_message);
+        }
+        Err(ApplyError::InternalError(error_message)) => {
+            response.set_status(ProcessResponse_Status::INTERNAL_ERROR);
+            response.set_error_message(error_message);
+        }
+    }
+    Ok(response)
+}
+
+fn query(
+    handlers: &[Box<dyn TransactionHandler>],
+    families: &HandlerFamilies,
+    connection: &RefCell<Connection>,
+    message: &ExternalExecutionMessage,
+) -> Result<QueryResponse, SocketError> {
+    let mut request: QueryRequest = connection::parse_content(message)?;
+    let family = TransactionFamily::new(request.take_family_name(), request.take_family_version());
+
+    let mut response = QueryResponse::new();
+    let result = match families.find_handler(handlers, &family) {
+        Some(handler) => {
+            let context = SocketContext {
+                connection,
+                context_id: request.take_context_id(),
+            };
+            handler.query(request.get_payload(), &ReadOnlyContext::new(&context))
+        }
+        None => Err(QueryError::Unsupported(format!(
+            "no handler for {} {}",
+            family.family_name(),
+            family.family_version()
+        ))),
+    };
+    match result {
+        Ok(result) => {
+            response.set_status(QueryResponse_Status::OK);
+            response.set_result(result);
+        }
+        Err(QueryError::InvalidQuery(error_message)) => {
+            response.set_status(QueryResponse_Status::INVALID_QUERY);
+            response.set_error_message(error_message);
+        }
+        Err(QueryError::InternalError(error_message)) => {
+            response.set_status(QueryResponse_Status::INTERNAL_ERROR);
+            response.set_error_message(error_message);
+        }
+        Err(QueryError::Unsupported(error_message)) => {
+            response.set_status(QueryResponse_Status::UNSUPPORTED);
+            response.set_error_message(error_message);
+        }
+    }
+    Ok(response)
+}
+
+/// The processor's connection to the adapter.
+///
+/// The adapter may send further transactions and queries while a handler is waiting for the
+/// response to a state request; these are held until the current one is complete. Pings are answered
+/// as soon as they are read.
+struct Connection {
+    reader: Box<dyn Read>,
+    writer: Box<dyn Write>,
+    queued_requests: VecDeque<ExternalExecutionMessage>,
+}
+
+impl Connection {
+    fn new(reader: Box<dyn Read>, writer: Box<dyn Write>) -> Self {
+        Connection {
+            reader,
+            writer,
+            queued_requests: VecDeque::new(),
+        }
+    }
+
+    fn send(
+        &mut self,
+        message_type: ExternalExecutionMessage_Type,
+        correlation_id: String,
+        content: &dyn Message,
+    ) -> Result<(), SocketError> {
+        connection::write_message(&mut *self.writer, message_type, correlation_id, content)
+    }
+
+    /// Reads the next message, answering any pings which precede it.
+    fn read(&mut self) -> Result<ExternalExecutionMessage, SocketError> {
+        loop {
+            let message = connection::read_message(&mut *self.reader)?;
+            if message.get_message_type() != ExternalExecutionMessage_Type::PING_REQUEST {
+                return Ok(message);
+            }
+            self.send(
+                ExternalExecutionMessage_Type::PING_RESPONSE,
+                message.get_correlation_id().into(),
+                &PingResponse::new(),
+            )?;
+        }
+    }
+
+    /// Sends a request and waits for its response.
+    fn request<M: Message>(
+        &mut self,
+        request_type: ExternalExecutionMessage_Type,
+        response_type: ExternalExecutionMessage_Type,
+        content: &dyn Message,
+    ) -> Result<M, SocketError> {
+        let correlation_id = uuid::Uuid::new_v4().to_string();
+        self.send(request_type, correlation_id.clone(), content)?;
+
+        loop {
+            let message = self.read()?;
+            match message.get_message_type() {
+                ExternalExecutionMessage_Type::PROCESS_REQUEST
+                | ExternalExecutionMessage_Type::QUERY_REQUEST => {
+                    self.queued_requests.push_back(message)
+                }
+                message_type
+                    if message_type == response_type
+                        && message.get_correlation_id() == correlation_id =>
+                {
+                    return connection::parse_content(&message);
+                }
+                message_type => {
+                    return Err(SocketError::ProtocolError(format!(
+                        "expected {:?} response to request {}, but received {:?}",
+                        response_type, correlation_id, message_type
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Returns the next process or query request.
+    fn next_request(&mut self) -> Result<ExternalExecutionMessage, SocketError> {
+        if let Some(message) = self.queued_requests.pop_front() {
+            return Ok(message);
+        }
+        let message = self.read()?;
+        match message.get_message_type() {
+            ExternalExecutionMessage_Type::PROCESS_REQUEST
+            | ExternalExecutionMessage_Type::QUERY_REQUEST => Ok(message),
+            message_type => Err(SocketError::ProtocolError(format!(
+                "expected process or query request, but received {:?}",
+                message_type
+            ))),
+        }
+    }
+}
+
+/// A `TransactionContext` which sends each operation to the adapter.
+struct SocketContext<'a> {
+    connection: &'a RefCell<Connection>,
+    context_id: Vec<u8>,
+}
+
+impl<'a> SocketContext<'a> {
+    fn request<M: Message>(
+        &self,
+        request_type: ExternalExecutionMessage_Type,
+        response_type: ExternalExecutionMessage_Type,
+        content: &dyn Message,
+    ) -> Result<M, ContextError> {
+        self.connection
+            .borrow_mut()
+            .request(request_type, response_type, content)
+            .map_err(|err| match err {
+                SocketError::IoError(err) => ContextError::ReceiveError(Box::new(err)),
+                err => ContextError::ResponseAttributeError(err.to_string()),
+            })
+    }
+}
+
+fn check_status(status: ResponseStatus, error_message: &str) -> Result<(), ContextError> {
+    match status {
+        ResponseStatus::OK => Ok(()),
+        _ => Err(ContextError::ResponseAttributeError(error_message.into())),
+    }
+}
+
+impl<'a> TransactionContext for SocketContext<'a> {
+    fn get_state_entries(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        let mut request = GetStateRequest::new();
+        request.set_context_id(self.context_id.clone());
+        request.set_addresses(RepeatedField::from_vec(addresses.to_vec()));
+        let mut response: GetStateResponse = self.request(
+            ExternalExecutionMessage_Type::GET_STATE_REQUEST,
+            ExternalExecutionMessage_Type::GET_STATE_RESPONSE,
+            &request,
+        )?;
+        check_status(response.get_status(), response.get_error_message())?;
+        Ok(response
+            .take_entries()
+            .into_vec()
+            .into_iter()
+            .map(|mut entry| (entry.take_address(), entry.take_data()))
+            .collect())
+    }
+
+    fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        let mut request = SetStateRequest::new();
+        request.set_context_id(self.context_id.clone());
+        request.set_entries(RepeatedField::from_vec(
+            entries
+                .into_iter()
+                .map(|(address, data)| {
+                    let mut entry = StateEntry::new();
+                    entry.set_address(address);
+                    entry.set_data(data);
+                    entry
+                })
+                .collect(),
+        ));
+        let response: StatusResponse = self.request(
+            ExternalExecutionMessage_Type::SET_STATE_REQUEST,
+            ExternalExecutionMessage_Type::SET_STATE_RESPONSE,
+            &request,
+        )?;
+        check_status(response.get_status(), response.get_error_message())
+    }
+
+    fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+        let mut request = DeleteStateRequest::new();
+        request.set_context_id(self.context_id.clone());
+        request.set_addresses(RepeatedField::from_vec(addresses.to_vec()));
+        let mut response: DeleteStateResponse = self.request(
+            ExternalExecutionMessage_Type::DELETE_STATE_REQUEST,
+            ExternalExecutionMessage_Type::DELETE_STATE_RESPONSE,
+            &request,
+        )?;
+        check_status(response.get_status(), response.get_error_message())?;
+        Ok(response.take_addresses().into_vec())
+    }
+
+    fn add_receipt_data(&self, data: Vec<u8>) -> Result<(), ContextError> {
+        let mut request = AddReceiptDataRequest::new();
+        request.set_context_id(self.context_id.clone());
+        request.set_data(data);
+        let response: StatusResponse = self.request(
+            ExternalExecutionMessage_Type::ADD_RECEIPT_DATA_REQUEST,
+            ExternalExecutionMessage_Type::ADD_RECEIPT_DATA_RESPONSE,
+            &request,
+        )?;
+        check_status(response.get_status(), response.get_error_message())
+    }
+
+    fn add_event(
+        &self,
+        event_type: String,
+        attributes: Vec<(String, String)>,
+        data: Vec<u8>,
+    ) -> Result<(), ContextError> {
+        let mut request = AddEventRequest::new();
+        request.set_context_id(self.context_id.clone());
+        request.set_event(
+            Event {
+                event_type,
+                attributes,
+                data,
+            }
+            .into_proto()
+            .map_err(|err| ContextError::SerializationError(Box::new(err)))?,
+        );
+        let response: StatusResponse = self.request(
+            ExternalExecutionMessage_Type::ADD_EVENT_REQUEST,
+            ExternalExecutionMessage_Type::ADD_EVENT_RESPONSE,
+            &request,
+        )?;
+        check_status(response.get_status(), response.get_error_message())
+    }
+}
@@ -0,0 +1,184 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Connections and message framing shared by the adapters and the client.
+//!
+//! Each message is an `ExternalExecutionMessage`, preceded by its length as a 4-byte big-endian
+//! integer.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use protobuf::Message;
+
+use crate::protos::external_execution::{ExternalExecutionMessage, ExternalExecutionMessage_Type};
+
+use super::{SocketAddress, SocketError};
+
+/// The largest message which will be read from a socket.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The writing half of a connection to a handler.
+pub(crate) trait Writer: Write + Send {
+    /// Closes the connection, which unblocks any thread reading from it.
+    fn close(&self) -> io::Result<()>;
+}
+
+/// A connected socket.
+pub(crate) trait Stream: Read + Writer {
+    fn try_clone_writer(&self) -> io::Result<Box<dyn Writer>>;
+}
+
+impl Writer for TcpStream {
+    fn close(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+}
+
+impl Stream for TcpStream {
+    fn try_clone_writer(&self) -> io::Result<Box<dyn Writer>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+}
+
+#[cfg(unix)]
+impl Writer for UnixStream {
+    fn close(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn try_clone_writer(&self) -> io::Result<Box<dyn Writer>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+}
+
+pub(crate) fn connect(address: &SocketAddress) -> io::Result<Box<dyn Stream>> {
+    match address {
+        SocketAddress::Tcp(address) => Ok(Box::new(TcpStream::connect(address.as_str())?)),
+        #[cfg(unix)]
+        SocketAddress::Unix(path) => Ok(Box::new(UnixStream::connect(path)?)),
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub fn bind(address: &SocketAddress) -> io::Result<Listener> {
+        match address {
+            SocketAddress::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address.as_str())?)),
+            #[cfg(unix)]
+            SocketAddress::Unix(path) => {
+                Ok(Listener::Unix(UnixListener::bind(path)?, path.clone()))
+            }
+        }
+    }
+
+    /// The address the listener is bound to; for TCP, this includes the port assigned by the
+    /// operating system if port 0 was requested.
+    pub fn local_address(&self) -> io::Result<SocketAddress> {
+        match self {
+            Listener::Tcp(listener) => Ok(SocketAddress::Tcp(listener.local_addr()?.to_string())),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(SocketAddress::Unix(path.clone())),
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Box<dyn Stream>> {
+        match self {
+            Listener::Tcp(listener) => Ok(Box::new(listener.accept()?.0)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Ok(Box::new(listener.accept()?.0)),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            if let Listener::Unix(_, path) = self {
+                if let Err(err) = std::fs::remove_file(&path) {
+                    warn!("unable to remove socket file {:?}: {}", path, err);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn read_message<R: Read + ?Sized>(
+    reader: &mut R,
+) -> Result<ExternalExecutionMessage, SocketError> {
+    let mut len_bytes = [0; 4];
+    reader.read_exact(&mut len_bytes)?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(SocketError::ProtocolError(format!(
+            "message of {} bytes exceeds the maximum of {} bytes",
+            len, MAX_MESSAGE_SIZE
+        )));
+    }
+
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    protobuf::parse_from_bytes(&bytes)
+        .map_err(|err| SocketError::ProtocolError(format!("unable to parse message: {}", err)))
+}
+
+pub(crate) fn write_message<W: Write + ?Sized>(
+    writer: &mut W,
+    message_type: ExternalExecutionMessage_Type,
+    correlation_id: String,
+    content: &dyn Message,
+) -> Result<(), SocketError> {
+    let mut message = ExternalExecutionMessage::new();
+    message.set_message_type(message_type);
+    message.set_correlation_id(correlation_id);
+    message.set_content(content.write_to_bytes().map_err(|err| {
+        SocketError::ProtocolError(format!("unable to serialize message content: {}", err))
+    })?);
+
+    let bytes = message.write_to_bytes().map_err(|err| {
+        SocketError::ProtocolError(format!("unable to serialize message: {}", err))
+    })?;
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub(crate) fn parse_content<M: Message>(
+    message: &ExternalExecutionMessage,
+) -> Result<M, SocketError> {
+    protobuf::parse_from_bytes(message.get_content()).map_err(|err| {
+        SocketError::ProtocolError(format!(
+            "unable to parse content of {:?} message: {}",
+            message.get_message_type(),
+            err
+        ))
+    })
+}
@@ -0,0 +1,933 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! The handlers connected to an adapter, shared by the adapters which serve external handlers.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+#[cfg(feature = "process-adapter")]
+use std::time::Duration;
+use std::time::Instant;
+
+use protobuf::{Message, RepeatedField};
+
+use crate::context::manager::sync::ContextManager;
+use crate::context::{ContextId, ContextLifecycle};
+use crate::execution::adapter::{ExecutionAdapterError, ExecutionOperationError};
+use crate::execution::{ExecutionRegistry, TransactionFamily};
+use crate::handler::{ApplyError, QueryError};
+use crate::protocol::receipt::Event;
+use crate::protocol::transaction::TransactionPair;
+#[cfg(feature = "process-adapter")]
+use crate::protos::external_execution::PingRequest;
+use crate::protos::external_execution::{
+    AddEventRequest, AddReceiptDataRequest, DeleteStateRequest, DeleteStateResponse,
+    ExternalExecutionMessage, ExternalExecutionMessage_Type, GetStateRequest, GetStateResponse,
+    ProcessRequest, ProcessResponse, ProcessResponse_Status, QueryRequest, QueryResponse,
+    QueryResponse_Status, RegisterRequest, ResponseStatus, SetStateRequest, StateEntry,
+    StatusResponse, UnregisterRequest,
+};
+use crate::protos::{FromProto, IntoProto};
+use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
+
+use super::connection::{self, Writer};
+use super::SocketError;
+
+type OnDoneCallback =
+    Box<dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send>;
+
+type OnQueryDoneCallback = Box<dyn Fn(Result<Vec<u8>, QueryError>) + Send>;
+
+/// The writing half of a connection, which is written to without holding the state lock.
+type SharedWriter = Arc<Mutex<Box<dyn Writer>>>;
+
+/// The external handlers connected to an adapter, and the transactions sent to them.
+///
+/// Each connection's messages are read on its own thread. Transaction families are registered
+/// with the `ExecutionRegistry` as handlers register them, and unregistered once no connected
+/// handler supports them. When a handler disconnects, any transactions and queries it was
+/// processing fail. A handler which cannot be written to is disconnected in the same way, and the
+/// transaction it could not be sent is returned to the executor to be routed again.
+#[derive(Clone)]
+pub(crate) struct HandlerConnections {
+    state: Arc<Mutex<AdapterState>>,
+    context_manager: ContextManager,
+}
+
+impl HandlerConnections {
+    pub fn new(context_manager: ContextManager) -> Self {
+        HandlerConnections {
+            state: Arc::new(Mutex::new(AdapterState::default())),
+            context_manager,
+        }
+    }
+
+    pub fn set_registry(&self, registry: Box<dyn ExecutionRegistry>) {
+        lock_state(&self.state).registry = Some(registry);
+    }
+
+    /// Adds a connection to a handler, whose messages are read from `reader` on a new thread
+    /// until it is closed. Returns the connection's ID.
+    pub fn add<R: Read + Send + 'static>(
+        &self,
+        thread_name: &str,
+        reader: R,
+        writer: Box<dyn Writer>,
+    ) -> Result<usize, SocketError> {
+        let mut locked_state = lock_state(&self.state);
+        let connection_id = locked_state.next_connection_id;
+        locked_state.next_connection_id += 1;
+        locked_state.connections.insert(
+            connection_id,
+            HandlerConnection {
+                writer: Arc::new(Mutex::new(writer)),
+                families: vec![],
+                ping_sent: None,
+            },
+        );
+
+        let thread_state = self.state.clone();
+        let thread_context_manager = self.context_manager.clone();
+        match thread::Builder::new()
+            .name(format!("{}-{}", thread_name, connection_id))
+            .spawn(move || {
+                read_connection(
+                    connection_id,
+                    reader,
+                    &thread_state,
+                    &thread_context_manager,
+                )
+            }) {
+            Ok(handle) => {
+                locked_state.reader_handles.push(handle);
+                Ok(connection_id)
+            }
+            Err(err) => {
+                locked_state.connections.remove(&connection_id);
+                Err(SocketError::IoError(err))
+            }
+        }
+    }
+
+    /// Whether the connection is open; a connection is removed once its reader is closed.
+    #[cfg(feature = "process-adapter")]
+    pub fn is_connected(&self, connection_id: usize) -> bool {
+        lock_state(&self.state)
+            .connections
+            .contains_key(&connection_id)
+    }
+
+    /// Sends a ping to the handler, unless an earlier ping is still unanswered.
+    #[cfg(feature = "process-adapter")]
+    pub fn ping(&self, connection_id: usize) -> Result<(), SocketError> {
+        let writer = {
+            let mut state = lock_state(&self.state);
+            let connection = state
+                .connections
+                .get_mut(&connection_id)
+                .ok_or_else(|| SocketError::ProtocolError("connection closed".into()))?;
+            if connection.ping_sent.is_some() {
+                return Ok(());
+            }
+            connection.ping_sent = Some(Instant::now());
+            connection.writer.clone()
+        };
+        write_to(
+            &writer,
+            ExternalExecutionMessage_Type::PING_REQUEST,
+            uuid::Uuid::new_v4().to_string(),
+            &PingRequest::new(),
+        )
+    }
+
+    /// How long the handler's oldest unanswered ping has been waiting, if there is one.
+    #[cfg(feature = "process-adapter")]
+    pub fn unanswered_ping(&self, connection_id: usize) -> Option<Duration> {
+        lock_state(&self.state)
+            .connections
+            .get(&connection_id)
+            .and_then(|connection| connection.ping_sent)
+            .map(|sent| sent.elapsed())
+    }
+
+    /// Sends the transaction to a handler which supports its family; if there is none, `on_done`
+    /// is called with a `RoutingError`.
+    pub fn execute(
+        &self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: OnDoneCallback,
+    ) -> Result<(), ExecutionOperationError> {
+        let family = TransactionFamily::from_pair(&transaction_pair);
+        let correlation_id = uuid::Uuid::new_v4().to_string();
+
+        let mut request = ProcessRequest::new();
+        request.set_context_id(context_id.to_vec());
+        request.set_transaction(
+            transaction_pair
+                .transaction()
+                .clone()
+                .into_proto()
+                .map_err(|err| {
+                    ExecutionOperationError::ExecuteError(format!(
+                        "Unable to serialize transaction: {}",
+                        err
+                    ))
+                })?,
+        );
+
+        // The transaction is pending before it is sent, so that the handler's response is
+        // expected however soon it arrives
+        let (connection_id, writer) = {
+            let mut state = lock_state(&self.state);
+            let routed = state.route(&family).and_then(|connection_id| {
+                state
+                    .writer(connection_id)
+                    .map(|writer| (connection_id, writer))
+            });
+            match routed {
+                Some((connection_id, writer)) => {
+                    state.pending.insert(
+                        correlation_id.clone(),
+                        PendingTransaction {
+                            connection_id,
+                            context_id,
+                            transaction_pair,
+                            on_done,
+                        },
+                    );
+                    (connection_id, writer)
+                }
+                None => {
+                    drop(state);
+                    on_done(Err(ExecutionAdapterError::RoutingError(Box::new(
+                        transaction_pair,
+                    ))));
+                    return Ok(());
+                }
+            }
+        };
+
+        if let Err(err) = write_to(
+            &writer,
+            ExternalExecutionMessage_Type::PROCESS_REQUEST,
+            correlation_id.clone(),
+            &request,
+        ) {
+            warn!(
+                "Unable to send transaction to handler {}, disconnecting: {}",
+                connection_id, err
+            );
+            // The handler never received the transaction, so it is returned to the executor to
+            // be routed to another adapter, if one supports it; it is only absent if the handler
+            // disconnected in the meantime, which has already completed it
+            let pending = lock_state(&self.state).pending.remove(&correlation_id);
+            disconnect(connection_id, &self.state, &self.context_manager);
+            if let Some(pending) = pending {
+                (pending.on_done)(Err(ExecutionAdapterError::RoutingError(Box::new(
+                    pending.transaction_pair,
+                ))));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends a query to a handler which supports the family, in a new read-only context at the
+    /// state root; if there is none, `on_done` is called with `QueryError::Unsupported`.
+    pub fn query(
+        &self,
+        family: TransactionFamily,
+        state_root: String,
+        payload: Vec<u8>,
+        on_done: OnQueryDoneCallback,
+    ) -> Result<(), ExecutionOperationError> {
+        let correlation_id = uuid::Uuid::new_v4().to_string();
+
+        let mut state = lock_state(&self.state);
+        let (connection_id, writer) = match state.route(&family).and_then(|connection_id| {
+            state
+                .writer(connection_id)
+                .map(|writer| (connection_id, writer))
+        }) {
+            Some(routed) => routed,
+            None => {
+                drop(state);
+                on_done(Err(QueryError::Unsupported(format!(
+                    "No handler for {} {}",
+                    family.family_name(),
+                    family.family_version()
+                ))));
+                return Ok(());
+            }
+        };
+
+        let context_id = self
+            .context_manager
+            .clone()
+            .create_context(&[], &state_root);
+
+        let mut request = QueryRequest::new();
+        request.set_context_id(context_id.to_vec());
+        request.set_family_name(family.family_name().into());
+        request.set_family_version(family.family_version().into());
+        request.set_payload(payload);
+
+        state.pending_queries.insert(
+            correlation_id.clone(),
+            PendingQuery {
+                connection_id,
+                context_id,
+                on_done,
+            },
+        );
+        drop(state);
+
+        if let Err(err) = write_to(
+            &writer,
+            ExternalExecutionMessage_Type::QUERY_REQUEST,
+            correlation_id,
+            &request,
+        ) {
+            // Disconnecting the handler fails the query and drops its context
+            warn!(
+                "Unable to send query to handler {}, disconnecting: {}",
+                connection_id, err
+            );
+            disconnect(connection_id, &self.state, &self.context_manager);
+        }
+
+        Ok(())
+    }
+
+    /// Closes the connection; its reader thread removes it once the handler's side is closed.
+    #[cfg(feature = "process-adapter")]
+    pub fn close(&self, connection_id: usize) {
+        let writer = lock_state(&self.state).writer(connection_id);
+        if let Some(writer) = writer {
+            if let Err(err) = close_writer(&writer) {
+                debug!("Unable to close handler connection: {}", err);
+            }
+        }
+    }
+
+    /// Closes every connection and waits for their reader threads to finish.
+    pub fn close_all(&self) -> Result<(), ExecutionOperationError> {
+        let (writers, reader_handles) = {
+            let mut state = lock_state(&self.state);
+            let writers = state
+                .connections
+                .values()
+                .map(|connection| connection.writer.clone())
+                .collect::<Vec<_>>();
+            (writers, state.reader_handles.drain(..).collect::<Vec<_>>())
+        };
+        for writer in writers {
+            if let Err(err) = close_writer(&writer) {
+                debug!("Unable to close handler connection: {}", err);
+            }
+        }
+        for handle in reader_handles {
+            handle.join().map_err(|_| {
+                ExecutionOperationError::StopError("Unable to join connection thread.".into())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A transaction which has been sent to a handler, but whose result has not been received.
+struct PendingTransaction {
+    connection_id: usize,
+    context_id: ContextId,
+    transaction_pair: TransactionPair,
+    on_done: OnDoneCallback,
+}
+
+/// A query which has been sent to a handler, but whose result has not been received.
+struct PendingQuery {
+    connection_id: usize,
+    context_id: ContextId,
+    on_done: OnQueryDoneCallback,
+}
+
+struct HandlerConnection {
+    writer: SharedWriter,
+    families: Vec<TransactionFamily>,
+    /// When the unanswered ping was sent, if there is one
+    #[cfg_attr(not(feature = "process-adapter"), allow(dead_code))]
+    ping_sent: Option<Instant>,
+}
+
+#[derive(Default)]
+struct AdapterState {
+    registry: Option<Box<dyn ExecutionRegistry>>,
+    next_connection_id: usize,
+    connections: HashMap<usize, HandlerConnection>,
+    pending: HashMap<String, PendingTransaction>,
+    pending_queries: HashMap<String, PendingQuery>,
+    reader_handles: Vec<thread::JoinHandle<()>>,
+}
+
+impl AdapterState {
+    /// Returns the connection's writer, if it is connected.
+    fn writer(&self, connection_id: usize) -> Option<SharedWriter> {
+        self.connections
+            .get(&connection_id)
+            .map(|connection| connection.writer.clone())
+    }
+
+    /// Returns the connected handler which supports the family and accepts the highest version.
+    ///
+    /// Of handlers accepting the same highest version, the one with the most specific version
+    /// requirement is returned, and of those registering the same requirement, the first
+    /// connected.
+    fn route(&self, family: &TransactionFamily) -> Option<usize> {
+        self.connections
+            .iter()
+            .flat_map(|(connection_id, connection)| {
+                connection
+                    .families
+                    .iter()
+                    .filter(|registered| registered.supports(family))
+                    .map(move |registered| {
+                        (
+                            (registered.maximum_version(), registered.minimum_version()),
+                            *connection_id,
+                        )
+                    })
+            })
+            .min_by_key(|(versions, connection_id)| (Reverse(*versions), *connection_id))
+            .map(|(_, connection_id)| connection_id)
+    }
+
+    /// Whether any connected handler has registered the family.
+    fn family_registered(&self, family: &TransactionFamily) -> bool {
+        self.connections
+            .values()
+            .any(|connection| connection.families.contains(family))
+    }
+
+    fn register(&mut self, connection_id: usize, family: TransactionFamily) {
+        if !self.family_registered(&family) {
+            if let Some(registry) = self.registry.as_mut() {
+                registry.register_transaction_family(family.clone());
+            }
+        }
+        if let Some(connection) = self.connections.get_mut(&connection_id) {
+            if !connection.families.contains(&family) {
+                connection.families.push(family);
+            }
+        }
+    }
+
+    fn unregister(&mut self, connection_id: usize, family: &TransactionFamily) {
+        if let Some(connection) = self.connections.get_mut(&connection_id) {
+            connection.families.retain(|f| f != family);
+        }
+        if !self.family_registered(family) {
+            if let Some(registry) = self.registry.as_mut() {
+                registry.unregister_transaction_family(family);
+            }
+        }
+    }
+
+    /// Whether the context belongs to a transaction which the connection is processing; handlers
+    /// may only access the contexts of their own transactions.
+    fn context_in_use(&self, connection_id: usize, context_id: &[u8]) -> bool {
+        self.pending.values().any(|pending| {
+            pending.connection_id == connection_id && pending.context_id[..] == context_id[..]
+        })
+    }
+
+    /// Whether the context belongs to a query which the connection is answering; handlers may
+    /// read, but not change, the contexts of their own queries.
+    fn context_queried(&self, connection_id: usize, context_id: &[u8]) -> bool {
+        self.pending_queries.values().any(|pending| {
+            pending.connection_id == connection_id && pending.context_id[..] == context_id[..]
+        })
+    }
+}
+
+fn lock_state(state: &Arc<Mutex<AdapterState>>) -> MutexGuard<AdapterState> {
+    state.lock().expect("socket adapter state lock poisoned")
+}
+
+fn read_connection<R: Read>(
+    connection_id: usize,
+    mut reader: R,
+    state: &Arc<Mutex<AdapterState>>,
+    context_manager: &ContextManager,
+) {
+    debug!("Handler {} connected", connection_id);
+    loop {
+        let message = match connection::read_message(&mut reader) {
+            Ok(message) => message,
+            Err(err) => {
+                debug!("Handler {} disconnected: {}", connection_id, err);
+                break;
+            }
+        };
+        if let Err(err) = handle_message(connection_id, message, state, context_manager) {
+            warn!("Closing connection to handler {}: {}", connection_id, err);
+            break;
+        }
+    }
+    disconnect(connection_id, state, context_manager);
+}
+
+/// Removes the connection, unregistering its families, and failing its pending transactions and
+/// queries.
+///
+/// The handler may have written to the contexts of its pending transactions before it
+/// disconnected, so they are failed rather than returned to be routed again.
+fn disconnect(
+    connection_id: usize,
+    state: &Arc<Mutex<AdapterState>>,
+    context_manager: &ContextManager,
+) {
+    let (writer, pending, pending_queries) = {
+        let mut state = lock_state(state);
+        let families = match state.connections.get(&connection_id) {
+            Some(connection) => connection.families.clone(),
+            None => vec![],
+        };
+        for family in &families {
+            state.unregister(connection_id, family);
+        }
+        let writer = state
+            .connections
+            .remove(&connection_id)
+            .map(|connection| connection.writer);
+
+        let correlation_ids = state
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.connection_id == connection_id)
+            .map(|(correlation_id, _)| correlation_id.clone())
+            .collect::<Vec<_>>();
+        let pending = correlation_ids
+            .iter()
+            .filter_map(|correlation_id| state.pending.remove(correlation_id))
+            .collect::<Vec<_>>();
+
+        let query_ids = state
+            .pending_queries
+            .iter()
+            .filter(|(_, pending)| pending.connection_id == connection_id)
+            .map(|(correlation_id, _)| correlation_id.clone())
+            .collect::<Vec<_>>();
+        let pending_queries = query_ids
+            .iter()
+            .filter_map(|correlation_id| state.pending_queries.remove(correlation_id))
+            .collect::<Vec<_>>();
+
+        (writer, pending, pending_queries)
+    };
+
+    // The reading half may already be closed; make sure the writing half is as well
+    if let Some(writer) = writer {
+        let _ = close_writer(&writer);
+    }
+
+    for pending in pending {
+        (pending.on_done)(Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+            SocketError::ProtocolError(format!(
+                "Handler {} disconnected while processing transaction {}",
+                connection_id,
+                pending.transaction_pair.transaction().header_signature()
+            )),
+        ))));
+    }
+    for pending in pending_queries {
+        context_manager.clone().drop_context(pending.context_id);
+        (pending.on_done)(Err(QueryError::InternalError(format!(
+            "Handler {} disconnected before answering the query",
+            connection_id
+        ))));
+    }
+}
+
+fn handle_message(
+    connection_id: usize,
+    message: ExternalExecutionMessage,
+    state: &Arc<Mutex<AdapterState>>,
+    context_manager: &ContextManager,
+) -> Result<(), SocketError> {
+    match message.get_message_type() {
+        ExternalExecutionMessage_Type::REGISTER_REQUEST => {
+            let request: RegisterRequest = connection::parse_content(&message)?;
+            let mut state = lock_state(state);
+            for version in request.get_family_versions() {
+                let family =
+                    TransactionFamily::new(request.get_family_name().into(), version.clone());
+                debug!(
+                    "Handler {} registered {} {}",
+                    connection_id,
+                    family.family_name(),
+                    family.family_version()
+                );
+                state.register(connection_id, family);
+            }
+            reply(
+                state,
+                connection_id,
+                &message,
+                ExternalExecutionMessage_Type::REGISTER_RESPONSE,
+                &status_response(Ok(())),
+            )
+        }
+        ExternalExecutionMessage_Type::UNREGISTER_REQUEST => {
+            let request: UnregisterRequest = connection::parse_content(&message)?;
+            let mut state = lock_state(state);
+            for version in request.get_family_versions() {
+                state.unregister(
+                    connection_id,
+                    &TransactionFamily::new(request.get_family_name().into(), version.clone()),
+                );
+            }
+            reply(
+                state,
+                connection_id,
+                &message,
+                ExternalExecutionMessage_Type::UNREGISTER_RESPONSE,
+                &status_response(Ok(())),
+            )
+        }
+        ExternalExecutionMessage_Type::PROCESS_RESPONSE => {
+            let response: ProcessResponse = connection::parse_content(&message)?;
+            let pending = {
+                let mut state = lock_state(state);
+                match state.pending.get(message.get_correlation_id()) {
+                    Some(pending) if pending.connection_id == connection_id => {
+                        state.pending.remove(message.get_correlation_id())
+                    }
+                    _ => None,
+                }
+            };
+            match pending {
+                Some(pending) => complete_transaction(pending, response),
+                None => warn!(
+                    "Handler {} sent a result for unknown request {}",
+                    connection_id,
+                    message.get_correlation_id()
+                ),
+            }
+            Ok(())
+        }
+        ExternalExecutionMessage_Type::QUERY_RESPONSE => {
+            let response: QueryResponse = connection::parse_content(&message)?;
+            let pending = {
+                let mut state = lock_state(state);
+                match state.pending_queries.get(message.get_correlation_id()) {
+                    Some(pending) if pending.connection_id == connection_id => {
+                        state.pending_queries.remove(message.get_correlation_id())
+                    }
+                    _ => None,
+                }
+            };
+            match pending {
+                Some(pending) => complete_query(pending, response, context_manager),
+                None => warn!(
+                    "Handler {} sent a result for unknown query {}",
+                    connection_id,
+                    message.get_correlation_id()
+                ),
+            }
+            Ok(())
+        }
+        ExternalExecutionMessage_Type::PING_RESPONSE => {
+            if let Some(connection) = lock_state(state).connections.get_mut(&connection_id) {
+                connection.ping_sent = None;
+            }
+            Ok(())
+        }
+        ExternalExecutionMessage_Type::GET_STATE_REQUEST => {
+            let request: GetStateRequest = connection::parse_content(&message)?;
+            let state = lock_state(state);
+            let mut response = GetStateResponse::new();
+            match context_for(&state, connection_id, request.get_context_id(), false).and_then(
+                |context_id| {
+                    context_manager
+                        .get(&context_id, request.get_addresses())
+                        .map_err(|err| err.to_string())
+                },
+            ) {
+                Ok(entries) => {
+                    response.set_status(ResponseStatus::OK);
+                    response.set_entries(RepeatedField::from_vec(
+                        entries
+                            .into_iter()
+                            .map(|(address, data)| {
+                                let mut entry = StateEntry::new();
+                                entry.set_address(address);
+                                entry.set_data(data);
+                                entry
+                            })
+                            .collect(),
+                    ));
+                }
+                Err(err) => {
+                    response.set_status(ResponseStatus::ERROR);
+                    response.set_error_message(err);
+                }
+            }
+            reply(
+                state,
+                connection_id,
+                &message,
+                ExternalExecutionMessage_Type::GET_STATE_RESPONSE,
+                &response,
+            )
+        }
+        ExternalExecutionMessage_Type::SET_STATE_REQUEST => {
+            let mut request: SetStateRequest = connection::parse_content(&message)?;
+            let state = lock_state(state);
+            let result = context_for(&state, connection_id, request.get_context_id(), true)
+                .and_then(|context_id| {
+                    request
+                        .take_entries()
+                        .into_vec()
+                        .into_iter()
+                        .try_for_each(|mut entry| {
+                            context_manager.set_state(
+                                &context_id,
+                                entry.take_address(),
+                                entry.take_data(),
+                            )
+                        })
+                        .map_err(|err| err.to_string())
+                });
+            reply(
+                state,
+                connection_id,
+                &message,
+                ExternalExecutionMessage_Type::SET_STATE_RESPONSE,
+                &status_response(result),
+            )
+        }
+        ExternalExecutionMessage_Type::DELETE_STATE_REQUEST => {
+            let request: DeleteStateRequest = connection::parse_content(&message)?;
+            let state = lock_state(state);
+            let mut response = DeleteStateResponse::new();
+            match context_for(&state, connection_id, request.get_context_id(), true).and_then(
+                |context_id| {
+                    let mut deleted = vec![];
+                    for address in request.get_addresses() {
+                        if context_manager
+                            .delete_state(&context_id, address)
+                            .map_err(|err| err.to_string())?
+                            .is_some()
+                        {
+                            deleted.push(address.clone());
+                        }
+                    }
+                    Ok(deleted)
+                },
+            ) {
+                Ok(deleted) => {
+                    response.set_status(ResponseStatus::OK);
+                    response.set_addresses(RepeatedField::from_vec(deleted));
+                }
+                Err(err) => {
+                    response.set_status(ResponseStatus::ERROR);
+                    response.set_error_message(err);
+                }
+            }
+            reply(
+                state,
+                connection_id,
+                &message,
+                ExternalExecutionMessage_Type::DELETE_STATE_RESPONSE,
+                &response,
+            )
+        }
+        ExternalExecutionMessage_Type::ADD_EVENT_REQUEST => {
+            let mut request: AddEventRequest = connection::parse_content(&message)?;
+            let state = lock_state(state);
+            let result = context_for(&state, connection_id, request.get_context_id(), true)
+                .and_then(|context_id| {
+                    let event =
+                        Event::from_proto(request.take_event()).map_err(|err| err.to_string())?;
+                    context_manager
+                        .add_event(&context_id, event)
+                        .map_err(|err| err.to_string())
+                });
+            reply(
+                state,
+                connection_id,
+                &message,
+                ExternalExecutionMessage_Type::ADD_EVENT_RESPONSE,
+                &status_response(result),
+            )
+        }
+        ExternalExecutionMessage_Type::ADD_RECEIPT_DATA_REQUEST => {
+            let mut request: AddReceiptDataRequest = connection::parse_content(&message)?;
+            let state = lock_state(state);
+            let result = context_for(&state, connection_id, request.get_context_id(), true)
+                .and_then(|context_id| {
+                    context_manager
+                        .add_data(&context_id, request.take_data())
+                        .map_err(|err| err.to_string())
+                });
+            reply(
+                state,
+                connection_id,
+                &message,
+                ExternalExecutionMessage_Type::ADD_RECEIPT_DATA_RESPONSE,
+                &status_response(result),
+            )
+        }
+        message_type => Err(SocketError::ProtocolError(format!(
+            "unexpected message type {:?}",
+            message_type
+        ))),
+    }
+}
+
+/// Returns the context ID from a request, if the connection may access that context; the
+/// contexts of queries may only be read.
+fn context_for(
+    state: &AdapterState,
+    connection_id: usize,
+    context_id: &[u8],
+    write: bool,
+) -> Result<ContextId, String> {
+    let valid = context_id.len() == std::mem::size_of::<ContextId>();
+    if valid && state.context_queried(connection_id, context_id) {
+        if write {
+            return Err(format!("context {} is read-only", hex::encode(context_id)));
+        }
+    } else if !valid || !state.context_in_use(connection_id, context_id) {
+        return Err(format!(
+            "context {} is not in use by this handler",
+            hex::encode(context_id)
+        ));
+    }
+    let mut id = ContextId::default();
+    id.copy_from_slice(context_id);
+    Ok(id)
+}
+
+fn status_response(result: Result<(), String>) -> StatusResponse {
+    let mut response = StatusResponse::new();
+    match result {
+        Ok(()) => response.set_status(ResponseStatus::OK),
+        Err(err) => {
+            response.set_status(ResponseStatus::ERROR);
+            response.set_error_message(err);
+        }
+    }
+    response
+}
+
+/// Sends the response to the handler, after releasing the state lock.
+fn reply(
+    state: MutexGuard<AdapterState>,
+    connection_id: usize,
+    request: &ExternalExecutionMessage,
+    message_type: ExternalExecutionMessage_Type,
+    content: &dyn Message,
+) -> Result<(), SocketError> {
+    let writer = state
+        .writer(connection_id)
+        .ok_or_else(|| SocketError::ProtocolError("connection closed".into()))?;
+    drop(state);
+    write_to(
+        &writer,
+        message_type,
+        request.get_correlation_id().into(),
+        content,
+    )
+}
+
+fn write_to(
+    writer: &SharedWriter,
+    message_type: ExternalExecutionMessage_Type,
+    correlation_id: String,
+    content: &dyn Message,
+) -> Result<(), SocketError> {
+    let mut writer = writer
+        .lock()
+        .map_err(|_| SocketError::ProtocolError("connection writer lock poisoned".into()))?;
+    connection::write_message(&mut **writer, message_type, correlation_id, content)
+}
+
+fn close_writer(writer: &SharedWriter) -> std::io::Result<()> {
+    match writer.lock() {
+        Ok(writer) => writer.close(),
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "connection writer lock poisoned",
+        )),
+    }
+}
+
+fn complete_transaction(pending: PendingTransaction, mut response: ProcessResponse) {
+    let transaction_id = pending
+        .transaction_pair
+        .transaction()
+        .header_signature()
+        .to_string();
+    let result = match response.get_status() {
+        ProcessResponse_Status::OK => Ok(ExecutionTaskCompletionNotification::Valid(
+            pending.context_id,
+            transaction_id,
+        )),
+        ProcessResponse_Status::INVALID_TRANSACTION => {
+            Ok(ExecutionTaskCompletionNotification::Invalid(
+                pending.context_id,
+                InvalidTransactionResult {
+                    transaction_id,
+                    error_message: response.take_error_message(),
+                    error_data: response.take_error_data(),
+                },
+            ))
+        }
+        ProcessResponse_Status::INTERNAL_ERROR | ProcessResponse_Status::STATUS_UNSET => {
+            Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+                ApplyError::InternalError(response.take_error_message()),
+            )))
+        }
+    };
+    (pending.on_done)(result);
+}
+
+fn complete_query(
+    pending: PendingQuery,
+    mut response: QueryResponse,
+    context_manager: &ContextManager,
+) {
+    context_manager.clone().drop_context(pending.context_id);
+    let result = match response.get_status() {
+        QueryResponse_Status::OK => Ok(response.take_result()),
+        QueryResponse_Status::INVALID_QUERY => {
+            Err(QueryError::InvalidQuery(response.take_error_message()))
+        }
+        QueryResponse_Status::UNSUPPORTED => {
+            Err(QueryError::Unsupported(response.take_error_message()))
+        }
+        QueryResponse_Status::INTERNAL_ERROR | QueryResponse_Status::STATUS_UNSET => {
+            Err(QueryError::InternalError(response.take_error_message()))
+        }
+    };
+    (pending.on_done)(result);
+}
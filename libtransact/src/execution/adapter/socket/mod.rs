@@ -0,0 +1,418 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Execution of transactions by handlers running in other processes.
+//!
+//! The `SocketExecutionAdapter` listens on a local Unix or TCP socket for connections from
+//! external transaction handlers. A handler registers the transaction families it supports, is
+//! sent the transactions of those families to process, and reads and writes state through
+//! requests which the adapter serves from the `ContextManager`.
+//!
+//! The `SocketTransactionProcessor` is the client side of the protocol: it connects to the adapter
+//! and serves any number of `TransactionHandler`s.
+//!
+//! Note, to use this module, the Transact library must have the `"socket-adapter"` feature
+//! enabled.
+
+mod adapter;
+mod client;
+mod connection;
+mod connections;
+
+use std::error::Error;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+pub use self::adapter::SocketExecutionAdapter;
+#[cfg(feature = "process-adapter")]
+pub(crate) use self::client::serve;
+pub use self::client::SocketTransactionProcessor;
+#[cfg(any(feature = "event-bus", feature = "process-adapter"))]
+pub(crate) use self::connection::Writer;
+#[cfg(feature = "event-bus")]
+pub(crate) use self::connection::{connect, Listener, Stream};
+#[cfg(feature = "process-adapter")]
+pub(crate) use self::connections::HandlerConnections;
+
+/// The address of a socket execution adapter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SocketAddress {
+    /// A TCP address, such as `"127.0.0.1:4004"`
+    Tcp(String),
+    /// The path of a Unix domain socket
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl std::fmt::Display for SocketAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SocketAddress::Tcp(address) => write!(f, "tcp://{}", address),
+            #[cfg(unix)]
+            SocketAddress::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SocketError {
+    /// Returned when reading from or writing to a socket fails
+    IoError(std::io::Error),
+    /// Returned when a message is malformed or unexpected
+    ProtocolError(String),
+    /// Returned when the adapter rejects a handler's registration
+    RegistrationError(String),
+}
+
+impl Error for SocketError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SocketError::IoError(err) => Some(err),
+            SocketError::ProtocolError(_) => None,
+            SocketError::RegistrationError(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SocketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SocketError::IoError(err) => write!(f, "socket I/O failed: {}", err),
+            SocketError::ProtocolError(msg) => write!(f, "protocol error: {}", msg),
+            SocketError::RegistrationError(msg) => write!(f, "registration failed: {}", msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for SocketError {
+    fn from(err: std::io::Error) -> Self {
+        SocketError::IoError(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::io::{self, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::sync::mpsc::{channel, Sender};
+    use std::thread;
+    use std::time::Duration;
+
+    use protobuf::RepeatedField;
+
+    use crate::context::manager::sync::ContextManager;
+    use crate::context::ContextLifecycle;
+    use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError};
+    use crate::execution::{ExecutionRegistry, TransactionFamily};
+    use crate::handler::QueryError;
+    use crate::protocol::command::{
+        AddEvent, BytesEntry, Command, DeleteState, GetState, ReturnInvalid, SetState,
+    };
+    use crate::protocol::receipt::{Event, TransactionResult};
+    use crate::protos::external_execution::{ExternalExecutionMessage_Type, RegisterRequest};
+    use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
+    use crate::state::hashmap::HashMapState;
+    use crate::workload::command::{make_command_transaction, CommandTransactionHandler};
+
+    use super::connection::{self, Writer};
+    use super::connections::HandlerConnections;
+
+    /// Reports registration changes over a channel.
+    struct ChannelRegistry {
+        sender: Sender<(bool, TransactionFamily)>,
+    }
+
+    impl ExecutionRegistry for ChannelRegistry {
+        fn register_transaction_family(&mut self, family: TransactionFamily) {
+            self.sender
+                .send((true, family))
+                .expect("Unable to send registration");
+        }
+
+        fn unregister_transaction_family(&mut self, family: &TransactionFamily) {
+            self.sender
+                .send((false, family.clone()))
+                .expect("Unable to send unregistration");
+        }
+    }
+
+    /// A connection whose writes fail once it has sent the given number of messages.
+    struct FailingWriter {
+        stream: TcpStream,
+        remaining_messages: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.remaining_messages == 0 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "write failed"));
+            }
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.remaining_messages = self.remaining_messages.saturating_sub(1);
+            self.stream.flush()
+        }
+    }
+
+    impl Writer for FailingWriter {
+        fn close(&self) -> io::Result<()> {
+            self.stream.shutdown(Shutdown::Both)
+        }
+    }
+
+    /// Connects a processor serving the command family to a socket adapter at the given address,
+    /// executes a valid and an invalid transaction through it, and queries the family, which the
+    /// command handler does not support.
+    fn run_command_transactions(address: SocketAddress) {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let mut context_manager = ContextManager::new(Box::new(state));
+
+        let mut adapter = SocketExecutionAdapter::new(address, context_manager.clone())
+            .expect("Unable to create adapter");
+        let (registry_tx, registry_rx) = channel();
+        adapter
+            .start(Box::new(ChannelRegistry {
+                sender: registry_tx,
+            }))
+            .expect("Unable to start adapter");
+
+        let mut processor = SocketTransactionProcessor::new(adapter.local_address().clone());
+        processor.add_handler(Box::new(CommandTransactionHandler::new()));
+        let processor_handle = thread::spawn(move || processor.run());
+
+        let (registered, family) = registry_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Processor did not register");
+        assert!(registered);
+        assert_eq!(family.family_name(), "command");
+
+        let txn_pair = make_command_transaction(&[
+            Command::SetState(SetState::new(vec![BytesEntry::new(
+                "abc".into(),
+                b"abc".to_vec(),
+            )])),
+            Command::GetState(GetState::new(vec!["abc".into()])),
+            Command::SetState(SetState::new(vec![BytesEntry::new(
+                "def".into(),
+                b"def".to_vec(),
+            )])),
+            Command::DeleteState(DeleteState::new(vec!["def".into()])),
+            Command::AddEvent(AddEvent::new(
+                "event".into(),
+                vec![BytesEntry::new("key".into(), b"value".to_vec())],
+                b"data".to_vec(),
+            )),
+        ]);
+        let txn_id = txn_pair.transaction().header_signature().to_string();
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let (result_tx, result_rx) = channel();
+        let valid_tx = result_tx.clone();
+        adapter
+            .execute(
+                txn_pair,
+                context_id,
+                Box::new(move |res| valid_tx.send(res).expect("Unable to send result")),
+            )
+            .expect("Unable to execute transaction");
+        assert_eq!(
+            result_rx
+                .recv_timeout(Duration::from_secs(5))
+                .expect("Did not receive result")
+                .expect("Execution failed"),
+            ExecutionTaskCompletionNotification::Valid(context_id, txn_id.clone())
+        );
+        assert_eq!(
+            context_manager
+                .get(&context_id, &["abc".into(), "def".into()])
+                .expect("Unable to get state"),
+            vec![("abc".to_string(), b"abc".to_vec())]
+        );
+
+        let receipt = context_manager
+            .get_transaction_receipt(&context_id, &txn_id)
+            .expect("Unable to get receipt");
+        match receipt.transaction_result {
+            TransactionResult::Valid { events, .. } => assert_eq!(
+                events,
+                vec![Event {
+                    event_type: "event".into(),
+                    attributes: vec![("key".into(), "value".into())],
+                    data: b"data".to_vec(),
+                }]
+            ),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let txn_pair =
+            make_command_transaction(&[Command::ReturnInvalid(ReturnInvalid::new("bad".into()))]);
+        let txn_id = txn_pair.transaction().header_signature().to_string();
+        let context_id = context_manager.create_context(&[], &state_id);
+        adapter
+            .execute(
+                txn_pair,
+                context_id,
+                Box::new(move |res| result_tx.send(res).expect("Unable to send result")),
+            )
+            .expect("Unable to execute transaction");
+        assert_eq!(
+            result_rx
+                .recv_timeout(Duration::from_secs(5))
+                .expect("Did not receive result")
+                .expect("Execution failed"),
+            ExecutionTaskCompletionNotification::Invalid(
+                context_id,
+                InvalidTransactionResult {
+                    transaction_id: txn_id,
+                    error_message: "bad".into(),
+                    error_data: vec![],
+                }
+            )
+        );
+
+        let (query_tx, query_rx) = channel();
+        adapter
+            .query(
+                family,
+                state_id,
+                b"abc".to_vec(),
+                Box::new(move |res| query_tx.send(res).expect("Unable to send result")),
+            )
+            .expect("Unable to send query");
+        match query_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Did not receive query result")
+        {
+            Err(QueryError::Unsupported(_)) => (),
+            res => panic!("Unexpected query result: {:?}", res),
+        }
+
+        Box::new(adapter).stop().expect("Unable to stop adapter");
+
+        // Once the adapter stops, the processor's connection is closed
+        processor_handle
+            .join()
+            .expect("Processor thread panicked")
+            .expect("Processor failed");
+    }
+
+    /// Tests executing transactions with a processor connected over TCP.
+    #[test]
+    fn test_tcp_socket_adapter() {
+        run_command_transactions(SocketAddress::Tcp("127.0.0.1:0".into()));
+    }
+
+    /// Tests executing transactions with a processor connected over a Unix domain socket.
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_adapter() {
+        let path = std::env::temp_dir().join(format!(
+            "transact-socket-adapter-{}.sock",
+            uuid::Uuid::new_v4()
+        ));
+        run_command_transactions(SocketAddress::Unix(path.clone()));
+        assert!(!path.exists());
+    }
+
+    /// Tests that a handler which cannot be sent a transaction is disconnected: its family is
+    /// unregistered, and the transaction is returned with a `RoutingError` so that it may be
+    /// routed to another adapter.
+    #[test]
+    fn test_handler_disconnected_on_write_failure() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let mut context_manager = ContextManager::new(Box::new(state));
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to bind listener");
+        let mut client = TcpStream::connect(listener.local_addr().expect("Unable to get address"))
+            .expect("Unable to connect");
+        let (server, _) = listener.accept().expect("Unable to accept connection");
+
+        let connections = HandlerConnections::new(context_manager.clone());
+        let (registry_tx, registry_rx) = channel();
+        connections.set_registry(Box::new(ChannelRegistry {
+            sender: registry_tx,
+        }));
+        // Only the response to the registration can be written
+        connections
+            .add(
+                "test-handler",
+                server.try_clone().expect("Unable to clone stream"),
+                Box::new(FailingWriter {
+                    stream: server,
+                    remaining_messages: 1,
+                }),
+            )
+            .expect("Unable to add connection");
+
+        let mut request = RegisterRequest::new();
+        request.set_family_name("command".into());
+        request.set_family_versions(RepeatedField::from_vec(vec!["0.1".into()]));
+        connection::write_message(
+            &mut client,
+            ExternalExecutionMessage_Type::REGISTER_REQUEST,
+            "register".into(),
+            &request,
+        )
+        .expect("Unable to send registration");
+        let response = connection::read_message(&mut client).expect("Unable to read response");
+        assert_eq!(
+            response.get_message_type(),
+            ExternalExecutionMessage_Type::REGISTER_RESPONSE
+        );
+        let (registered, family) = registry_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Handler did not register");
+        assert!(registered);
+
+        let txn_pair =
+            make_command_transaction(&[Command::ReturnInvalid(ReturnInvalid::new("bad".into()))]);
+        let context_id = context_manager.create_context(&[], &state_id);
+        let (result_tx, result_rx) = channel();
+        connections
+            .execute(
+                txn_pair,
+                context_id,
+                Box::new(move |res| result_tx.send(res).expect("Unable to send result")),
+            )
+            .expect("Unable to execute transaction");
+
+        match result_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Did not receive result")
+        {
+            Err(ExecutionAdapterError::RoutingError(_)) => (),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            registry_rx
+                .recv_timeout(Duration::from_secs(5))
+                .expect("Handler was not unregistered"),
+            (false, family)
+        );
+
+        connections
+            .close_all()
+            .expect("Unable to close connections");
+    }
+}
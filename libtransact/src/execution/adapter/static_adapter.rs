@@ -18,15 +18,21 @@
 //!
 //! This module provides the `StaticExecutionAdapter`, an implementation of `ExecutionAdapter`
 //! which execute transactions via `TransactionHandler` instances directly.
+use std::cmp::Reverse;
+use std::collections::HashSet;
 use std::sync::mpsc::{channel, Sender};
 use std::thread;
 
 use crate::context::manager::sync::ContextManager;
 use crate::context::manager::ContextManagerError;
-use crate::context::ContextId;
+use crate::context::{ContextId, ContextLifecycle};
+use crate::execution::adapter::determinism::DeterminismCheck;
+use crate::execution::adapter::invocation::InvocationContext;
 use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError, ExecutionOperationError};
 use crate::execution::{ExecutionRegistry, TransactionFamily};
-use crate::handler::{ApplyError, ContextError, TransactionContext, TransactionHandler};
+use crate::handler::{
+    ApplyError, ContextError, QueryError, ReadOnlyContext, TransactionContext, TransactionHandler,
+};
 use crate::protocol::receipt::Event;
 use crate::protocol::transaction::TransactionPair;
 use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
@@ -35,10 +41,17 @@ use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionRe
 type OnDoneCallback =
     Box<dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send>;
 
+type OnQueryDoneCallback = Box<dyn Fn(Result<Vec<u8>, QueryError>) + Send>;
+
 /// The StaticExecutionAdapter to wrap TransactionHandlers
 ///
 /// This struct takes a series of transaction handlers which can be used to execution transactions.
-/// These transactions are executed on a single background thread.
+/// These transactions are executed on a single background thread. While applying a transaction, a
+/// handler may invoke the adapter's other handlers through its context.
+///
+/// Handlers may be added, replaced and removed while the adapter is running. Changes are made on
+/// the background thread, in order with the transactions sent to the adapter, so transactions sent
+/// before a change are executed by the handlers the adapter had when they were sent.
 pub struct StaticExecutionAdapter {
     join_handle: thread::JoinHandle<bool>,
     sender: Sender<StaticAdapterCommand>,
@@ -57,11 +70,32 @@ impl StaticExecutionAdapter {
     pub fn new_adapter(
         handlers: Vec<Box<dyn TransactionHandler>>,
         context_manager: ContextManager,
+    ) -> Result<Self, ExecutionAdapterError> {
+        Self::new_adapter_with_determinism_check(handlers, context_manager, None)
+    }
+
+    /// Creates a new adapter which checks transactions for non-determinism, if possible.
+    ///
+    /// Each transaction sampled by the `DeterminismCheck` is applied twice. Unless the check has
+    /// an observer, a transaction whose runs differ fails with a `GeneralExecutionError` wrapping
+    /// a `NondeterminismError`.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionAdapterError` is returned if the background thread cannot be created.
+    pub fn new_adapter_with_determinism_check(
+        handlers: Vec<Box<dyn TransactionHandler>>,
+        context_manager: ContextManager,
+        determinism_check: Option<DeterminismCheck>,
     ) -> Result<Self, ExecutionAdapterError> {
         let (sender, receiver) = channel();
         let join_handle = thread::Builder::new()
             .name("StaticExecutionAdapter".into())
             .spawn(move || {
+                let mut context_manager = context_manager;
+                let mut handlers = handlers;
+                let mut families = HandlerFamilies::new(&handlers);
+                let mut execution_registry: Option<Box<dyn ExecutionRegistry>> = None;
                 while let Ok(cmd) = receiver.recv() {
                     match cmd {
                         StaticAdapterCommand::Execute(execute_cmd) => {
@@ -69,14 +103,56 @@ impl StaticExecutionAdapter {
                             debug!("Executing {:?} in context {:?}", &txn_pair, &context_id);
                             execute_transaction(
                                 &handlers,
+                                &families,
+                                determinism_check.as_ref(),
                                 txn_pair,
                                 &context_manager,
                                 context_id,
                                 on_done,
                             );
                         }
-                        StaticAdapterCommand::Start(mut execution_registry) => {
-                            register_handlers(&handlers, &mut *execution_registry);
+                        StaticAdapterCommand::Query(query_cmd) => {
+                            let (family, state_root, payload, on_done) = *query_cmd;
+                            on_done(query(
+                                &handlers,
+                                &families,
+                                &mut context_manager,
+                                &family,
+                                &state_root,
+                                &payload,
+                            ));
+                        }
+                        StaticAdapterCommand::Start(mut registry) => {
+                            for family in families.registered() {
+                                registry.register_transaction_family(family);
+                            }
+                            execution_registry = Some(registry);
+                        }
+                        StaticAdapterCommand::AddHandler(handler) => {
+                            update_handlers(
+                                &mut handlers,
+                                &mut families,
+                                &mut execution_registry,
+                                |handlers| handlers.push(handler),
+                            );
+                        }
+                        StaticAdapterCommand::ReplaceHandler(handler) => {
+                            update_handlers(
+                                &mut handlers,
+                                &mut families,
+                                &mut execution_registry,
+                                |handlers| replace_handler(handlers, handler),
+                            );
+                        }
+                        StaticAdapterCommand::RemoveHandler(family_name) => {
+                            update_handlers(
+                                &mut handlers,
+                                &mut families,
+                                &mut execution_registry,
+                                |handlers| {
+                                    handlers.retain(|handler| handler.family_name() != family_name)
+                                },
+                            );
                         }
                         StaticAdapterCommand::Stop => {
                             break;
@@ -92,48 +168,90 @@ impl StaticExecutionAdapter {
             sender,
         })
     }
+
+    /// Adds a handler, registering any of its families which the adapter's other handlers do not
+    /// support.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionOperationError::HandlerUpdateError` is returned if the adapter has stopped.
+    pub fn add_handler(
+        &self,
+        handler: Box<dyn TransactionHandler>,
+    ) -> Result<(), ExecutionOperationError> {
+        self.send_update(StaticAdapterCommand::AddHandler(handler))
+    }
+
+    /// Replaces the handlers of a family with the given handler, which may support different
+    /// versions of the family; if the adapter has no handler for the family, the handler is added.
+    ///
+    /// Versions which only the new handler supports are registered before those it does not
+    /// support are unregistered, so the family remains registered while a handler is swapped for
+    /// one supporting a newer version.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionOperationError::HandlerUpdateError` is returned if the adapter has stopped.
+    pub fn replace_handler(
+        &self,
+        handler: Box<dyn TransactionHandler>,
+    ) -> Result<(), ExecutionOperationError> {
+        self.send_update(StaticAdapterCommand::ReplaceHandler(handler))
+    }
+
+    /// Removes the handlers of a family, unregistering the versions they supported.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionOperationError::HandlerUpdateError` is returned if the adapter has stopped.
+    pub fn remove_handler(&self, family_name: &str) -> Result<(), ExecutionOperationError> {
+        self.send_update(StaticAdapterCommand::RemoveHandler(family_name.into()))
+    }
+
+    fn send_update(&self, command: StaticAdapterCommand) -> Result<(), ExecutionOperationError> {
+        self.sender.send(command).map_err(|err| {
+            ExecutionOperationError::HandlerUpdateError(format!(
+                "Unable to send handler update: {}",
+                err
+            ))
+        })
+    }
 }
 
 fn execute_transaction(
     handlers: &[Box<dyn TransactionHandler>],
+    families: &HandlerFamilies,
+    determinism_check: Option<&DeterminismCheck>,
     transaction_pair: TransactionPair,
     context_manager: &ContextManager,
     context_id: ContextId,
     on_done: OnDoneCallback,
 ) {
     let family = TransactionFamily::from_pair(&transaction_pair);
-    match handlers.iter().find(|handler| {
-        handler.family_name() == family.family_name()
-            && handler
-                .family_versions()
-                .iter()
-                .any(|v| v == family.family_version())
-    }) {
+    match families.find_handler(handlers, &family) {
         Some(handler) => {
             let mut static_context = StaticContext::new(context_manager, &context_id);
-
-            match handler.apply(&transaction_pair, &mut static_context) {
-                Ok(_) => on_done(Ok(ExecutionTaskCompletionNotification::Valid(
-                    context_id,
-                    transaction_pair.transaction().header_signature().to_owned(),
-                ))),
-                Err(ApplyError::InvalidTransaction(error_message)) => {
-                    on_done(Ok(ExecutionTaskCompletionNotification::Invalid(
-                        context_id,
-                        InvalidTransactionResult {
-                            transaction_id: transaction_pair
-                                .transaction()
-                                .header_signature()
-                                .to_owned(),
-                            error_message,
-                            error_data: vec![],
-                        },
-                    )))
+            let apply = |context: &mut dyn TransactionContext| {
+                handler.apply(
+                    &transaction_pair,
+                    &mut InvocationContext::new(handlers, families, &transaction_pair, &*context),
+                )
+            };
+            let result = match determinism_check {
+                Some(check) if check.sample() => {
+                    match check.apply_with(&transaction_pair, &mut static_context, apply) {
+                        Ok(result) => result,
+                        Err(err) => {
+                            on_done(Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+                                err,
+                            ))));
+                            return;
+                        }
+                    }
                 }
-                Err(err) => on_done(Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
-                    err,
-                )))),
-            }
+                _ => apply(&mut static_context),
+            };
+            notify_result(transaction_pair, context_id, result, on_done);
         }
         None => on_done(Err(ExecutionAdapterError::RoutingError(Box::new(
             transaction_pair,
@@ -141,16 +259,173 @@ fn execute_transaction(
     };
 }
 
-fn register_handlers(
+/// Answers a query with the family's handler, in a read-only context at the given state root.
+fn query(
     handlers: &[Box<dyn TransactionHandler>],
-    execution_registry: &mut dyn ExecutionRegistry,
+    families: &HandlerFamilies,
+    context_manager: &mut ContextManager,
+    family: &TransactionFamily,
+    state_root: &str,
+    payload: &[u8],
+) -> Result<Vec<u8>, QueryError> {
+    let handler = families.find_handler(handlers, family).ok_or_else(|| {
+        QueryError::Unsupported(format!(
+            "No handler for {} {}",
+            family.family_name(),
+            family.family_version()
+        ))
+    })?;
+
+    let context_id = context_manager.create_context(&[], state_root);
+    let result = {
+        let static_context = StaticContext::new(context_manager, &context_id);
+        handler.query(payload, &ReadOnlyContext::new(&static_context))
+    };
+    context_manager.drop_context(context_id);
+
+    result
+}
+
+/// The families registered by the versions of a list of handlers, each with the index of the
+/// handler registering it.
+///
+/// The versions are parsed when the handlers change, rather than for each transaction routed to
+/// the handlers.
+pub(crate) struct HandlerFamilies {
+    families: Vec<(TransactionFamily, usize)>,
+}
+
+impl HandlerFamilies {
+    pub(crate) fn new(handlers: &[Box<dyn TransactionHandler>]) -> Self {
+        HandlerFamilies {
+            families: handlers
+                .iter()
+                .enumerate()
+                .flat_map(|(index, handler)| {
+                    handler.family_versions().iter().map(move |version| {
+                        (
+                            TransactionFamily::new(
+                                handler.family_name().to_owned(),
+                                version.clone(),
+                            ),
+                            index,
+                        )
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the handler which supports the family and accepts the highest version, if any do.
+    ///
+    /// Of handlers accepting the same highest version, the one with the most specific version
+    /// requirement is returned, and of those registering the same requirement, the first.
+    ///
+    /// The handlers must be those the families were created from.
+    pub(crate) fn find_handler<'a>(
+        &self,
+        handlers: &'a [Box<dyn TransactionHandler>],
+        family: &TransactionFamily,
+    ) -> Option<&'a dyn TransactionHandler> {
+        self.families
+            .iter()
+            .filter(|(registered, _)| registered.supports(family))
+            .min_by_key(|(registered, index)| {
+                (
+                    Reverse((registered.maximum_version(), registered.minimum_version())),
+                    *index,
+                )
+            })
+            .map(|(_, index)| &*handlers[*index])
+    }
+
+    /// The families supported by the handlers, in the order the handlers list them.
+    fn registered(&self) -> Vec<TransactionFamily> {
+        let mut seen = HashSet::new();
+        self.families
+            .iter()
+            .map(|(family, _)| family)
+            .filter(|family| seen.insert(*family))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Reports the result of applying a transaction to the executor.
+pub(crate) fn notify_result(
+    transaction_pair: TransactionPair,
+    context_id: ContextId,
+    result: Result<(), ApplyError>,
+    on_done: OnDoneCallback,
+) {
+    match result {
+        Ok(_) => on_done(Ok(ExecutionTaskCompletionNotification::Valid(
+            context_id,
+            transaction_pair.transaction().header_signature().to_owned(),
+        ))),
+        Err(ApplyError::InvalidTransaction(error_message)) => {
+            on_done(Ok(ExecutionTaskCompletionNotification::Invalid(
+                context_id,
+                InvalidTransactionResult {
+                    transaction_id: transaction_pair.transaction().header_signature().to_owned(),
+                    error_message,
+                    error_data: vec![],
+                },
+            )))
+        }
+        Err(err) => on_done(Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+            err,
+        )))),
+    }
+}
+
+/// Replaces the handlers of the handler's family with the handler, in the place of the first.
+fn replace_handler(
+    handlers: &mut Vec<Box<dyn TransactionHandler>>,
+    handler: Box<dyn TransactionHandler>,
 ) {
-    for handler in handlers {
-        for version in handler.family_versions() {
-            execution_registry.register_transaction_family(TransactionFamily::new(
-                handler.family_name().to_owned(),
-                version.clone(),
-            ));
+    let position = handlers
+        .iter()
+        .position(|existing| existing.family_name() == handler.family_name());
+    handlers.retain(|existing| existing.family_name() != handler.family_name());
+    match position {
+        Some(index) => handlers.insert(index, handler),
+        None => handlers.push(handler),
+    }
+}
+
+/// Changes the handlers, registering the families which only the changed handlers support, and
+/// then unregistering those which they no longer support. The registry is only updated once the
+/// adapter has started.
+fn update_handlers<F>(
+    handlers: &mut Vec<Box<dyn TransactionHandler>>,
+    families: &mut HandlerFamilies,
+    execution_registry: &mut Option<Box<dyn ExecutionRegistry>>,
+    change: F,
+) where
+    F: FnOnce(&mut Vec<Box<dyn TransactionHandler>>),
+{
+    let before = families.registered();
+    change(handlers);
+    *families = HandlerFamilies::new(handlers);
+    let after = families.registered();
+
+    if let Some(registry) = execution_registry {
+        for family in after.iter().filter(|family| !before.contains(family)) {
+            debug!(
+                "Registering {} {}",
+                family.family_name(),
+                family.family_version()
+            );
+            registry.register_transaction_family(family.clone());
+        }
+        for family in before.iter().filter(|family| !after.contains(family)) {
+            debug!(
+                "Unregistering {} {}",
+                family.family_name(),
+                family.family_version()
+            );
+            registry.unregister_transaction_family(family);
         }
     }
 }
@@ -190,6 +465,25 @@ impl ExecutionAdapter for StaticExecutionAdapter {
             })
     }
 
+    fn query(
+        &self,
+        family: TransactionFamily,
+        state_root: String,
+        payload: Vec<u8>,
+        on_done: OnQueryDoneCallback,
+    ) -> Result<(), ExecutionOperationError> {
+        self.sender
+            .send(StaticAdapterCommand::Query(Box::new((
+                family, state_root, payload, on_done,
+            ))))
+            .map_err(|err| {
+                ExecutionOperationError::ExecuteError(format!(
+                    "Unable to send query for static execution: {}",
+                    err
+                ))
+            })
+    }
+
     fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
         self.sender
             .send(StaticAdapterCommand::Stop)
@@ -209,15 +503,20 @@ enum StaticAdapterCommand {
     Start(Box<dyn ExecutionRegistry>),
     Stop,
     Execute(Box<(TransactionPair, ContextId, OnDoneCallback)>),
+    Query(Box<(TransactionFamily, String, Vec<u8>, OnQueryDoneCallback)>),
+    AddHandler(Box<dyn TransactionHandler>),
+    ReplaceHandler(Box<dyn TransactionHandler>),
+    RemoveHandler(String),
 }
 
-struct StaticContext<'a, 'b> {
+/// A `TransactionContext` which reads and writes a context through the `ContextManager`.
+pub(crate) struct StaticContext<'a, 'b> {
     context_manager: &'a ContextManager,
     context_id: &'b ContextId,
 }
 
 impl<'a, 'b> StaticContext<'a, 'b> {
-    fn new(context_manager: &'a ContextManager, context_id: &'b ContextId) -> Self {
+    pub(crate) fn new(context_manager: &'a ContextManager, context_id: &'b ContextId) -> Self {
         StaticContext {
             context_manager,
             context_id,
@@ -296,19 +595,22 @@ mod test {
 
     use std::collections::HashMap;
     use std::sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
     };
     use std::time;
 
     use crate::context::ContextLifecycle;
+    use crate::execution::adapter::determinism::{Difference, NondeterminismReport};
+    use crate::handler::Invocation;
     use crate::protocol::command::{
         AddEvent, AddReceiptData, BytesEntry, Command, DeleteState, GetState, ReturnInternalError,
         ReturnInvalid, SetState, Sleep, SleepType,
     };
-    use crate::protocol::receipt::TransactionResult;
+    use crate::protocol::receipt::{StateChange, TransactionResult};
     use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
     use crate::state::hashmap::HashMapState;
+    use crate::state::Write;
     use crate::workload::command::{make_command_transaction, CommandTransactionHandler};
 
     fn create_bytes_entry(state_writes: Vec<(String, Vec<u8>)>) -> Vec<BytesEntry> {
@@ -834,6 +1136,723 @@ mod test {
         assert!(Box::new(static_adapter).stop().is_ok());
     }
 
+    /// Find handlers for transactions of several versions, and check that the handler accepting
+    /// the highest version is chosen, with the most specific requirement breaking ties.
+    #[test]
+    fn static_adapter_find_highest_compatible_handler() {
+        let handlers: Vec<Box<dyn TransactionHandler>> = vec![
+            Box::new(VersionedHandler::new(&["~1.2"])),
+            Box::new(VersionedHandler::new(&["^1.0"])),
+            Box::new(VersionedHandler::new(&[">=1.1,<2.0"])),
+            Box::new(VersionedHandler::new(&["1.0"])),
+        ];
+        let families = HandlerFamilies::new(&handlers);
+        let family_name = handlers[0].family_name().to_string();
+
+        let find = |version: &str| {
+            families
+                .find_handler(
+                    &handlers,
+                    &TransactionFamily::new(family_name.clone(), version.into()),
+                )
+                .map(|handler| handler.family_versions()[0].clone())
+        };
+
+        assert_eq!(find("1.2.5"), Some(">=1.1,<2.0".to_string()));
+        assert_eq!(find("1.0"), Some("^1.0".to_string()));
+        assert_eq!(find("2.0"), None);
+    }
+
+    /// Add, replace and remove handlers while the static adapter is running, and check that the
+    /// registry is updated and transactions are executed by the current handlers.
+    #[test]
+    fn static_adapter_update_handlers() {
+        let registry = RecordingRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut static_adapter =
+            StaticExecutionAdapter::new_adapter(vec![], context_manager.clone())
+                .expect("Could not create adapter");
+
+        assert!(static_adapter.start(Box::new(registry.clone())).is_ok());
+
+        let mut execute = || {
+            let txn_pair = make_command_transaction(&[Command::SetState(SetState::new(
+                create_bytes_entry(vec![("abc".into(), b"abc".to_vec())]),
+            ))]);
+            let context_id = context_manager.create_context(&[], &state_id);
+            let (send, recv) = std::sync::mpsc::channel();
+            assert!(static_adapter
+                .execute(
+                    txn_pair,
+                    context_id,
+                    Box::new(move |res| {
+                        send.send(res).expect("Unable to send result");
+                    }),
+                )
+                .is_ok());
+            match recv.recv().unwrap() {
+                Ok(ExecutionTaskCompletionNotification::Valid(..)) => true,
+                Err(ExecutionAdapterError::RoutingError(_)) => false,
+                _ => panic!("Unexpected result"),
+            }
+        };
+
+        assert!(static_adapter
+            .add_handler(Box::new(VersionedHandler::new(&["0.1"])))
+            .is_ok());
+        assert!(execute());
+        assert_eq!(registry.take(), vec![(true, "0.1".to_string())]);
+
+        // The new version is registered before the old version is unregistered
+        assert!(static_adapter
+            .replace_handler(Box::new(VersionedHandler::new(&["0.2"])))
+            .is_ok());
+        assert!(!execute());
+        assert_eq!(
+            registry.take(),
+            vec![(true, "0.2".to_string()), (false, "0.1".to_string())]
+        );
+
+        assert!(static_adapter
+            .replace_handler(Box::new(VersionedHandler::new(&[">=0.1,<1.0"])))
+            .is_ok());
+        assert!(execute());
+        assert_eq!(
+            registry.take(),
+            vec![(true, ">=0.1,<1.0".to_string()), (false, "0.2".to_string())]
+        );
+
+        assert!(static_adapter.remove_handler("command").is_ok());
+        assert!(!execute());
+        assert_eq!(registry.take(), vec![(false, ">=0.1,<1.0".to_string())]);
+
+        assert!(Box::new(static_adapter).stop().is_ok());
+    }
+
+    /// Apply transactions twice with the determinism check, and check that a handler whose runs
+    /// differ is flagged, by an error or to the observer, while a deterministic handler's changes
+    /// are made once.
+    #[test]
+    fn static_adapter_determinism_check() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut execute = |handler: Box<dyn TransactionHandler>, check: DeterminismCheck| {
+            let mut static_adapter = StaticExecutionAdapter::new_adapter_with_determinism_check(
+                vec![handler],
+                context_manager.clone(),
+                Some(check),
+            )
+            .expect("Could not create adapter");
+            assert!(static_adapter
+                .start(Box::new(MockRegistry::default()))
+                .is_ok());
+
+            let txn_pair = make_command_transaction(&[
+                Command::SetState(SetState::new(create_bytes_entry(vec![(
+                    "abc".into(),
+                    b"abc".to_vec(),
+                )]))),
+                Command::AddReceiptData(AddReceiptData::new(b"data".to_vec())),
+            ]);
+            let context_id = context_manager.create_context(&[], &state_id);
+            let (send, recv) = std::sync::mpsc::channel();
+            assert!(static_adapter
+                .execute(
+                    txn_pair,
+                    context_id.clone(),
+                    Box::new(move |res| {
+                        send.send(res).expect("Unable to send result");
+                    }),
+                )
+                .is_ok());
+            let result = recv.recv().unwrap();
+            assert!(Box::new(static_adapter).stop().is_ok());
+
+            let values = context_manager
+                .get(&context_id, &["abc".to_owned(), "count".to_owned()])
+                .unwrap();
+            (result, values)
+        };
+
+        let (result, values) = execute(
+            Box::new(CommandTransactionHandler::new()),
+            DeterminismCheck::new(),
+        );
+        assert!(match result {
+            Ok(ExecutionTaskCompletionNotification::Valid(..)) => true,
+            _ => false,
+        });
+        assert_eq!(values, vec![("abc".to_owned(), b"abc".to_vec())]);
+
+        let (result, values) = execute(
+            Box::new(CountingHandler::default()),
+            DeterminismCheck::new(),
+        );
+        assert!(match result {
+            Err(ExecutionAdapterError::GeneralExecutionError(_)) => true,
+            _ => false,
+        });
+        assert!(values.is_empty());
+
+        let reports: Arc<Mutex<Vec<NondeterminismReport>>> = Arc::default();
+        let observed = Arc::clone(&reports);
+        let (result, values) = execute(
+            Box::new(CountingHandler::default()),
+            DeterminismCheck::new().with_observer(Box::new(move |report| {
+                observed.lock().unwrap().push(report.clone())
+            })),
+        );
+        assert!(match result {
+            Ok(ExecutionTaskCompletionNotification::Valid(..)) => true,
+            _ => false,
+        });
+        assert_eq!(values, vec![("count".to_owned(), vec![0])]);
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            reports[0].differences(),
+            &[Difference::State {
+                address: "count".into(),
+                first: Some(StateChange::Set {
+                    key: "count".into(),
+                    value: vec![0],
+                }),
+                second: Some(StateChange::Set {
+                    key: "count".into(),
+                    value: vec![1],
+                }),
+            }]
+        );
+
+        let (result, values) = execute(
+            Box::new(CountingHandler::default()),
+            DeterminismCheck::new().with_sample_rate(0.0),
+        );
+        assert!(result.is_ok());
+        assert_eq!(values, vec![("count".to_owned(), vec![0])]);
+    }
+
+    /// Apply a transaction which changes its context and is then invalid with the determinism
+    /// check, and check that none of its changes are made to the context.
+    #[test]
+    fn static_adapter_determinism_check_invalid() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut static_adapter = StaticExecutionAdapter::new_adapter_with_determinism_check(
+            vec![Box::new(CommandTransactionHandler::new())],
+            context_manager.clone(),
+            Some(DeterminismCheck::new()),
+        )
+        .expect("Could not create adapter");
+        assert!(static_adapter
+            .start(Box::new(MockRegistry::default()))
+            .is_ok());
+
+        let txn_pair = make_command_transaction(&[
+            Command::SetState(SetState::new(create_bytes_entry(vec![(
+                "abc".into(),
+                b"abc".to_vec(),
+            )]))),
+            Command::AddReceiptData(AddReceiptData::new(b"data".to_vec())),
+            Command::ReturnInvalid(ReturnInvalid::new("invalid".into())),
+        ]);
+        let transaction_id = txn_pair.transaction().header_signature().to_string();
+        let context_id = context_manager.create_context(&[], &state_id);
+        let (send, recv) = std::sync::mpsc::channel();
+        assert!(static_adapter
+            .execute(
+                txn_pair,
+                context_id.clone(),
+                Box::new(move |res| {
+                    send.send(res).expect("Unable to send result");
+                }),
+            )
+            .is_ok());
+        let result = recv.recv().unwrap();
+        assert!(Box::new(static_adapter).stop().is_ok());
+
+        assert!(match result {
+            Ok(ExecutionTaskCompletionNotification::Invalid(..)) => true,
+            _ => false,
+        });
+        assert!(context_manager
+            .get(&context_id, &["abc".to_owned()])
+            .unwrap()
+            .is_empty());
+        let receipt = context_manager
+            .get_transaction_receipt(&context_id, &transaction_id)
+            .unwrap();
+        assert!(match receipt.transaction_result {
+            TransactionResult::Valid {
+                state_changes,
+                data,
+                ..
+            } => state_changes.is_empty() && data.is_empty(),
+            TransactionResult::Invalid { .. } => false,
+        });
+    }
+
+    /// Apply a transaction whose handler invokes another with the determinism check, and check
+    /// that the invoked handler's changes and events are compared with the rest of each run's and
+    /// made once.
+    #[test]
+    fn static_adapter_determinism_check_invocation() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let mut static_adapter = StaticExecutionAdapter::new_adapter_with_determinism_check(
+            vec![
+                Box::new(ExchangeHandler {
+                    inner: CommandTransactionHandler::new(),
+                    invocations: vec![("token".into(), b"aa01")],
+                }),
+                Box::new(TokenHandler::new("token", &["aa"], None)),
+            ],
+            context_manager.clone(),
+            Some(DeterminismCheck::new()),
+        )
+        .expect("Could not create adapter");
+        assert!(static_adapter
+            .start(Box::new(MockRegistry::default()))
+            .is_ok());
+
+        let txn_pair = make_command_transaction(&[]);
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+        let (send, recv) = std::sync::mpsc::channel();
+        assert!(static_adapter
+            .execute(
+                txn_pair,
+                context_id.clone(),
+                Box::new(move |res| {
+                    send.send(res).expect("Unable to send result");
+                }),
+            )
+            .is_ok());
+        assert_eq!(
+            recv.recv().unwrap().unwrap(),
+            ExecutionTaskCompletionNotification::Valid(context_id.clone(), txn_id.clone())
+        );
+        assert!(Box::new(static_adapter).stop().is_ok());
+
+        assert_eq!(
+            context_manager
+                .get(&context_id, &["aa01".to_owned(), "ab00".to_owned()])
+                .unwrap(),
+            vec![
+                ("aa01".to_owned(), b"token".to_vec()),
+                ("ab00".to_owned(), b"token".to_vec()),
+            ]
+        );
+        let receipt = context_manager
+            .get_transaction_receipt(&context_id, &txn_id)
+            .unwrap();
+        match receipt.transaction_result {
+            TransactionResult::Valid { events, .. } => assert_eq!(events.len(), 1),
+            _ => panic!("transaction is invalid"),
+        }
+    }
+
+    /// Invoke handlers from a transaction's handler, and check that invoked handlers are restricted
+    /// to their namespaces, that their changes and events are only kept if they succeed, and that
+    /// cycles, invocations nested too deeply and invocations of handlers declaring no namespaces
+    /// fail.
+    #[test]
+    fn static_adapter_invocation() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let mut context_manager = ContextManager::new(Box::new(state));
+
+        let invocations = [
+            ("token", "aa01"),
+            ("token", "ac01"),
+            ("wrapper", "aa02"),
+            ("cyclic", "aa03"),
+            ("t2", "aa04"),
+            ("t1", "aa05"),
+            ("undeclared", "aa06"),
+        ];
+        let mut handlers: Vec<Box<dyn TransactionHandler>> = vec![
+            Box::new(ExchangeHandler {
+                inner: CommandTransactionHandler::new(),
+                invocations: invocations
+                    .iter()
+                    .map(|(family_name, payload)| (family_name.to_string(), payload.as_bytes()))
+                    .collect(),
+            }),
+            Box::new(TokenHandler::new("token", &["aa"], None)),
+            Box::new(TokenHandler::new("wrapper", &["aa"], Some("restricted"))),
+            Box::new(TokenHandler::new("restricted", &["ac"], None)),
+            Box::new(TokenHandler::new("cyclic", &["aa"], Some("command"))),
+            Box::new(TokenHandler::new("undeclared", &[], None)),
+        ];
+        // t1 invokes t2, which invokes t3, and so on up to t9
+        let names = (1..=9).map(|i| format!("t{}", i)).collect::<Vec<_>>();
+        for (i, name) in names.iter().enumerate() {
+            handlers.push(Box::new(TokenHandler::new(
+                name,
+                &["aa"],
+                names.get(i + 1).map(String::as_str),
+            )));
+        }
+
+        let mut static_adapter =
+            StaticExecutionAdapter::new_adapter(handlers, context_manager.clone())
+                .expect("Could not create adapter");
+        assert!(static_adapter
+            .start(Box::new(MockRegistry::default()))
+            .is_ok());
+
+        let txn_pair = make_command_transaction(&[]);
+        let txn_id = txn_pair.transaction().header_signature().to_owned();
+        let context_id = context_manager.create_context(&[], &state_id);
+        let (send, recv) = std::sync::mpsc::channel();
+        assert!(static_adapter
+            .execute(
+                txn_pair,
+                context_id.clone(),
+                Box::new(move |res| {
+                    send.send(res).expect("Unable to send result");
+                }),
+            )
+            .is_ok());
+        assert_eq!(
+            recv.recv().unwrap().unwrap(),
+            ExecutionTaskCompletionNotification::Valid(context_id.clone(), txn_id.clone())
+        );
+        assert!(Box::new(static_adapter).stop().is_ok());
+
+        let addresses = [
+            "aa01", "aa02", "aa03", "aa04", "aa05", "aa06", "ab00", "ab01", "ab02", "ab03", "ab04",
+            "ab05", "ab06", "ac01",
+        ]
+        .iter()
+        .map(|address| address.to_string())
+        .collect::<Vec<_>>();
+        let entry = |address: &str, value: &str| (address.to_string(), value.as_bytes().to_vec());
+        assert_eq!(
+            context_manager.get(&context_id, &addresses).unwrap(),
+            vec![
+                entry("aa01", "token"),
+                entry("aa04", "t9"),
+                entry("ab00", "token"),
+                entry("ab01", "error"),
+                entry("ab02", "error"),
+                entry("ab03", "error"),
+                entry("ab04", "t2"),
+                entry("ab05", "error"),
+                entry("ab06", "error"),
+            ]
+        );
+
+        let receipt = context_manager
+            .get_transaction_receipt(&context_id, &txn_id)
+            .unwrap();
+        let events = match receipt.transaction_result {
+            TransactionResult::Valid { events, .. } => events,
+            _ => panic!("transaction is invalid"),
+        };
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| event.attributes[0].1.as_str())
+                .collect::<Vec<_>>(),
+            vec!["token", "t2", "t3", "t4", "t5", "t6", "t7", "t8", "t9"]
+        );
+    }
+
+    /// A command family handler which invokes each of the families in turn, and writes the bytes
+    /// each returns, or "error", to the addresses "ab00", "ab01" and so on.
+    struct ExchangeHandler {
+        inner: CommandTransactionHandler,
+        invocations: Vec<(String, &'static [u8])>,
+    }
+
+    impl TransactionHandler for ExchangeHandler {
+        fn family_name(&self) -> &str {
+            self.inner.family_name()
+        }
+
+        fn family_versions(&self) -> &[String] {
+            self.inner.family_versions()
+        }
+
+        fn apply(
+            &self,
+            _transaction_pair: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            for (i, (family_name, payload)) in self.invocations.iter().enumerate() {
+                let value = context
+                    .invoke(family_name, "1.0", payload)
+                    .unwrap_or_else(|_| b"error".to_vec());
+                context.set_state_entry(format!("ab{:02}", i), value)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// A handler which, when invoked, writes its family name to the address in the payload, adds
+    /// an event, and then invokes the next family, if it has one.
+    struct TokenHandler {
+        family_name: String,
+        versions: Vec<String>,
+        namespaces: Vec<String>,
+        next: Option<String>,
+    }
+
+    impl TokenHandler {
+        fn new(family_name: &str, namespaces: &[&str], next: Option<&str>) -> Self {
+            TokenHandler {
+                family_name: family_name.into(),
+                versions: vec!["1.0".into()],
+                namespaces: namespaces.iter().map(|prefix| prefix.to_string()).collect(),
+                next: next.map(String::from),
+            }
+        }
+    }
+
+    impl TransactionHandler for TokenHandler {
+        fn family_name(&self) -> &str {
+            &self.family_name
+        }
+
+        fn family_versions(&self) -> &[String] {
+            &self.versions
+        }
+
+        fn namespaces(&self) -> &[String] {
+            &self.namespaces
+        }
+
+        fn apply(
+            &self,
+            _transaction_pair: &TransactionPair,
+            _context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            Ok(())
+        }
+
+        fn invoke(
+            &self,
+            invocation: &Invocation,
+            context: &mut dyn TransactionContext,
+        ) -> Result<Vec<u8>, ApplyError> {
+            let address = String::from_utf8(invocation.payload().to_vec())
+                .map_err(|err| ApplyError::InvalidTransaction(err.to_string()))?;
+            context.set_state_entry(address, self.family_name.as_bytes().to_vec())?;
+            context.add_event(
+                "invoked".into(),
+                vec![("family".into(), self.family_name.clone())],
+                vec![],
+            )?;
+            if let Some(next) = &self.next {
+                context.invoke(next, "1.0", invocation.payload())?;
+            }
+            Ok(self.family_name.as_bytes().to_vec())
+        }
+    }
+
+    /// Query handlers in a read-only context at a state root, through the static adapter.
+    #[test]
+    fn static_adapter_query() {
+        let state = HashMapState::new();
+        let state_id = state
+            .commit(
+                &HashMapState::state_id(&HashMap::new()),
+                &[StateChange::Set {
+                    key: "abc".into(),
+                    value: b"abc".to_vec(),
+                }],
+            )
+            .expect("Unable to commit state");
+
+        let context_manager = ContextManager::new(Box::new(state));
+        let mut static_adapter = StaticExecutionAdapter::new_adapter(
+            vec![Box::new(QueryingHandler {
+                inner: CommandTransactionHandler::new(),
+            })],
+            context_manager.clone(),
+        )
+        .expect("Could not create adapter");
+        assert!(static_adapter
+            .start(Box::new(MockRegistry::default()))
+            .is_ok());
+
+        let query = |family_name: &str, payload: &[u8]| {
+            let (send, recv) = std::sync::mpsc::channel();
+            static_adapter
+                .query(
+                    TransactionFamily::new(family_name.into(), "0.1".into()),
+                    state_id.clone(),
+                    payload.to_vec(),
+                    Box::new(move |res| send.send(res).expect("Unable to send result")),
+                )
+                .expect("Unable to send query");
+            recv.recv().unwrap()
+        };
+
+        assert_eq!(query("command", b"abc").unwrap(), b"abc".to_vec());
+        assert_eq!(query("command", b"def").unwrap(), Vec::<u8>::new());
+        assert!(match query("command", b"write") {
+            Err(QueryError::InvalidQuery(_)) => true,
+            _ => false,
+        });
+        assert_eq!(query("command", b"abc").unwrap(), b"abc".to_vec());
+        assert!(match query("unknown", b"abc") {
+            Err(QueryError::Unsupported(_)) => true,
+            _ => false,
+        });
+
+        assert!(Box::new(static_adapter).stop().is_ok());
+    }
+
+    /// A command family handler answering queries with the value at the address in the payload,
+    /// or, given "write", attempting to change the value at "abc".
+    struct QueryingHandler {
+        inner: CommandTransactionHandler,
+    }
+
+    impl TransactionHandler for QueryingHandler {
+        fn family_name(&self) -> &str {
+            self.inner.family_name()
+        }
+
+        fn family_versions(&self) -> &[String] {
+            self.inner.family_versions()
+        }
+
+        fn apply(
+            &self,
+            transaction_pair: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            self.inner.apply(transaction_pair, context)
+        }
+
+        fn query(
+            &self,
+            payload: &[u8],
+            context: &dyn TransactionContext,
+        ) -> Result<Vec<u8>, QueryError> {
+            if payload == b"write" {
+                context.set_state_entry("abc".into(), b"changed".to_vec())?;
+                return Ok(vec![]);
+            }
+            let address = String::from_utf8(payload.to_vec())
+                .map_err(|err| QueryError::InvalidQuery(err.to_string()))?;
+            Ok(context.get_state_entry(&address)?.unwrap_or_default())
+        }
+    }
+
+    /// A non-deterministic command family handler, which writes the number of transactions it
+    /// has applied.
+    struct CountingHandler {
+        inner: CommandTransactionHandler,
+        count: AtomicUsize,
+    }
+
+    impl Default for CountingHandler {
+        fn default() -> Self {
+            CountingHandler {
+                inner: CommandTransactionHandler::new(),
+                count: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl TransactionHandler for CountingHandler {
+        fn family_name(&self) -> &str {
+            self.inner.family_name()
+        }
+
+        fn family_versions(&self) -> &[String] {
+            self.inner.family_versions()
+        }
+
+        fn apply(
+            &self,
+            _transaction_pair: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            let count = self.count.fetch_add(1, Ordering::SeqCst);
+            context.set_state_entry("count".into(), vec![count as u8])?;
+            Ok(())
+        }
+    }
+
+    /// A command family handler supporting the given versions.
+    struct VersionedHandler {
+        inner: CommandTransactionHandler,
+        versions: Vec<String>,
+    }
+
+    impl VersionedHandler {
+        fn new(versions: &[&str]) -> Self {
+            VersionedHandler {
+                inner: CommandTransactionHandler::new(),
+                versions: versions.iter().map(|version| version.to_string()).collect(),
+            }
+        }
+    }
+
+    impl TransactionHandler for VersionedHandler {
+        fn family_name(&self) -> &str {
+            self.inner.family_name()
+        }
+
+        fn family_versions(&self) -> &[String] {
+            &self.versions
+        }
+
+        fn apply(
+            &self,
+            transaction_pair: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            self.inner.apply(transaction_pair, context)
+        }
+    }
+
+    /// Records the versions registered and unregistered, in order.
+    #[derive(Clone, Default)]
+    struct RecordingRegistry {
+        changes: Arc<Mutex<Vec<(bool, String)>>>,
+    }
+
+    impl RecordingRegistry {
+        fn take(&self) -> Vec<(bool, String)> {
+            std::mem::replace(&mut *self.changes.lock().unwrap(), vec![])
+        }
+    }
+
+    impl ExecutionRegistry for RecordingRegistry {
+        fn register_transaction_family(&mut self, family: TransactionFamily) {
+            self.changes
+                .lock()
+                .unwrap()
+                .push((true, family.family_version().to_string()));
+        }
+
+        fn unregister_transaction_family(&mut self, family: &TransactionFamily) {
+            self.changes
+                .lock()
+                .unwrap()
+                .push((false, family.family_version().to_string()));
+        }
+    }
+
     #[derive(Clone, Default)]
     struct MockRegistry {
         registered: Arc<AtomicBool>,
@@ -0,0 +1,210 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use pwasm_utils::rules;
+use wasmi::{
+    Error, ImportsBuilder, MemoryRef, Module, ModuleInstance, NotStartedModuleRef, RuntimeValue,
+    Trap, TrapKind,
+};
+
+use crate::handler::{ApplyError, TransactionContext, TransactionHandler};
+use crate::protocol::transaction::TransactionPair;
+
+use super::externals::{HostExternals, HostResolver, HostTrap};
+use super::WasmError;
+
+/// The fuel consumed by each page of memory a contract grows.
+const MEMORY_PAGE_FUEL: u32 = 10_000;
+
+/// A WebAssembly contract which processes the transactions of a family version.
+///
+/// As a `TransactionHandler`, a contract may also be executed by the `StaticExecutionAdapter`.
+pub struct WasmContract {
+    family_name: String,
+    family_versions: Vec<String>,
+    module: Module,
+    fuel_limit: u64,
+}
+
+impl WasmContract {
+    /// Loads a contract from the bytes of a WebAssembly module.
+    ///
+    /// The module is instrumented to consume fuel as it executes; each transaction may consume at
+    /// most `fuel_limit`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `WasmError` if the module is invalid, uses floating point instructions, imports
+    /// anything other than the host functions, or does not export the contract interface.
+    pub fn new(
+        family_name: String,
+        family_version: String,
+        wasm: &[u8],
+        fuel_limit: u64,
+    ) -> Result<Self, WasmError> {
+        let module: parity_wasm::elements::Module = parity_wasm::deserialize_buffer(wasm)
+            .map_err(|err| WasmError::InvalidModule(err.to_string()))?;
+        let module = pwasm_utils::inject_gas_counter(
+            module,
+            &rules::Set::default()
+                .with_grow_cost(MEMORY_PAGE_FUEL)
+                .with_forbidden_floats(),
+        )
+        .map_err(|_| {
+            WasmError::InvalidModule(
+                "module uses floating point instructions or cannot be metered".into(),
+            )
+        })?;
+        let module = Module::from_parity_wasm_module(module)
+            .map_err(|err| WasmError::InvalidModule(err.to_string()))?;
+
+        let contract = WasmContract {
+            family_name,
+            family_versions: vec![family_version],
+            module,
+            fuel_limit,
+        };
+
+        // Check the imports and exports without running the module
+        let (instance, _) = contract.instantiate()?;
+        for name in &["alloc", "apply"] {
+            if instance
+                .not_started_instance()
+                .export_by_name(name)
+                .and_then(|export| export.as_func().cloned())
+                .is_none()
+            {
+                return Err(WasmError::MissingExport((*name).into()));
+            }
+        }
+
+        Ok(contract)
+    }
+
+    pub fn fuel_limit(&self) -> u64 {
+        self.fuel_limit
+    }
+
+    fn instantiate(&self) -> Result<(NotStartedModuleRef, MemoryRef), WasmError> {
+        let instance = ModuleInstance::new(
+            &self.module,
+            &ImportsBuilder::new().with_resolver("env", &HostResolver),
+        )
+        .map_err(|err| WasmError::InvalidModule(err.to_string()))?;
+        let memory = instance
+            .not_started_instance()
+            .export_by_name("memory")
+            .and_then(|export| export.as_memory().cloned())
+            .ok_or_else(|| WasmError::MissingExport("memory".into()))?;
+        Ok((instance, memory))
+    }
+
+    /// Converts a trap into the result of the transaction. Traps caused by the contract, including
+    /// running out of fuel, are deterministic and make the transaction invalid.
+    fn trap_to_apply_error(&self, trap: &Trap) -> ApplyError {
+        match trap.kind() {
+            TrapKind::Host(host_error) => match host_error.downcast_ref::<HostTrap>() {
+                Some(HostTrap::OutOfFuel) => ApplyError::InvalidTransaction(format!(
+                    "contract exceeded its fuel limit of {}",
+                    self.fuel_limit
+                )),
+                Some(HostTrap::Context(msg)) => ApplyError::InternalError(msg.clone()),
+                Some(host_trap) => {
+                    ApplyError::InvalidTransaction(format!("contract trapped: {}", host_trap))
+                }
+                None => ApplyError::InternalError(format!("unknown host error: {}", host_error)),
+            },
+            kind => ApplyError::InvalidTransaction(format!("contract trapped: {:?}", kind)),
+        }
+    }
+
+    fn error_to_apply_error(&self, err: Error) -> ApplyError {
+        match err {
+            Error::Trap(trap) => self.trap_to_apply_error(&trap),
+            err => ApplyError::InternalError(format!("unable to invoke contract: {}", err)),
+        }
+    }
+}
+
+impl TransactionHandler for WasmContract {
+    fn family_name(&self) -> &str {
+        &self.family_name
+    }
+
+    fn family_versions(&self) -> &[String] {
+        &self.family_versions
+    }
+
+    fn apply(
+        &self,
+        transaction: &TransactionPair,
+        context: &mut dyn TransactionContext,
+    ) -> Result<(), ApplyError> {
+        let (instance, memory) = self
+            .instantiate()
+            .map_err(|err| ApplyError::InternalError(err.to_string()))?;
+        let mut externals = HostExternals::new(&*context, memory.clone(), self.fuel_limit);
+
+        let instance = instance
+            .run_start(&mut externals)
+            .map_err(|trap| self.trap_to_apply_error(&trap))?;
+
+        let payload = transaction.transaction().payload();
+        let payload_ptr = match instance
+            .invoke_export(
+                "alloc",
+                &[RuntimeValue::I32(payload.len() as i32)],
+                &mut externals,
+            )
+            .map_err(|err| self.error_to_apply_error(err))?
+        {
+            Some(RuntimeValue::I32(ptr)) => ptr,
+            _ => {
+                return Err(ApplyError::InvalidTransaction(
+                    "contract's alloc function did not return a pointer".into(),
+                ))
+            }
+        };
+        memory.set(payload_ptr as u32, payload).map_err(|_| {
+            ApplyError::InvalidTransaction(
+                "contract's alloc function returned an invalid pointer".into(),
+            )
+        })?;
+
+        match instance
+            .invoke_export(
+                "apply",
+                &[
+                    RuntimeValue::I32(payload_ptr),
+                    RuntimeValue::I32(payload.len() as i32),
+                ],
+                &mut externals,
+            )
+            .map_err(|err| self.error_to_apply_error(err))?
+        {
+            Some(RuntimeValue::I32(0)) => Ok(()),
+            Some(RuntimeValue::I32(code)) => Err(ApplyError::InvalidTransaction(
+                externals
+                    .take_error_message()
+                    .unwrap_or_else(|| format!("contract returned {}", code)),
+            )),
+            _ => Err(ApplyError::InvalidTransaction(
+                "contract's apply function did not return a status".into(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,290 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! The host functions available to contracts.
+
+use std::convert::TryFrom;
+
+use wasmi::{
+    Error, Externals, FuncInstance, FuncRef, HostError, MemoryRef, ModuleImportResolver,
+    RuntimeArgs, RuntimeValue, Signature, Trap, TrapKind, ValueType,
+};
+
+use crate::handler::{ContextError, TransactionContext};
+
+/// The fuel consumed by each host function call, in addition to one unit per byte passed to or
+/// from the host.
+const HOST_CALL_FUEL: u64 = 100;
+
+const GAS: usize = 0;
+const GET_STATE: usize = 1;
+const SET_STATE: usize = 2;
+const DELETE_STATE: usize = 3;
+const ADD_EVENT: usize = 4;
+const ADD_RECEIPT_DATA: usize = 5;
+const SET_ERROR_MESSAGE: usize = 6;
+
+/// Resolves the imports of the `env` module.
+pub(super) struct HostResolver;
+
+impl ModuleImportResolver for HostResolver {
+    fn resolve_func(&self, field_name: &str, signature: &Signature) -> Result<FuncRef, Error> {
+        let (index, params, result): (usize, &[ValueType], Option<ValueType>) = match field_name {
+            // Imported by the fuel metering instrumentation
+            "gas" => (GAS, &[ValueType::I32][..], None),
+            "get_state" => (GET_STATE, &[ValueType::I32; 4][..], Some(ValueType::I32)),
+            "set_state" => (SET_STATE, &[ValueType::I32; 4][..], Some(ValueType::I32)),
+            "delete_state" => (DELETE_STATE, &[ValueType::I32; 2][..], Some(ValueType::I32)),
+            "add_event" => (ADD_EVENT, &[ValueType::I32; 6][..], Some(ValueType::I32)),
+            "add_receipt_data" => (
+                ADD_RECEIPT_DATA,
+                &[ValueType::I32; 2][..],
+                Some(ValueType::I32),
+            ),
+            "set_error_message" => (SET_ERROR_MESSAGE, &[ValueType::I32; 2][..], None),
+            _ => {
+                return Err(Error::Instantiation(format!(
+                    "unknown host function '{}'",
+                    field_name
+                )))
+            }
+        };
+
+        let expected = Signature::new(params, result);
+        if signature != &expected {
+            return Err(Error::Instantiation(format!(
+                "host function '{}' has signature {:?}, not {:?}",
+                field_name, expected, signature
+            )));
+        }
+
+        Ok(FuncInstance::alloc_host(expected, index))
+    }
+}
+
+/// The reasons the host stops a contract.
+#[derive(Debug)]
+pub(super) enum HostTrap {
+    /// The contract consumed all of its fuel
+    OutOfFuel,
+    /// The contract passed a string which is not valid UTF-8
+    InvalidUtf8,
+    /// The contract passed malformed event attributes
+    InvalidAttributes,
+    /// The contract accessed an address it is not allowed to
+    Unauthorized(String),
+    /// The transaction context returned an error
+    Context(String),
+}
+
+impl HostError for HostTrap {}
+
+impl std::fmt::Display for HostTrap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HostTrap::OutOfFuel => f.write_str("out of fuel"),
+            HostTrap::InvalidUtf8 => f.write_str("string is not valid UTF-8"),
+            HostTrap::InvalidAttributes => f.write_str("malformed event attributes"),
+            HostTrap::Unauthorized(msg) => write!(f, "unauthorized: {}", msg),
+            HostTrap::Context(msg) => write!(f, "context error: {}", msg),
+        }
+    }
+}
+
+impl From<ContextError> for HostTrap {
+    fn from(err: ContextError) -> Self {
+        match err {
+            ContextError::AuthorizationError(msg) => HostTrap::Unauthorized(msg),
+            err => HostTrap::Context(err.to_string()),
+        }
+    }
+}
+
+/// The state of a single contract invocation.
+pub(super) struct HostExternals<'a> {
+    context: &'a dyn TransactionContext,
+    memory: MemoryRef,
+    remaining_fuel: u64,
+    error_message: Option<String>,
+}
+
+impl<'a> HostExternals<'a> {
+    pub fn new(context: &'a dyn TransactionContext, memory: MemoryRef, fuel_limit: u64) -> Self {
+        HostExternals {
+            context,
+            memory,
+            remaining_fuel: fuel_limit,
+            error_message: None,
+        }
+    }
+
+    /// Takes the message set by the contract with `set_error_message`, if any.
+    pub fn take_error_message(&mut self) -> Option<String> {
+        self.error_message.take()
+    }
+
+    fn consume_fuel(&mut self, amount: u64) -> Result<(), Trap> {
+        if amount > self.remaining_fuel {
+            self.remaining_fuel = 0;
+            return Err(Trap::new(TrapKind::Host(Box::new(HostTrap::OutOfFuel))));
+        }
+        self.remaining_fuel -= amount;
+        Ok(())
+    }
+
+    /// Reads `len` bytes from the contract's memory, consuming fuel for each.
+    fn read(&mut self, ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+        let len = usize::try_from(len).map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+        self.consume_fuel(len as u64)?;
+        self.memory
+            .get(ptr as u32, len)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+    }
+
+    fn read_string(&mut self, ptr: i32, len: i32) -> Result<String, Trap> {
+        String::from_utf8(self.read(ptr, len)?)
+            .map_err(|_| Trap::new(TrapKind::Host(Box::new(HostTrap::InvalidUtf8))))
+    }
+
+    /// Writes bytes to the contract's memory, consuming fuel for each.
+    fn write(&mut self, ptr: i32, bytes: &[u8]) -> Result<(), Trap> {
+        self.consume_fuel(bytes.len() as u64)?;
+        self.memory
+            .set(ptr as u32, bytes)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+    }
+
+    /// Decodes a sequence of length-prefixed strings into key-value pairs.
+    fn read_attributes(&mut self, ptr: i32, len: i32) -> Result<Vec<(String, String)>, Trap> {
+        let bytes = self.read(ptr, len)?;
+        let mut strings = vec![];
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            if rest.len() < 4 {
+                return Err(Trap::new(TrapKind::Host(Box::new(
+                    HostTrap::InvalidAttributes,
+                ))));
+            }
+            let mut len_bytes = [0; 4];
+            len_bytes.copy_from_slice(&rest[..4]);
+            let len = u32::from_le_bytes(len_bytes) as usize;
+            if rest.len() - 4 < len {
+                return Err(Trap::new(TrapKind::Host(Box::new(
+                    HostTrap::InvalidAttributes,
+                ))));
+            }
+            strings.push(
+                String::from_utf8(rest[4..4 + len].to_vec())
+                    .map_err(|_| Trap::new(TrapKind::Host(Box::new(HostTrap::InvalidUtf8))))?,
+            );
+            rest = &rest[4 + len..];
+        }
+
+        if strings.len() % 2 != 0 {
+            return Err(Trap::new(TrapKind::Host(Box::new(
+                HostTrap::InvalidAttributes,
+            ))));
+        }
+        let mut attributes = vec![];
+        let mut strings = strings.into_iter();
+        while let (Some(key), Some(value)) = (strings.next(), strings.next()) {
+            attributes.push((key, value));
+        }
+        Ok(attributes)
+    }
+}
+
+fn context_trap(err: ContextError) -> Trap {
+    Trap::new(TrapKind::Host(Box::new(HostTrap::from(err))))
+}
+
+impl<'a> Externals for HostExternals<'a> {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        if index == GAS {
+            let amount: u32 = args.nth_checked(0)?;
+            self.consume_fuel(u64::from(amount))?;
+            return Ok(None);
+        }
+        self.consume_fuel(HOST_CALL_FUEL)?;
+
+        match index {
+            GET_STATE => {
+                let address = self.read_string(args.nth_checked(0)?, args.nth_checked(1)?)?;
+                let dst_ptr: i32 = args.nth_checked(2)?;
+                let dst_len: i32 = args.nth_checked(3)?;
+                match self
+                    .context
+                    .get_state_entry(&address)
+                    .map_err(context_trap)?
+                {
+                    Some(value) => {
+                        if value.len() <= dst_len.max(0) as usize {
+                            self.write(dst_ptr, &value)?;
+                        }
+                        Ok(Some(RuntimeValue::I32(value.len() as i32)))
+                    }
+                    None => Ok(Some(RuntimeValue::I32(-1))),
+                }
+            }
+            SET_STATE => {
+                let address = self.read_string(args.nth_checked(0)?, args.nth_checked(1)?)?;
+                let value = self.read(args.nth_checked(2)?, args.nth_checked(3)?)?;
+                self.context
+                    .set_state_entry(address, value)
+                    .map_err(context_trap)?;
+                Ok(Some(RuntimeValue::I32(0)))
+            }
+            DELETE_STATE => {
+                let address = self.read_string(args.nth_checked(0)?, args.nth_checked(1)?)?;
+                let deleted = self
+                    .context
+                    .delete_state_entry(&address)
+                    .map_err(context_trap)?;
+                Ok(Some(RuntimeValue::I32(if deleted.is_some() {
+                    1
+                } else {
+                    0
+                })))
+            }
+            ADD_EVENT => {
+                let event_type = self.read_string(args.nth_checked(0)?, args.nth_checked(1)?)?;
+                let attributes =
+                    self.read_attributes(args.nth_checked(2)?, args.nth_checked(3)?)?;
+                let data = self.read(args.nth_checked(4)?, args.nth_checked(5)?)?;
+                self.context
+                    .add_event(event_type, attributes, data)
+                    .map_err(context_trap)?;
+                Ok(Some(RuntimeValue::I32(0)))
+            }
+            ADD_RECEIPT_DATA => {
+                let data = self.read(args.nth_checked(0)?, args.nth_checked(1)?)?;
+                self.context.add_receipt_data(data).map_err(context_trap)?;
+                Ok(Some(RuntimeValue::I32(0)))
+            }
+            SET_ERROR_MESSAGE => {
+                self.error_message =
+                    Some(self.read_string(args.nth_checked(0)?, args.nth_checked(1)?)?);
+                Ok(None)
+            }
+            _ => Err(Trap::new(TrapKind::UnexpectedSignature)),
+        }
+    }
+}
@@ -0,0 +1,250 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Execution of WebAssembly smart contracts.
+//!
+//! A `WasmContract` is a WebAssembly module which processes the transactions of a single family
+//! version. Contracts are run by a pure-Rust interpreter, in a fresh instance for each
+//! transaction, and can only affect the world through the host functions below. The
+//! `WasmExecutionAdapter` executes transactions using a fixed set of contracts, while the
+//! `WasmRegistryExecutionAdapter` executes the contracts stored in state by the contract registry
+//! family.
+//!
+//! # Contract interface
+//!
+//! A contract must export:
+//!
+//! * `memory`, its linear memory
+//! * `alloc(len: i32) -> i32`, which returns a pointer to `len` bytes the host may write to
+//! * `apply(payload_ptr: i32, payload_len: i32) -> i32`, which processes the transaction whose
+//!   payload has been written at `payload_ptr`; a return value of `0` indicates the transaction is
+//!   valid, and any other value that it is invalid
+//!
+//! A contract may import the following functions from the `env` module. Strings are passed as
+//! pointer and length pairs of UTF-8 bytes.
+//!
+//! * `get_state(addr_ptr, addr_len, dst_ptr, dst_len) -> i32` returns the length of the value at
+//!   the address, or `-1` if it is not set; the value is copied to `dst_ptr` only if it fits in
+//!   `dst_len` bytes
+//! * `set_state(addr_ptr, addr_len, value_ptr, value_len) -> i32` sets the value at the address,
+//!   returning `0`
+//! * `delete_state(addr_ptr, addr_len) -> i32` returns `1` if the address was deleted, or `0` if it
+//!   was not set
+//! * `add_event(type_ptr, type_len, attrs_ptr, attrs_len, data_ptr, data_len) -> i32` adds an
+//!   event, returning `0`; its attributes are a sequence of alternating keys and values, each
+//!   preceded by its length as a 4-byte little-endian integer
+//! * `add_receipt_data(data_ptr, data_len) -> i32` adds data to the receipt, returning `0`
+//! * `set_error_message(msg_ptr, msg_len)` sets the message reported if the transaction is invalid
+//!
+//! # Fuel
+//!
+//! Each contract has a fuel limit. Executing instructions, growing memory and calling host
+//! functions consume fuel; a transaction which exhausts its fuel is invalid. As fuel is counted
+//! from the module's instructions, every node running a contract will stop it at the same point.
+//! For the same reason, contracts may not use floating point instructions.
+//!
+//! Note, to use this module, the Transact library must have the `"wasm-adapter"` feature enabled.
+
+mod adapter;
+mod contract;
+mod externals;
+pub mod registry;
+mod registry_adapter;
+
+use std::error::Error;
+
+pub use self::adapter::WasmExecutionAdapter;
+pub use self::contract::WasmContract;
+pub use self::registry_adapter::WasmRegistryExecutionAdapter;
+
+#[derive(Debug)]
+pub enum WasmError {
+    /// Returned when a module cannot be parsed, metered or instantiated
+    InvalidModule(String),
+    /// Returned when a module does not provide the contract interface
+    MissingExport(String),
+}
+
+impl Error for WasmError {}
+
+impl std::fmt::Display for WasmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WasmError::InvalidModule(msg) => write!(f, "invalid WebAssembly module: {}", msg),
+            WasmError::MissingExport(name) => {
+                write!(f, "contract does not export required item '{}'", name)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use crate::context::manager::sync::ContextManager;
+    use crate::context::{ContextId, ContextLifecycle};
+    use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError};
+    use crate::protocol::receipt::TransactionResult;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
+    use crate::signing::hash::HashSigner;
+    use crate::state::hashmap::HashMapState;
+
+    /// Stores its payload at address "ab01" and in the receipt. A payload beginning with "x" is
+    /// rejected, and an empty payload loops forever.
+    pub const TEST_CONTRACT: &str = r#"
+        (module
+          (import "env" "set_state" (func $set_state (param i32 i32 i32 i32) (result i32)))
+          (import "env" "add_receipt_data" (func $add_receipt_data (param i32 i32) (result i32)))
+          (import "env" "set_error_message" (func $set_error_message (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "ab01")
+          (data (i32.const 16) "rejected")
+          (global $heap (mut i32) (i32.const 1024))
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $heap))
+            (global.set $heap (i32.add (global.get $heap) (local.get $len)))
+            (local.get $ptr))
+          (func (export "apply") (param $ptr i32) (param $len i32) (result i32)
+            (if (i32.eqz (local.get $len))
+              (then (loop $forever (br $forever))))
+            (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 120))
+              (then
+                (call $set_error_message (i32.const 16) (i32.const 8))
+                (return (i32.const 1))))
+            (drop (call $set_state (i32.const 0) (i32.const 4) (local.get $ptr) (local.get $len)))
+            (drop (call $add_receipt_data (local.get $ptr) (local.get $len)))
+            (i32.const 0)))
+    "#;
+
+    const FUEL_LIMIT: u64 = 100_000;
+
+    fn execute(
+        adapter: &WasmExecutionAdapter,
+        context_manager: &mut ContextManager,
+        payload: &[u8],
+    ) -> (
+        ContextId,
+        String,
+        Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>,
+    ) {
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let txn_pair = TransactionBuilder::new()
+            .with_family_name("wasm_test".into())
+            .with_family_version("1.0".into())
+            .with_inputs(vec![vec![0xab, 0x01]])
+            .with_outputs(vec![vec![0xab, 0x01]])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(payload.to_vec())
+            .build_pair(&HashSigner::default())
+            .expect("Unable to build transaction");
+        let txn_id = txn_pair.transaction().header_signature().to_string();
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let (result_tx, result_rx) = channel();
+        adapter
+            .execute(
+                txn_pair,
+                context_id,
+                Box::new(move |res| result_tx.send(res).expect("Unable to send result")),
+            )
+            .expect("Unable to execute transaction");
+        let result = result_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Did not receive result");
+
+        (context_id, txn_id, result)
+    }
+
+    /// Tests that a contract can set state and receipt data, reject a transaction, and is stopped
+    /// when it exhausts its fuel.
+    #[test]
+    fn test_wasm_adapter() {
+        let wasm = wat::parse_str(TEST_CONTRACT).expect("Unable to compile test contract");
+        let contract = WasmContract::new("wasm_test".into(), "1.0".into(), &wasm, FUEL_LIMIT)
+            .expect("Unable to load contract");
+
+        let mut context_manager = ContextManager::new(Box::new(HashMapState::new()));
+        let adapter = WasmExecutionAdapter::new(vec![contract], context_manager.clone())
+            .expect("Unable to create adapter");
+
+        let (context_id, txn_id, result) = execute(&adapter, &mut context_manager, b"hello");
+        assert_eq!(
+            result.expect("Execution failed"),
+            ExecutionTaskCompletionNotification::Valid(context_id, txn_id.clone())
+        );
+        assert_eq!(
+            context_manager
+                .get(&context_id, &["ab01".into()])
+                .expect("Unable to get state"),
+            vec![("ab01".to_string(), b"hello".to_vec())]
+        );
+        match context_manager
+            .get_transaction_receipt(&context_id, &txn_id)
+            .expect("Unable to get receipt")
+            .transaction_result
+        {
+            TransactionResult::Valid { data, .. } => assert_eq!(data, vec![b"hello".to_vec()]),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let (context_id, txn_id, result) = execute(&adapter, &mut context_manager, b"xyz");
+        assert_eq!(
+            result.expect("Execution failed"),
+            ExecutionTaskCompletionNotification::Invalid(
+                context_id,
+                InvalidTransactionResult {
+                    transaction_id: txn_id,
+                    error_message: "rejected".into(),
+                    error_data: vec![],
+                }
+            )
+        );
+
+        let (context_id, txn_id, result) = execute(&adapter, &mut context_manager, b"");
+        assert_eq!(
+            result.expect("Execution failed"),
+            ExecutionTaskCompletionNotification::Invalid(
+                context_id,
+                InvalidTransactionResult {
+                    transaction_id: txn_id,
+                    error_message: format!("contract exceeded its fuel limit of {}", FUEL_LIMIT),
+                    error_data: vec![],
+                }
+            )
+        );
+
+        Box::new(adapter).stop().expect("Unable to stop adapter");
+    }
+
+    /// Tests that modules which do not provide the contract interface are rejected.
+    #[test]
+    fn test_wasm_contract_missing_export() {
+        let wasm = wat::parse_str(r#"(module (memory (export "memory") 1))"#)
+            .expect("Unable to compile module");
+        match WasmContract::new("wasm_test".into(), "1.0".into(), &wasm, FUEL_LIMIT) {
+            Err(WasmError::MissingExport(name)) => assert_eq!(name, "alloc"),
+            res => panic!("Unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! The contract registry transaction family, which stores contracts in state.
+
+use protobuf::{Message, RepeatedField};
+use sha2::{Digest, Sha512};
+
+use crate::handler::{ApplyError, TransactionContext, TransactionHandler};
+use crate::protocol::transaction::TransactionPair;
+use crate::protos::contract_registry::{
+    Contract, ContractRegistry, ContractRegistryPayload, ContractRegistryPayload_Action,
+    ContractRegistry_Version, CreateContractAction, DeleteContractAction, UpdateOwnersAction,
+};
+
+use super::WasmContract;
+
+pub const CONTRACT_REGISTRY_FAMILY_NAME: &str = "contract_registry";
+pub const CONTRACT_REGISTRY_FAMILY_VERSION: &str = "1.0";
+
+/// The address prefix of contract registry records.
+pub const CONTRACT_REGISTRY_PREFIX: &str = "00ec01";
+/// The address prefix of contracts.
+pub const CONTRACT_PREFIX: &str = "00ec02";
+
+/// Returns the address of the registry record of the named contract.
+pub fn compute_contract_registry_address(name: &str) -> String {
+    let hash = Sha512::digest(name.as_bytes());
+    format!("{}{}", CONTRACT_REGISTRY_PREFIX, &hex::encode(hash)[..64])
+}
+
+/// Returns the address of a version of the named contract.
+pub fn compute_contract_address(name: &str, version: &str) -> String {
+    let hash = Sha512::digest(format!("{},{}", name, version).as_bytes());
+    format!("{}{}", CONTRACT_PREFIX, &hex::encode(hash)[..64])
+}
+
+/// Handles the transactions of the contract registry family.
+///
+/// Each contract is identified by its name, and may have any number of versions. A version's
+/// bytecode must be a valid contract, and is stored with the address prefixes the contract may
+/// read and write.
+pub struct ContractRegistryHandler {
+    family_name: String,
+    family_versions: Vec<String>,
+}
+
+impl ContractRegistryHandler {
+    pub fn new() -> Self {
+        ContractRegistryHandler {
+            family_name: CONTRACT_REGISTRY_FAMILY_NAME.into(),
+            family_versions: vec![CONTRACT_REGISTRY_FAMILY_VERSION.into()],
+        }
+    }
+
+    fn create_contract(
+        &self,
+        action: &CreateContractAction,
+        signer: &[u8],
+        context: &mut dyn TransactionContext,
+    ) -> Result<(), ApplyError> {
+        if action.get_name().is_empty() || action.get_version().is_empty() {
+            return Err(ApplyError::InvalidTransaction(
+                "contract name and version are required".into(),
+            ));
+        }
+        if action.get_name() == CONTRACT_REGISTRY_FAMILY_NAME {
+            return Err(ApplyError::InvalidTransaction(format!(
+                "contract name {} is reserved",
+                CONTRACT_REGISTRY_FAMILY_NAME
+            )));
+        }
+        WasmContract::new(
+            action.get_name().into(),
+            action.get_version().into(),
+            action.get_contract(),
+            0,
+        )
+        .map_err(|err| ApplyError::InvalidTransaction(err.to_string()))?;
+
+        let mut registry = match get_registry(context, action.get_name())? {
+            Some(registry) => {
+                check_owner(&registry, signer)?;
+                registry
+            }
+            None => {
+                let mut registry = ContractRegistry::new();
+                registry.set_name(action.get_name().into());
+                let mut owners = vec![signer.to_vec()];
+                for owner in action.get_owners() {
+                    if !owners.contains(owner) {
+                        owners.push(owner.clone());
+                    }
+                }
+                registry.set_owners(RepeatedField::from_vec(owners));
+                registry
+            }
+        };
+
+        if registry
+            .get_versions()
+            .iter()
+            .any(|version| version.get_version() == action.get_version())
+        {
+            return Err(ApplyError::InvalidTransaction(format!(
+                "contract {} {} already exists",
+                action.get_name(),
+                action.get_version()
+            )));
+        }
+
+        let mut version = ContractRegistry_Version::new();
+        version.set_version(action.get_version().into());
+        version.set_contract_sha512(Sha512::digest(action.get_contract()).to_vec());
+        version.set_creator(signer.to_vec());
+        registry.mut_versions().push(version);
+
+        let mut contract = Contract::new();
+        contract.set_name(action.get_name().into());
+        contract.set_version(action.get_version().into());
+        contract.set_inputs(RepeatedField::from_slice(action.get_inputs()));
+        contract.set_outputs(RepeatedField::from_slice(action.get_outputs()));
+        contract.set_creator(signer.to_vec());
+        contract.set_contract(action.get_contract().to_vec());
+
+        context.set_state_entries(vec![
+            (
+                compute_contract_registry_address(action.get_name()),
+                serialize(&registry)?,
+            ),
+            (
+                compute_contract_address(action.get_name(), action.get_version()),
+                serialize(&contract)?,
+            ),
+        ])?;
+        Ok(())
+    }
+
+    fn delete_contract(
+        &self,
+        action: &DeleteContractAction,
+        signer: &[u8],
+        context: &mut dyn TransactionContext,
+    ) -> Result<(), ApplyError> {
+        let mut registry = get_registry(context, action.get_name())?.ok_or_else(|| {
+            ApplyError::InvalidTransaction(format!("contract {} does not exist", action.get_name()))
+        })?;
+        check_owner(&registry, signer)?;
+
+        let index = registry
+            .get_versions()
+            .iter()
+            .position(|version| version.get_version() == action.get_version())
+            .ok_or_else(|| {
+                ApplyError::InvalidTransaction(format!(
+                    "contract {} {} does not exist",
+                    action.get_name(),
+                    action.get_version()
+                ))
+            })?;
+        registry.mut_versions().remove(index);
+
+        context.set_state_entry(
+            compute_contract_registry_address(action.get_name()),
+            serialize(&registry)?,
+        )?;
+        context.delete_state_entry(&compute_contract_address(
+            action.get_name(),
+            action.get_version(),
+        ))?;
+        Ok(())
+    }
+
+    fn update_owners(
+        &self,
+        action: &UpdateOwnersAction,
+        signer: &[u8],
+        context: &mut dyn TransactionContext,
+    ) -> Result<(), ApplyError> {
+        let mut registry = get_registry(context, action.get_name())?.ok_or_else(|| {
+            ApplyError::InvalidTransaction(format!("contract {} does not exist", action.get_name()))
+        })?;
+        check_owner(&registry, signer)?;
+
+        if action.get_owners().is_empty() {
+            return Err(ApplyError::InvalidTransaction(
+                "a contract must have at least one owner".into(),
+            ));
+        }
+        registry.set_owners(RepeatedField::from_slice(action.get_owners()));
+
+        context.set_state_entry(
+            compute_contract_registry_address(action.get_name()),
+            serialize(&registry)?,
+        )?;
+        Ok(())
+    }
+}
+
+impl Default for ContractRegistryHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransactionHandler for ContractRegistryHandler {
+    fn family_name(&self) -> &str {
+        &self.family_name
+    }
+
+    fn family_versions(&self) -> &[String] {
+        &self.family_versions
+    }
+
+    fn apply(
+        &self,
+        transaction: &TransactionPair,
+        context: &mut dyn TransactionContext,
+    ) -> Result<(), ApplyError> {
+        let payload: ContractRegistryPayload =
+            protobuf::parse_from_bytes(transaction.transaction().payload()).map_err(|err| {
+                ApplyError::InvalidTransaction(format!("unable to parse payload: {}", err))
+            })?;
+        let signer = transaction.header().signer_public_key();
+
+        match payload.get_action() {
+            ContractRegistryPayload_Action::CREATE_CONTRACT => {
+                self.create_contract(payload.get_create_contract(), signer, context)
+            }
+            ContractRegistryPayload_Action::DELETE_CONTRACT => {
+                self.delete_contract(payload.get_delete_contract(), signer, context)
+            }
+            ContractRegistryPayload_Action::UPDATE_OWNERS => {
+                self.update_owners(payload.get_update_owners(), signer, context)
+            }
+            ContractRegistryPayload_Action::ACTION_UNSET => Err(ApplyError::InvalidTransaction(
+                "payload action is not set".into(),
+            )),
+        }
+    }
+}
+
+/// Reads a version of a contract from state, if it exists.
+pub(super) fn get_contract(
+    context: &dyn TransactionContext,
+    name: &str,
+    version: &str,
+) -> Result<Option<Contract>, ApplyError> {
+    context
+        .get_state_entry(&compute_contract_address(name, version))?
+        .map(|bytes| {
+            protobuf::parse_from_bytes(&bytes).map_err(|err| {
+                ApplyError::InternalError(format!("unable to parse contract: {}", err))
+            })
+        })
+        .transpose()
+}
+
+fn get_registry(
+    context: &dyn TransactionContext,
+    name: &str,
+) -> Result<Option<ContractRegistry>, ApplyError> {
+    context
+        .get_state_entry(&compute_contract_registry_address(name))?
+        .map(|bytes| {
+            protobuf::parse_from_bytes(&bytes).map_err(|err| {
+                ApplyError::InternalError(format!("unable to parse contract registry: {}", err))
+            })
+        })
+        .transpose()
+}
+
+fn check_owner(registry: &ContractRegistry, signer: &[u8]) -> Result<(), ApplyError> {
+    if registry.get_owners().iter().any(|owner| owner == signer) {
+        Ok(())
+    } else {
+        Err(ApplyError::InvalidTransaction(format!(
+            "signer is not an owner of contract {}",
+            registry.get_name()
+        )))
+    }
+}
+
+fn serialize(message: &dyn Message) -> Result<Vec<u8>, ApplyError> {
+    message
+        .write_to_bytes()
+        .map_err(|err| ApplyError::InternalError(format!("unable to serialize: {}", err)))
+}
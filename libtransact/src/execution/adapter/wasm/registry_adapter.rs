@@ -0,0 +1,555 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use sha2::{Digest, Sha512};
+
+use crate::context::manager::sync::ContextManager;
+use crate::context::ContextId;
+use crate::execution::adapter::static_adapter::{notify_result, StaticContext};
+use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError, ExecutionOperationError};
+use crate::execution::{ExecutionRegistry, TransactionFamily};
+use crate::handler::{ApplyError, ContextError, TransactionContext, TransactionHandler};
+use crate::protocol::transaction::TransactionPair;
+use crate::protos::contract_registry::{ContractRegistryPayload, ContractRegistryPayload_Action};
+use crate::scheduler::ExecutionTaskCompletionNotification;
+
+use super::registry::{get_contract, ContractRegistryHandler};
+use super::WasmContract;
+
+type OnDoneCallback =
+    Box<dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send>;
+
+/// An `ExecutionAdapter` which executes transactions using the contracts stored by the contract
+/// registry family.
+///
+/// The adapter executes contract registry transactions itself. Once a contract version has been
+/// created, its family is registered with the `ExecutionRegistry`, and its transactions are
+/// executed by the contract stored in the transaction's context; a transaction whose contract is
+/// not in its context is invalid. Contracts may only read their stored inputs and write their
+/// stored outputs.
+///
+/// Families are not unregistered when a contract version is deleted, as the version may still
+/// exist in other contexts.
+pub struct WasmRegistryExecutionAdapter {
+    join_handle: thread::JoinHandle<()>,
+    sender: Sender<RegistryAdapterCommand>,
+}
+
+impl WasmRegistryExecutionAdapter {
+    /// Creates a new adapter, which limits each contract transaction to `fuel_limit`.
+    ///
+    /// `contract_families` are the families of contracts already stored in state, which are
+    /// registered when the adapter is started.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionAdapterError` is returned if the background thread cannot be created.
+    pub fn new(
+        context_manager: ContextManager,
+        fuel_limit: u64,
+        contract_families: Vec<TransactionFamily>,
+    ) -> Result<Self, ExecutionAdapterError> {
+        let (sender, receiver) = channel();
+        let join_handle = thread::Builder::new()
+            .name("WasmRegistryExecutionAdapter".into())
+            .spawn(move || {
+                let mut core = RegistryAdapterCore {
+                    context_manager,
+                    fuel_limit,
+                    registry_handler: ContractRegistryHandler::new(),
+                    execution_registry: None,
+                    families: contract_families.into_iter().collect(),
+                    contracts: HashMap::new(),
+                };
+                while let Ok(cmd) = receiver.recv() {
+                    match cmd {
+                        RegistryAdapterCommand::Execute(execute_cmd) => {
+                            let (txn_pair, context_id, on_done) = *execute_cmd;
+                            core.execute(txn_pair, context_id, on_done);
+                        }
+                        RegistryAdapterCommand::Start(execution_registry) => {
+                            core.start(execution_registry);
+                        }
+                        RegistryAdapterCommand::Stop => break,
+                    }
+                }
+            })
+            .map_err(|err| ExecutionAdapterError::GeneralExecutionError(Box::new(err)))?;
+
+        Ok(WasmRegistryExecutionAdapter {
+            join_handle,
+            sender,
+        })
+    }
+}
+
+impl ExecutionAdapter for WasmRegistryExecutionAdapter {
+    fn start(
+        &mut self,
+        execution_registry: Box<dyn ExecutionRegistry>,
+    ) -> Result<(), ExecutionOperationError> {
+        self.sender
+            .send(RegistryAdapterCommand::Start(execution_registry))
+            .map_err(|err| {
+                ExecutionOperationError::StartError(format!(
+                    "Unable to start contract registry adapter: {}",
+                    err
+                ))
+            })
+    }
+
+    fn execute(
+        &self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: OnDoneCallback,
+    ) -> Result<(), ExecutionOperationError> {
+        self.sender
+            .send(RegistryAdapterCommand::Execute(Box::new((
+                transaction_pair,
+                context_id,
+                on_done,
+            ))))
+            .map_err(|err| {
+                ExecutionOperationError::ExecuteError(format!(
+                    "Unable to send transaction to contract registry adapter: {}",
+                    err
+                ))
+            })
+    }
+
+    fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
+        self.sender
+            .send(RegistryAdapterCommand::Stop)
+            .map_err(|err| {
+                ExecutionOperationError::StopError(format!("Unable to send stop command: {}", err))
+            })?;
+
+        self.join_handle.join().map_err(|_| {
+            ExecutionOperationError::StopError("Unable to join internal thread.".into())
+        })
+    }
+}
+
+enum RegistryAdapterCommand {
+    Start(Box<dyn ExecutionRegistry>),
+    Stop,
+    Execute(Box<(TransactionPair, ContextId, OnDoneCallback)>),
+}
+
+struct RegistryAdapterCore {
+    context_manager: ContextManager,
+    fuel_limit: u64,
+    registry_handler: ContractRegistryHandler,
+    execution_registry: Option<Box<dyn ExecutionRegistry>>,
+    /// The contract families known to the adapter
+    families: HashSet<TransactionFamily>,
+    /// Loaded contracts, keyed by the SHA-512 hash of their bytecode
+    contracts: HashMap<Vec<u8>, WasmContract>,
+}
+
+impl RegistryAdapterCore {
+    fn start(&mut self, mut execution_registry: Box<dyn ExecutionRegistry>) {
+        for version in self.registry_handler.family_versions() {
+            execution_registry.register_transaction_family(TransactionFamily::new(
+                self.registry_handler.family_name().into(),
+                version.clone(),
+            ));
+        }
+        for family in &self.families {
+            execution_registry.register_transaction_family(family.clone());
+        }
+        self.execution_registry = Some(execution_registry);
+    }
+
+    fn execute(
+        &mut self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: OnDoneCallback,
+    ) {
+        let family = TransactionFamily::from_pair(&transaction_pair);
+
+        if family.family_name() == self.registry_handler.family_name() {
+            if !self
+                .registry_handler
+                .family_versions()
+                .iter()
+                .any(|version| version == family.family_version())
+            {
+                on_done(Err(ExecutionAdapterError::RoutingError(Box::new(
+                    transaction_pair,
+                ))));
+                return;
+            }
+
+            let mut context = StaticContext::new(&self.context_manager, &context_id);
+            let result = self.registry_handler.apply(&transaction_pair, &mut context);
+            if result.is_ok() {
+                self.register_created_contract(&transaction_pair);
+            }
+            notify_result(transaction_pair, context_id, result, on_done);
+            return;
+        }
+
+        let result = self.apply_contract(&transaction_pair, &family, &context_id);
+        notify_result(transaction_pair, context_id, result, on_done);
+    }
+
+    fn apply_contract(
+        &mut self,
+        transaction_pair: &TransactionPair,
+        family: &TransactionFamily,
+        context_id: &ContextId,
+    ) -> Result<(), ApplyError> {
+        let context = StaticContext::new(&self.context_manager, context_id);
+        let record = get_contract(&context, family.family_name(), family.family_version())?
+            .ok_or_else(|| {
+                ApplyError::InvalidTransaction(format!(
+                    "contract {} {} is not registered",
+                    family.family_name(),
+                    family.family_version()
+                ))
+            })?;
+
+        let contract_sha512 = Sha512::digest(record.get_contract()).to_vec();
+        if !self.contracts.contains_key(&contract_sha512) {
+            let contract = WasmContract::new(
+                record.get_name().into(),
+                record.get_version().into(),
+                record.get_contract(),
+                self.fuel_limit,
+            )
+            .map_err(|err| ApplyError::InternalError(err.to_string()))?;
+            self.contracts.insert(contract_sha512.clone(), contract);
+        }
+        let contract = &self.contracts[&contract_sha512];
+
+        let mut namespace_context = NamespaceContext {
+            inner: &context,
+            inputs: record.get_inputs(),
+            outputs: record.get_outputs(),
+        };
+        contract.apply(transaction_pair, &mut namespace_context)
+    }
+
+    fn register_created_contract(&mut self, transaction_pair: &TransactionPair) {
+        let payload: ContractRegistryPayload =
+            match protobuf::parse_from_bytes(transaction_pair.transaction().payload()) {
+                Ok(payload) => payload,
+                Err(_) => return,
+            };
+        if payload.get_action() != ContractRegistryPayload_Action::CREATE_CONTRACT {
+            return;
+        }
+
+        let family = TransactionFamily::new(
+            payload.get_create_contract().get_name().into(),
+            payload.get_create_contract().get_version().into(),
+        );
+        if self.families.insert(family.clone()) {
+            if let Some(execution_registry) = self.execution_registry.as_mut() {
+                execution_registry.register_transaction_family(family);
+            }
+        }
+    }
+}
+
+/// Restricts a contract to the address prefixes stored with it.
+struct NamespaceContext<'a> {
+    inner: &'a dyn TransactionContext,
+    inputs: &'a [String],
+    outputs: &'a [String],
+}
+
+fn check_namespaces(
+    addresses: &[String],
+    prefixes: &[String],
+    access: &str,
+) -> Result<(), ContextError> {
+    for address in addresses {
+        if !prefixes.iter().any(|prefix| address.starts_with(prefix)) {
+            return Err(ContextError::AuthorizationError(format!(
+                "contract may not {} address {}",
+                access, address
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl<'a> TransactionContext for NamespaceContext<'a> {
+    fn get_state_entries(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        check_namespaces(addresses, self.inputs, "read")?;
+        self.inner.get_state_entries(addresses)
+    }
+
+    fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        check_namespaces(
+            &entries
+                .iter()
+                .map(|(address, _)| address.clone())
+                .collect::<Vec<_>>(),
+            self.outputs,
+            "write",
+        )?;
+        self.inner.set_state_entries(entries)
+    }
+
+    fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+        check_namespaces(addresses, self.outputs, "delete")?;
+        self.inner.delete_state_entries(addresses)
+    }
+
+    fn add_receipt_data(&self, data: Vec<u8>) -> Result<(), ContextError> {
+        self.inner.add_receipt_data(data)
+    }
+
+    fn add_event(
+        &self,
+        event_type: String,
+        attributes: Vec<(String, String)>,
+        data: Vec<u8>,
+    ) -> Result<(), ContextError> {
+        self.inner.add_event(event_type, attributes, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+
+    use protobuf::{Message, RepeatedField};
+
+    use crate::context::ContextLifecycle;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::protos::contract_registry::CreateContractAction;
+    use crate::signing::hash::HashSigner;
+    use crate::signing::Signer;
+    use crate::state::hashmap::HashMapState;
+
+    use super::super::tests::TEST_CONTRACT;
+
+    /// Reports registrations over a channel.
+    struct ChannelRegistry {
+        sender: Sender<TransactionFamily>,
+    }
+
+    impl ExecutionRegistry for ChannelRegistry {
+        fn register_transaction_family(&mut self, family: TransactionFamily) {
+            self.sender
+                .send(family)
+                .expect("Unable to send registration");
+        }
+
+        fn unregister_transaction_family(&mut self, _family: &TransactionFamily) {}
+    }
+
+    fn create_contract_payload(version: &str, outputs: &[&str], contract: &[u8]) -> Vec<u8> {
+        let mut action = CreateContractAction::new();
+        action.set_name("wasm_test".into());
+        action.set_version(version.into());
+        action.set_inputs(RepeatedField::from_vec(vec!["ab".into()]));
+        action.set_outputs(RepeatedField::from_vec(
+            outputs.iter().map(|output| output.to_string()).collect(),
+        ));
+        action.set_contract(contract.to_vec());
+        let mut payload = ContractRegistryPayload::new();
+        payload.set_action(ContractRegistryPayload_Action::CREATE_CONTRACT);
+        payload.set_create_contract(action);
+        payload
+            .write_to_bytes()
+            .expect("Unable to serialize payload")
+    }
+
+    /// Executes a transaction in a new context which follows the previous one, returning the
+    /// result's error message, if the transaction was invalid.
+    fn execute(
+        adapter: &WasmRegistryExecutionAdapter,
+        context_manager: &mut ContextManager,
+        previous_context: &mut Option<ContextId>,
+        family: (&str, &str),
+        payload: Vec<u8>,
+        signer: &dyn Signer,
+    ) -> Option<String> {
+        let txn_pair = TransactionBuilder::new()
+            .with_family_name(family.0.into())
+            .with_family_version(family.1.into())
+            .with_inputs(vec![])
+            .with_outputs(vec![])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(payload)
+            .build_pair(signer)
+            .expect("Unable to build transaction");
+        let dependencies = previous_context.iter().cloned().collect::<Vec<_>>();
+        let context_id =
+            context_manager.create_context(&dependencies, &HashMapState::state_id(&HashMap::new()));
+
+        let (result_tx, result_rx) = channel();
+        adapter
+            .execute(
+                txn_pair,
+                context_id,
+                Box::new(move |res| result_tx.send(res).expect("Unable to send result")),
+            )
+            .expect("Unable to execute transaction");
+        match result_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Did not receive result")
+            .expect("Execution failed")
+        {
+            ExecutionTaskCompletionNotification::Valid(..) => {
+                *previous_context = Some(context_id);
+                None
+            }
+            ExecutionTaskCompletionNotification::Invalid(_, result) => Some(result.error_message),
+        }
+    }
+
+    fn next_registration(registry_rx: &Receiver<TransactionFamily>) -> TransactionFamily {
+        registry_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Family not registered")
+    }
+
+    /// Tests that contracts created through the registry are registered and executed, restricted
+    /// to their namespaces, and may only be updated by their owners.
+    #[test]
+    fn test_wasm_registry_adapter() {
+        let wasm = wat::parse_str(TEST_CONTRACT).expect("Unable to compile test contract");
+        let mut context_manager = ContextManager::new(Box::new(HashMapState::new()));
+        let mut adapter =
+            WasmRegistryExecutionAdapter::new(context_manager.clone(), 100_000, vec![])
+                .expect("Unable to create adapter");
+        let (registry_tx, registry_rx) = channel();
+        adapter
+            .start(Box::new(ChannelRegistry {
+                sender: registry_tx,
+            }))
+            .expect("Unable to start adapter");
+        assert_eq!(
+            next_registration(&registry_rx),
+            TransactionFamily::new("contract_registry".into(), "1.0".into())
+        );
+
+        let signer = HashSigner::default();
+        let mut previous_context = None;
+
+        // The contract has not been created
+        assert_eq!(
+            execute(
+                &adapter,
+                &mut context_manager,
+                &mut previous_context,
+                ("wasm_test", "1.0"),
+                b"hello".to_vec(),
+                &signer,
+            ),
+            Some("contract wasm_test 1.0 is not registered".into())
+        );
+
+        assert_eq!(
+            execute(
+                &adapter,
+                &mut context_manager,
+                &mut previous_context,
+                ("contract_registry", "1.0"),
+                create_contract_payload("1.0", &["ab"], &wasm),
+                &signer,
+            ),
+            None
+        );
+        assert_eq!(
+            next_registration(&registry_rx),
+            TransactionFamily::new("wasm_test".into(), "1.0".into())
+        );
+        assert_eq!(
+            execute(
+                &adapter,
+                &mut context_manager,
+                &mut previous_context,
+                ("wasm_test", "1.0"),
+                b"hello".to_vec(),
+                &signer,
+            ),
+            None
+        );
+        assert_eq!(
+            context_manager
+                .get(
+                    &previous_context.expect("No valid context"),
+                    &["ab01".into()]
+                )
+                .expect("Unable to get state"),
+            vec![("ab01".to_string(), b"hello".to_vec())]
+        );
+
+        // Version 2.0 may not write the address the test contract sets
+        assert_eq!(
+            execute(
+                &adapter,
+                &mut context_manager,
+                &mut previous_context,
+                ("contract_registry", "1.0"),
+                create_contract_payload("2.0", &["cd"], &wasm),
+                &signer,
+            ),
+            None
+        );
+        assert_eq!(
+            next_registration(&registry_rx),
+            TransactionFamily::new("wasm_test".into(), "2.0".into())
+        );
+        assert_eq!(
+            execute(
+                &adapter,
+                &mut context_manager,
+                &mut previous_context,
+                ("wasm_test", "2.0"),
+                b"hello".to_vec(),
+                &signer,
+            ),
+            Some("contract trapped: unauthorized: contract may not write address ab01".into())
+        );
+
+        // Only an owner may create further versions
+        assert_eq!(
+            execute(
+                &adapter,
+                &mut context_manager,
+                &mut previous_context,
+                ("contract_registry", "1.0"),
+                create_contract_payload("3.0", &["ab"], &wasm),
+                &HashSigner::new(b"other".to_vec()),
+            ),
+            Some("signer is not an owner of contract wasm_test".into())
+        );
+
+        Box::new(adapter).stop().expect("Unable to stop adapter");
+    }
+}
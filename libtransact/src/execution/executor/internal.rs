@@ -37,9 +37,15 @@ use std::thread::JoinHandle;
 
 use log::warn;
 
+use crate::context::ContextId;
 use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError};
 use crate::execution::{ExecutionRegistry, TransactionFamily};
-use crate::scheduler::{ExecutionTask, ExecutionTaskCompletionNotifier};
+use crate::scheduler::{
+    ExecutionTask, ExecutionTaskCompletionNotification, ExecutionTaskCompletionNotifier,
+    InvalidTransactionResult,
+};
+
+use super::routing::{LeastOutstandingStrategy, RoutingCandidate, RoutingStrategy};
 
 /// The `TransactionPair` and `ContextId` along with where to send
 /// results.
@@ -59,11 +65,29 @@ pub enum RegistrationChange {
     RegisterRequest((TransactionFamily, NamedExecutionEventSender)),
 }
 
+/// Why an `ExecutionAdapter` returned a transaction without executing it, along with the index of
+/// the adapter.
+pub enum RetryReason {
+    /// The adapter could not route the transaction to a handler; the transaction is sent to
+    /// another adapter if there is one, and otherwise to the same adapter again.
+    Unavailable(usize),
+    /// The adapter failed before it could execute the transaction; the transaction is only sent
+    /// to other adapters.
+    Failed(usize),
+}
+
 /// One of either a `RegistrationChange` or an `ExecutionEvent`.
 /// The single internal thread in the Executor is listening for these.
 pub enum ExecutorCommand {
     RegistrationChange(RegistrationChange),
     Execution(Box<ExecutionEvent>),
+    /// An `ExecutionAdapter` returned the event's transaction without executing it.
+    Retry(Box<ExecutionEvent>, RetryReason),
+    /// The `ExecutionAdapter` with the given index finished executing the transaction with the
+    /// given ID.
+    Completed(usize, String),
+    /// The `ExecutionAdapter` with the given index failed, and can no longer execute transactions.
+    AdapterFailed(usize),
     Shutdown,
 }
 
@@ -149,6 +173,7 @@ impl std::fmt::Display for ExecutorThreadError {
 
 pub struct ExecutorThread {
     execution_adapters: Vec<Box<dyn ExecutionAdapter>>,
+    routing_strategy: Option<Box<dyn RoutingStrategy>>,
     join_handles: Vec<JoinHandle<()>>,
     internal_thread: Option<JoinHandle<()>>,
     sender: Option<ExecutorCommandSender>,
@@ -157,8 +182,19 @@ pub struct ExecutorThread {
 
 impl ExecutorThread {
     pub fn new(execution_adapters: Vec<Box<dyn ExecutionAdapter>>) -> Self {
+        Self::new_with_routing_strategy(
+            execution_adapters,
+            Box::new(LeastOutstandingStrategy::new()),
+        )
+    }
+
+    pub fn new_with_routing_strategy(
+        execution_adapters: Vec<Box<dyn ExecutionAdapter>>,
+        routing_strategy: Box<dyn RoutingStrategy>,
+    ) -> Self {
         ExecutorThread {
             execution_adapters,
+            routing_strategy: Some(routing_strategy),
             join_handles: vec![],
             internal_thread: None,
             sender: None,
@@ -171,16 +207,18 @@ impl ExecutorThread {
     }
 
     pub fn start(&mut self) -> Result<(), ExecutorThreadError> {
-        if self.sender.is_none() {
+        if let (None, Some(routing_strategy)) = (&self.sender, self.routing_strategy.take()) {
             let (registry_sender, receiver) = channel();
 
             for (index, mut execution_adapter) in self.execution_adapters.drain(0..).enumerate() {
                 let (sender, adapter_receiver) = channel();
                 let ee_sender = NamedExecutionEventSender::new(sender, index);
+                let failed = Arc::new(AtomicBool::new(false));
 
                 if let Err(err) = execution_adapter.start(Box::new(InternalRegistry {
                     event_sender: ee_sender,
                     registry_sender: registry_sender.clone(),
+                    failed: Arc::clone(&failed),
                 })) {
                     warn!("Unable to start execution adapter: {}", err);
                     return Err(ExecutorThreadError::ResourcesUnavailable);
@@ -188,6 +226,7 @@ impl ExecutorThread {
 
                 match Self::start_execution_adapter_thread(
                     Arc::clone(&self.stop),
+                    failed,
                     execution_adapter,
                     adapter_receiver,
                     &registry_sender,
@@ -204,7 +243,7 @@ impl ExecutorThread {
             }
 
             self.sender = Some(registry_sender);
-            match self.start_thread(receiver) {
+            match self.start_thread(receiver, routing_strategy) {
                 Ok(join_handle) => {
                     self.internal_thread = Some(join_handle);
                 }
@@ -236,8 +275,14 @@ impl ExecutorThread {
         }
     }
 
+    /// Starts the thread which passes transactions to the adapter.
+    ///
+    /// `failed` is set once the adapter fails to execute a transaction; from then on, every
+    /// transaction sent to it is returned to be executed by another adapter, until the adapter
+    /// registers a transaction family again.
     fn start_execution_adapter_thread(
         stop: Arc<AtomicBool>,
+        failed: Arc<AtomicBool>,
         execution_adapter: Box<dyn ExecutionAdapter>,
         receiver: ExecutionEventReceiver,
         sender: &ExecutorCommandSender,
@@ -247,143 +292,168 @@ impl ExecutorThread {
 
         std::thread::Builder::new()
             .name(format!("execution_adapter_thread_{}", index))
-            .spawn(move || loop {
-                if let Ok(execution_command) = receiver.recv() {
-                    match execution_command {
-                        ExecutionCommand::Event(execution_event) => {
-                            let sender = sender.clone();
-                            let (completion_notifier, task) = *execution_event;
-                            let (pair, context_id) = task.take();
-
-                            let callback = Box::new(move |result| {
-                                // Without this line, the function is considered a FnOnce, instead
-                                // of an Fn.  This seems to be a strange quirk of the compiler
-                                let completion_notifier = completion_notifier.clone();
-                                match result {
-                                    Ok(tp_processing_result) => {
-                                        completion_notifier.notify(tp_processing_result);
-                                    }
-                                    Err(ExecutionAdapterError::TimeoutError(transaction_pair)) => {
-                                        let execution_task =
-                                            ExecutionTask::new(*transaction_pair, context_id);
-                                        let execution_event = (completion_notifier, execution_task);
-                                        if let Err(err) = sender.send(ExecutorCommand::Execution(
-                                            Box::new(execution_event),
-                                        )) {
-                                            warn!("During retry of TimeOutError: {}", err);
+            .spawn(move || {
+                loop {
+                    if let Ok(execution_command) = receiver.recv() {
+                        match execution_command {
+                            ExecutionCommand::Event(execution_event)
+                                if failed.load(Ordering::SeqCst) =>
+                            {
+                                Self::retry_elsewhere(index, *execution_event, &sender);
+                            }
+                            ExecutionCommand::Event(execution_event) => {
+                                let sender = sender.clone();
+                                let (completion_notifier, task) = *execution_event;
+                                let (pair, context_id) = task.take();
+                                let transaction_id =
+                                    pair.transaction().header_signature().to_string();
+
+                                // Kept so that the transaction can be sent to another adapter if this
+                                // one fails to execute it
+                                let retry_notifier = completion_notifier.clone();
+                                let retry_pair = pair.clone();
+
+                                let callback = Box::new(move |result| {
+                                    // Without this line, the function is considered a FnOnce, instead
+                                    // of an Fn.  This seems to be a strange quirk of the compiler
+                                    let completion_notifier = completion_notifier.clone();
+                                    let command = match result {
+                                        Ok(tp_processing_result) => {
+                                            completion_notifier.notify(tp_processing_result);
+                                            ExecutorCommand::Completed(
+                                                index,
+                                                transaction_id.clone(),
+                                            )
                                         }
-                                    }
-                                    Err(ExecutionAdapterError::RoutingError(transaction_pair)) => {
-                                        let execution_task =
-                                            ExecutionTask::new(*transaction_pair, context_id);
-                                        let execution_event = (completion_notifier, execution_task);
-                                        if let Err(err) = sender.send(ExecutorCommand::Execution(
-                                            Box::new(execution_event),
-                                        )) {
-                                            warn!("During retry of RoutingError: {}", err);
+                                        Err(ExecutionAdapterError::RoutingError(
+                                            transaction_pair,
+                                        )) => {
+                                            let execution_task =
+                                                ExecutionTask::new(*transaction_pair, context_id);
+                                            ExecutorCommand::Retry(
+                                                Box::new((completion_notifier, execution_task)),
+                                                RetryReason::Unavailable(index),
+                                            )
+                                        }
+                                        Err(err) => {
+                                            // The handler may have written to the context before
+                                            // failing or timing out, so the transaction is
+                                            // reported invalid rather than retried on that state
+                                            error!("Execution Error: {}", err);
+                                            completion_notifier.notify(execution_failure(
+                                                context_id,
+                                                transaction_id.clone(),
+                                                format!("Execution failed: {}", err),
+                                            ));
+                                            ExecutorCommand::Completed(
+                                                index,
+                                                transaction_id.clone(),
+                                            )
                                         }
+                                    };
+                                    if let Err(err) = sender.send(command) {
+                                        warn!("During report of execution result: {}", err);
                                     }
-                                    Err(ExecutionAdapterError::GeneralExecutionError(err)) => {
-                                        error!("General Execution Error: {}", err);
+                                });
+                                if let Err(err) =
+                                    execution_adapter.execute(pair, context_id, callback)
+                                {
+                                    error!("Unable to execute on adapter {}: {}", index, err);
+                                    failed.store(true, Ordering::SeqCst);
+                                    if let Err(err) =
+                                        sender.send(ExecutorCommand::AdapterFailed(index))
+                                    {
+                                        warn!("During report of failed adapter {}: {}", index, err);
                                     }
+                                    let execution_task = ExecutionTask::new(retry_pair, context_id);
+                                    Self::retry_elsewhere(
+                                        index,
+                                        (retry_notifier, execution_task),
+                                        &sender,
+                                    );
+                                }
+                            }
+                            ExecutionCommand::Sentinel => {
+                                if let Err(err) = execution_adapter.stop() {
+                                    error!("Unable to cleanly stop adapter {}: {}", index, err);
                                 }
-                            });
-                            if let Err(err) = execution_adapter.execute(pair, context_id, callback)
-                            {
-                                error!("Unable to execute on adapter {}: {}", index, err);
                                 break;
                             }
                         }
-                        ExecutionCommand::Sentinel => {
-                            if let Err(err) = execution_adapter.stop() {
-                                error!("Unable to cleanly stop adapter {}: {}", index, err);
-                            }
-                            break;
+                    } else if stop.load(Ordering::Relaxed) {
+                        if let Err(err) = execution_adapter.stop() {
+                            error!("Unable to cleanly stop adapter {}: {}", index, err);
                         }
+                        break;
                     }
-                } else if stop.load(Ordering::Relaxed) {
-                    if let Err(err) = execution_adapter.stop() {
-                        error!("Unable to cleanly stop adapter {}: {}", index, err);
-                    }
-                    break;
                 }
             })
     }
 
+    /// Returns a transaction which the adapter failed to execute, so that it is executed by
+    /// another adapter.
+    fn retry_elsewhere(
+        index: usize,
+        execution_event: ExecutionEvent,
+        sender: &ExecutorCommandSender,
+    ) {
+        if let Err(err) = sender.send(ExecutorCommand::Retry(
+            Box::new(execution_event),
+            RetryReason::Failed(index),
+        )) {
+            warn!("During retry of transaction from failed adapter: {}", err);
+        }
+    }
+
     fn start_thread(
         &self,
         receiver: ExecutorCommandReceiver,
+        routing_strategy: Box<dyn RoutingStrategy>,
     ) -> Result<JoinHandle<()>, std::io::Error> {
         let stop = Arc::clone(&self.stop);
         std::thread::Builder::new()
             .name("internal_executor_thread".to_string())
             .spawn(move || {
-                let mut fanout_threads: HashMap<
-                    TransactionFamily,
-                    HashSet<NamedExecutionEventSender>,
-                > = HashMap::new();
-                let mut parked: ParkedExecutionEventsMap = HashMap::new();
-                let mut unparked = vec![];
+                let mut router = Router::new(routing_strategy);
                 loop {
-                    for execution_event in unparked.drain(0..) {
-                        Self::try_send_execution_event(
-                            Box::new(execution_event),
-                            &fanout_threads,
-                            &mut parked,
-                        );
-                    }
-
                     match receiver.recv() {
                         Ok(ExecutorCommand::Execution(execution_event)) => {
                             if stop.load(Ordering::Relaxed) {
-                                Self::shutdown_fanout_threads(&fanout_threads);
+                                router.shutdown_fanout_threads();
+                                break;
+                            }
+                            router.route(*execution_event);
+                        }
+                        Ok(ExecutorCommand::Retry(execution_event, reason)) => {
+                            if stop.load(Ordering::Relaxed) {
+                                router.shutdown_fanout_threads();
                                 break;
                             }
-                            Self::try_send_execution_event(
-                                execution_event,
-                                &fanout_threads,
-                                &mut parked,
-                            )
+                            router.retry(*execution_event, reason);
+                        }
+                        Ok(ExecutorCommand::Completed(index, transaction_id)) => {
+                            router.complete(index, &transaction_id);
+                        }
+                        Ok(ExecutorCommand::AdapterFailed(index)) => {
+                            router.fail_adapter(index);
                         }
                         Ok(ExecutorCommand::RegistrationChange(
                             RegistrationChange::RegisterRequest((transaction_family, sender)),
                         )) => {
                             if stop.load(Ordering::Relaxed) {
-                                Self::shutdown_fanout_threads(&fanout_threads);
+                                router.shutdown_fanout_threads();
                                 break;
                             }
-
-                            if let Some(p) = parked.get_mut(&transaction_family) {
-                                unparked.append(p);
-                            }
-
-                            let found = if let Some(ea_senders) =
-                                fanout_threads.get_mut(&transaction_family)
-                            {
-                                ea_senders.insert(sender);
-                                None
-                            } else {
-                                let mut s = HashSet::new();
-                                s.insert(sender);
-                                Some(s)
-                            };
-
-                            if let Some(f) = found {
-                                fanout_threads.insert(transaction_family, f);
-                            }
+                            router.register(transaction_family, sender);
                         }
                         Ok(ExecutorCommand::RegistrationChange(
                             RegistrationChange::UnregisterRequest((transaction_family, sender)),
                         )) => {
-                            fanout_threads
-                                .entry(transaction_family)
-                                .and_modify(|ea_senders| {
-                                    ea_senders.remove(&sender);
-                                });
+                            router.unregister(transaction_family, &sender);
                         }
 
                         Ok(ExecutorCommand::Shutdown) => {
-                            Self::shutdown_fanout_threads(&fanout_threads);
+                            router.shutdown_fanout_threads();
                             break;
                         }
                         Err(err) => {
@@ -394,11 +464,289 @@ impl ExecutorThread {
                 }
             })
     }
+}
+
+/// The notification for a transaction which could not be executed.
+fn execution_failure(
+    context_id: ContextId,
+    transaction_id: String,
+    error_message: String,
+) -> ExecutionTaskCompletionNotification {
+    ExecutionTaskCompletionNotification::Invalid(
+        context_id,
+        InvalidTransactionResult {
+            transaction_id,
+            error_message,
+            error_data: vec![],
+        },
+    )
+}
+
+/// The state of the internal executor thread: the adapters registered for each transaction
+/// family, and the transactions waiting for one to be registered.
+struct Router {
+    fanout_threads: HashMap<TransactionFamily, HashSet<NamedExecutionEventSender>>,
+    parked: ParkedExecutionEventsMap,
+    routing_strategy: Box<dyn RoutingStrategy>,
+    /// The number of transactions each adapter is executing
+    outstanding: HashMap<usize, usize>,
+    /// The adapters which have returned each transaction without executing it
+    failed_adapters: HashMap<String, HashSet<usize>>,
+    /// The adapters which have failed, which are only sent the sentinel on shutdown, unless they
+    /// register a transaction family again
+    failed: HashMap<usize, NamedExecutionEventSender>,
+}
+
+impl Router {
+    fn new(routing_strategy: Box<dyn RoutingStrategy>) -> Self {
+        Router {
+            fanout_threads: HashMap::new(),
+            parked: HashMap::new(),
+            routing_strategy,
+            outstanding: HashMap::new(),
+            failed_adapters: HashMap::new(),
+            failed: HashMap::new(),
+        }
+    }
+
+    fn register(
+        &mut self,
+        transaction_family: TransactionFamily,
+        sender: NamedExecutionEventSender,
+    ) {
+        if self.failed.remove(&sender.name).is_some() {
+            // The adapter has recovered, so the transactions it failed to execute may be sent to
+            // it again
+            info!(
+                "Failed adapter {} registered {} {}, routing transactions to it again",
+                sender.name,
+                transaction_family.family_name(),
+                transaction_family.family_version()
+            );
+            for failed_adapters in self.failed_adapters.values_mut() {
+                failed_adapters.remove(&sender.name);
+            }
+        }
+        let unparked_families = self
+            .parked
+            .keys()
+            .filter(|parked_family| transaction_family.supports(parked_family))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        self.fanout_threads
+            .entry(transaction_family)
+            .or_insert_with(HashSet::new)
+            .insert(sender);
+
+        for parked_family in unparked_families {
+            if let Some(unparked) = self.parked.remove(&parked_family) {
+                for execution_event in unparked {
+                    self.route(execution_event);
+                }
+            }
+        }
+    }
 
-    fn shutdown_fanout_threads(
-        fanout_threads: &HashMap<TransactionFamily, HashSet<NamedExecutionEventSender>>,
+    fn unregister(
+        &mut self,
+        transaction_family: TransactionFamily,
+        sender: &NamedExecutionEventSender,
     ) {
-        for sender in fanout_threads
+        self.fanout_threads
+            .entry(transaction_family)
+            .and_modify(|ea_senders| {
+                ea_senders.remove(sender);
+            });
+    }
+
+    /// Removes an adapter from every transaction family, returning its sender if it was
+    /// registered for any.
+    fn remove_adapter(&mut self, index: usize) -> Option<NamedExecutionEventSender> {
+        let mut removed = None;
+        for ea_senders in self.fanout_threads.values_mut() {
+            if let Some(sender) = ea_senders
+                .iter()
+                .find(|sender| sender.name == index)
+                .cloned()
+            {
+                ea_senders.remove(&sender);
+                removed = Some(sender);
+            }
+        }
+        self.outstanding.remove(&index);
+        removed
+    }
+
+    fn fail_adapter(&mut self, index: usize) {
+        if let Some(sender) = self.remove_adapter(index) {
+            self.failed.insert(index, sender);
+        }
+    }
+
+    fn complete(&mut self, index: usize, transaction_id: &str) {
+        if let Some(outstanding) = self.outstanding.get_mut(&index) {
+            *outstanding = outstanding.saturating_sub(1);
+        }
+        self.failed_adapters.remove(transaction_id);
+    }
+
+    fn retry(&mut self, execution_event: ExecutionEvent, reason: RetryReason) {
+        let transaction_id = execution_event
+            .1
+            .pair()
+            .transaction()
+            .header_signature()
+            .to_string();
+        let (index, failed) = match reason {
+            RetryReason::Unavailable(index) => (index, false),
+            RetryReason::Failed(index) => (index, true),
+        };
+        if let Some(outstanding) = self.outstanding.get_mut(&index) {
+            *outstanding = outstanding.saturating_sub(1);
+        }
+        let failed_adapters = self
+            .failed_adapters
+            .entry(transaction_id.clone())
+            .or_insert_with(HashSet::new);
+        failed_adapters.insert(index);
+
+        let tf = TransactionFamily::from_pair(execution_event.1.pair());
+        let supporting = Self::supporting_families(&self.fanout_threads, &tf);
+        let all_failed = !supporting.is_empty()
+            && supporting.iter().all(|(_, ea_senders)| {
+                ea_senders
+                    .iter()
+                    .all(|sender| failed_adapters.contains(&sender.name))
+            });
+        if all_failed {
+            if failed {
+                error!(
+                    "Transaction {} failed on every adapter registered for {} {}",
+                    transaction_id,
+                    tf.family_name(),
+                    tf.family_version()
+                );
+                self.failed_adapters.remove(&transaction_id);
+                // Report the failure, so that the scheduler does not wait for the transaction
+                let (notifier, task) = execution_event;
+                notifier.notify(execution_failure(
+                    *task.context_id(),
+                    transaction_id,
+                    format!(
+                        "No adapter registered for {} {} was able to execute the transaction",
+                        tf.family_name(),
+                        tf.family_version()
+                    ),
+                ));
+                return;
+            }
+            // The adapters were only unavailable, so they may all be tried again
+            self.failed_adapters.remove(&transaction_id);
+        }
+
+        self.route(execution_event);
+    }
+
+    /// The registered families which support the given family, and the adapters registered for
+    /// each, ordered from the most to the least preferred family.
+    fn supporting_families<'a>(
+        fanout_threads: &'a HashMap<TransactionFamily, HashSet<NamedExecutionEventSender>>,
+        transaction_family: &TransactionFamily,
+    ) -> Vec<(
+        &'a TransactionFamily,
+        &'a HashSet<NamedExecutionEventSender>,
+    )> {
+        let mut supporting = fanout_threads
+            .iter()
+            .filter(|(family, ea_senders)| {
+                !ea_senders.is_empty() && family.supports(transaction_family)
+            })
+            .collect::<Vec<_>>();
+        supporting.sort_by(|(a, _), (b, _)| {
+            (b.maximum_version(), b.minimum_version())
+                .cmp(&(a.maximum_version(), a.minimum_version()))
+                .then_with(|| a.family_version().cmp(b.family_version()))
+        });
+        supporting
+    }
+
+    /// Sends the event to one of the adapters registered for the most preferred family supporting
+    /// its transaction family, chosen by the routing strategy, or parks it if there are none.
+    fn route(&mut self, mut execution_event: ExecutionEvent) {
+        let tf = TransactionFamily::from_pair(execution_event.1.pair());
+        loop {
+            let no_failures = HashSet::new();
+            let failed_adapters = self
+                .failed_adapters
+                .get(execution_event.1.pair().transaction().header_signature())
+                .unwrap_or(&no_failures);
+            // The candidates are the adapters of the most preferred family with any adapter which
+            // has not already failed to execute the transaction
+            let supporting = Self::supporting_families(&self.fanout_threads, &tf)
+                .into_iter()
+                .map(|(family, ea_senders)| {
+                    let candidates = ea_senders
+                        .iter()
+                        .filter(|sender| !failed_adapters.contains(&sender.name))
+                        .collect::<Vec<_>>();
+                    (family, candidates)
+                })
+                .find(|(_, candidates)| !candidates.is_empty());
+            let (registered_family, mut candidates) = match supporting {
+                Some(supporting) => supporting,
+                None => {
+                    Self::park_execution_event(&mut self.parked, execution_event, tf);
+                    return;
+                }
+            };
+            candidates.sort_by_key(|sender| sender.name);
+
+            let routing_candidates = candidates
+                .iter()
+                .map(|sender| {
+                    RoutingCandidate::new(
+                        sender.name,
+                        self.outstanding.get(&sender.name).cloned().unwrap_or(0),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let selected = self
+                .routing_strategy
+                .select(registered_family, &routing_candidates);
+            let sender = candidates
+                .iter()
+                .find(|sender| sender.name == selected)
+                .unwrap_or(&candidates[0]);
+            let index = sender.name;
+
+            match sender
+                .sender
+                .send(ExecutionCommand::Event(Box::new(execution_event)))
+            {
+                Ok(()) => {
+                    *self.outstanding.entry(index).or_insert(0) += 1;
+                    return;
+                }
+                Err(err) => {
+                    // The adapter's thread has stopped, so route the event to another adapter
+                    warn!(
+                        "During send of ExecutionCommand to adapter {}: {}",
+                        index, err
+                    );
+                    self.remove_adapter(index);
+                    match err.0 {
+                        ExecutionCommand::Event(returned) => execution_event = *returned,
+                        ExecutionCommand::Sentinel => return,
+                    }
+                }
+            }
+        }
+    }
+
+    fn shutdown_fanout_threads(&self) {
+        for sender in self
+            .fanout_threads
             .values()
             .fold(HashSet::new(), |mut set, item| {
                 for s in item {
@@ -406,6 +754,8 @@ impl ExecutorThread {
                 }
                 set
             })
+            .into_iter()
+            .chain(self.failed.values())
         {
             if let Err(err) = sender.sender.send(ExecutionCommand::Sentinel) {
                 warn!("During stop of ExecutorThread internal thread: {}", err);
@@ -413,25 +763,6 @@ impl ExecutorThread {
         }
     }
 
-    fn try_send_execution_event(
-        execution_event: Box<ExecutionEvent>,
-        fanout_threads: &HashMap<TransactionFamily, HashSet<NamedExecutionEventSender>>,
-        parked: &mut ParkedExecutionEventsMap,
-    ) {
-        let tf = TransactionFamily::from_pair(&execution_event.1.pair());
-        if let Some(ea_senders) = fanout_threads.get(&tf) {
-            if let Some(sender) = ea_senders.iter().nth(0) {
-                if let Err(err) = sender.sender.send(ExecutionCommand::Event(execution_event)) {
-                    warn!("During send of ExecutionCommand: {}", err);
-                }
-            } else {
-                Self::park_execution_event(parked, *execution_event, tf);
-            }
-        } else {
-            Self::park_execution_event(parked, *execution_event, tf);
-        }
-    }
-
     fn park_execution_event(
         parked: &mut ParkedExecutionEventsMap,
         execution_event: ExecutionEvent,
@@ -600,6 +931,11 @@ mod tests {
                     }
                 },
                 ExecutorCommand::Shutdown => panic!("Should not have called shutdown during test"),
+                ExecutorCommand::Retry(..)
+                | ExecutorCommand::Completed(..)
+                | ExecutorCommand::AdapterFailed(_) => {
+                    panic!("No adapter should have reported back during test")
+                }
             }
         }
 
@@ -738,10 +1074,15 @@ mod tests {
 struct InternalRegistry {
     registry_sender: ExecutorCommandSender,
     event_sender: NamedExecutionEventSender,
+    /// Whether the adapter has failed, shared with its thread
+    failed: Arc<AtomicBool>,
 }
 
 impl ExecutionRegistry for InternalRegistry {
+    /// Registering a family also marks a failed adapter as recovered, so that it is sent
+    /// transactions again.
     fn register_transaction_family(&mut self, family: TransactionFamily) {
+        self.failed.store(false, Ordering::SeqCst);
         if let Err(err) = self
             .registry_sender
             .send(ExecutorCommand::RegistrationChange(
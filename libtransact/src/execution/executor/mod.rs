@@ -17,9 +17,11 @@
 
 mod internal;
 mod reader;
+pub mod routing;
 
 use internal::ExecutorThread;
 use reader::ExecutionTaskReader;
+use routing::RoutingStrategy;
 
 use crate::execution::adapter::ExecutionAdapter;
 use crate::scheduler::multi::SubSchedulerHandler;
@@ -98,6 +100,22 @@ impl Executor {
             executor_thread: ExecutorThread::new(execution_adapters),
         }
     }
+
+    /// Creates an `Executor` which uses the given strategy to choose between the adapters
+    /// registered for a transaction family, rather than sending each transaction to the adapter
+    /// executing the fewest transactions.
+    pub fn new_with_routing_strategy(
+        execution_adapters: Vec<Box<dyn ExecutionAdapter>>,
+        routing_strategy: Box<dyn RoutingStrategy>,
+    ) -> Self {
+        Executor {
+            readers: Arc::new(Mutex::new(HashMap::new())),
+            executor_thread: ExecutorThread::new_with_routing_strategy(
+                execution_adapters,
+                routing_strategy,
+            ),
+        }
+    }
 }
 
 impl SubSchedulerHandler for Executor {
@@ -140,14 +158,24 @@ impl std::fmt::Display for ExecutorError {
 #[cfg(test)]
 mod tests {
 
+    use super::routing::RoundRobinStrategy;
     use super::*;
+    use crate::context::manager::sync::ContextManager;
+    use crate::context::{ContextId, ContextLifecycle};
+    use crate::execution::adapter::static_adapter::StaticExecutionAdapter;
     use crate::execution::adapter::test_adapter::TestExecutionAdapter;
+    use crate::execution::adapter::{ExecutionAdapterError, ExecutionOperationError};
+    use crate::execution::{ExecutionRegistry, TransactionFamily};
+    use crate::handler::{ApplyError, TransactionContext, TransactionHandler};
+    use crate::protocol::receipt::TransactionResult;
     use crate::protocol::transaction::{HashMethod, TransactionBuilder, TransactionPair};
     use crate::scheduler::ExecutionTask;
     use crate::scheduler::ExecutionTaskCompletionNotification;
     use crate::scheduler::ExecutionTaskCompletionNotifier;
     use crate::signing::{hash::HashSigner, Signer};
+    use crate::state::hashmap::HashMapState;
     use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use std::time::Duration;
 
     static FAMILY_NAME1: &str = "test1";
@@ -214,6 +242,407 @@ mod tests {
         );
     }
 
+    /// Tests that transactions sent to an adapter which fails are executed by another adapter
+    /// registered for the same family.
+    #[test]
+    fn test_executor_failover() {
+        let test_execution_adapter = TestExecutionAdapter::new();
+        let adapter = test_execution_adapter.clone();
+
+        let mut executor = Executor::new_with_routing_strategy(
+            vec![
+                Box::new(FailingExecutionAdapter),
+                Box::new(test_execution_adapter),
+            ],
+            Box::new(RoundRobinStrategy::new()),
+        );
+
+        executor.start().expect("Executor did not correctly start");
+
+        adapter.register("test1", "1.0");
+        adapter.register("test2", "1.0");
+
+        let iterator = MockTaskExecutionIterator::new();
+        let notifier = MockExecutionTaskCompletionNotifier::new();
+
+        executor
+            .execute(Box::new(iterator), Box::new(notifier.clone()))
+            .expect("Start has been called so the executor can execute");
+
+        std::thread::sleep(Duration::from_millis(200));
+
+        assert_eq!(
+            notifier.num_results(),
+            NUMBER_OF_TRANSACTIONS,
+            "All transactions received a result"
+        );
+    }
+
+    /// Tests that a transaction which every adapter fails to execute is reported invalid, without
+    /// anything written to its context.
+    #[test]
+    fn test_executor_all_adapters_fail() {
+        let mut context_manager = ContextManager::new(Box::new(HashMapState::new()));
+        let context_id =
+            context_manager.create_context(&[], &HashMapState::state_id(&HashMap::new()));
+
+        let mut executor = Executor::new(vec![
+            Box::new(FailingExecutionAdapter),
+            Box::new(FailingExecutionAdapter),
+        ]);
+        executor.start().expect("Executor did not correctly start");
+
+        let pair = create_txn(&HashSigner::default(), FAMILY_NAME1);
+        let transaction_id = pair.transaction().header_signature().to_string();
+        let notifier = MockExecutionTaskCompletionNotifier::new();
+        executor
+            .execute(
+                Box::new(vec![ExecutionTask::new(pair, context_id)].into_iter()),
+                Box::new(notifier.clone()),
+            )
+            .expect("Start has been called so the executor can execute");
+
+        std::thread::sleep(Duration::from_millis(200));
+
+        match notifier.take_results().as_slice() {
+            [ExecutionTaskCompletionNotification::Invalid(id, result)] => {
+                assert_eq!(id, &context_id);
+                assert_eq!(result.transaction_id, transaction_id);
+            }
+            _ => panic!("Expected a single invalid result"),
+        }
+        assert_clean_context(&context_manager, &context_id, &transaction_id);
+
+        executor.stop();
+    }
+
+    /// Tests that a transaction whose handler fails after changing its context is reported
+    /// invalid, rather than retried by another adapter on the changed context.
+    #[test]
+    fn test_executor_no_retry_after_execution_error() {
+        let mut context_manager = ContextManager::new(Box::new(HashMapState::new()));
+        let context_id =
+            context_manager.create_context(&[], &HashMapState::state_id(&HashMap::new()));
+
+        let applied = Arc::new(AtomicUsize::new(0));
+        let adapter = || {
+            StaticExecutionAdapter::new_adapter(
+                vec![Box::new(WriteThenFailHandler {
+                    family_versions: vec![FAMILY_VERSION.to_string()],
+                    applied: applied.clone(),
+                })],
+                context_manager.clone(),
+            )
+            .expect("Unable to create adapter")
+        };
+        let mut executor = Executor::new_with_routing_strategy(
+            vec![Box::new(adapter()), Box::new(adapter())],
+            Box::new(RoundRobinStrategy::new()),
+        );
+        executor.start().expect("Executor did not correctly start");
+
+        let pair = create_txn(&HashSigner::default(), FAMILY_NAME1);
+        let notifier = MockExecutionTaskCompletionNotifier::new();
+        executor
+            .execute(
+                Box::new(vec![ExecutionTask::new(pair, context_id)].into_iter()),
+                Box::new(notifier.clone()),
+            )
+            .expect("Start has been called so the executor can execute");
+
+        std::thread::sleep(Duration::from_millis(200));
+
+        match notifier.take_results().as_slice() {
+            [ExecutionTaskCompletionNotification::Invalid(id, _)] => assert_eq!(id, &context_id),
+            _ => panic!("Expected a single invalid result"),
+        }
+        assert_eq!(applied.load(Ordering::SeqCst), 1);
+
+        executor.stop();
+    }
+
+    /// Tests that a transaction which times out is reported invalid, rather than retried by
+    /// another adapter on a context its handler may have written to.
+    #[test]
+    fn test_executor_no_retry_after_timeout() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let mut executor = Executor::new(vec![
+            Box::new(TimeoutExecutionAdapter {
+                attempts: attempts.clone(),
+            }),
+            Box::new(TimeoutExecutionAdapter {
+                attempts: attempts.clone(),
+            }),
+        ]);
+        executor.start().expect("Executor did not correctly start");
+
+        let context_id = [0; 16];
+        let notifier = MockExecutionTaskCompletionNotifier::new();
+        executor
+            .execute(
+                Box::new(
+                    vec![ExecutionTask::new(
+                        create_txn(&HashSigner::default(), FAMILY_NAME1),
+                        context_id,
+                    )]
+                    .into_iter(),
+                ),
+                Box::new(notifier.clone()),
+            )
+            .expect("Start has been called so the executor can execute");
+
+        std::thread::sleep(Duration::from_millis(200));
+
+        match notifier.take_results().as_slice() {
+            [ExecutionTaskCompletionNotification::Invalid(id, _)] => assert_eq!(id, &context_id),
+            _ => panic!("Expected a single invalid result"),
+        }
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        executor.stop();
+    }
+
+    /// Tests that an adapter which failed is sent transactions again once it registers a family
+    /// again, including those it failed to execute.
+    #[test]
+    fn test_executor_adapter_recovers() {
+        let adapter = RecoveringExecutionAdapter::default();
+        let mut executor = Executor::new(vec![Box::new(adapter.clone())]);
+        executor.start().expect("Executor did not correctly start");
+
+        let signer = HashSigner::default();
+        let context_id = [0; 16];
+        let notifier = MockExecutionTaskCompletionNotifier::new();
+        executor
+            .execute(
+                Box::new(
+                    vec![ExecutionTask::new(
+                        create_txn(&signer, FAMILY_NAME1),
+                        context_id,
+                    )]
+                    .into_iter(),
+                ),
+                Box::new(notifier.clone()),
+            )
+            .expect("Start has been called so the executor can execute");
+
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(notifier.num_results(), 0);
+
+        adapter.recover();
+        executor
+            .execute(
+                Box::new(
+                    vec![ExecutionTask::new(
+                        create_txn(&signer, FAMILY_NAME2),
+                        context_id,
+                    )]
+                    .into_iter(),
+                ),
+                Box::new(notifier.clone()),
+            )
+            .expect("Start has been called so the executor can execute");
+
+        std::thread::sleep(Duration::from_millis(200));
+
+        let results = notifier.take_results();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| match result {
+            ExecutionTaskCompletionNotification::Valid(..) => true,
+            _ => false,
+        }));
+
+        executor.stop();
+    }
+
+    fn assert_clean_context(
+        context_manager: &ContextManager,
+        context_id: &ContextId,
+        transaction_id: &str,
+    ) {
+        let receipt = context_manager
+            .get_transaction_receipt(context_id, transaction_id)
+            .expect("Unable to get receipt");
+        match receipt.transaction_result {
+            TransactionResult::Valid {
+                state_changes,
+                events,
+                data,
+            } => {
+                assert!(state_changes.is_empty());
+                assert!(events.is_empty());
+                assert!(data.is_empty());
+            }
+            TransactionResult::Invalid { .. } => panic!("Expected a receipt of the context"),
+        }
+    }
+
+    /// Writes to its context, then fails with an internal error.
+    struct WriteThenFailHandler {
+        family_versions: Vec<String>,
+        applied: Arc<AtomicUsize>,
+    }
+
+    impl TransactionHandler for WriteThenFailHandler {
+        fn family_name(&self) -> &str {
+            FAMILY_NAME1
+        }
+
+        fn family_versions(&self) -> &[String] {
+            &self.family_versions
+        }
+
+        fn apply(
+            &self,
+            _transaction: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            self.applied.fetch_add(1, Ordering::SeqCst);
+            context
+                .set_state_entry(KEY6.into(), BYTES2.to_vec())
+                .map_err(|err| ApplyError::InternalError(err.to_string()))?;
+            Err(ApplyError::InternalError("handler failed".into()))
+        }
+    }
+
+    /// Registers both test families, but fails to execute any transaction.
+    struct FailingExecutionAdapter;
+
+    impl ExecutionAdapter for FailingExecutionAdapter {
+        fn start(
+            &mut self,
+            mut execution_registry: Box<dyn ExecutionRegistry>,
+        ) -> Result<(), ExecutionOperationError> {
+            for family_name in &[FAMILY_NAME1, FAMILY_NAME2] {
+                execution_registry.register_transaction_family(TransactionFamily::new(
+                    family_name.to_string(),
+                    FAMILY_VERSION.to_string(),
+                ));
+            }
+            Ok(())
+        }
+
+        fn execute(
+            &self,
+            _transaction_pair: TransactionPair,
+            _context_id: ContextId,
+            _on_done: Box<
+                dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send,
+            >,
+        ) -> Result<(), ExecutionOperationError> {
+            Err(ExecutionOperationError::ExecuteError(
+                "Adapter has failed".into(),
+            ))
+        }
+
+        fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
+            Ok(())
+        }
+    }
+
+    /// Registers the first test family, and times out executing any transaction.
+    struct TimeoutExecutionAdapter {
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl ExecutionAdapter for TimeoutExecutionAdapter {
+        fn start(
+            &mut self,
+            mut execution_registry: Box<dyn ExecutionRegistry>,
+        ) -> Result<(), ExecutionOperationError> {
+            execution_registry.register_transaction_family(TransactionFamily::new(
+                FAMILY_NAME1.to_string(),
+                FAMILY_VERSION.to_string(),
+            ));
+            Ok(())
+        }
+
+        fn execute(
+            &self,
+            transaction_pair: TransactionPair,
+            _context_id: ContextId,
+            on_done: Box<
+                dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send,
+            >,
+        ) -> Result<(), ExecutionOperationError> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            on_done(Err(ExecutionAdapterError::TimeoutError(Box::new(
+                transaction_pair,
+            ))));
+            Ok(())
+        }
+
+        fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
+            Ok(())
+        }
+    }
+
+    /// Registers both test families, and fails to execute any transaction until it recovers, when
+    /// it registers them again.
+    #[derive(Clone, Default)]
+    struct RecoveringExecutionAdapter {
+        registry: Arc<Mutex<Option<Box<dyn ExecutionRegistry>>>>,
+        recovered: Arc<AtomicBool>,
+    }
+
+    impl RecoveringExecutionAdapter {
+        fn recover(&self) {
+            self.recovered.store(true, Ordering::SeqCst);
+            if let Some(registry) = self
+                .registry
+                .lock()
+                .expect("Registry lock is poisoned")
+                .as_mut()
+            {
+                register_test_families(&mut **registry);
+            }
+        }
+    }
+
+    impl ExecutionAdapter for RecoveringExecutionAdapter {
+        fn start(
+            &mut self,
+            mut execution_registry: Box<dyn ExecutionRegistry>,
+        ) -> Result<(), ExecutionOperationError> {
+            register_test_families(&mut *execution_registry);
+            *self.registry.lock().expect("Registry lock is poisoned") = Some(execution_registry);
+            Ok(())
+        }
+
+        fn execute(
+            &self,
+            transaction_pair: TransactionPair,
+            context_id: ContextId,
+            on_done: Box<
+                dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send,
+            >,
+        ) -> Result<(), ExecutionOperationError> {
+            if !self.recovered.load(Ordering::SeqCst) {
+                return Err(ExecutionOperationError::ExecuteError(
+                    "Adapter has failed".into(),
+                ));
+            }
+            on_done(Ok(ExecutionTaskCompletionNotification::Valid(
+                context_id,
+                transaction_pair.transaction().header_signature().into(),
+            )));
+            Ok(())
+        }
+
+        fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
+            Ok(())
+        }
+    }
+
+    fn register_test_families(execution_registry: &mut dyn ExecutionRegistry) {
+        for family_name in &[FAMILY_NAME1, FAMILY_NAME2] {
+            execution_registry.register_transaction_family(TransactionFamily::new(
+                family_name.to_string(),
+                FAMILY_VERSION.to_string(),
+            ));
+        }
+    }
+
     fn create_txn(signer: &dyn Signer, family_name: &str) -> TransactionPair {
         TransactionBuilder::new()
             .with_batcher_public_key(hex::decode(KEY1).unwrap())
@@ -287,6 +716,14 @@ mod tests {
                 .expect("The MockTaskExecutionIterator lock is poisoned")
                 .len()
         }
+
+        fn take_results(&self) -> Vec<ExecutionTaskCompletionNotification> {
+            self.results
+                .lock()
+                .expect("The MockTaskExecutionIterator lock is poisoned")
+                .drain(..)
+                .collect()
+        }
     }
 
     impl ExecutionTaskCompletionNotifier for MockExecutionTaskCompletionNotifier {
@@ -0,0 +1,181 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Strategies for choosing which of several execution adapters executes a transaction.
+//!
+//! Adapters are identified by their index in the list of adapters the `Executor` was created
+//! with.
+
+use std::collections::HashMap;
+
+use crate::execution::TransactionFamily;
+
+/// An adapter which may execute a transaction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RoutingCandidate {
+    adapter_index: usize,
+    outstanding: usize,
+}
+
+impl RoutingCandidate {
+    pub fn new(adapter_index: usize, outstanding: usize) -> Self {
+        RoutingCandidate {
+            adapter_index,
+            outstanding,
+        }
+    }
+
+    /// The index of the adapter in the list of adapters the `Executor` was created with.
+    pub fn adapter_index(&self) -> usize {
+        self.adapter_index
+    }
+
+    /// The number of transactions the adapter is currently executing.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding
+    }
+}
+
+/// Chooses which of the adapters registered for a transaction family executes a transaction.
+pub trait RoutingStrategy: Send {
+    /// Returns the index of the adapter which should execute a transaction supported by the given
+    /// registered family.
+    ///
+    /// `candidates` is never empty, and is ordered by adapter index. If the returned index is not
+    /// one of the candidates, the first candidate is used.
+    fn select(&mut self, family: &TransactionFamily, candidates: &[RoutingCandidate]) -> usize;
+}
+
+/// Sends the transactions of each family to its adapters in turn.
+#[derive(Default)]
+pub struct RoundRobinStrategy {
+    next: HashMap<TransactionFamily, usize>,
+}
+
+impl RoundRobinStrategy {
+    pub fn new() -> Self {
+        RoundRobinStrategy::default()
+    }
+}
+
+impl RoutingStrategy for RoundRobinStrategy {
+    fn select(&mut self, family: &TransactionFamily, candidates: &[RoutingCandidate]) -> usize {
+        let next = self.next.entry(family.clone()).or_insert(0);
+        let selected = candidates[*next % candidates.len()].adapter_index;
+        *next = next.wrapping_add(1);
+        selected
+    }
+}
+
+/// Sends each transaction to the adapter executing the fewest transactions, preferring the lowest
+/// index when several are equally loaded.
+///
+/// This is the `Executor`'s default strategy.
+#[derive(Default)]
+pub struct LeastOutstandingStrategy;
+
+impl LeastOutstandingStrategy {
+    pub fn new() -> Self {
+        LeastOutstandingStrategy
+    }
+}
+
+impl RoutingStrategy for LeastOutstandingStrategy {
+    fn select(&mut self, _family: &TransactionFamily, candidates: &[RoutingCandidate]) -> usize {
+        least_outstanding(candidates)
+    }
+}
+
+/// Sends all of a family's transactions to the same adapter, so long as it remains registered for
+/// the family; when it is not, the least loaded candidate takes its place.
+#[derive(Default)]
+pub struct StickyByFamilyStrategy {
+    assignments: HashMap<TransactionFamily, usize>,
+}
+
+impl StickyByFamilyStrategy {
+    pub fn new() -> Self {
+        StickyByFamilyStrategy::default()
+    }
+}
+
+impl RoutingStrategy for StickyByFamilyStrategy {
+    fn select(&mut self, family: &TransactionFamily, candidates: &[RoutingCandidate]) -> usize {
+        if let Some(assigned) = self.assignments.get(family) {
+            if candidates
+                .iter()
+                .any(|candidate| candidate.adapter_index == *assigned)
+            {
+                return *assigned;
+            }
+        }
+        let selected = least_outstanding(candidates);
+        self.assignments.insert(family.clone(), selected);
+        selected
+    }
+}
+
+fn least_outstanding(candidates: &[RoutingCandidate]) -> usize {
+    candidates
+        .iter()
+        .min_by_key(|candidate| (candidate.outstanding, candidate.adapter_index))
+        .map(|candidate| candidate.adapter_index)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn family(name: &str) -> TransactionFamily {
+        TransactionFamily::new(name.into(), "1.0".into())
+    }
+
+    /// Tests that each strategy selects adapters as documented.
+    #[test]
+    fn test_routing_strategies() {
+        let candidates = vec![
+            RoutingCandidate::new(0, 3),
+            RoutingCandidate::new(2, 1),
+            RoutingCandidate::new(5, 1),
+        ];
+
+        let mut round_robin = RoundRobinStrategy::new();
+        let selected = (0..4)
+            .map(|_| round_robin.select(&family("a"), &candidates))
+            .collect::<Vec<_>>();
+        assert_eq!(selected, vec![0, 2, 5, 0]);
+        // Each family has its own rotation
+        assert_eq!(round_robin.select(&family("b"), &candidates), 0);
+
+        let mut least_outstanding = LeastOutstandingStrategy::new();
+        assert_eq!(least_outstanding.select(&family("a"), &candidates), 2);
+
+        let mut sticky = StickyByFamilyStrategy::new();
+        assert_eq!(sticky.select(&family("a"), &candidates), 2);
+        let loaded = vec![
+            RoutingCandidate::new(0, 0),
+            RoutingCandidate::new(2, 10),
+            RoutingCandidate::new(5, 0),
+        ];
+        assert_eq!(sticky.select(&family("a"), &loaded), 2);
+        // Once the assigned adapter is no longer a candidate, another takes its place
+        let remaining = vec![RoutingCandidate::new(0, 4), RoutingCandidate::new(5, 2)];
+        assert_eq!(sticky.select(&family("a"), &remaining), 5);
+        assert_eq!(sticky.select(&family("a"), &loaded), 5);
+    }
+}
@@ -20,31 +20,58 @@
 
 pub mod adapter;
 pub mod executor;
+mod version;
+
+pub use self::version::{FamilyVersion, VersionError, VersionRequirement};
+
+use std::hash::{Hash, Hasher};
 
 use crate::protocol::transaction::TransactionPair;
 
 /// A Transaction Family Descriptor
-#[derive(Eq, PartialEq, Debug, Hash, Clone)]
+///
+/// The family version is either the version declared by a transaction, or, when the family is
+/// registered by a handler, a `VersionRequirement` describing the versions the handler supports.
+/// A registered family supports transactions whose version string is equal to its own, or whose
+/// version is accepted by its requirement.
+///
+/// Families are equal if their names and version strings are.
+#[derive(Debug, Clone)]
 pub struct TransactionFamily {
     family_name: String,
     family_version: String,
+    version: Option<FamilyVersion>,
+    version_requirement: Option<VersionRequirement>,
 }
 
 impl TransactionFamily {
     /// Constructs a new Transaction Family Descriptor.
+    ///
+    /// The version is parsed both as a version and as a requirement, so a family registered by
+    /// a handler should be constructed once, when it is registered, rather than for each
+    /// transaction routed to it.
     pub fn new(family_name: String, family_version: String) -> Self {
+        let version = family_version.parse().ok();
+        let version_requirement = family_version.parse().ok();
         TransactionFamily {
             family_name,
             family_version,
+            version,
+            version_requirement,
         }
     }
 
     /// Creates a Transaction Family Descriptor using the information in a TransactionPair.
+    ///
+    /// A transaction declares a version rather than a requirement, so only the version is parsed.
     pub fn from_pair(transaction_pair: &TransactionPair) -> Self {
-        Self::new(
-            transaction_pair.header().family_name().to_string(),
-            transaction_pair.header().family_version().to_string(),
-        )
+        let family_version = transaction_pair.header().family_version().to_string();
+        TransactionFamily {
+            family_name: transaction_pair.header().family_name().to_string(),
+            version: family_version.parse().ok(),
+            version_requirement: None,
+            family_version,
+        }
     }
 
     pub fn family_name(&self) -> &str {
@@ -54,6 +81,61 @@ impl TransactionFamily {
     pub fn family_version(&self) -> &str {
         &self.family_version
     }
+
+    /// The family version, if it is a semantic version.
+    pub fn version(&self) -> Option<&FamilyVersion> {
+        self.version.as_ref()
+    }
+
+    /// The family version as a requirement, if it is one.
+    pub fn version_requirement(&self) -> Option<&VersionRequirement> {
+        self.version_requirement.as_ref()
+    }
+
+    /// Whether this registered family supports transactions of the given family.
+    pub fn supports(&self, family: &TransactionFamily) -> bool {
+        self.family_name == family.family_name
+            && (self.family_version == family.family_version
+                || match (&self.version_requirement, &family.version) {
+                    (Some(requirement), Some(version)) => requirement.matches(version),
+                    _ => false,
+                })
+    }
+
+    /// The lowest version this registered family's requirement accepts.
+    ///
+    /// Of several registered families supporting a transaction which accept the same highest
+    /// version, the one with the highest minimum version is the most specific, and is preferred.
+    pub fn minimum_version(&self) -> Option<FamilyVersion> {
+        self.version_requirement
+            .as_ref()
+            .map(VersionRequirement::minimum)
+    }
+
+    /// The highest version this registered family's requirement accepts.
+    ///
+    /// Of several registered families supporting a transaction, the one accepting the highest
+    /// version is the most compatible, and is preferred.
+    pub fn maximum_version(&self) -> Option<FamilyVersion> {
+        self.version_requirement
+            .as_ref()
+            .map(VersionRequirement::maximum)
+    }
+}
+
+impl PartialEq for TransactionFamily {
+    fn eq(&self, other: &Self) -> bool {
+        self.family_name == other.family_name && self.family_version == other.family_version
+    }
+}
+
+impl Eq for TransactionFamily {}
+
+impl Hash for TransactionFamily {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.family_name.hash(state);
+        self.family_version.hash(state);
+    }
 }
 
 /// The registry of transaction families
@@ -69,3 +151,33 @@ pub trait ExecutionRegistry: Send {
     /// Signals that a transaction family can no longer be processed by an ExecutionAdapter.
     fn unregister_transaction_family(&mut self, family: &TransactionFamily);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn family(version: &str) -> TransactionFamily {
+        TransactionFamily::new("test".into(), version.into())
+    }
+
+    /// Tests that a registered family supports transactions with an equal version string, or a
+    /// version its requirement accepts.
+    #[test]
+    fn test_family_supports() {
+        assert_eq!(family("1.2").version(), Some(&FamilyVersion::new(1, 2, 0)));
+        assert!(family("^1.2").version().is_none());
+        assert!(family("v1-beta").version_requirement().is_none());
+
+        assert!(family("1.0").supports(&family("1.0")));
+        assert!(!family("1.0").supports(&family("1.1")));
+        assert!(family("^1.2").supports(&family("1.4")));
+        assert!(!family("^1.2").supports(&family("2.0")));
+        assert!(family(">=1.0,<2.0").supports(&family("1.9.9")));
+        assert!(family("*").supports(&family("3.1")));
+        assert!(family("v1-beta").supports(&family("v1-beta")));
+        assert!(!TransactionFamily::new("other".into(), "*".into()).supports(&family("1.0")));
+
+        assert!(family("^1.2").minimum_version() > family("^1.0").minimum_version());
+        assert!(family("^1.0").maximum_version() > family("~1.2").maximum_version());
+    }
+}
@@ -0,0 +1,399 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Semantic versions of transaction families, and the version requirements with which handlers
+//! register the versions they support.
+
+use std::str::FromStr;
+
+/// A transaction family version of the form `major[.minor[.patch]]`; omitted components are
+/// zero, so `"1.0"` is the version 1.0.0.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FamilyVersion {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl FamilyVersion {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        FamilyVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    pub fn major(&self) -> u64 {
+        self.major
+    }
+
+    pub fn minor(&self) -> u64 {
+        self.minor
+    }
+
+    pub fn patch(&self) -> u64 {
+        self.patch
+    }
+
+    /// The greatest version below this one, or 0.0.0 if there is none.
+    fn predecessor(self) -> Self {
+        match (self.major, self.minor, self.patch) {
+            (major, minor, patch) if patch > 0 => FamilyVersion::new(major, minor, patch - 1),
+            (major, minor, _) if minor > 0 => FamilyVersion::new(major, minor - 1, u64::MAX),
+            (major, _, _) if major > 0 => FamilyVersion::new(major - 1, u64::MAX, u64::MAX),
+            _ => self,
+        }
+    }
+}
+
+impl FromStr for FamilyVersion {
+    type Err = VersionError;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        match PartialVersion::parse(version.trim()) {
+            Some(PartialVersion {
+                major: Some(major),
+                minor,
+                patch,
+                wildcard: false,
+            }) => Ok(FamilyVersion::new(
+                major,
+                minor.unwrap_or(0),
+                patch.unwrap_or(0),
+            )),
+            _ => Err(VersionError::InvalidVersion(version.into())),
+        }
+    }
+}
+
+impl std::fmt::Display for FamilyVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// A set of family versions, written as comma-separated comparators which a version must all
+/// satisfy.
+///
+/// The comparators follow Cargo's syntax:
+///
+/// * `^1.2` accepts versions compatible with 1.2.0, from 1.2.0 up to, but not including, 2.0.0
+/// * `~1.2` accepts versions from 1.2.0 up to, but not including, 1.3.0
+/// * `>=1.0`, `>1.0`, `<2.0` and `<=2.0` compare versions
+/// * `*`, `1.*` and `1.2.*` accept any version, any 1.x version, and any 1.2.x version
+///
+/// Unlike Cargo, a bare version, such as `1.0`, accepts only that version, as does `=1.0`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct VersionRequirement {
+    comparators: Vec<Comparator>,
+}
+
+impl VersionRequirement {
+    /// Whether the requirement accepts the version.
+    pub fn matches(&self, version: &FamilyVersion) -> bool {
+        self.comparators
+            .iter()
+            .all(|comparator| comparator.matches(version))
+    }
+
+    /// The lowest version the requirement could accept.
+    pub fn minimum(&self) -> FamilyVersion {
+        self.comparators
+            .iter()
+            .filter_map(|comparator| match comparator.op {
+                Op::Exact | Op::GreaterEq => Some(comparator.version),
+                Op::Greater => Some(FamilyVersion::new(
+                    comparator.version.major,
+                    comparator.version.minor,
+                    comparator.version.patch + 1,
+                )),
+                Op::Less | Op::LessEq => None,
+            })
+            .max()
+            .unwrap_or_else(|| FamilyVersion::new(0, 0, 0))
+    }
+
+    /// The highest version the requirement could accept; a requirement without an upper bound
+    /// could accept any version, up to the greatest representable one.
+    pub fn maximum(&self) -> FamilyVersion {
+        self.comparators
+            .iter()
+            .filter_map(|comparator| match comparator.op {
+                Op::Exact | Op::LessEq => Some(comparator.version),
+                Op::Less => Some(comparator.version.predecessor()),
+                Op::Greater | Op::GreaterEq => None,
+            })
+            .min()
+            .unwrap_or_else(|| FamilyVersion::new(u64::MAX, u64::MAX, u64::MAX))
+    }
+}
+
+impl FromStr for VersionRequirement {
+    type Err = VersionError;
+
+    fn from_str(requirement: &str) -> Result<Self, Self::Err> {
+        let mut comparators = vec![];
+        for term in requirement.split(',') {
+            comparators.extend(
+                parse_term(term.trim())
+                    .ok_or_else(|| VersionError::InvalidRequirement(requirement.into()))?,
+            );
+        }
+        Ok(VersionRequirement { comparators })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VersionError {
+    InvalidVersion(String),
+    InvalidRequirement(String),
+}
+
+impl std::error::Error for VersionError {}
+
+impl std::fmt::Display for VersionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VersionError::InvalidVersion(version) => {
+                write!(f, "invalid family version: {}", version)
+            }
+            VersionError::InvalidRequirement(requirement) => {
+                write!(f, "invalid family version requirement: {}", requirement)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Comparator {
+    op: Op,
+    version: FamilyVersion,
+}
+
+impl Comparator {
+    fn new(op: Op, major: u64, minor: u64, patch: u64) -> Self {
+        Comparator {
+            op,
+            version: FamilyVersion::new(major, minor, patch),
+        }
+    }
+
+    fn matches(&self, version: &FamilyVersion) -> bool {
+        match self.op {
+            Op::Exact => *version == self.version,
+            Op::Greater => *version > self.version,
+            Op::GreaterEq => *version >= self.version,
+            Op::Less => *version < self.version,
+            Op::LessEq => *version <= self.version,
+        }
+    }
+}
+
+/// A version which may omit components, or end in a wildcard.
+struct PartialVersion {
+    major: Option<u64>,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    wildcard: bool,
+}
+
+impl PartialVersion {
+    fn parse(version: &str) -> Option<Self> {
+        let mut components = [None; 3];
+        let mut wildcard = false;
+        let mut count = 0;
+        for component in version.split('.') {
+            if count == components.len() || wildcard {
+                return None;
+            }
+            match component {
+                "*" | "x" | "X" => wildcard = true,
+                _ if !component.is_empty() && component.bytes().all(|b| b.is_ascii_digit()) => {
+                    components[count] = Some(component.parse().ok()?);
+                }
+                _ => return None,
+            }
+            count += 1;
+        }
+        Some(PartialVersion {
+            major: components[0],
+            minor: components[1],
+            patch: components[2],
+            wildcard,
+        })
+    }
+}
+
+/// Converts a single comparator, such as `^1.2` or `1.*`, into the comparators it stands for.
+fn parse_term(term: &str) -> Option<Vec<Comparator>> {
+    let (op, version) = [">=", "<=", ">", "<", "=", "^", "~"]
+        .iter()
+        .find(|op| term.starts_with(*op))
+        .map(|op| (*op, term[op.len()..].trim_start()))
+        .unwrap_or(("", term));
+    let PartialVersion {
+        major,
+        minor,
+        patch,
+        wildcard,
+    } = PartialVersion::parse(version)?;
+
+    let major = match major {
+        Some(major) => major,
+        // A lone wildcard accepts any version
+        None if wildcard && op.is_empty() => return Some(vec![]),
+        None => return None,
+    };
+    let lower = Comparator::new(Op::GreaterEq, major, minor.unwrap_or(0), patch.unwrap_or(0));
+    // The comparators accepting every version which starts with the given components
+    let prefix = || match minor {
+        Some(minor) => vec![lower, Comparator::new(Op::Less, major, minor + 1, 0)],
+        None => vec![lower, Comparator::new(Op::Less, major + 1, 0, 0)],
+    };
+
+    Some(match (op, minor, patch) {
+        ("", _, _) | ("=", _, _) if wildcard => prefix(),
+        ("", minor, patch) | ("=", minor, patch) => vec![Comparator::new(
+            Op::Exact,
+            major,
+            minor.unwrap_or(0),
+            patch.unwrap_or(0),
+        )],
+        ("^", _, _) if major > 0 || minor.is_none() => {
+            vec![lower, Comparator::new(Op::Less, major + 1, 0, 0)]
+        }
+        ("^", Some(minor), _) if minor > 0 || patch.is_none() => {
+            vec![lower, Comparator::new(Op::Less, 0, minor + 1, 0)]
+        }
+        ("^", Some(minor), Some(patch)) => {
+            vec![lower, Comparator::new(Op::Less, 0, minor, patch + 1)]
+        }
+        ("~", _, _) => prefix(),
+        (">=", _, _) => vec![lower],
+        (">", Some(minor), Some(patch)) => vec![Comparator::new(Op::Greater, major, minor, patch)],
+        (">", Some(minor), None) => vec![Comparator::new(Op::GreaterEq, major, minor + 1, 0)],
+        (">", None, _) => vec![Comparator::new(Op::GreaterEq, major + 1, 0, 0)],
+        ("<", minor, patch) => vec![Comparator::new(
+            Op::Less,
+            major,
+            minor.unwrap_or(0),
+            patch.unwrap_or(0),
+        )],
+        ("<=", Some(minor), Some(patch)) => vec![Comparator::new(Op::LessEq, major, minor, patch)],
+        ("<=", Some(minor), None) => vec![Comparator::new(Op::Less, major, minor + 1, 0)],
+        ("<=", None, _) => vec![Comparator::new(Op::Less, major + 1, 0, 0)],
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str) -> FamilyVersion {
+        version.parse().expect("Unable to parse version")
+    }
+
+    fn requirement(requirement: &str) -> VersionRequirement {
+        requirement.parse().expect("Unable to parse requirement")
+    }
+
+    /// Tests that versions are parsed with omitted components as zero, and that invalid versions
+    /// are rejected.
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(version("1"), FamilyVersion::new(1, 0, 0));
+        assert_eq!(version("1.2"), FamilyVersion::new(1, 2, 0));
+        assert_eq!(version("1.2.3"), FamilyVersion::new(1, 2, 3));
+        assert_eq!(version("1.2.3").to_string(), "1.2.3");
+
+        for invalid in &["", "1.", "1.2.3.4", "v1", "1.*", "1.2-beta"] {
+            assert_eq!(
+                invalid.parse::<FamilyVersion>(),
+                Err(VersionError::InvalidVersion(invalid.to_string()))
+            );
+        }
+    }
+
+    /// Tests that each form of requirement accepts the documented versions.
+    #[test]
+    fn test_requirement_matches() {
+        let cases = vec![
+            ("1.0", vec!["1.0", "1.0.0"], vec!["1.0.1", "1.1", "0.9"]),
+            ("=1.2.3", vec!["1.2.3"], vec!["1.2.4"]),
+            ("^1.2", vec!["1.2", "1.9.9"], vec!["1.1", "2.0"]),
+            ("^0.2", vec!["0.2", "0.2.7"], vec!["0.3", "0.1"]),
+            ("^0.0.3", vec!["0.0.3"], vec!["0.0.4"]),
+            ("~1.2", vec!["1.2", "1.2.9"], vec!["1.3"]),
+            (">=1.0,<2.0", vec!["1.0", "1.5.2"], vec!["0.9", "2.0"]),
+            (">= 1.0, < 2.0", vec!["1.0", "1.5.2"], vec!["0.9", "2.0"]),
+            (">1.2", vec!["1.3"], vec!["1.2.9"]),
+            ("<=1.2", vec!["1.2.9"], vec!["1.3"]),
+            ("*", vec!["0.1", "9.9.9"], vec![]),
+            ("1.*", vec!["1.0", "1.9"], vec!["2.0"]),
+            ("1.2.x", vec!["1.2.0", "1.2.5"], vec!["1.3"]),
+        ];
+        for (req, accepted, rejected) in cases {
+            let parsed = requirement(req);
+            for v in accepted {
+                assert!(parsed.matches(&version(v)), "{} should accept {}", req, v);
+            }
+            for v in rejected {
+                assert!(!parsed.matches(&version(v)), "{} should reject {}", req, v);
+            }
+        }
+
+        assert_eq!(requirement("^1.2").minimum(), version("1.2"));
+        assert_eq!(requirement(">1.2.3,<2").minimum(), version("1.2.4"));
+        assert_eq!(requirement("*").minimum(), version("0"));
+
+        let max = u64::MAX;
+        assert_eq!(
+            requirement("^1.2").maximum(),
+            FamilyVersion::new(1, max, max)
+        );
+        assert_eq!(requirement("~1.2").maximum(), FamilyVersion::new(1, 2, max));
+        assert_eq!(requirement("^0.0.3").maximum(), version("0.0.3"));
+        assert_eq!(requirement(">=1.0,<=1.4.2").maximum(), version("1.4.2"));
+        assert_eq!(requirement("=1.2").maximum(), version("1.2"));
+        assert_eq!(
+            requirement(">=1.0").maximum(),
+            FamilyVersion::new(max, max, max)
+        );
+        assert_eq!(
+            requirement("*").maximum(),
+            FamilyVersion::new(max, max, max)
+        );
+
+        for invalid in &["", "^", "1.*.2", ">=1.0,", "^*", "1.0 || 2.0"] {
+            assert_eq!(
+                invalid.parse::<VersionRequirement>(),
+                Err(VersionError::InvalidRequirement(invalid.to_string()))
+            );
+        }
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::error::Error;
+use std::fmt;
+
+use crate::contract::context::error::ContractContextError;
+use crate::database::DatabaseError;
+use crate::protos::ProtoConversionError;
+use crate::scheduler::simulation::SimulationError;
+use crate::state::merkle::StateDatabaseError;
+use crate::state::StateWriteError;
+
+#[derive(Debug)]
+pub enum GenesisError {
+    /// The database already contains state.
+    NonEmptyDatabase,
+    /// The database could not be read.
+    DatabaseError(DatabaseError),
+    /// The empty state could not be created in the database.
+    StateDatabaseError(StateDatabaseError),
+    /// The initial state could not be committed.
+    StateWriteError(StateWriteError),
+    /// A key-value entry could not be set.
+    KeyValueError(ContractContextError),
+    /// The genesis data could not be serialized.
+    ProtoConversionError(ProtoConversionError),
+    /// The genesis data has batches, but no executor was given to execute them.
+    NoExecutor,
+    /// The batches could not be executed.
+    SimulationError(SimulationError),
+    /// A batch was invalid; every genesis batch must be valid.
+    InvalidBatch(String),
+    /// A genesis record does not match the genesis data or the state built from it.
+    RecordMismatch(String),
+}
+
+impl Error for GenesisError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GenesisError::NonEmptyDatabase => None,
+            GenesisError::DatabaseError(err) => Some(err),
+            GenesisError::StateDatabaseError(err) => Some(err),
+            GenesisError::StateWriteError(err) => Some(err),
+            GenesisError::KeyValueError(err) => Some(err),
+            GenesisError::ProtoConversionError(err) => Some(err),
+            GenesisError::NoExecutor => None,
+            GenesisError::SimulationError(err) => Some(err),
+            GenesisError::InvalidBatch(_) => None,
+            GenesisError::RecordMismatch(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GenesisError::NonEmptyDatabase => {
+                f.write_str("genesis state must be built in an empty database")
+            }
+            GenesisError::DatabaseError(err) => write!(f, "unable to read database: {}", err),
+            GenesisError::StateDatabaseError(err) => {
+                write!(f, "unable to create empty state: {}", err)
+            }
+            GenesisError::StateWriteError(err) => {
+                write!(f, "unable to commit genesis state: {}", err)
+            }
+            GenesisError::KeyValueError(err) => {
+                write!(f, "unable to set key-value entry: {}", err)
+            }
+            GenesisError::ProtoConversionError(err) => {
+                write!(f, "unable to serialize genesis data: {}", err)
+            }
+            GenesisError::NoExecutor => {
+                f.write_str("genesis data has batches, but no executor was given")
+            }
+            GenesisError::SimulationError(err) => {
+                write!(f, "unable to execute genesis batches: {}", err)
+            }
+            GenesisError::InvalidBatch(msg) => write!(f, "invalid genesis batch: {}", msg),
+            GenesisError::RecordMismatch(msg) => {
+                write!(f, "genesis record does not match: {}", msg)
+            }
+        }
+    }
+}
+
+impl From<DatabaseError> for GenesisError {
+    fn from(err: DatabaseError) -> Self {
+        GenesisError::DatabaseError(err)
+    }
+}
+
+impl From<StateDatabaseError> for GenesisError {
+    fn from(err: StateDatabaseError) -> Self {
+        GenesisError::StateDatabaseError(err)
+    }
+}
+
+impl From<StateWriteError> for GenesisError {
+    fn from(err: StateWriteError) -> Self {
+        GenesisError::StateWriteError(err)
+    }
+}
+
+impl From<ContractContextError> for GenesisError {
+    fn from(err: ContractContextError) -> Self {
+        GenesisError::KeyValueError(err)
+    }
+}
+
+impl From<ProtoConversionError> for GenesisError {
+    fn from(err: ProtoConversionError) -> Self {
+        GenesisError::ProtoConversionError(err)
+    }
+}
+
+impl From<SimulationError> for GenesisError {
+    fn from(err: SimulationError) -> Self {
+        GenesisError::SimulationError(err)
+    }
+}
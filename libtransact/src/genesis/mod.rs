@@ -0,0 +1,599 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Building and checking the initial state of a network.
+//!
+//! `GenesisData` declares the initial state: data set at addresses, values set at natural keys
+//! through a `KeyValueTransactionContext`, and batches executed on top of them. A
+//! `GenesisBuilder` builds this state in an empty database and returns a `GenesisRecord` with the
+//! resulting state root and a hash of the data, so that every node can build its own initial
+//! state from the same data and check that it matches the record.
+
+mod error;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use sha2::{Digest, Sha512};
+
+use crate::context::manager::sync::ContextManager;
+use crate::contract::address::key_hash::KeyHashAddresser;
+use crate::contract::context::key_value::KeyValueTransactionContext;
+use crate::database::Database;
+use crate::execution::executor::Executor;
+use crate::handler::{ContextError, TransactionContext};
+use crate::protocol::batch::BatchPair;
+use crate::protocol::key_value_state::StateEntryValue;
+use crate::protocol::receipt::TransactionResult;
+use crate::protos;
+use crate::protos::{
+    FromBytes, FromNative, FromProto, IntoBytes, IntoNative, IntoProto, ProtoConversionError,
+};
+use crate::scheduler::simulation::Simulator;
+use crate::state::merkle::{MerkleRadixTree, MerkleState};
+use crate::state::{self, Write};
+
+pub use crate::genesis::error::GenesisError;
+
+/// The initial state of a network.
+///
+/// The entries are set first, then the key-value entries, and then the batches are executed, in
+/// the order they were added.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GenesisData {
+    entries: Vec<(String, Vec<u8>)>,
+    key_value_entries: Vec<KeyValueEntry>,
+    batches: Vec<BatchPair>,
+}
+
+impl GenesisData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the data at the address.
+    pub fn with_entry(mut self, address: String, data: Vec<u8>) -> Self {
+        self.entries.push((address, data));
+        self
+    }
+
+    /// Sets the values at a natural key.
+    pub fn with_key_value_entry(mut self, entry: KeyValueEntry) -> Self {
+        self.key_value_entries.push(entry);
+        self
+    }
+
+    /// Executes the batch; every transaction in it must be valid.
+    pub fn with_batch(mut self, batch: BatchPair) -> Self {
+        self.batches.push(batch);
+        self
+    }
+
+    pub fn entries(&self) -> &[(String, Vec<u8>)] {
+        &self.entries
+    }
+
+    pub fn key_value_entries(&self) -> &[KeyValueEntry] {
+        &self.key_value_entries
+    }
+
+    pub fn batches(&self) -> &[BatchPair] {
+        &self.batches
+    }
+}
+
+/// Values set at a natural key, whose address is computed by a `KeyHashAddresser` with the given
+/// prefix.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyValueEntry {
+    prefix: String,
+    key: String,
+    values: Vec<StateEntryValue>,
+}
+
+impl KeyValueEntry {
+    pub fn new(prefix: String, key: String, values: Vec<StateEntryValue>) -> Self {
+        KeyValueEntry {
+            prefix,
+            key,
+            values,
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn values(&self) -> &[StateEntryValue] {
+        &self.values
+    }
+}
+
+/// The result of building the initial state declared by a `GenesisData`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenesisRecord {
+    state_root: String,
+    data_hash: String,
+}
+
+impl GenesisRecord {
+    pub fn new(state_root: String, data_hash: String) -> Self {
+        GenesisRecord {
+            state_root,
+            data_hash,
+        }
+    }
+
+    /// The state root of the initial state.
+    pub fn state_root(&self) -> &str {
+        &self.state_root
+    }
+
+    /// The SHA-512 hash of the serialized `GenesisData`, in hex.
+    pub fn data_hash(&self) -> &str {
+        &self.data_hash
+    }
+}
+
+/// Builds the initial state declared by a `GenesisData`.
+pub struct GenesisBuilder<'a> {
+    database: Box<dyn Database>,
+    executor: Option<(&'a Executor, ContextManager)>,
+}
+
+impl<'a> GenesisBuilder<'a> {
+    /// Creates a builder which builds the initial state in the given database, which must be
+    /// empty.
+    pub fn new(database: Box<dyn Database>) -> Self {
+        GenesisBuilder {
+            database,
+            executor: None,
+        }
+    }
+
+    /// Executes the genesis batches with the executor, which must already be started, and whose
+    /// adapters must use the given context manager. The context manager must read state from the
+    /// builder's database.
+    pub fn with_executor(
+        mut self,
+        executor: &'a Executor,
+        context_manager: ContextManager,
+    ) -> Self {
+        self.executor = Some((executor, context_manager));
+        self
+    }
+
+    /// Builds the initial state declared by the data, and returns its record.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `GenesisError` if the database is not empty, if the data has batches but no
+    /// executor was given, if any batch is invalid, or if the state cannot be written.
+    pub fn build(&self, data: &GenesisData) -> Result<GenesisRecord, GenesisError> {
+        let data_hash = hash(data)?;
+        if !data.batches.is_empty() && self.executor.is_none() {
+            return Err(GenesisError::NoExecutor);
+        }
+        if self.database.get_reader()?.count()? != 0 {
+            return Err(GenesisError::NonEmptyDatabase);
+        }
+
+        let state = MerkleState::new(self.database.clone());
+        let mut state_root = MerkleRadixTree::new(self.database.clone(), None)?.get_merkle_root();
+
+        let mut context = GenesisContext::default();
+        for (address, value) in &data.entries {
+            context.set(address.clone(), value.clone());
+        }
+        for entry in &data.key_value_entries {
+            let values = entry
+                .values
+                .iter()
+                .map(|value| (value.key().to_string(), value.value().clone()))
+                .collect::<HashMap<_, _>>();
+            KeyValueTransactionContext::new(
+                &mut context,
+                KeyHashAddresser::new(entry.prefix.clone()),
+            )
+            .set_state_entry(&entry.key, values)?;
+        }
+        state_root = state.commit(&state_root, &context.into_state_changes())?;
+
+        if let Some((executor, context_manager)) = &self.executor {
+            if !data.batches.is_empty() {
+                state_root = execute_batches(
+                    executor,
+                    context_manager,
+                    &state,
+                    &state_root,
+                    &data.batches,
+                )?;
+            }
+        }
+
+        Ok(GenesisRecord {
+            state_root,
+            data_hash,
+        })
+    }
+
+    /// Builds the initial state declared by the data, and checks that it matches the record.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `GenesisError::RecordMismatch` if the hash of the data or the state root differ
+    /// from the record's, or any error returned by `build`.
+    pub fn check(&self, data: &GenesisData, record: &GenesisRecord) -> Result<(), GenesisError> {
+        let data_hash = hash(data)?;
+        if data_hash != record.data_hash {
+            return Err(GenesisError::RecordMismatch(format!(
+                "data hash {} != {}",
+                data_hash, record.data_hash
+            )));
+        }
+
+        let built = self.build(data)?;
+        if built.state_root != record.state_root {
+            return Err(GenesisError::RecordMismatch(format!(
+                "state root {} != {}",
+                built.state_root, record.state_root
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn hash(data: &GenesisData) -> Result<String, GenesisError> {
+    let bytes = data.clone().into_bytes()?;
+    Ok(hex::encode(Sha512::digest(&bytes).as_slice()))
+}
+
+/// Executes the batches on top of `state_root`, and commits their changes if all are valid.
+fn execute_batches(
+    executor: &Executor,
+    context_manager: &ContextManager,
+    state: &MerkleState,
+    state_root: &str,
+    batches: &[BatchPair],
+) -> Result<String, GenesisError> {
+    let result = Simulator::new(executor, context_manager.clone(), state.clone())
+        .simulate_batches(state_root, batches.to_vec())?;
+
+    for batch_result in &result.batch_results {
+        for receipt in &batch_result.receipts {
+            if let TransactionResult::Invalid { error_message, .. } = &receipt.transaction_result {
+                return Err(GenesisError::InvalidBatch(format!(
+                    "transaction {} of batch {} is invalid: {}",
+                    receipt.transaction_id,
+                    batch_result.batch.batch().header_signature(),
+                    error_message
+                )));
+            }
+        }
+    }
+
+    Ok(state.commit(&state_root.to_string(), &result.state_changes())?)
+}
+
+/// A `TransactionContext` over the initial entries, before they are committed. Events and receipt
+/// data are not allowed, since there is no transaction to receive them.
+#[derive(Default)]
+struct GenesisContext {
+    state: RefCell<BTreeMap<String, Vec<u8>>>,
+}
+
+impl GenesisContext {
+    fn set(&self, address: String, value: Vec<u8>) {
+        self.state.borrow_mut().insert(address, value);
+    }
+
+    /// Returns the entries as changes, ordered by address.
+    fn into_state_changes(self) -> Vec<state::StateChange> {
+        self.state
+            .into_inner()
+            .into_iter()
+            .map(|(key, value)| state::StateChange::Set { key, value })
+            .collect()
+    }
+}
+
+impl TransactionContext for GenesisContext {
+    fn get_state_entries(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        let state = self.state.borrow();
+        Ok(addresses
+            .iter()
+            .filter_map(|address| {
+                state
+                    .get(address)
+                    .map(|value| (address.clone(), value.clone()))
+            })
+            .collect())
+    }
+
+    fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        for (address, value) in entries {
+            self.set(address, value);
+        }
+        Ok(())
+    }
+
+    fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+        let mut state = self.state.borrow_mut();
+        Ok(addresses
+            .iter()
+            .filter(|address| state.remove(*address).is_some())
+            .cloned()
+            .collect())
+    }
+
+    fn add_receipt_data(&self, _data: Vec<u8>) -> Result<(), ContextError> {
+        Err(ContextError::AuthorizationError(
+            "receipt data cannot be added to genesis state".into(),
+        ))
+    }
+
+    fn add_event(
+        &self,
+        _event_type: String,
+        _attributes: Vec<(String, String)>,
+        _data: Vec<u8>,
+    ) -> Result<(), ContextError> {
+        Err(ContextError::AuthorizationError(
+            "events cannot be added to genesis state".into(),
+        ))
+    }
+}
+
+impl FromProto<protos::genesis::GenesisKeyValueEntry> for KeyValueEntry {
+    fn from_proto(
+        mut entry: protos::genesis::GenesisKeyValueEntry,
+    ) -> Result<Self, ProtoConversionError> {
+        Ok(KeyValueEntry {
+            prefix: entry.take_prefix(),
+            key: entry.take_key(),
+            values: entry
+                .take_values()
+                .into_iter()
+                .map(StateEntryValue::from_proto)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl FromNative<KeyValueEntry> for protos::genesis::GenesisKeyValueEntry {
+    fn from_native(entry: KeyValueEntry) -> Result<Self, ProtoConversionError> {
+        let mut proto_entry = protos::genesis::GenesisKeyValueEntry::new();
+        proto_entry.set_prefix(entry.prefix);
+        proto_entry.set_key(entry.key);
+        proto_entry.set_values(
+            entry
+                .values
+                .into_iter()
+                .map(StateEntryValue::into_proto)
+                .collect::<Result<_, _>>()?,
+        );
+        Ok(proto_entry)
+    }
+}
+
+impl IntoProto<protos::genesis::GenesisKeyValueEntry> for KeyValueEntry {}
+impl IntoNative<KeyValueEntry> for protos::genesis::GenesisKeyValueEntry {}
+
+impl FromProto<protos::genesis::GenesisData> for GenesisData {
+    fn from_proto(mut data: protos::genesis::GenesisData) -> Result<Self, ProtoConversionError> {
+        Ok(GenesisData {
+            entries: data
+                .take_entries()
+                .into_iter()
+                .map(|mut entry| (entry.take_address(), entry.take_data()))
+                .collect(),
+            key_value_entries: data
+                .take_key_value_entries()
+                .into_iter()
+                .map(KeyValueEntry::from_proto)
+                .collect::<Result<_, _>>()?,
+            batches: data
+                .take_batches()
+                .into_iter()
+                .map(BatchPair::from_proto)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl FromNative<GenesisData> for protos::genesis::GenesisData {
+    fn from_native(data: GenesisData) -> Result<Self, ProtoConversionError> {
+        let mut proto_data = protos::genesis::GenesisData::new();
+        proto_data.set_entries(
+            data.entries
+                .into_iter()
+                .map(|(address, value)| {
+                    let mut entry = protos::genesis::GenesisEntry::new();
+                    entry.set_address(address);
+                    entry.set_data(value);
+                    entry
+                })
+                .collect(),
+        );
+        proto_data.set_key_value_entries(
+            data.key_value_entries
+                .into_iter()
+                .map(KeyValueEntry::into_proto)
+                .collect::<Result<_, _>>()?,
+        );
+        proto_data.set_batches(
+            data.batches
+                .into_iter()
+                .map(BatchPair::into_proto)
+                .collect::<Result<_, _>>()?,
+        );
+        Ok(proto_data)
+    }
+}
+
+impl FromBytes<GenesisData> for GenesisData {
+    fn from_bytes(bytes: &[u8]) -> Result<GenesisData, ProtoConversionError> {
+        let proto: protos::genesis::GenesisData =
+            protobuf::parse_from_bytes(bytes).map_err(|_| {
+                ProtoConversionError::SerializationError(
+                    "Unable to get GenesisData from bytes".to_string(),
+                )
+            })?;
+        proto.into_native()
+    }
+}
+
+impl IntoBytes for GenesisData {
+    fn into_bytes(self) -> Result<Vec<u8>, ProtoConversionError> {
+        let proto = self.into_proto()?;
+        let bytes = proto.write_to_bytes().map_err(|_| {
+            ProtoConversionError::SerializationError(
+                "Unable to get bytes from GenesisData".to_string(),
+            )
+        })?;
+        Ok(bytes)
+    }
+}
+
+impl IntoProto<protos::genesis::GenesisData> for GenesisData {}
+impl IntoNative<GenesisData> for protos::genesis::GenesisData {}
+
+impl FromProto<protos::genesis::GenesisRecord> for GenesisRecord {
+    fn from_proto(
+        mut record: protos::genesis::GenesisRecord,
+    ) -> Result<Self, ProtoConversionError> {
+        Ok(GenesisRecord {
+            state_root: record.take_state_root(),
+            data_hash: record.take_data_hash(),
+        })
+    }
+}
+
+impl FromNative<GenesisRecord> for protos::genesis::GenesisRecord {
+    fn from_native(record: GenesisRecord) -> Result<Self, ProtoConversionError> {
+        let mut proto_record = protos::genesis::GenesisRecord::new();
+        proto_record.set_state_root(record.state_root);
+        proto_record.set_data_hash(record.data_hash);
+        Ok(proto_record)
+    }
+}
+
+impl FromBytes<GenesisRecord> for GenesisRecord {
+    fn from_bytes(bytes: &[u8]) -> Result<GenesisRecord, ProtoConversionError> {
+        let proto: protos::genesis::GenesisRecord =
+            protobuf::parse_from_bytes(bytes).map_err(|_| {
+                ProtoConversionError::SerializationError(
+                    "Unable to get GenesisRecord from bytes".to_string(),
+                )
+            })?;
+        proto.into_native()
+    }
+}
+
+impl IntoBytes for GenesisRecord {
+    fn into_bytes(self) -> Result<Vec<u8>, ProtoConversionError> {
+        let proto = self.into_proto()?;
+        let bytes = proto.write_to_bytes().map_err(|_| {
+            ProtoConversionError::SerializationError(
+                "Unable to get bytes from GenesisRecord".to_string(),
+            )
+        })?;
+        Ok(bytes)
+    }
+}
+
+impl IntoProto<protos::genesis::GenesisRecord> for GenesisRecord {}
+impl IntoNative<GenesisRecord> for protos::genesis::GenesisRecord {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::protocol::key_value_state::{StateEntryValueBuilder, ValueType};
+    use crate::state::merkle::INDEXES;
+    use crate::state::Read;
+
+    fn genesis_data() -> GenesisData {
+        let values = vec![
+            StateEntryValueBuilder::new()
+                .with_key("name".into())
+                .with_value(ValueType::String("alice".into()))
+                .build()
+                .expect("Unable to build value"),
+            StateEntryValueBuilder::new()
+                .with_key("balance".into())
+                .with_value(ValueType::UInt64(100))
+                .build()
+                .expect("Unable to build value"),
+        ];
+        GenesisData::new()
+            .with_entry("abcdef00".into(), b"one".to_vec())
+            .with_entry("abcdef01".into(), b"two".to_vec())
+            .with_key_value_entry(KeyValueEntry::new("cd".into(), "alice".into(), values))
+    }
+
+    /// Test that building the same genesis data in two databases gives the same state root, that
+    /// the record checks against the data, and that genesis state cannot be built twice in the
+    /// same database.
+    #[test]
+    fn genesis_reproducible() {
+        let data = genesis_data();
+        let data =
+            GenesisData::from_bytes(&data.clone().into_bytes().expect("Unable to serialize"))
+                .expect("Unable to deserialize");
+
+        let database: Box<dyn Database> = Box::new(BTreeDatabase::new(&INDEXES));
+        let record = GenesisBuilder::new(database.clone())
+            .build(&data)
+            .expect("Unable to build genesis state");
+
+        let state = MerkleState::new(database.clone());
+        let entries = state
+            .get(&record.state_root().to_string(), &["abcdef00".to_string()])
+            .expect("Unable to read state");
+        assert_eq!(Some(&b"one".to_vec()), entries.get("abcdef00"));
+
+        match GenesisBuilder::new(database).build(&data) {
+            Err(GenesisError::NonEmptyDatabase) => (),
+            res => panic!("Expected NonEmptyDatabase, got {:?}", res),
+        }
+
+        GenesisBuilder::new(Box::new(BTreeDatabase::new(&INDEXES)))
+            .check(&data, &record)
+            .expect("Genesis record does not match");
+
+        let other = GenesisRecord::new("0".repeat(64), record.data_hash().into());
+        match GenesisBuilder::new(Box::new(BTreeDatabase::new(&INDEXES))).check(&data, &other) {
+            Err(GenesisError::RecordMismatch(_)) => (),
+            res => panic!("Expected RecordMismatch, got {:?}", res),
+        }
+
+        let data = data.with_entry("abcdef02".into(), b"three".to_vec());
+        match GenesisBuilder::new(Box::new(BTreeDatabase::new(&INDEXES))).check(&data, &record) {
+            Err(GenesisError::RecordMismatch(_)) => (),
+            res => panic!("Expected RecordMismatch, got {:?}", res),
+        }
+    }
+}
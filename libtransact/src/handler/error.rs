@@ -54,6 +54,8 @@ pub enum ContextError {
     SendError(Box<dyn Error>),
     /// Returned when an error is returned when sending a message
     ReceiveError(Box<dyn Error>),
+    /// Returned when invoking another transaction family's handler fails
+    InvocationError(ApplyError),
 }
 
 impl Error for ContextError {
@@ -65,6 +67,7 @@ impl Error for ContextError {
             ContextError::SerializationError(err) => Some(&**err),
             ContextError::SendError(err) => Some(&**err),
             ContextError::ReceiveError(err) => Some(&**err),
+            ContextError::InvocationError(err) => Some(err),
         }
     }
 }
@@ -84,6 +87,7 @@ impl std::fmt::Display for ContextError {
             }
             ContextError::SendError(ref err) => write!(f, "SendError: {}", err.description()),
             ContextError::ReceiveError(ref err) => write!(f, "ReceiveError: {}", err.description()),
+            ContextError::InvocationError(ref err) => write!(f, "InvocationError: {}", err),
         }
     }
 }
@@ -94,7 +98,41 @@ impl From<ContextError> for ApplyError {
             ContextError::TransactionReceiptError(..) => {
                 ApplyError::InternalError(format!("{}", context_error))
             }
+            ContextError::InvocationError(err) => err,
             _ => ApplyError::InvalidTransaction(format!("{}", context_error)),
         }
     }
 }
+
+#[derive(Debug)]
+pub enum QueryError {
+    /// Returned for a query which the handler cannot answer, such as a malformed one.
+    InvalidQuery(String),
+    /// Returned when an internal error occurs while answering a query.
+    InternalError(String),
+    /// Returned when no handler supports queries of the requested family.
+    Unsupported(String),
+}
+
+impl Error for QueryError {}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            QueryError::InvalidQuery(ref s) => write!(f, "InvalidQuery: {}", s),
+            QueryError::InternalError(ref s) => write!(f, "InternalError: {}", s),
+            QueryError::Unsupported(ref s) => write!(f, "Unsupported: {}", s),
+        }
+    }
+}
+
+impl From<ContextError> for QueryError {
+    fn from(context_error: ContextError) -> Self {
+        match context_error {
+            ContextError::TransactionReceiptError(..) => {
+                QueryError::InternalError(format!("{}", context_error))
+            }
+            _ => QueryError::InvalidQuery(format!("{}", context_error)),
+        }
+    }
+}
@@ -0,0 +1,572 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Inference of a transaction's inputs and outputs by tracing its execution.
+//!
+//! `infer_inputs_and_outputs` applies a candidate transaction with its handler against a state
+//! root, allowing it to read and write any address, and records the addresses it reads, sets and
+//! deletes. It returns the transaction's builder with its inputs set to the addresses read and its
+//! outputs set to the addresses set or deleted, either exactly or as the prefixes covering them.
+//! The addresses accessed by handlers it invokes are included.
+//!
+//! As elsewhere, inputs and outputs are the bytes of hex-encoded addresses, or of prefixes of
+//! them.
+//!
+//! Note, to use this module, the Transact library must have the `"io-inference"` feature
+//! enabled.
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+
+use crate::execution::adapter::invocation::InvocationContext;
+use crate::execution::adapter::static_adapter::HandlerFamilies;
+use crate::protocol::transaction::{TransactionBuildError, TransactionBuilder};
+use crate::signing::Signer;
+use crate::state::Read;
+
+use super::{ApplyError, ContextError, TransactionContext, TransactionHandler};
+
+/// How the inferred inputs and outputs cover the addresses accessed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressCoverage {
+    /// Each address accessed is listed
+    Exact,
+    /// The prefixes of the given number of bytes of the addresses accessed are listed
+    Prefixes(usize),
+}
+
+/// A transaction builder with inferred inputs and outputs, and the addresses they were inferred
+/// from.
+pub struct InferredTransaction {
+    builder: TransactionBuilder,
+    reads: Vec<String>,
+    writes: Vec<String>,
+    broader_inputs: Vec<String>,
+    broader_outputs: Vec<String>,
+}
+
+impl InferredTransaction {
+    /// The builder, with the inferred inputs and outputs.
+    pub fn builder(&self) -> &TransactionBuilder {
+        &self.builder
+    }
+
+    pub fn into_builder(self) -> TransactionBuilder {
+        self.builder
+    }
+
+    /// The addresses read, in order.
+    pub fn reads(&self) -> &[String] {
+        &self.reads
+    }
+
+    /// The addresses set or deleted, in order.
+    pub fn writes(&self) -> &[String] {
+        &self.writes
+    }
+
+    /// The inputs declared by the candidate transaction which are broader than needed, because
+    /// they cover no address read or are shorter than the inferred inputs they cover.
+    pub fn broader_inputs(&self) -> &[String] {
+        &self.broader_inputs
+    }
+
+    /// The outputs declared by the candidate transaction which are broader than needed.
+    pub fn broader_outputs(&self) -> &[String] {
+        &self.broader_outputs
+    }
+}
+
+#[derive(Debug)]
+pub enum InferenceError {
+    /// The candidate transaction could not be built
+    BuildError(TransactionBuildError),
+    /// The handler did not apply the candidate transaction successfully
+    ApplyError(ApplyError),
+    /// An address accessed is not a hex-encoded address
+    InvalidAddress(String),
+}
+
+impl Error for InferenceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InferenceError::BuildError(err) => Some(err),
+            InferenceError::ApplyError(err) => Some(err),
+            InferenceError::InvalidAddress(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for InferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InferenceError::BuildError(err) => write!(f, "unable to build transaction: {}", err),
+            InferenceError::ApplyError(err) => write!(f, "unable to apply transaction: {}", err),
+            InferenceError::InvalidAddress(address) => {
+                write!(f, "address {} is not hex-encoded", address)
+            }
+        }
+    }
+}
+
+impl From<TransactionBuildError> for InferenceError {
+    fn from(err: TransactionBuildError) -> Self {
+        InferenceError::BuildError(err)
+    }
+}
+
+impl From<ApplyError> for InferenceError {
+    fn from(err: ApplyError) -> Self {
+        InferenceError::ApplyError(err)
+    }
+}
+
+/// Applies the candidate transaction with the handler against `state_root`, and returns its
+/// builder with the inferred inputs and outputs.
+///
+/// The candidate is signed with the given signer to be applied; any inputs and outputs it declares
+/// are only compared with those inferred, and a warning is logged for each which is broader than
+/// needed. State is not changed.
+///
+/// The handler may invoke any of the given handlers, which are likewise allowed to read and write
+/// any address within their namespaces.
+///
+/// # Errors
+///
+/// Returns an `InferenceError` if the candidate cannot be built, if the handler does not apply it
+/// successfully, or if it accesses an address which is not hex-encoded.
+pub fn infer_inputs_and_outputs(
+    handler: &dyn TransactionHandler,
+    handlers: &[Box<dyn TransactionHandler>],
+    state: &dyn Read<StateId = String, Key = String, Value = Vec<u8>>,
+    state_root: &str,
+    builder: TransactionBuilder,
+    signer: &dyn Signer,
+    coverage: AddressCoverage,
+) -> Result<InferredTransaction, InferenceError> {
+    let declared_inputs = builder.inputs().map(<[_]>::to_vec);
+    let declared_outputs = builder.outputs().map(<[_]>::to_vec);
+
+    let transaction_pair = builder
+        .clone()
+        .with_inputs(vec![])
+        .with_outputs(vec![])
+        .build_pair(signer)?;
+    let context = TracingContext::new(state, state_root);
+    handler.apply(
+        &transaction_pair,
+        &mut InvocationContext::new(
+            handlers,
+            &HandlerFamilies::new(handlers),
+            &transaction_pair,
+            &context,
+        ),
+    )?;
+
+    let reads = context.reads.into_inner();
+    let writes = context.writes.into_inner();
+    let inputs = cover(&reads, coverage)?;
+    let outputs = cover(&writes, coverage)?;
+
+    let transaction_id = transaction_pair.transaction().header_signature();
+    let broader_inputs = declared_inputs
+        .map(|declared| broader_than_needed(&declared, &reads, &inputs))
+        .unwrap_or_default();
+    for input in &broader_inputs {
+        warn!(
+            "Input {} of transaction {} is broader than needed",
+            input, transaction_id
+        );
+    }
+    let broader_outputs = declared_outputs
+        .map(|declared| broader_than_needed(&declared, &writes, &outputs))
+        .unwrap_or_default();
+    for output in &broader_outputs {
+        warn!(
+            "Output {} of transaction {} is broader than needed",
+            output, transaction_id
+        );
+    }
+
+    Ok(InferredTransaction {
+        builder: builder.with_inputs(inputs).with_outputs(outputs),
+        reads,
+        writes,
+        broader_inputs,
+        broader_outputs,
+    })
+}
+
+/// Returns the entries covering the addresses, without duplicates.
+fn cover(addresses: &[String], coverage: AddressCoverage) -> Result<Vec<Vec<u8>>, InferenceError> {
+    let mut entries = BTreeSet::new();
+    for address in addresses {
+        let mut entry =
+            hex::decode(address).map_err(|_| InferenceError::InvalidAddress(address.clone()))?;
+        if let AddressCoverage::Prefixes(len) = coverage {
+            entry.truncate(len);
+        }
+        entries.insert(entry);
+    }
+    Ok(entries.into_iter().collect())
+}
+
+/// Returns the declared entries, hex-encoded, which cover none of the addresses, or which are
+/// shorter than an inferred entry they cover.
+fn broader_than_needed(
+    declared: &[Vec<u8>],
+    addresses: &[String],
+    inferred: &[Vec<u8>],
+) -> Vec<String> {
+    declared
+        .iter()
+        .map(hex::encode)
+        .filter(|entry| {
+            !addresses.iter().any(|address| address.starts_with(entry))
+                || inferred.iter().map(hex::encode).any(|inferred| {
+                    inferred.starts_with(entry.as_str()) && inferred.len() > entry.len()
+                })
+        })
+        .collect()
+}
+
+/// A `TransactionContext` which reads from state, keeps its writes to itself, and records the
+/// addresses read and written.
+///
+/// Handlers are given an `InvocationContext` over it, so that the addresses accessed by the
+/// handlers they invoke are recorded too.
+struct TracingContext<'a> {
+    state: &'a dyn Read<StateId = String, Key = String, Value = Vec<u8>>,
+    state_root: String,
+    // The value of each address written, or None if deleted
+    changes: RefCell<HashMap<String, Option<Vec<u8>>>>,
+    reads: RefCell<Vec<String>>,
+    writes: RefCell<Vec<String>>,
+}
+
+impl<'a> TracingContext<'a> {
+    fn new(
+        state: &'a dyn Read<StateId = String, Key = String, Value = Vec<u8>>,
+        state_root: &str,
+    ) -> Self {
+        TracingContext {
+            state,
+            state_root: state_root.into(),
+            changes: RefCell::new(HashMap::new()),
+            reads: RefCell::new(vec![]),
+            writes: RefCell::new(vec![]),
+        }
+    }
+
+    fn record(addresses: &RefCell<Vec<String>>, address: &str) {
+        let mut addresses = addresses.borrow_mut();
+        if !addresses.iter().any(|recorded| recorded == address) {
+            addresses.push(address.into());
+        }
+    }
+
+    fn get(&self, addresses: &[String]) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        let changes = self.changes.borrow();
+        let unchanged = addresses
+            .iter()
+            .filter(|address| !changes.contains_key(*address))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut values = self
+            .state
+            .get(&self.state_root, &unchanged)
+            .map_err(|err| ContextError::ReceiveError(Box::new(err)))?;
+        Ok(addresses
+            .iter()
+            .filter_map(|address| {
+                match changes.get(address) {
+                    Some(value) => value.clone(),
+                    None => values.remove(address),
+                }
+                .map(|value| (address.clone(), value))
+            })
+            .collect())
+    }
+}
+
+impl<'a> TransactionContext for TracingContext<'a> {
+    fn get_state_entries(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        for address in addresses {
+            Self::record(&self.reads, address);
+        }
+        self.get(addresses)
+    }
+
+    fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        for (address, value) in entries {
+            Self::record(&self.writes, &address);
+            self.changes.borrow_mut().insert(address, Some(value));
+        }
+        Ok(())
+    }
+
+    fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+        for address in addresses {
+            Self::record(&self.writes, address);
+        }
+        let deleted = self
+            .get(addresses)?
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect::<Vec<_>>();
+        for address in &deleted {
+            self.changes.borrow_mut().insert(address.clone(), None);
+        }
+        Ok(deleted)
+    }
+
+    fn add_receipt_data(&self, _data: Vec<u8>) -> Result<(), ContextError> {
+        Ok(())
+    }
+
+    fn add_event(
+        &self,
+        _event_type: String,
+        _attributes: Vec<(String, String)>,
+        _data: Vec<u8>,
+    ) -> Result<(), ContextError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::handler::Invocation;
+    use crate::protocol::transaction::{HashMethod, TransactionPair};
+    use crate::signing::hash::HashSigner;
+    use crate::state::hashmap::HashMapState;
+    use crate::state::{StateChange, Write};
+
+    const SOURCE: &str = "aa000001";
+    const DESTINATION: &str = "aa000002";
+    const LIMIT: &str = "bb000001";
+
+    /// Moves the value at the source address to the destination address, when applied or
+    /// invoked.
+    struct MoveHandler {
+        versions: Vec<String>,
+        namespaces: Vec<String>,
+    }
+
+    impl TransactionHandler for MoveHandler {
+        fn family_name(&self) -> &str {
+            "move"
+        }
+
+        fn family_versions(&self) -> &[String] {
+            &self.versions
+        }
+
+        fn namespaces(&self) -> &[String] {
+            &self.namespaces
+        }
+
+        fn apply(
+            &self,
+            _transaction_pair: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            let value = context
+                .get_state_entry(SOURCE)?
+                .ok_or_else(|| ApplyError::InvalidTransaction("nothing to move".into()))?;
+            context.delete_state_entry(SOURCE)?;
+            context.set_state_entry(DESTINATION.into(), value)?;
+            Ok(())
+        }
+
+        fn invoke(
+            &self,
+            invocation: &Invocation,
+            context: &mut dyn TransactionContext,
+        ) -> Result<Vec<u8>, ApplyError> {
+            self.apply(invocation.transaction_pair(), context)?;
+            Ok(vec![])
+        }
+    }
+
+    /// Reads the limit address, and then invokes the move handler.
+    struct RelayHandler {
+        versions: Vec<String>,
+    }
+
+    impl TransactionHandler for RelayHandler {
+        fn family_name(&self) -> &str {
+            "relay"
+        }
+
+        fn family_versions(&self) -> &[String] {
+            &self.versions
+        }
+
+        fn apply(
+            &self,
+            _transaction_pair: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            context.get_state_entry(LIMIT)?;
+            context.invoke("move", "1.0", &[])?;
+            Ok(())
+        }
+    }
+
+    /// Infer the inputs and outputs of a transaction, exactly and as prefixes, and check that
+    /// declared inputs and outputs broader than needed are reported.
+    #[test]
+    fn infer_move_inputs_and_outputs() {
+        let state = HashMapState::new();
+        let state_root = state
+            .commit(
+                &HashMapState::state_id(&HashMap::new()),
+                &[StateChange::Set {
+                    key: SOURCE.into(),
+                    value: b"value".to_vec(),
+                }],
+            )
+            .expect("Unable to commit state");
+        let handler = MoveHandler {
+            versions: vec!["1.0".into()],
+            namespaces: vec!["aa".into()],
+        };
+        let signer = HashSigner::default();
+        let builder = TransactionBuilder::new()
+            .with_family_name("move".into())
+            .with_family_version("1.0".into())
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(vec![]);
+
+        let inferred = infer_inputs_and_outputs(
+            &handler,
+            &[],
+            &state,
+            &state_root,
+            builder.clone(),
+            &signer,
+            AddressCoverage::Exact,
+        )
+        .expect("Unable to infer inputs and outputs");
+        assert_eq!(inferred.reads(), &[SOURCE.to_string()]);
+        assert_eq!(
+            inferred.writes(),
+            &[SOURCE.to_string(), DESTINATION.to_string()]
+        );
+        assert!(inferred.broader_inputs().is_empty());
+        let pair = inferred
+            .into_builder()
+            .build_pair(&signer)
+            .expect("Unable to build transaction");
+        assert_eq!(pair.header().inputs(), &[hex::decode(SOURCE).unwrap()]);
+        assert_eq!(
+            pair.header().outputs(),
+            &[
+                hex::decode(SOURCE).unwrap(),
+                hex::decode(DESTINATION).unwrap()
+            ]
+        );
+
+        let inferred = infer_inputs_and_outputs(
+            &handler,
+            &[],
+            &state,
+            &state_root,
+            builder
+                .with_inputs(vec![hex::decode("aa").unwrap(), hex::decode("bb").unwrap()])
+                .with_outputs(vec![
+                    hex::decode(SOURCE).unwrap(),
+                    hex::decode(DESTINATION).unwrap(),
+                ]),
+            &signer,
+            AddressCoverage::Prefixes(3),
+        )
+        .expect("Unable to infer inputs and outputs");
+        assert_eq!(
+            inferred.broader_inputs(),
+            &["aa".to_string(), "bb".to_string()]
+        );
+        assert!(inferred.broader_outputs().is_empty());
+        let pair = inferred
+            .into_builder()
+            .build_pair(&signer)
+            .expect("Unable to build transaction");
+        assert_eq!(pair.header().inputs(), &[hex::decode("aa0000").unwrap()]);
+        assert_eq!(pair.header().outputs(), &[hex::decode("aa0000").unwrap()]);
+
+        // State was not changed
+        assert_eq!(
+            state
+                .get(&state_root, &[SOURCE.to_string(), DESTINATION.to_string()])
+                .expect("Unable to read state")
+                .len(),
+            1
+        );
+    }
+
+    /// Infer the inputs and outputs of a transaction whose handler invokes another, and check
+    /// that the addresses accessed by the invoked handler are included.
+    #[test]
+    fn infer_invoked_inputs_and_outputs() {
+        let state = HashMapState::new();
+        let state_root = state
+            .commit(
+                &HashMapState::state_id(&HashMap::new()),
+                &[StateChange::Set {
+                    key: SOURCE.into(),
+                    value: b"value".to_vec(),
+                }],
+            )
+            .expect("Unable to commit state");
+        let handlers: Vec<Box<dyn TransactionHandler>> = vec![Box::new(MoveHandler {
+            versions: vec!["1.0".into()],
+            namespaces: vec!["aa".into()],
+        })];
+        let signer = HashSigner::default();
+        let builder = TransactionBuilder::new()
+            .with_family_name("relay".into())
+            .with_family_version("1.0".into())
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(vec![]);
+
+        let inferred = infer_inputs_and_outputs(
+            &RelayHandler {
+                versions: vec!["1.0".into()],
+            },
+            &handlers,
+            &state,
+            &state_root,
+            builder,
+            &signer,
+            AddressCoverage::Exact,
+        )
+        .expect("Unable to infer inputs and outputs");
+        assert_eq!(inferred.reads(), &[LIMIT.to_string(), SOURCE.to_string()]);
+        assert_eq!(
+            inferred.writes(),
+            &[SOURCE.to_string(), DESTINATION.to_string()]
+        );
+    }
+}
@@ -0,0 +1,62 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use crate::protocol::transaction::TransactionPair;
+
+/// A call made by one transaction family's handler to another's, while applying a transaction.
+pub struct Invocation<'a> {
+    transaction_pair: &'a TransactionPair,
+    caller: &'a str,
+    payload: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> Invocation<'a> {
+    pub fn new(
+        transaction_pair: &'a TransactionPair,
+        caller: &'a str,
+        payload: &'a [u8],
+        depth: usize,
+    ) -> Self {
+        Invocation {
+            transaction_pair,
+            caller,
+            payload,
+            depth,
+        }
+    }
+
+    /// The transaction being applied, whose family's handler made the first invocation.
+    pub fn transaction_pair(&self) -> &TransactionPair {
+        self.transaction_pair
+    }
+
+    /// The name of the family whose handler made the invocation.
+    pub fn caller(&self) -> &str {
+        self.caller
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        self.payload
+    }
+
+    /// How deeply the invocation is nested; an invocation made while applying the transaction
+    /// itself has a depth of 1.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
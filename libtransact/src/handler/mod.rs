@@ -24,8 +24,16 @@
 //! writing from state, as well appending events and other opaque data to the receipt.
 
 mod error;
+#[cfg(feature = "io-inference")]
+pub mod inference;
+mod invocation;
+mod read_only;
+#[cfg(feature = "context-recorder")]
+pub mod recorder;
 
-pub use crate::handler::error::{ApplyError, ContextError};
+pub use crate::handler::error::{ApplyError, ContextError, QueryError};
+pub use crate::handler::invocation::Invocation;
+pub use crate::handler::read_only::ReadOnlyContext;
 use crate::protocol::transaction::TransactionPair;
 
 pub trait TransactionContext {
@@ -120,6 +128,34 @@ pub trait TransactionContext {
         attributes: Vec<(String, String)>,
         data: Vec<u8>,
     ) -> Result<(), ContextError>;
+
+    /// invoke calls the handler of another transaction family with a payload, as part of this
+    /// transaction, and returns the bytes it returns. The handler reads and writes this context,
+    /// restricted to the namespaces it declares, and its changes, events and receipt data are
+    /// kept only if it succeeds.
+    ///
+    /// Invocations may not be nested more deeply than the executing adapter allows, nor call a
+    /// family which is already being applied or invoked. Contexts which do not support
+    /// invocations, such as those of external handlers, return an `InvocationError`.
+    ///
+    /// # Arguments
+    ///
+    /// * `family_name` - the name of the family to invoke
+    /// * `family_version` - the version of the family to invoke
+    /// * `payload` - the payload passed to the family's handler
+    fn invoke(
+        &self,
+        family_name: &str,
+        family_version: &str,
+        _payload: &[u8],
+    ) -> Result<Vec<u8>, ContextError> {
+        Err(ContextError::InvocationError(ApplyError::InternalError(
+            format!(
+                "Unable to invoke {} {}: this context does not support invocations",
+                family_name, family_version
+            ),
+        )))
+    }
 }
 
 pub trait TransactionHandler: Send {
@@ -132,9 +168,18 @@ pub trait TransactionHandler: Send {
     fn family_name(&self) -> &str;
 
     /// family_versions should return a list of versions this transaction
-    /// family handler can process, e.g. ["1.0"]
+    /// family handler can process, e.g. ["1.0"]. Each may also be a version
+    /// requirement, e.g. "^1.2" or ">=1.0,<2.0"; see
+    /// `execution::VersionRequirement`.
     fn family_versions(&self) -> &[String];
 
+    /// namespaces should return the address prefixes, in hex, which this
+    /// handler may read and write when invoked by another family's handler.
+    /// A handler which declares no namespaces may not be invoked.
+    fn namespaces(&self) -> &[String] {
+        &[]
+    }
+
     /// Apply is the single method where all the business logic for a
     /// transaction family is defined. The method will be called by the
     /// transaction processor upon receiving a TpProcessRequest that the
@@ -145,4 +190,40 @@ pub trait TransactionHandler: Send {
         transaction: &TransactionPair,
         context: &mut dyn TransactionContext,
     ) -> Result<(), ApplyError>;
+
+    /// Invoke is called when another family's handler invokes this one
+    /// through its context, and returns bytes to the invoking handler. The
+    /// context is that of the transaction being applied, restricted to the
+    /// handler's namespaces.
+    ///
+    /// By default, handlers may not be invoked, and handlers which declare
+    /// no namespaces are never invoked.
+    fn invoke(
+        &self,
+        _invocation: &Invocation,
+        _context: &mut dyn TransactionContext,
+    ) -> Result<Vec<u8>, ApplyError> {
+        Err(ApplyError::InvalidTransaction(format!(
+            "{} may not be invoked",
+            self.family_name()
+        )))
+    }
+
+    /// Query answers a request for information about state, such as a
+    /// contract's view function, without being submitted as a transaction.
+    /// The context reads state at the state root the query was made against,
+    /// and is read-only: every attempt to change state, or to add events or
+    /// receipt data, fails.
+    ///
+    /// By default, queries are not supported.
+    fn query(
+        &self,
+        _payload: &[u8],
+        _context: &dyn TransactionContext,
+    ) -> Result<Vec<u8>, QueryError> {
+        Err(QueryError::Unsupported(format!(
+            "{} does not support queries",
+            self.family_name()
+        )))
+    }
 }
@@ -0,0 +1,65 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use crate::handler::{ContextError, TransactionContext};
+
+/// A `TransactionContext` which reads state through another context, and refuses every change.
+///
+/// Queries are answered with a read-only context, so that a query cannot change state.
+pub struct ReadOnlyContext<'a> {
+    context: &'a dyn TransactionContext,
+}
+
+impl<'a> ReadOnlyContext<'a> {
+    pub fn new(context: &'a dyn TransactionContext) -> Self {
+        ReadOnlyContext { context }
+    }
+}
+
+fn read_only() -> ContextError {
+    ContextError::AuthorizationError("the context is read-only".into())
+}
+
+impl<'a> TransactionContext for ReadOnlyContext<'a> {
+    fn get_state_entries(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        self.context.get_state_entries(addresses)
+    }
+
+    fn set_state_entries(&self, _entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        Err(read_only())
+    }
+
+    fn delete_state_entries(&self, _addresses: &[String]) -> Result<Vec<String>, ContextError> {
+        Err(read_only())
+    }
+
+    fn add_receipt_data(&self, _data: Vec<u8>) -> Result<(), ContextError> {
+        Err(read_only())
+    }
+
+    fn add_event(
+        &self,
+        _event_type: String,
+        _attributes: Vec<(String, String)>,
+        _data: Vec<u8>,
+    ) -> Result<(), ContextError> {
+        Err(read_only())
+    }
+}
@@ -0,0 +1,1366 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Recording of the calls a `TransactionHandler` makes to its `TransactionContext`, and offline
+//! replay of the recordings.
+//!
+//! A `RecordingHandler` wraps a handler and writes a `TransactionTrace` of each transaction the
+//! handler applies to a trace file: every read, with the values it returned, every set, delete,
+//! event and piece of receipt data, every invocation of another handler, with the bytes it
+//! returned, and the handler's result. `replay` applies a traced
+//! transaction to a handler, serving its reads from the trace, and checks that the handler makes
+//! the same calls and returns the same result.
+//!
+//! Trace files hold a sequence of traces, each written as its length, a 4-byte big-endian
+//! integer, followed by the trace serialized as a protobuf message.
+//!
+//! Note, to use this module, the Transact library must have the `"context-recorder"` feature
+//! enabled.
+
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use protobuf::Message;
+
+use crate::protocol::receipt::Event;
+use crate::protocol::transaction::{Transaction, TransactionPair};
+use crate::protos;
+use crate::protos::{
+    FromBytes, FromNative, FromProto, IntoBytes, IntoNative, IntoProto, ProtoConversionError,
+};
+
+use super::{
+    ApplyError, ContextError, Invocation, QueryError, TransactionContext, TransactionHandler,
+};
+
+/// Traces larger than this are rejected when reading a trace file.
+const MAX_TRACE_SIZE: usize = 64 * 1024 * 1024;
+
+/// A call made by a handler to its context, and the result the context returned.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ContextCall {
+    GetState {
+        addresses: Vec<String>,
+        result: Result<Vec<(String, Vec<u8>)>, String>,
+    },
+    SetState {
+        entries: Vec<(String, Vec<u8>)>,
+        result: Result<(), String>,
+    },
+    DeleteState {
+        addresses: Vec<String>,
+        result: Result<Vec<String>, String>,
+    },
+    AddEvent {
+        event: Event,
+        result: Result<(), String>,
+    },
+    AddReceiptData {
+        data: Vec<u8>,
+        result: Result<(), String>,
+    },
+    Invoke {
+        family_name: String,
+        family_version: String,
+        payload: Vec<u8>,
+        result: Result<Vec<u8>, InvocationFailure>,
+    },
+}
+
+/// How a recorded invocation failed, kept so that replay returns the same kind of error.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvocationFailure {
+    /// The invocation returned an `InvocationError` with an `InvalidTransaction`
+    Invalid(String),
+    /// The invocation returned an `InvocationError` with an `InternalError`
+    InternalError(String),
+    /// The invocation returned any other context error
+    ContextError(String),
+}
+
+impl<'a> From<&'a ContextError> for InvocationFailure {
+    fn from(err: &'a ContextError) -> Self {
+        match err {
+            ContextError::InvocationError(ApplyError::InvalidTransaction(message)) => {
+                InvocationFailure::Invalid(message.clone())
+            }
+            ContextError::InvocationError(ApplyError::InternalError(message)) => {
+                InvocationFailure::InternalError(message.clone())
+            }
+            err => InvocationFailure::ContextError(err.to_string()),
+        }
+    }
+}
+
+/// An error recorded in a trace, which replay returns to the handler.
+trait RecordedError {
+    fn into_context_error(self) -> ContextError;
+}
+
+impl RecordedError for String {
+    fn into_context_error(self) -> ContextError {
+        ContextError::ResponseAttributeError(self)
+    }
+}
+
+impl RecordedError for InvocationFailure {
+    fn into_context_error(self) -> ContextError {
+        match self {
+            InvocationFailure::Invalid(message) => {
+                ContextError::InvocationError(ApplyError::InvalidTransaction(message))
+            }
+            InvocationFailure::InternalError(message) => {
+                ContextError::InvocationError(ApplyError::InternalError(message))
+            }
+            InvocationFailure::ContextError(message) => {
+                ContextError::ResponseAttributeError(message)
+            }
+        }
+    }
+}
+
+/// The result a handler returned for a transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceOutcome {
+    Valid,
+    Invalid(String),
+    InternalError(String),
+}
+
+impl<'a> From<&'a Result<(), ApplyError>> for TraceOutcome {
+    fn from(result: &'a Result<(), ApplyError>) -> Self {
+        match result {
+            Ok(()) => TraceOutcome::Valid,
+            Err(ApplyError::InvalidTransaction(message)) => TraceOutcome::Invalid(message.clone()),
+            Err(ApplyError::InternalError(message)) => TraceOutcome::InternalError(message.clone()),
+        }
+    }
+}
+
+/// The calls a handler made to its context while applying a transaction, and its result.
+#[derive(Clone, Debug)]
+pub struct TransactionTrace {
+    transaction_pair: TransactionPair,
+    calls: Vec<ContextCall>,
+    outcome: TraceOutcome,
+}
+
+impl TransactionTrace {
+    pub fn new(
+        transaction_pair: TransactionPair,
+        calls: Vec<ContextCall>,
+        outcome: TraceOutcome,
+    ) -> Self {
+        TransactionTrace {
+            transaction_pair,
+            calls,
+            outcome,
+        }
+    }
+
+    pub fn transaction_pair(&self) -> &TransactionPair {
+        &self.transaction_pair
+    }
+
+    pub fn calls(&self) -> &[ContextCall] {
+        &self.calls
+    }
+
+    pub fn outcome(&self) -> &TraceOutcome {
+        &self.outcome
+    }
+}
+
+/// A `TransactionContext` which records the calls made to the context it wraps.
+pub struct RecordingContext<'a> {
+    context: &'a dyn TransactionContext,
+    calls: RefCell<Vec<ContextCall>>,
+}
+
+impl<'a> RecordingContext<'a> {
+    pub fn new(context: &'a dyn TransactionContext) -> Self {
+        RecordingContext {
+            context,
+            calls: RefCell::new(vec![]),
+        }
+    }
+
+    /// Returns the calls made to the context, in order.
+    pub fn into_calls(self) -> Vec<ContextCall> {
+        self.calls.into_inner()
+    }
+
+    fn record<T: Clone>(
+        &self,
+        result: Result<T, ContextError>,
+        call: impl FnOnce(Result<T, String>) -> ContextCall,
+    ) -> Result<T, ContextError> {
+        let recorded = match &result {
+            Ok(value) => Ok(value.clone()),
+            Err(err) => Err(err.to_string()),
+        };
+        self.calls.borrow_mut().push(call(recorded));
+        result
+    }
+}
+
+impl<'a> TransactionContext for RecordingContext<'a> {
+    fn get_state_entries(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        self.record(self.context.get_state_entries(addresses), |result| {
+            ContextCall::GetState {
+                addresses: addresses.to_vec(),
+                result,
+            }
+        })
+    }
+
+    fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        self.record(self.context.set_state_entries(entries.clone()), |result| {
+            ContextCall::SetState { entries, result }
+        })
+    }
+
+    fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+        self.record(self.context.delete_state_entries(addresses), |result| {
+            ContextCall::DeleteState {
+                addresses: addresses.to_vec(),
+                result,
+            }
+        })
+    }
+
+    fn add_receipt_data(&self, data: Vec<u8>) -> Result<(), ContextError> {
+        self.record(self.context.add_receipt_data(data.clone()), |result| {
+            ContextCall::AddReceiptData { data, result }
+        })
+    }
+
+    fn add_event(
+        &self,
+        event_type: String,
+        attributes: Vec<(String, String)>,
+        data: Vec<u8>,
+    ) -> Result<(), ContextError> {
+        let event = Event {
+            event_type,
+            attributes,
+            data,
+        };
+        self.record(
+            self.context.add_event(
+                event.event_type.clone(),
+                event.attributes.clone(),
+                event.data.clone(),
+            ),
+            |result| ContextCall::AddEvent { event, result },
+        )
+    }
+
+    fn invoke(
+        &self,
+        family_name: &str,
+        family_version: &str,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, ContextError> {
+        let result = self.context.invoke(family_name, family_version, payload);
+        self.calls.borrow_mut().push(ContextCall::Invoke {
+            family_name: family_name.into(),
+            family_version: family_version.into(),
+            payload: payload.to_vec(),
+            result: match &result {
+                Ok(output) => Ok(output.clone()),
+                Err(err) => Err(InvocationFailure::from(err)),
+            },
+        });
+        result
+    }
+}
+
+/// A `TransactionHandler` which records a trace of each transaction the handler it wraps applies.
+///
+/// Failures to write a trace are logged, and do not affect the transaction. Invocations and
+/// queries are passed to the wrapped handler without being recorded, and the wrapped handler's
+/// namespaces still restrict it when invoked.
+pub struct RecordingHandler {
+    handler: Box<dyn TransactionHandler>,
+    writer: TraceWriter,
+}
+
+impl RecordingHandler {
+    pub fn new(handler: Box<dyn TransactionHandler>, writer: TraceWriter) -> Self {
+        RecordingHandler { handler, writer }
+    }
+}
+
+impl TransactionHandler for RecordingHandler {
+    fn family_name(&self) -> &str {
+        self.handler.family_name()
+    }
+
+    fn family_versions(&self) -> &[String] {
+        self.handler.family_versions()
+    }
+
+    fn namespaces(&self) -> &[String] {
+        self.handler.namespaces()
+    }
+
+    fn apply(
+        &self,
+        transaction_pair: &TransactionPair,
+        context: &mut dyn TransactionContext,
+    ) -> Result<(), ApplyError> {
+        let mut recording_context = RecordingContext::new(&*context);
+        let result = self.handler.apply(transaction_pair, &mut recording_context);
+
+        let trace = TransactionTrace::new(
+            transaction_pair.clone(),
+            recording_context.into_calls(),
+            TraceOutcome::from(&result),
+        );
+        if let Err(err) = self.writer.write(trace) {
+            error!(
+                "Unable to record trace of transaction {}: {}",
+                transaction_pair.transaction().header_signature(),
+                err
+            );
+        }
+
+        result
+    }
+
+    fn invoke(
+        &self,
+        invocation: &Invocation,
+        context: &mut dyn TransactionContext,
+    ) -> Result<Vec<u8>, ApplyError> {
+        self.handler.invoke(invocation, context)
+    }
+
+    fn query(
+        &self,
+        payload: &[u8],
+        context: &dyn TransactionContext,
+    ) -> Result<Vec<u8>, QueryError> {
+        self.handler.query(payload, context)
+    }
+}
+
+/// Writes traces to a trace file. Clones write to the same file, so several `RecordingHandler`s
+/// may share one.
+#[derive(Clone)]
+pub struct TraceWriter {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl TraceWriter {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        TraceWriter {
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+
+    /// Creates a trace file at the given path, replacing any existing file.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, TraceError> {
+        Ok(Self::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    /// Writes the trace, and flushes the file.
+    pub fn write(&self, trace: TransactionTrace) -> Result<(), TraceError> {
+        let bytes = trace.into_bytes()?;
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| TraceError::IoError(io::Error::new(ErrorKind::Other, "lock poisoned")))?;
+        writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads the traces in a trace file, in the order they were written.
+pub struct TraceReader<R: Read> {
+    reader: R,
+}
+
+impl TraceReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, TraceError> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(reader: R) -> Self {
+        TraceReader { reader }
+    }
+
+    fn read_trace(&mut self) -> Result<Option<TransactionTrace>, TraceError> {
+        let mut len_bytes = [0; 4];
+        let mut read = 0;
+        while read < len_bytes.len() {
+            match self.reader.read(&mut len_bytes[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(TraceError::InvalidTrace("trace file is truncated".into())),
+                Ok(n) => read += n,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let len = u32::from_be_bytes(len_bytes) as usize;
+        if len > MAX_TRACE_SIZE {
+            return Err(TraceError::InvalidTrace(format!(
+                "trace of {} bytes exceeds the maximum of {} bytes",
+                len, MAX_TRACE_SIZE
+            )));
+        }
+        let mut bytes = vec![0; len];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(Some(TransactionTrace::from_bytes(&bytes)?))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = Result<TransactionTrace, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_trace().transpose()
+    }
+}
+
+/// Applies a traced transaction with the handler, serving its reads from the trace, and checks
+/// that the handler makes the recorded calls, in order and with the same arguments, and returns
+/// the recorded result.
+///
+/// # Errors
+///
+/// Returns a `ReplayError` describing the first difference from the trace.
+pub fn replay(
+    handler: &dyn TransactionHandler,
+    trace: &TransactionTrace,
+) -> Result<(), ReplayError> {
+    let mut context = ReplayContext {
+        calls: trace.calls(),
+        next: Cell::new(0),
+        divergence: RefCell::new(None),
+    };
+    let result = handler.apply(trace.transaction_pair(), &mut context);
+
+    if let Some(divergence) = context.divergence.into_inner() {
+        return Err(ReplayError::Diverged(divergence));
+    }
+    if context.next.get() < trace.calls().len() {
+        return Err(ReplayError::Diverged(format!(
+            "the handler made {} of the {} recorded calls",
+            context.next.get(),
+            trace.calls().len()
+        )));
+    }
+    let outcome = TraceOutcome::from(&result);
+    if &outcome != trace.outcome() {
+        return Err(ReplayError::OutcomeMismatch {
+            expected: trace.outcome().clone(),
+            actual: outcome,
+        });
+    }
+    Ok(())
+}
+
+/// A `TransactionContext` which checks each call against the next recorded call, and returns the
+/// recorded result.
+struct ReplayContext<'a> {
+    calls: &'a [ContextCall],
+    next: Cell<usize>,
+    divergence: RefCell<Option<String>>,
+}
+
+impl<'a> ReplayContext<'a> {
+    /// Returns the recorded result of the next call if it matches; otherwise, records the
+    /// divergence, after which every call fails.
+    fn expect<T, E, M, D>(&self, matches: M, describe: D) -> Result<T, ContextError>
+    where
+        E: RecordedError,
+        M: FnOnce(&ContextCall) -> Option<Result<T, E>>,
+        D: FnOnce() -> String,
+    {
+        if self.divergence.borrow().is_some() {
+            return Err(ContextError::ResponseAttributeError(
+                "replay has diverged from the trace".into(),
+            ));
+        }
+
+        let index = self.next.get();
+        match self.calls.get(index).and_then(matches) {
+            Some(result) => {
+                self.next.set(index + 1);
+                result.map_err(RecordedError::into_context_error)
+            }
+            None => {
+                let expected = match self.calls.get(index) {
+                    Some(call) => format!("{:?}", call),
+                    None => "no further calls".into(),
+                };
+                *self.divergence.borrow_mut() = Some(format!(
+                    "call {} was {}, but {} was recorded",
+                    index,
+                    describe(),
+                    expected
+                ));
+                Err(ContextError::ResponseAttributeError(
+                    "replay has diverged from the trace".into(),
+                ))
+            }
+        }
+    }
+}
+
+impl<'a> TransactionContext for ReplayContext<'a> {
+    fn get_state_entries(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+        self.expect(
+            |call| match call {
+                ContextCall::GetState {
+                    addresses: recorded,
+                    result,
+                } if recorded.as_slice() == addresses => Some(result.clone()),
+                _ => None,
+            },
+            || format!("get_state_entries({:?})", addresses),
+        )
+    }
+
+    fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        self.expect(
+            |call| match call {
+                ContextCall::SetState {
+                    entries: recorded,
+                    result,
+                } if *recorded == entries => Some(result.clone()),
+                _ => None,
+            },
+            || format!("set_state_entries({:?})", entries),
+        )
+    }
+
+    fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+        self.expect(
+            |call| match call {
+                ContextCall::DeleteState {
+                    addresses: recorded,
+                    result,
+                } if recorded.as_slice() == addresses => Some(result.clone()),
+                _ => None,
+            },
+            || format!("delete_state_entries({:?})", addresses),
+        )
+    }
+
+    fn add_receipt_data(&self, data: Vec<u8>) -> Result<(), ContextError> {
+        self.expect(
+            |call| match call {
+                ContextCall::AddReceiptData {
+                    data: recorded,
+                    result,
+                } if *recorded == data => Some(result.clone()),
+                _ => None,
+            },
+            || format!("add_receipt_data({:?})", data),
+        )
+        .map_err(receipt_error)
+    }
+
+    fn add_event(
+        &self,
+        event_type: String,
+        attributes: Vec<(String, String)>,
+        data: Vec<u8>,
+    ) -> Result<(), ContextError> {
+        let event = Event {
+            event_type,
+            attributes,
+            data,
+        };
+        self.expect(
+            |call| match call {
+                ContextCall::AddEvent {
+                    event: recorded,
+                    result,
+                } if *recorded == event => Some(result.clone()),
+                _ => None,
+            },
+            || format!("add_event({:?})", event),
+        )
+        .map_err(receipt_error)
+    }
+
+    fn invoke(
+        &self,
+        family_name: &str,
+        family_version: &str,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, ContextError> {
+        self.expect(
+            |call| match call {
+                ContextCall::Invoke {
+                    family_name: recorded_name,
+                    family_version: recorded_version,
+                    payload: recorded_payload,
+                    result,
+                } if recorded_name == family_name
+                    && recorded_version == family_version
+                    && recorded_payload.as_slice() == payload =>
+                {
+                    Some(result.clone())
+                }
+                _ => None,
+            },
+            || format!("invoke({}, {}, {:?})", family_name, family_version, payload),
+        )
+    }
+}
+
+/// Failures to add events and receipt data are reported to handlers as receipt errors, as the
+/// context manager reports them.
+fn receipt_error(err: ContextError) -> ContextError {
+    match err {
+        ContextError::ResponseAttributeError(message) => {
+            ContextError::TransactionReceiptError(message)
+        }
+        err => err,
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    IoError(io::Error),
+    InvalidTrace(String),
+}
+
+impl Error for TraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TraceError::IoError(err) => Some(err),
+            TraceError::InvalidTrace(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TraceError::IoError(err) => write!(f, "unable to read or write trace: {}", err),
+            TraceError::InvalidTrace(msg) => write!(f, "invalid trace: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(err: io::Error) -> Self {
+        TraceError::IoError(err)
+    }
+}
+
+impl From<ProtoConversionError> for TraceError {
+    fn from(err: ProtoConversionError) -> Self {
+        TraceError::InvalidTrace(err.to_string())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    /// The handler's calls to its context differed from the trace
+    Diverged(String),
+    /// The handler made the recorded calls, but returned a different result
+    OutcomeMismatch {
+        expected: TraceOutcome,
+        actual: TraceOutcome,
+    },
+}
+
+impl Error for ReplayError {}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ReplayError::Diverged(msg) => write!(f, "replay diverged from trace: {}", msg),
+            ReplayError::OutcomeMismatch { expected, actual } => write!(
+                f,
+                "replay returned {:?}, but {:?} was recorded",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl FromProto<protos::context_trace::ContextCall> for ContextCall {
+    fn from_proto(
+        mut call: protos::context_trace::ContextCall,
+    ) -> Result<Self, ProtoConversionError> {
+        use protos::context_trace::{ContextCall_InvocationFailure, ContextCall_Type};
+
+        let error = if call.get_failed() {
+            Some(call.take_error_message())
+        } else {
+            None
+        };
+        Ok(match call.get_call_type() {
+            ContextCall_Type::GET_STATE => ContextCall::GetState {
+                addresses: call.take_addresses().into_vec(),
+                result: error.map_or_else(
+                    || Ok(entries_from_proto(call.take_entries().into_vec())),
+                    Err,
+                ),
+            },
+            ContextCall_Type::SET_STATE => ContextCall::SetState {
+                entries: entries_from_proto(call.take_entries().into_vec()),
+                result: error.map_or(Ok(()), Err),
+            },
+            ContextCall_Type::DELETE_STATE => ContextCall::DeleteState {
+                addresses: call.take_addresses().into_vec(),
+                result: error.map_or_else(|| Ok(call.take_deleted().into_vec()), Err),
+            },
+            ContextCall_Type::ADD_EVENT => ContextCall::AddEvent {
+                event: Event::from_proto(call.take_event())?,
+                result: error.map_or(Ok(()), Err),
+            },
+            ContextCall_Type::ADD_RECEIPT_DATA => ContextCall::AddReceiptData {
+                data: call.take_receipt_data(),
+                result: error.map_or(Ok(()), Err),
+            },
+            ContextCall_Type::INVOKE => ContextCall::Invoke {
+                family_name: call.take_family_name(),
+                family_version: call.take_family_version(),
+                payload: call.take_payload(),
+                result: match error {
+                    None => Ok(call.take_output()),
+                    Some(message) => Err(match call.get_invocation_failure() {
+                        ContextCall_InvocationFailure::INVALID => {
+                            InvocationFailure::Invalid(message)
+                        }
+                        ContextCall_InvocationFailure::INTERNAL_ERROR => {
+                            InvocationFailure::InternalError(message)
+                        }
+                        ContextCall_InvocationFailure::CONTEXT_ERROR => {
+                            InvocationFailure::ContextError(message)
+                        }
+                        ContextCall_InvocationFailure::FAILURE_UNSET => {
+                            return Err(ProtoConversionError::InvalidTypeError(
+                                "Cannot convert failed invocation with failure unset".into(),
+                            ));
+                        }
+                    }),
+                },
+            },
+            ContextCall_Type::TYPE_UNSET => {
+                return Err(ProtoConversionError::InvalidTypeError(
+                    "Cannot convert ContextCall with type unset".into(),
+                ));
+            }
+        })
+    }
+}
+
+impl FromNative<ContextCall> for protos::context_trace::ContextCall {
+    fn from_native(call: ContextCall) -> Result<Self, ProtoConversionError> {
+        use protos::context_trace::{ContextCall_InvocationFailure, ContextCall_Type};
+
+        let mut proto_call = protos::context_trace::ContextCall::new();
+        let error = match call {
+            ContextCall::GetState { addresses, result } => {
+                proto_call.set_call_type(ContextCall_Type::GET_STATE);
+                proto_call.set_addresses(addresses.into());
+                match result {
+                    Ok(entries) => {
+                        proto_call.set_entries(entries_into_proto(entries));
+                        None
+                    }
+                    Err(error) => Some(error),
+                }
+            }
+            ContextCall::SetState { entries, result } => {
+                proto_call.set_call_type(ContextCall_Type::SET_STATE);
+                proto_call.set_entries(entries_into_proto(entries));
+                result.err()
+            }
+            ContextCall::DeleteState { addresses, result } => {
+                proto_call.set_call_type(ContextCall_Type::DELETE_STATE);
+                proto_call.set_addresses(addresses.into());
+                match result {
+                    Ok(deleted) => {
+                        proto_call.set_deleted(deleted.into());
+                        None
+                    }
+                    Err(error) => Some(error),
+                }
+            }
+            ContextCall::AddEvent { event, result } => {
+                proto_call.set_call_type(ContextCall_Type::ADD_EVENT);
+                proto_call.set_event(event.into_proto()?);
+                result.err()
+            }
+            ContextCall::AddReceiptData { data, result } => {
+                proto_call.set_call_type(ContextCall_Type::ADD_RECEIPT_DATA);
+                proto_call.set_receipt_data(data);
+                result.err()
+            }
+            ContextCall::Invoke {
+                family_name,
+                family_version,
+                payload,
+                result,
+            } => {
+                proto_call.set_call_type(ContextCall_Type::INVOKE);
+                proto_call.set_family_name(family_name);
+                proto_call.set_family_version(family_version);
+                proto_call.set_payload(payload);
+                match result {
+                    Ok(output) => {
+                        proto_call.set_output(output);
+                        None
+                    }
+                    Err(failure) => {
+                        let (failure, error) = match failure {
+                            InvocationFailure::Invalid(message) => {
+                                (ContextCall_InvocationFailure::INVALID, message)
+                            }
+                            InvocationFailure::InternalError(message) => {
+                                (ContextCall_InvocationFailure::INTERNAL_ERROR, message)
+                            }
+                            InvocationFailure::ContextError(message) => {
+                                (ContextCall_InvocationFailure::CONTEXT_ERROR, message)
+                            }
+                        };
+                        proto_call.set_invocation_failure(failure);
+                        Some(error)
+                    }
+                }
+            }
+        };
+        if let Some(error) = error {
+            proto_call.set_failed(true);
+            proto_call.set_error_message(error);
+        }
+        Ok(proto_call)
+    }
+}
+
+impl IntoProto<protos::context_trace::ContextCall> for ContextCall {}
+impl IntoNative<ContextCall> for protos::context_trace::ContextCall {}
+
+fn entries_from_proto(
+    entries: Vec<protos::context_trace::TraceStateEntry>,
+) -> Vec<(String, Vec<u8>)> {
+    entries
+        .into_iter()
+        .map(|mut entry| (entry.take_address(), entry.take_data()))
+        .collect()
+}
+
+fn entries_into_proto(
+    entries: Vec<(String, Vec<u8>)>,
+) -> protobuf::RepeatedField<protos::context_trace::TraceStateEntry> {
+    entries
+        .into_iter()
+        .map(|(address, data)| {
+            let mut entry = protos::context_trace::TraceStateEntry::new();
+            entry.set_address(address);
+            entry.set_data(data);
+            entry
+        })
+        .collect()
+}
+
+impl FromProto<protos::context_trace::TransactionTrace> for TransactionTrace {
+    fn from_proto(
+        mut trace: protos::context_trace::TransactionTrace,
+    ) -> Result<Self, ProtoConversionError> {
+        use protos::context_trace::TransactionTrace_Outcome;
+
+        let transaction_pair = Transaction::from_proto(trace.take_transaction())?
+            .into_pair()
+            .map_err(|err| {
+                ProtoConversionError::DeserializationError(format!(
+                    "Unable to get transaction header: {}",
+                    err
+                ))
+            })?;
+        let calls = trace
+            .take_calls()
+            .into_iter()
+            .map(ContextCall::from_proto)
+            .collect::<Result<_, _>>()?;
+        let outcome = match trace.get_outcome() {
+            TransactionTrace_Outcome::VALID => TraceOutcome::Valid,
+            TransactionTrace_Outcome::INVALID => TraceOutcome::Invalid(trace.take_error_message()),
+            TransactionTrace_Outcome::INTERNAL_ERROR => {
+                TraceOutcome::InternalError(trace.take_error_message())
+            }
+            TransactionTrace_Outcome::OUTCOME_UNSET => {
+                return Err(ProtoConversionError::InvalidTypeError(
+                    "Cannot convert TransactionTrace with outcome unset".into(),
+                ));
+            }
+        };
+
+        Ok(TransactionTrace::new(transaction_pair, calls, outcome))
+    }
+}
+
+impl FromNative<TransactionTrace> for protos::context_trace::TransactionTrace {
+    fn from_native(trace: TransactionTrace) -> Result<Self, ProtoConversionError> {
+        use protos::context_trace::TransactionTrace_Outcome;
+
+        let mut proto_trace = protos::context_trace::TransactionTrace::new();
+        let (transaction, _) = trace.transaction_pair.take();
+        proto_trace.set_transaction(transaction.into_proto()?);
+        proto_trace.set_calls(
+            trace
+                .calls
+                .into_iter()
+                .map(ContextCall::into_proto)
+                .collect::<Result<_, _>>()?,
+        );
+        match trace.outcome {
+            TraceOutcome::Valid => proto_trace.set_outcome(TransactionTrace_Outcome::VALID),
+            TraceOutcome::Invalid(message) => {
+                proto_trace.set_outcome(TransactionTrace_Outcome::INVALID);
+                proto_trace.set_error_message(message);
+            }
+            TraceOutcome::InternalError(message) => {
+                proto_trace.set_outcome(TransactionTrace_Outcome::INTERNAL_ERROR);
+                proto_trace.set_error_message(message);
+            }
+        }
+        Ok(proto_trace)
+    }
+}
+
+impl FromBytes<TransactionTrace> for TransactionTrace {
+    fn from_bytes(bytes: &[u8]) -> Result<TransactionTrace, ProtoConversionError> {
+        let proto: protos::context_trace::TransactionTrace = protobuf::parse_from_bytes(bytes)
+            .map_err(|_| {
+                ProtoConversionError::SerializationError(
+                    "Unable to get TransactionTrace from bytes".to_string(),
+                )
+            })?;
+        proto.into_native()
+    }
+}
+
+impl IntoBytes for TransactionTrace {
+    fn into_bytes(self) -> Result<Vec<u8>, ProtoConversionError> {
+        let proto = self.into_proto()?;
+        let bytes = proto.write_to_bytes().map_err(|_| {
+            ProtoConversionError::SerializationError(
+                "Unable to get bytes from TransactionTrace".to_string(),
+            )
+        })?;
+        Ok(bytes)
+    }
+}
+
+impl IntoProto<protos::context_trace::TransactionTrace> for TransactionTrace {}
+impl IntoNative<TransactionTrace> for protos::context_trace::TransactionTrace {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::context::manager::sync::ContextManager;
+    use crate::context::ContextLifecycle;
+    use crate::execution::adapter::invocation::InvocationContext;
+    use crate::execution::adapter::static_adapter::{HandlerFamilies, StaticContext};
+    use crate::protocol::command::{
+        AddEvent, AddReceiptData, BytesEntry, Command, DeleteState, GetState, SetState,
+    };
+    use crate::state::hashmap::HashMapState;
+    use crate::workload::command::{make_command_transaction, CommandTransactionHandler};
+
+    /// An in-memory trace file.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns Ok without calling its context.
+    struct NoopHandler {
+        inner: CommandTransactionHandler,
+    }
+
+    impl TransactionHandler for NoopHandler {
+        fn family_name(&self) -> &str {
+            self.inner.family_name()
+        }
+
+        fn family_versions(&self) -> &[String] {
+            self.inner.family_versions()
+        }
+
+        fn apply(
+            &self,
+            _transaction_pair: &TransactionPair,
+            _context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            Ok(())
+        }
+    }
+
+    /// Tests that a recording handler writes a trace of its handler's calls to the trace file,
+    /// and that replaying the trace succeeds with the same handler and fails with another.
+    #[test]
+    fn test_record_and_replay() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let mut context_manager = ContextManager::new(Box::new(state));
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let buffer = SharedBuffer::default();
+        let handler = RecordingHandler::new(
+            Box::new(CommandTransactionHandler::new()),
+            TraceWriter::new(Box::new(buffer.clone())),
+        );
+
+        let txn_pair = make_command_transaction(&[
+            Command::SetState(SetState::new(vec![BytesEntry::new(
+                "abc".into(),
+                b"abc".to_vec(),
+            )])),
+            Command::GetState(GetState::new(vec!["abc".into()])),
+            Command::DeleteState(DeleteState::new(vec!["abc".into()])),
+            Command::AddEvent(AddEvent::new(
+                "event".into(),
+                vec![BytesEntry::new("key".into(), b"value".to_vec())],
+                b"data".to_vec(),
+            )),
+            Command::AddReceiptData(AddReceiptData::new(b"receipt".to_vec())),
+        ]);
+        let mut context = StaticContext::new(&context_manager, &context_id);
+        handler
+            .apply(&txn_pair, &mut context)
+            .expect("Unable to apply transaction");
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        let traces = TraceReader::new(&bytes[..])
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Unable to read traces");
+        assert_eq!(traces.len(), 1);
+        let trace = &traces[0];
+        assert_eq!(
+            trace.transaction_pair().transaction(),
+            txn_pair.transaction()
+        );
+        assert_eq!(trace.outcome(), &TraceOutcome::Valid);
+        assert_eq!(
+            trace.calls()[0],
+            ContextCall::SetState {
+                entries: vec![("abc".into(), b"abc".to_vec())],
+                result: Ok(()),
+            }
+        );
+        assert_eq!(
+            trace.calls()[1],
+            ContextCall::GetState {
+                addresses: vec!["abc".into()],
+                result: Ok(vec![("abc".into(), b"abc".to_vec())]),
+            }
+        );
+        assert_eq!(
+            trace.calls().last(),
+            Some(&ContextCall::AddReceiptData {
+                data: b"receipt".to_vec(),
+                result: Ok(()),
+            })
+        );
+
+        assert_eq!(replay(&CommandTransactionHandler::new(), trace), Ok(()));
+        match replay(
+            &NoopHandler {
+                inner: CommandTransactionHandler::new(),
+            },
+            trace,
+        ) {
+            Err(ReplayError::Diverged(_)) => (),
+            res => panic!("Unexpected replay result: {:?}", res),
+        }
+    }
+
+    /// Tests that invocations are recorded with the bytes returned or the kind of error, and
+    /// that replay returns them to the handler.
+    #[test]
+    fn test_record_and_replay_invocations() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let mut context_manager = ContextManager::new(Box::new(state));
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let buffer = SharedBuffer::default();
+        let handlers: Vec<Box<dyn TransactionHandler>> = vec![Box::new(EchoHandler {
+            versions: vec!["1.0".into()],
+            namespaces: vec!["ec".into()],
+        })];
+        let handler = RecordingHandler::new(
+            Box::new(InvokingHandler {
+                inner: CommandTransactionHandler::new(),
+            }),
+            TraceWriter::new(Box::new(buffer.clone())),
+        );
+
+        let txn_pair = make_command_transaction(&[]);
+        let static_context = StaticContext::new(&context_manager, &context_id);
+        handler
+            .apply(
+                &txn_pair,
+                &mut InvocationContext::new(
+                    &handlers,
+                    &HandlerFamilies::new(&handlers),
+                    &txn_pair,
+                    &static_context,
+                ),
+            )
+            .expect("Unable to apply transaction");
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        let traces = TraceReader::new(&bytes[..])
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Unable to read traces");
+        assert_eq!(traces.len(), 1);
+        let trace = &traces[0];
+        assert_eq!(
+            trace.calls(),
+            &[
+                ContextCall::Invoke {
+                    family_name: "echo".into(),
+                    family_version: "1.0".into(),
+                    payload: b"ping".to_vec(),
+                    result: Ok(b"ping".to_vec()),
+                },
+                ContextCall::SetState {
+                    entries: vec![("abc".into(), b"ping".to_vec())],
+                    result: Ok(()),
+                },
+                ContextCall::Invoke {
+                    family_name: "echo".into(),
+                    family_version: "1.0".into(),
+                    payload: vec![],
+                    result: Err(InvocationFailure::Invalid("empty payload".into())),
+                },
+            ]
+        );
+
+        assert_eq!(
+            replay(
+                &InvokingHandler {
+                    inner: CommandTransactionHandler::new(),
+                },
+                trace
+            ),
+            Ok(())
+        );
+    }
+
+    /// Invoke and query a restricted handler through a `RecordingHandler`, and check that the
+    /// calls reach the wrapped handler, and that it is still restricted to its namespaces.
+    #[test]
+    fn test_recording_handler_forwards_invocations() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let mut context_manager = ContextManager::new(Box::new(state));
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let handlers: Vec<Box<dyn TransactionHandler>> = vec![Box::new(RecordingHandler::new(
+            Box::new(WritingHandler {
+                versions: vec!["1.0".into()],
+                namespaces: vec!["ec".into()],
+            }),
+            TraceWriter::new(Box::new(SharedBuffer::default())),
+        ))];
+        assert_eq!(handlers[0].namespaces(), &["ec".to_string()]);
+
+        let txn_pair = make_command_transaction(&[]);
+        let static_context = StaticContext::new(&context_manager, &context_id);
+        assert_eq!(
+            handlers[0]
+                .query(b"ec01", &static_context)
+                .expect("Unable to query"),
+            b"ec01".to_vec()
+        );
+
+        let families = HandlerFamilies::new(&handlers);
+        let context = InvocationContext::new(&handlers, &families, &txn_pair, &static_context);
+        assert_eq!(
+            context.invoke("writer", "1.0", b"ec01").unwrap(),
+            b"ec01".to_vec()
+        );
+        match context.invoke("writer", "1.0", b"ab01") {
+            Err(ContextError::InvocationError(ApplyError::InvalidTransaction(message))) => {
+                assert!(message.contains("ab01"), "unexpected error: {}", message)
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        assert_eq!(
+            context_manager
+                .get(&context_id, &["ab01".to_string(), "ec01".to_string()])
+                .unwrap(),
+            vec![("ec01".to_string(), b"writer".to_vec())]
+        );
+    }
+
+    /// When invoked, writes its family name to the address in the payload and returns the
+    /// address; queries return their payload.
+    struct WritingHandler {
+        versions: Vec<String>,
+        namespaces: Vec<String>,
+    }
+
+    impl TransactionHandler for WritingHandler {
+        fn family_name(&self) -> &str {
+            "writer"
+        }
+
+        fn family_versions(&self) -> &[String] {
+            &self.versions
+        }
+
+        fn namespaces(&self) -> &[String] {
+            &self.namespaces
+        }
+
+        fn apply(
+            &self,
+            _transaction_pair: &TransactionPair,
+            _context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            Err(ApplyError::InvalidTransaction(
+                "writer may only be invoked".into(),
+            ))
+        }
+
+        fn invoke(
+            &self,
+            invocation: &Invocation,
+            context: &mut dyn TransactionContext,
+        ) -> Result<Vec<u8>, ApplyError> {
+            let address = String::from_utf8(invocation.payload().to_vec())
+                .map_err(|err| ApplyError::InvalidTransaction(err.to_string()))?;
+            context.set_state_entry(address.clone(), b"writer".to_vec())?;
+            Ok(address.into_bytes())
+        }
+
+        fn query(
+            &self,
+            payload: &[u8],
+            _context: &dyn TransactionContext,
+        ) -> Result<Vec<u8>, QueryError> {
+            Ok(payload.to_vec())
+        }
+    }
+
+    /// Invokes echo with "ping", writes the bytes it returns to "abc", and then invokes it with
+    /// an empty payload, which must fail as invalid.
+    struct InvokingHandler {
+        inner: CommandTransactionHandler,
+    }
+
+    impl TransactionHandler for InvokingHandler {
+        fn family_name(&self) -> &str {
+            self.inner.family_name()
+        }
+
+        fn family_versions(&self) -> &[String] {
+            self.inner.family_versions()
+        }
+
+        fn apply(
+            &self,
+            _transaction_pair: &TransactionPair,
+            context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            let output = context.invoke("echo", "1.0", b"ping")?;
+            context.set_state_entry("abc".into(), output)?;
+            match context.invoke("echo", "1.0", b"") {
+                Err(ContextError::InvocationError(ApplyError::InvalidTransaction(_))) => Ok(()),
+                res => Err(ApplyError::InternalError(format!(
+                    "Unexpected invocation result: {:?}",
+                    res
+                ))),
+            }
+        }
+    }
+
+    /// When invoked, returns its payload, or fails if it is empty.
+    struct EchoHandler {
+        versions: Vec<String>,
+        namespaces: Vec<String>,
+    }
+
+    impl TransactionHandler for EchoHandler {
+        fn family_name(&self) -> &str {
+            "echo"
+        }
+
+        fn family_versions(&self) -> &[String] {
+            &self.versions
+        }
+
+        fn namespaces(&self) -> &[String] {
+            &self.namespaces
+        }
+
+        fn apply(
+            &self,
+            _transaction_pair: &TransactionPair,
+            _context: &mut dyn TransactionContext,
+        ) -> Result<(), ApplyError> {
+            Err(ApplyError::InvalidTransaction(
+                "echo may only be invoked".into(),
+            ))
+        }
+
+        fn invoke(
+            &self,
+            invocation: &Invocation,
+            _context: &mut dyn TransactionContext,
+        ) -> Result<Vec<u8>, ApplyError> {
+            if invocation.payload().is_empty() {
+                return Err(ApplyError::InvalidTransaction("empty payload".into()));
+            }
+            Ok(invocation.payload().to_vec())
+        }
+    }
+}
@@ -119,11 +119,19 @@ pub mod context;
 #[cfg(feature = "contract")]
 pub mod contract;
 pub mod database;
+#[cfg(feature = "event-bus")]
+pub mod events;
 pub mod execution;
+#[cfg(feature = "genesis")]
+pub mod genesis;
 pub mod handler;
+#[cfg(feature = "pipeline")]
+pub mod pipeline;
 pub mod protocol;
 #[allow(renamed_and_removed_lints)]
 pub mod protos;
+#[cfg(feature = "receipt-store")]
+pub mod receipt_store;
 #[cfg(feature = "sawtooth-compat")]
 pub mod sawtooth;
 pub mod scheduler;
@@ -0,0 +1,103 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::error::Error;
+use std::fmt;
+
+use crate::execution::adapter::ExecutionAdapterError;
+use crate::execution::executor::ExecutorError;
+use crate::scheduler::simulation::SimulationError;
+use crate::state::merkle::StateDatabaseError;
+use crate::state::StateWriteError;
+
+#[derive(Debug)]
+pub enum PipelineError {
+    /// No handlers or adapters were given, so no transaction could be executed.
+    NoAdapters,
+    /// The empty state could not be created in the database.
+    StateDatabaseError(StateDatabaseError),
+    /// An execution adapter could not be created.
+    ExecutionAdapterError(ExecutionAdapterError),
+    /// The executor could not be started.
+    ExecutorError(ExecutorError),
+    /// The batches could not be executed.
+    SimulationError(SimulationError),
+    /// The state changes of the batches could not be committed.
+    StateWriteError(StateWriteError),
+}
+
+impl Error for PipelineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PipelineError::NoAdapters => None,
+            PipelineError::StateDatabaseError(err) => Some(err),
+            PipelineError::ExecutionAdapterError(err) => Some(err),
+            PipelineError::ExecutorError(err) => Some(err),
+            PipelineError::SimulationError(err) => Some(err),
+            PipelineError::StateWriteError(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PipelineError::NoAdapters => f.write_str("no handlers or adapters were given"),
+            PipelineError::StateDatabaseError(err) => {
+                write!(f, "unable to create empty state: {}", err)
+            }
+            PipelineError::ExecutionAdapterError(err) => {
+                write!(f, "unable to create execution adapter: {}", err)
+            }
+            PipelineError::ExecutorError(err) => write!(f, "unable to start executor: {}", err),
+            PipelineError::SimulationError(err) => write!(f, "unable to execute batches: {}", err),
+            PipelineError::StateWriteError(err) => {
+                write!(f, "unable to commit state changes: {}", err)
+            }
+        }
+    }
+}
+
+impl From<StateDatabaseError> for PipelineError {
+    fn from(err: StateDatabaseError) -> Self {
+        PipelineError::StateDatabaseError(err)
+    }
+}
+
+impl From<ExecutionAdapterError> for PipelineError {
+    fn from(err: ExecutionAdapterError) -> Self {
+        PipelineError::ExecutionAdapterError(err)
+    }
+}
+
+impl From<ExecutorError> for PipelineError {
+    fn from(err: ExecutorError) -> Self {
+        PipelineError::ExecutorError(err)
+    }
+}
+
+impl From<SimulationError> for PipelineError {
+    fn from(err: SimulationError) -> Self {
+        PipelineError::SimulationError(err)
+    }
+}
+
+impl From<StateWriteError> for PipelineError {
+    fn from(err: StateWriteError) -> Self {
+        PipelineError::StateWriteError(err)
+    }
+}
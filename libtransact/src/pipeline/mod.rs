@@ -0,0 +1,299 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! An execution pipeline assembled from a few choices.
+//!
+//! A `PipelineBuilder` connects a database, the merkle state stored in it, a `ContextManager`, the
+//! execution adapters for the given handlers and an `Executor`. The resulting `Pipeline` executes
+//! batches on top of a state root with the chosen kind of scheduler, returning their results and
+//! the resulting state root, and commits their state changes if asked to.
+
+mod error;
+
+use crate::context::manager::sync::ContextManager;
+use crate::database::btree::BTreeDatabase;
+use crate::database::Database;
+use crate::execution::adapter::static_adapter::StaticExecutionAdapter;
+use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError};
+use crate::execution::executor::Executor;
+use crate::handler::TransactionHandler;
+use crate::protocol::batch::BatchPair;
+#[cfg(feature = "batch-commit")]
+use crate::scheduler::commit::BatchCommitter;
+use crate::scheduler::simulation::{SchedulerKind, SimulationResult, Simulator};
+use crate::state::merkle::{self, MerkleRadixTree, MerkleState};
+use crate::state::Write;
+
+pub use crate::pipeline::error::PipelineError;
+
+/// Creates an execution adapter which uses the pipeline's context manager.
+pub type AdapterFactory =
+    Box<dyn FnOnce(ContextManager) -> Result<Box<dyn ExecutionAdapter>, ExecutionAdapterError>>;
+
+/// Where a pipeline stores state.
+pub enum StorageBackend {
+    /// A new in-memory `BTreeDatabase`.
+    InMemory,
+    /// The given database, which must have the merkle state indexes.
+    Database(Box<dyn Database>),
+}
+
+impl Default for StorageBackend {
+    fn default() -> Self {
+        StorageBackend::InMemory
+    }
+}
+
+/// Builds a `Pipeline`.
+///
+/// By default, state is stored in memory and batches are executed with a single
+/// `SerialScheduler`. At least one handler or adapter must be given.
+#[derive(Default)]
+pub struct PipelineBuilder {
+    backend: StorageBackend,
+    scheduler_kind: SchedulerKind,
+    handlers: Vec<Box<dyn TransactionHandler>>,
+    adapter_factories: Vec<AdapterFactory>,
+}
+
+impl PipelineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_backend(mut self, backend: StorageBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_scheduler_kind(mut self, scheduler_kind: SchedulerKind) -> Self {
+        self.scheduler_kind = scheduler_kind;
+        self
+    }
+
+    /// Executes transactions of the handler's family through a `StaticExecutionAdapter`, which
+    /// is shared by all of the handlers given.
+    pub fn with_handler(mut self, handler: Box<dyn TransactionHandler>) -> Self {
+        self.handlers.push(handler);
+        self
+    }
+
+    /// Executes transactions through the adapter created by the factory.
+    pub fn with_adapter(mut self, adapter_factory: AdapterFactory) -> Self {
+        self.adapter_factories.push(adapter_factory);
+        self
+    }
+
+    /// Builds the pipeline, starting its executor.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PipelineError` if no handlers or adapters were given, if the empty state cannot
+    /// be created, or if an adapter or the executor cannot be started.
+    pub fn build(self) -> Result<Pipeline, PipelineError> {
+        if self.handlers.is_empty() && self.adapter_factories.is_empty() {
+            return Err(PipelineError::NoAdapters);
+        }
+
+        let database = match self.backend {
+            StorageBackend::InMemory => {
+                Box::new(BTreeDatabase::new(&merkle::INDEXES)) as Box<dyn Database>
+            }
+            StorageBackend::Database(database) => database,
+        };
+        let empty_state_root = MerkleRadixTree::new(database.clone(), None)?.get_merkle_root();
+        let state = MerkleState::new(database.clone());
+        let context_manager = ContextManager::new(Box::new(state.clone()));
+
+        let mut adapters: Vec<Box<dyn ExecutionAdapter>> = vec![];
+        if !self.handlers.is_empty() {
+            adapters.push(Box::new(StaticExecutionAdapter::new_adapter(
+                self.handlers,
+                context_manager.clone(),
+            )?));
+        }
+        for adapter_factory in self.adapter_factories {
+            adapters.push(adapter_factory(context_manager.clone())?);
+        }
+
+        let mut executor = Executor::new(adapters);
+        executor.start()?;
+
+        Ok(Pipeline {
+            database,
+            state,
+            context_manager,
+            executor,
+            scheduler_kind: self.scheduler_kind,
+            empty_state_root,
+        })
+    }
+}
+
+/// Executes batches on top of merkle state, and commits their state changes.
+///
+/// The pipeline's executor runs until the pipeline is shut down.
+pub struct Pipeline {
+    database: Box<dyn Database>,
+    state: MerkleState,
+    context_manager: ContextManager,
+    executor: Executor,
+    scheduler_kind: SchedulerKind,
+    empty_state_root: String,
+}
+
+impl Pipeline {
+    /// The state root of the empty state.
+    pub fn empty_state_root(&self) -> &str {
+        &self.empty_state_root
+    }
+
+    /// The database which state is stored in.
+    pub fn database(&self) -> &dyn Database {
+        &*self.database
+    }
+
+    /// The merkle state, for reading entries at a state root.
+    pub fn state(&self) -> &MerkleState {
+        &self.state
+    }
+
+    pub fn context_manager(&self) -> &ContextManager {
+        &self.context_manager
+    }
+
+    /// Executes the batches on top of `state_root`, in order, without committing their state
+    /// changes.
+    ///
+    /// The result holds the results of the batches and the state root that committing the state
+    /// changes of the valid batches would produce.
+    pub fn execute_batches(
+        &self,
+        state_root: &str,
+        batches: Vec<BatchPair>,
+    ) -> Result<SimulationResult, PipelineError> {
+        Ok(Simulator::new(
+            &self.executor,
+            self.context_manager.clone(),
+            self.state.clone(),
+        )
+        .with_scheduler_kind(self.scheduler_kind)
+        .simulate_batches(state_root, batches)?)
+    }
+
+    /// Executes the batches on top of `state_root`, as `execute_batches` does, and commits the
+    /// state changes of the valid batches.
+    pub fn execute_and_commit_batches(
+        &self,
+        state_root: &str,
+        batches: Vec<BatchPair>,
+    ) -> Result<SimulationResult, PipelineError> {
+        let result = self.execute_batches(state_root, batches)?;
+        self.commit(state_root, &result)?;
+        Ok(result)
+    }
+
+    /// Commits the state changes of the valid batches of a result of `execute_batches` on top of
+    /// `state_root`, returning the new state root.
+    pub fn commit(
+        &self,
+        state_root: &str,
+        result: &SimulationResult,
+    ) -> Result<String, PipelineError> {
+        Ok(self
+            .state
+            .commit(&state_root.to_string(), &result.state_changes())?)
+    }
+
+    /// A `BatchCommitter` which executes batches with the pipeline's executor and kind of
+    /// scheduler, and commits them to the pipeline's database; the database must have the
+    /// committer's `INDEXES`.
+    #[cfg(feature = "batch-commit")]
+    pub fn batch_committer(&self) -> BatchCommitter {
+        BatchCommitter::new(
+            &self.executor,
+            self.context_manager.clone(),
+            self.database.clone(),
+        )
+        .with_scheduler_kind(self.scheduler_kind)
+    }
+
+    /// Stops the pipeline's executor.
+    pub fn shutdown(self) {
+        self.executor.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::state::Read;
+    use crate::workload::command::{make_set_state_batch, CommandTransactionHandler};
+
+    /// Execute a valid and an invalid batch through pipelines with each kind of scheduler, and
+    /// verify that they produce the same state root, that nothing is committed by
+    /// `execute_batches`, and that committing makes the valid batch's changes readable.
+    #[test]
+    fn pipeline_execute_and_commit() {
+        let address = "ab".repeat(35);
+        let batches = vec![
+            make_set_state_batch(&address, b"valid", false),
+            make_set_state_batch(&"cd".repeat(35), b"invalid", true),
+        ];
+
+        assert!(match PipelineBuilder::new().build() {
+            Err(PipelineError::NoAdapters) => true,
+            _ => false,
+        });
+
+        let mut state_roots = vec![];
+        for scheduler_kind in &[SchedulerKind::Serial, SchedulerKind::Multi(2)] {
+            let pipeline = PipelineBuilder::new()
+                .with_scheduler_kind(*scheduler_kind)
+                .with_handler(Box::new(CommandTransactionHandler::new()))
+                .build()
+                .expect("Unable to build pipeline");
+            let empty_state_root = pipeline.empty_state_root().to_string();
+
+            let result = pipeline
+                .execute_batches(&empty_state_root, batches.clone())
+                .expect("Unable to execute batches");
+            assert_eq!(result.batch_results.len(), 2);
+            assert!(!result.is_valid());
+            assert_ne!(result.state_root, empty_state_root);
+            assert!(pipeline
+                .state()
+                .get(&result.state_root, &[address.clone()])
+                .is_err());
+
+            let committed = pipeline
+                .execute_and_commit_batches(&empty_state_root, batches.clone())
+                .expect("Unable to execute and commit batches");
+            assert_eq!(committed.state_root, result.state_root);
+            let entries = pipeline
+                .state()
+                .get(&committed.state_root, &[address.clone()])
+                .expect("Unable to read committed state");
+            assert_eq!(Some(&b"valid".to_vec()), entries.get(&address));
+
+            state_roots.push(committed.state_root);
+            pipeline.shutdown();
+        }
+        assert_eq!(state_roots[0], state_roots[1]);
+    }
+}
@@ -338,7 +338,7 @@ impl IntoBytes for Transaction {
 impl IntoProto<protos::transaction::Transaction> for Transaction {}
 impl IntoNative<Transaction> for protos::transaction::Transaction {}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TransactionPair {
     transaction: Transaction,
     header: TransactionHeader,
@@ -455,6 +455,18 @@ impl TransactionBuilder {
         self
     }
 
+    /// The inputs declared so far, if any.
+    #[cfg(feature = "io-inference")]
+    pub(crate) fn inputs(&self) -> Option<&[Vec<u8>]> {
+        self.inputs.as_ref().map(Vec::as_slice)
+    }
+
+    /// The outputs declared so far, if any.
+    #[cfg(feature = "io-inference")]
+    pub(crate) fn outputs(&self) -> Option<&[Vec<u8>]> {
+        self.outputs.as_ref().map(Vec::as_slice)
+    }
+
     pub fn with_nonce(mut self, nonce: Vec<u8>) -> TransactionBuilder {
         self.nonce = Some(nonce);
         self
@@ -0,0 +1,70 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::error::Error;
+use std::fmt;
+
+use crate::database::DatabaseError;
+use crate::protos::ProtoConversionError;
+
+#[derive(Debug)]
+pub enum ReceiptStoreError {
+    /// The database could not be read or written.
+    DatabaseError(DatabaseError),
+    /// A receipt or index entry could not be serialized or deserialized.
+    ProtoConversionError(ProtoConversionError),
+    /// An index entry refers to the receipt of the given transaction, but it is not stored.
+    MissingReceipt(String),
+}
+
+impl Error for ReceiptStoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReceiptStoreError::DatabaseError(err) => Some(err),
+            ReceiptStoreError::ProtoConversionError(err) => Some(err),
+            ReceiptStoreError::MissingReceipt(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ReceiptStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReceiptStoreError::DatabaseError(err) => {
+                write!(f, "unable to access receipt store: {}", err)
+            }
+            ReceiptStoreError::ProtoConversionError(err) => {
+                write!(f, "unable to convert receipt store entry: {}", err)
+            }
+            ReceiptStoreError::MissingReceipt(transaction_id) => {
+                write!(f, "receipt of transaction {} is missing", transaction_id)
+            }
+        }
+    }
+}
+
+impl From<DatabaseError> for ReceiptStoreError {
+    fn from(err: DatabaseError) -> Self {
+        ReceiptStoreError::DatabaseError(err)
+    }
+}
+
+impl From<ProtoConversionError> for ReceiptStoreError {
+    fn from(err: ProtoConversionError) -> Self {
+        ReceiptStoreError::ProtoConversionError(err)
+    }
+}
@@ -0,0 +1,412 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Persistent storage of transaction receipts.
+//!
+//! A `ReceiptStore` saves the receipts of committed batches in the indexes of a `Database`: each
+//! receipt by its transaction ID, the transaction IDs of each batch by batch ID, and the batch IDs
+//! saved with each state root by state root. Receipts can be looked up by transaction ID, paged
+//! through by batch or state root, and pruned along with the state roots they were saved with.
+
+mod error;
+
+use protobuf::Message;
+
+use crate::database::{Database, DatabaseReader, DatabaseWriter};
+use crate::protocol::receipt::TransactionReceipt;
+use crate::protos;
+use crate::protos::{FromBytes, IntoBytes, ProtoConversionError};
+use crate::scheduler::BatchExecutionResult;
+
+pub use crate::receipt_store::error::ReceiptStoreError;
+
+/// The index which receipts are stored in, by transaction ID.
+pub const RECEIPT_INDEX: &str = "receipts";
+/// The index which the transaction IDs of each batch are stored in, by batch ID.
+pub const BATCH_RECEIPT_INDEX: &str = "receipt_batches";
+/// The index which the batch IDs saved with each state root are stored in, by state root.
+pub const STATE_ROOT_RECEIPT_INDEX: &str = "receipt_state_roots";
+
+/// The indexes a database must have to be used by a `ReceiptStore`.
+pub const INDEXES: [&str; 3] = [RECEIPT_INDEX, BATCH_RECEIPT_INDEX, STATE_ROOT_RECEIPT_INDEX];
+
+/// A page of the receipts of a batch or state root.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReceiptPage {
+    /// The receipts on the page, in the order they were saved.
+    pub receipts: Vec<TransactionReceipt>,
+    /// The number of receipts of the batch or state root, across all pages.
+    pub total: usize,
+}
+
+/// Saves and looks up transaction receipts in a database.
+#[derive(Clone)]
+pub struct ReceiptStore {
+    database: Box<dyn Database>,
+}
+
+impl ReceiptStore {
+    /// Creates a store over the given database, which must have the `INDEXES`.
+    pub fn new(database: Box<dyn Database>) -> Self {
+        ReceiptStore { database }
+    }
+
+    /// Saves the receipts of the batches, which were committed to produce `state_root`.
+    pub fn add_receipts(
+        &self,
+        state_root: &str,
+        batch_results: &[BatchExecutionResult],
+    ) -> Result<(), ReceiptStoreError> {
+        let mut db_writer = self.database.get_writer()?;
+        Self::write_receipts(&mut *db_writer, state_root, batch_results)?;
+        db_writer.commit()?;
+        Ok(())
+    }
+
+    /// Writes the receipts of the batches, as `add_receipts` does, with the given writer, without
+    /// committing it; this allows receipts to be committed along with other data.
+    pub fn write_receipts(
+        db_writer: &mut dyn DatabaseWriter,
+        state_root: &str,
+        batch_results: &[BatchExecutionResult],
+    ) -> Result<(), ReceiptStoreError> {
+        let mut root_entry: protos::receipt_store::ReceiptStateRootEntry =
+            read_entry(db_writer.as_reader(), STATE_ROOT_RECEIPT_INDEX, state_root)?
+                .unwrap_or_default();
+
+        for batch_result in batch_results {
+            let batch_id = batch_result.batch.batch().header_signature();
+
+            let mut batch_entry = protos::receipt_store::ReceiptBatchEntry::new();
+            batch_entry.set_state_root(state_root.into());
+            for receipt in &batch_result.receipts {
+                db_writer.index_put(
+                    RECEIPT_INDEX,
+                    receipt.transaction_id.as_bytes(),
+                    &receipt.clone().into_bytes()?,
+                )?;
+                batch_entry
+                    .mut_transaction_ids()
+                    .push(receipt.transaction_id.clone());
+            }
+            write_entry(db_writer, BATCH_RECEIPT_INDEX, batch_id, &batch_entry)?;
+
+            if !root_entry.get_batch_ids().iter().any(|id| id == batch_id) {
+                root_entry.mut_batch_ids().push(batch_id.into());
+            }
+        }
+
+        write_entry(db_writer, STATE_ROOT_RECEIPT_INDEX, state_root, &root_entry)
+    }
+
+    /// Returns the receipt of the transaction, if it has been saved.
+    pub fn get_receipt(
+        &self,
+        transaction_id: &str,
+    ) -> Result<Option<TransactionReceipt>, ReceiptStoreError> {
+        let reader = self.database.get_reader()?;
+        match reader.index_get(RECEIPT_INDEX, transaction_id.as_bytes())? {
+            Some(bytes) => Ok(Some(TransactionReceipt::from_bytes(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns at most `limit` of the receipts of the batch, starting at `offset`. The page is
+    /// empty if the batch has no saved receipts.
+    pub fn list_batch_receipts(
+        &self,
+        batch_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<ReceiptPage, ReceiptStoreError> {
+        let reader = self.database.get_reader()?;
+        let transaction_ids = read_entry::<protos::receipt_store::ReceiptBatchEntry>(
+            &*reader,
+            BATCH_RECEIPT_INDEX,
+            batch_id,
+        )?
+        .map(|mut entry| entry.take_transaction_ids().into_vec())
+        .unwrap_or_default();
+        read_page(&*reader, &transaction_ids, offset, limit)
+    }
+
+    /// Returns at most `limit` of the receipts saved with the state root, starting at `offset`.
+    /// The page is empty if the state root has no saved receipts.
+    pub fn list_state_root_receipts(
+        &self,
+        state_root: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<ReceiptPage, ReceiptStoreError> {
+        let reader = self.database.get_reader()?;
+        let batch_ids = read_entry::<protos::receipt_store::ReceiptStateRootEntry>(
+            &*reader,
+            STATE_ROOT_RECEIPT_INDEX,
+            state_root,
+        )?
+        .map(|mut entry| entry.take_batch_ids().into_vec())
+        .unwrap_or_default();
+
+        let mut transaction_ids = vec![];
+        for batch_id in batch_ids {
+            if let Some(mut entry) = read_entry::<protos::receipt_store::ReceiptBatchEntry>(
+                &*reader,
+                BATCH_RECEIPT_INDEX,
+                &batch_id,
+            )? {
+                if entry.get_state_root() == state_root {
+                    transaction_ids.extend(entry.take_transaction_ids().into_iter());
+                }
+            }
+        }
+        read_page(&*reader, &transaction_ids, offset, limit)
+    }
+
+    /// Removes the receipts saved with the given state roots, returning the IDs of the
+    /// transactions whose receipts were removed. This should be called with the state roots
+    /// pruned from state.
+    ///
+    /// The receipts of a batch which was saved again with a later state root are kept.
+    pub fn prune(&self, state_roots: &[String]) -> Result<Vec<String>, ReceiptStoreError> {
+        let mut db_writer = self.database.get_writer()?;
+        let mut pruned = vec![];
+
+        for state_root in state_roots {
+            let root_entry = match read_entry::<protos::receipt_store::ReceiptStateRootEntry>(
+                db_writer.as_reader(),
+                STATE_ROOT_RECEIPT_INDEX,
+                state_root,
+            )? {
+                Some(root_entry) => root_entry,
+                None => continue,
+            };
+
+            for batch_id in root_entry.get_batch_ids() {
+                let batch_entry = match read_entry::<protos::receipt_store::ReceiptBatchEntry>(
+                    db_writer.as_reader(),
+                    BATCH_RECEIPT_INDEX,
+                    batch_id,
+                )? {
+                    Some(batch_entry) => batch_entry,
+                    None => continue,
+                };
+                if batch_entry.get_state_root() != state_root {
+                    continue;
+                }
+
+                for transaction_id in batch_entry.get_transaction_ids() {
+                    if db_writer
+                        .as_reader()
+                        .index_get(RECEIPT_INDEX, transaction_id.as_bytes())?
+                        .is_some()
+                    {
+                        db_writer.index_delete(RECEIPT_INDEX, transaction_id.as_bytes())?;
+                        pruned.push(transaction_id.clone());
+                    }
+                }
+                db_writer.index_delete(BATCH_RECEIPT_INDEX, batch_id.as_bytes())?;
+            }
+            db_writer.index_delete(STATE_ROOT_RECEIPT_INDEX, state_root.as_bytes())?;
+        }
+
+        db_writer.commit()?;
+        Ok(pruned)
+    }
+}
+
+fn read_page(
+    reader: &dyn DatabaseReader,
+    transaction_ids: &[String],
+    offset: usize,
+    limit: usize,
+) -> Result<ReceiptPage, ReceiptStoreError> {
+    let receipts = transaction_ids
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(
+            |transaction_id| match reader.index_get(RECEIPT_INDEX, transaction_id.as_bytes())? {
+                Some(bytes) => Ok(TransactionReceipt::from_bytes(&bytes)?),
+                None => Err(ReceiptStoreError::MissingReceipt(transaction_id.clone())),
+            },
+        )
+        .collect::<Result<_, ReceiptStoreError>>()?;
+
+    Ok(ReceiptPage {
+        receipts,
+        total: transaction_ids.len(),
+    })
+}
+
+fn read_entry<M: Message>(
+    reader: &dyn DatabaseReader,
+    index: &str,
+    key: &str,
+) -> Result<Option<M>, ReceiptStoreError> {
+    match reader.index_get(index, key.as_bytes())? {
+        Some(bytes) => Ok(Some(protobuf::parse_from_bytes(&bytes).map_err(|_| {
+            ProtoConversionError::SerializationError(format!(
+                "Unable to get {} entry from bytes",
+                index
+            ))
+        })?)),
+        None => Ok(None),
+    }
+}
+
+fn write_entry<M: Message>(
+    db_writer: &mut dyn DatabaseWriter,
+    index: &str,
+    key: &str,
+    entry: &M,
+) -> Result<(), ReceiptStoreError> {
+    let bytes = entry.write_to_bytes().map_err(|_| {
+        ProtoConversionError::SerializationError(format!(
+            "Unable to get bytes from {} entry",
+            index
+        ))
+    })?;
+    db_writer.index_put(index, key.as_bytes(), &bytes)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::command::{BytesEntry, Command, SetState};
+    use crate::protocol::receipt::{StateChange, TransactionReceiptBuilder};
+    use crate::signing::hash::HashSigner;
+    use crate::workload::command::make_command_transaction;
+
+    fn batch_result(keys: &[&str]) -> BatchExecutionResult {
+        let transactions = keys
+            .iter()
+            .map(|key| {
+                make_command_transaction(&[Command::SetState(SetState::new(vec![
+                    BytesEntry::new(key.to_string(), key.as_bytes().to_vec()),
+                ]))])
+            })
+            .collect::<Vec<_>>();
+        let receipts = transactions
+            .iter()
+            .zip(keys)
+            .map(|(transaction, key)| {
+                TransactionReceiptBuilder::new()
+                    .valid()
+                    .with_state_changes(vec![StateChange::Set {
+                        key: key.to_string(),
+                        value: key.as_bytes().to_vec(),
+                    }])
+                    .with_transaction_id(transaction.transaction().header_signature().into())
+                    .build()
+                    .expect("Unable to build receipt")
+            })
+            .collect();
+        let batch = BatchBuilder::new()
+            .with_transactions(
+                transactions
+                    .into_iter()
+                    .map(|transaction| transaction.take().0)
+                    .collect(),
+            )
+            .build_pair(&HashSigner::default())
+            .expect("Unable to build batch");
+        BatchExecutionResult { batch, receipts }
+    }
+
+    fn transaction_ids(receipts: &[TransactionReceipt]) -> Vec<String> {
+        receipts
+            .iter()
+            .map(|receipt| receipt.transaction_id.clone())
+            .collect()
+    }
+
+    /// Save the receipts of two batches with one state root and a third batch with another, and
+    /// verify that they can be looked up and paged through, and that pruning the first root
+    /// removes only its receipts.
+    #[test]
+    fn receipt_store() {
+        let store = ReceiptStore::new(Box::new(BTreeDatabase::new(&INDEXES)));
+
+        let first = batch_result(&["a", "b", "c"]);
+        let second = batch_result(&["d"]);
+        let third = batch_result(&["e", "f"]);
+        store
+            .add_receipts("root1", &[first.clone(), second.clone()])
+            .expect("Unable to add receipts");
+        store
+            .add_receipts("root2", &[third.clone()])
+            .expect("Unable to add receipts");
+
+        let receipt = &first.receipts[1];
+        assert_eq!(
+            store
+                .get_receipt(&receipt.transaction_id)
+                .expect("Unable to get receipt"),
+            Some(receipt.clone())
+        );
+        assert_eq!(
+            store.get_receipt("unknown").expect("Unable to get receipt"),
+            None
+        );
+
+        let page = store
+            .list_batch_receipts(first.batch.batch().header_signature(), 1, 5)
+            .expect("Unable to list receipts");
+        assert_eq!(page.total, 3);
+        assert_eq!(page.receipts, first.receipts[1..].to_vec());
+
+        let page = store
+            .list_state_root_receipts("root1", 2, 2)
+            .expect("Unable to list receipts");
+        assert_eq!(page.total, 4);
+        assert_eq!(
+            transaction_ids(&page.receipts),
+            vec![
+                first.receipts[2].transaction_id.clone(),
+                second.receipts[0].transaction_id.clone()
+            ]
+        );
+
+        let pruned = store
+            .prune(&["root1".to_string()])
+            .expect("Unable to prune receipts");
+        assert_eq!(pruned.len(), 4);
+        assert_eq!(
+            store
+                .get_receipt(&receipt.transaction_id)
+                .expect("Unable to get receipt"),
+            None
+        );
+        assert_eq!(
+            store
+                .list_state_root_receipts("root1", 0, 10)
+                .expect("Unable to list receipts"),
+            ReceiptPage::default()
+        );
+        assert_eq!(
+            store
+                .list_state_root_receipts("root2", 0, 10)
+                .expect("Unable to list receipts")
+                .receipts,
+            third.receipts
+        );
+    }
+}
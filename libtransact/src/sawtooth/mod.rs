@@ -21,6 +21,16 @@
 //!
 //! Note, to use this module, the Transact library must have the `"sawtooth-compat"` feature
 //! enabled.
+//!
+//! With the `"sawtooth-tp-adapter"` feature, this module also provides the
+//! `SawtoothTpExecutionAdapter`, which executes transactions using unmodified Sawtooth transaction
+//! processors, written in any language, running in separate processes.
+
+#[cfg(feature = "sawtooth-tp-adapter")]
+mod tp_adapter;
+
+#[cfg(feature = "sawtooth-tp-adapter")]
+pub use self::tp_adapter::SawtoothTpExecutionAdapter;
 
 use sawtooth_sdk::messages::processor::TpProcessRequest;
 use sawtooth_sdk::messages::transaction::TransactionHeader as SawtoothTxnHeader;
@@ -0,0 +1,1024 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! An execution adapter which speaks the Sawtooth validator to transaction processor protocol.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+use protobuf::{Message as ProtobufMessage, RepeatedField};
+use sawtooth_sdk::messages::events::{Event as SawtoothEvent, TpEventAddRequest};
+use sawtooth_sdk::messages::events::{TpEventAddResponse, TpEventAddResponse_Status};
+use sawtooth_sdk::messages::network::PingResponse;
+use sawtooth_sdk::messages::processor::{
+    TpProcessRequest, TpProcessResponse, TpProcessResponse_Status, TpRegisterRequest,
+    TpRegisterRequest_TpProcessRequestHeaderStyle, TpRegisterResponse, TpRegisterResponse_Status,
+    TpUnregisterResponse, TpUnregisterResponse_Status,
+};
+use sawtooth_sdk::messages::receipt::{
+    TpReceiptAddDataRequest, TpReceiptAddDataResponse, TpReceiptAddDataResponse_Status,
+};
+use sawtooth_sdk::messages::state_context::{
+    TpStateDeleteRequest, TpStateDeleteResponse, TpStateDeleteResponse_Status, TpStateEntry,
+    TpStateGetRequest, TpStateGetResponse, TpStateGetResponse_Status, TpStateSetRequest,
+    TpStateSetResponse, TpStateSetResponse_Status,
+};
+use sawtooth_sdk::messages::validator::{Message, Message_MessageType};
+
+use crate::context::manager::sync::ContextManager;
+use crate::context::ContextId;
+use crate::execution::adapter::{ExecutionAdapter, ExecutionAdapterError, ExecutionOperationError};
+use crate::execution::{ExecutionRegistry, TransactionFamily};
+use crate::handler::ApplyError;
+use crate::protocol::receipt::Event;
+use crate::protocol::transaction::TransactionPair;
+use crate::scheduler::{ExecutionTaskCompletionNotification, InvalidTransactionResult};
+
+use super::as_sawtooth_header;
+
+type OnDoneCallback =
+    Box<dyn Fn(Result<ExecutionTaskCompletionNotification, ExecutionAdapterError>) + Send>;
+
+/// An `ExecutionAdapter` which executes transactions using Sawtooth transaction processors.
+///
+/// The adapter binds a ZMQ socket, to which transaction processors connect exactly as they would
+/// connect to a Sawtooth validator. Processors register their families with the
+/// `ExecutionRegistry`, are sent the transactions of those families, and read and write state
+/// through the adapter, which serves these requests from the `ContextManager`. As in Sawtooth,
+/// reads are limited to a transaction's inputs and writes to its outputs.
+///
+/// Processors which exit without unregistering are not detected; their transactions will not
+/// complete.
+pub struct SawtoothTpExecutionAdapter {
+    endpoint: String,
+    sender: Sender<TpAdapterCommand>,
+    // Wakes the adapter's thread when a command is sent
+    waker: Mutex<zmq::Socket>,
+    join_handle: thread::JoinHandle<()>,
+}
+
+impl SawtoothTpExecutionAdapter {
+    /// Creates a new adapter, bound to the given ZMQ endpoint, such as `"tcp://127.0.0.1:4004"`.
+    ///
+    /// # Errors
+    ///
+    /// `ExecutionAdapterError` is returned if the endpoint cannot be bound or the background
+    /// thread cannot be created.
+    pub fn new(
+        endpoint: &str,
+        context_manager: ContextManager,
+    ) -> Result<Self, ExecutionAdapterError> {
+        let zmq_context = zmq::Context::new();
+
+        let router = zmq_context.socket(zmq::ROUTER).map_err(general_error)?;
+        router.bind(endpoint).map_err(general_error)?;
+        let endpoint = router
+            .get_last_endpoint()
+            .map_err(general_error)?
+            .map_err(|_| {
+                ExecutionAdapterError::GeneralExecutionError(Box::new(TpAdapterError(
+                    "bound endpoint is not valid UTF-8".into(),
+                )))
+            })?;
+
+        let wake_endpoint = format!("inproc://sawtooth-tp-adapter-{}", uuid::Uuid::new_v4());
+        let wake_receiver = zmq_context.socket(zmq::PULL).map_err(general_error)?;
+        wake_receiver.bind(&wake_endpoint).map_err(general_error)?;
+        let waker = zmq_context.socket(zmq::PUSH).map_err(general_error)?;
+        waker.connect(&wake_endpoint).map_err(general_error)?;
+
+        let (sender, receiver) = channel();
+        let join_handle = thread::Builder::new()
+            .name("SawtoothTpExecutionAdapter".into())
+            .spawn(move || {
+                let mut core = TpAdapterCore {
+                    router,
+                    wake_receiver,
+                    receiver,
+                    context_manager,
+                    registry: None,
+                    processors: HashMap::new(),
+                    pending: HashMap::new(),
+                };
+                if let Err(err) = core.run() {
+                    error!("Sawtooth TP adapter stopped unexpectedly: {}", err);
+                }
+            })
+            .map_err(|err| ExecutionAdapterError::GeneralExecutionError(Box::new(err)))?;
+
+        Ok(SawtoothTpExecutionAdapter {
+            endpoint,
+            sender,
+            waker: Mutex::new(waker),
+            join_handle,
+        })
+    }
+
+    /// The endpoint transaction processors should connect to; if a wildcard port was requested,
+    /// this includes the assigned port.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    fn send_command(&self, command: TpAdapterCommand) -> Result<(), String> {
+        self.sender.send(command).map_err(|err| err.to_string())?;
+        self.waker
+            .lock()
+            .map_err(|_| "waker lock poisoned".to_string())?
+            .send(&[] as &[u8], 0)
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl ExecutionAdapter for SawtoothTpExecutionAdapter {
+    fn start(
+        &mut self,
+        execution_registry: Box<dyn ExecutionRegistry>,
+    ) -> Result<(), ExecutionOperationError> {
+        self.send_command(TpAdapterCommand::Start(execution_registry))
+            .map_err(|err| {
+                ExecutionOperationError::StartError(format!(
+                    "Unable to start Sawtooth TP adapter: {}",
+                    err
+                ))
+            })
+    }
+
+    fn execute(
+        &self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: OnDoneCallback,
+    ) -> Result<(), ExecutionOperationError> {
+        self.send_command(TpAdapterCommand::Execute(Box::new((
+            transaction_pair,
+            context_id,
+            on_done,
+        ))))
+        .map_err(|err| {
+            ExecutionOperationError::ExecuteError(format!(
+                "Unable to send transaction to Sawtooth TP adapter: {}",
+                err
+            ))
+        })
+    }
+
+    fn stop(self: Box<Self>) -> Result<(), ExecutionOperationError> {
+        self.send_command(TpAdapterCommand::Stop).map_err(|err| {
+            ExecutionOperationError::StopError(format!("Unable to send stop command: {}", err))
+        })?;
+
+        self.join_handle.join().map_err(|_| {
+            ExecutionOperationError::StopError("Unable to join internal thread.".into())
+        })
+    }
+}
+
+enum TpAdapterCommand {
+    Start(Box<dyn ExecutionRegistry>),
+    Stop,
+    Execute(Box<(TransactionPair, ContextId, OnDoneCallback)>),
+}
+
+#[derive(Debug)]
+struct TpAdapterError(String);
+
+impl std::error::Error for TpAdapterError {}
+
+impl std::fmt::Display for TpAdapterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<zmq::Error> for TpAdapterError {
+    fn from(err: zmq::Error) -> Self {
+        TpAdapterError(format!("ZMQ error: {}", err))
+    }
+}
+
+impl From<protobuf::ProtobufError> for TpAdapterError {
+    fn from(err: protobuf::ProtobufError) -> Self {
+        TpAdapterError(format!("protobuf error: {}", err))
+    }
+}
+
+fn general_error(err: zmq::Error) -> ExecutionAdapterError {
+    ExecutionAdapterError::GeneralExecutionError(Box::new(err))
+}
+
+/// A family registered by a transaction processor.
+struct Registration {
+    family: TransactionFamily,
+    raw_header: bool,
+}
+
+/// A transaction which has been sent to a transaction processor, but whose result has not been
+/// received.
+struct PendingTransaction {
+    identity: Vec<u8>,
+    context_id: ContextId,
+    transaction_pair: TransactionPair,
+    on_done: OnDoneCallback,
+}
+
+struct TpAdapterCore {
+    router: zmq::Socket,
+    wake_receiver: zmq::Socket,
+    receiver: Receiver<TpAdapterCommand>,
+    context_manager: ContextManager,
+    registry: Option<Box<dyn ExecutionRegistry>>,
+    /// The families registered by each connected processor, keyed by ZMQ identity
+    processors: HashMap<Vec<u8>, Vec<Registration>>,
+    /// Transactions sent to processors, keyed by correlation ID
+    pending: HashMap<String, PendingTransaction>,
+}
+
+impl TpAdapterCore {
+    fn run(&mut self) -> Result<(), TpAdapterError> {
+        loop {
+            let (router_ready, wake_ready) = {
+                let mut items = [
+                    self.router.as_poll_item(zmq::POLLIN),
+                    self.wake_receiver.as_poll_item(zmq::POLLIN),
+                ];
+                zmq::poll(&mut items, -1)?;
+                (items[0].is_readable(), items[1].is_readable())
+            };
+
+            if wake_ready {
+                while self.wake_receiver.recv_bytes(zmq::DONTWAIT).is_ok() {}
+                while let Ok(command) = self.receiver.try_recv() {
+                    match command {
+                        TpAdapterCommand::Start(registry) => {
+                            let mut registry = registry;
+                            for registration in self.processors.values().flatten() {
+                                registry.register_transaction_family(registration.family.clone());
+                            }
+                            self.registry = Some(registry);
+                        }
+                        TpAdapterCommand::Execute(execute) => {
+                            let (transaction_pair, context_id, on_done) = *execute;
+                            self.execute(transaction_pair, context_id, on_done);
+                        }
+                        TpAdapterCommand::Stop => return Ok(()),
+                    }
+                }
+            }
+
+            if router_ready {
+                loop {
+                    match self.router.recv_multipart(zmq::DONTWAIT) {
+                        Ok(frames) => self.handle_frames(frames),
+                        Err(zmq::Error::EAGAIN) => break,
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            }
+        }
+    }
+
+    fn execute(
+        &mut self,
+        transaction_pair: TransactionPair,
+        context_id: ContextId,
+        on_done: OnDoneCallback,
+    ) {
+        let family = TransactionFamily::from_pair(&transaction_pair);
+
+        // Send the transaction to the processor with the fewest outstanding transactions
+        let route = self
+            .processors
+            .iter()
+            .filter_map(|(identity, registrations)| {
+                registrations
+                    .iter()
+                    .find(|registration| registration.family == family)
+                    .map(|registration| (identity, registration.raw_header))
+            })
+            .min_by_key(|(identity, _)| {
+                self.pending
+                    .values()
+                    .filter(|pending| &&pending.identity == identity)
+                    .count()
+            })
+            .map(|(identity, raw_header)| (identity.clone(), raw_header));
+        let (identity, raw_header) = match route {
+            Some(route) => route,
+            None => {
+                on_done(Err(ExecutionAdapterError::RoutingError(Box::new(
+                    transaction_pair,
+                ))));
+                return;
+            }
+        };
+
+        let mut request = TpProcessRequest::new();
+        if raw_header {
+            request.set_header_bytes(transaction_pair.transaction().header().to_vec());
+        } else {
+            let mut header = as_sawtooth_header(transaction_pair.header());
+            header.set_payload_sha512(hex::encode(transaction_pair.header().payload_hash()));
+            request.set_header(header);
+        }
+        request.set_payload(transaction_pair.transaction().payload().to_vec());
+        request.set_signature(transaction_pair.transaction().header_signature().into());
+        request.set_context_id(hex::encode(context_id));
+
+        let correlation_id = uuid::Uuid::new_v4().to_string();
+        match self.send(
+            &identity,
+            Message_MessageType::TP_PROCESS_REQUEST,
+            correlation_id.clone(),
+            &request,
+        ) {
+            Ok(()) => {
+                self.pending.insert(
+                    correlation_id,
+                    PendingTransaction {
+                        identity,
+                        context_id,
+                        transaction_pair,
+                        on_done,
+                    },
+                );
+            }
+            Err(err) => {
+                warn!("Unable to send transaction to processor: {}", err);
+                on_done(Err(ExecutionAdapterError::RoutingError(Box::new(
+                    transaction_pair,
+                ))));
+            }
+        }
+    }
+
+    fn send(
+        &self,
+        identity: &[u8],
+        message_type: Message_MessageType,
+        correlation_id: String,
+        content: &dyn ProtobufMessage,
+    ) -> Result<(), TpAdapterError> {
+        let mut message = Message::new();
+        message.set_message_type(message_type);
+        message.set_correlation_id(correlation_id);
+        message.set_content(content.write_to_bytes()?);
+        self.router
+            .send_multipart(vec![identity.to_vec(), message.write_to_bytes()?], 0)?;
+        Ok(())
+    }
+
+    fn handle_frames(&mut self, mut frames: Vec<Vec<u8>>) {
+        if frames.len() != 2 {
+            warn!("Received message with {} frames; ignoring", frames.len());
+            return;
+        }
+        let message_bytes = frames.pop().unwrap_or_default();
+        let identity = frames.pop().unwrap_or_default();
+
+        let message: Message = match protobuf::parse_from_bytes(&message_bytes) {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("Unable to parse message from processor: {}", err);
+                return;
+            }
+        };
+
+        if let Err(err) = self.handle_message(&identity, &message) {
+            warn!(
+                "Unable to handle {:?} message from processor: {}",
+                message.get_message_type(),
+                err
+            );
+        }
+    }
+
+    fn handle_message(&mut self, identity: &[u8], message: &Message) -> Result<(), TpAdapterError> {
+        let correlation_id = message.get_correlation_id().to_string();
+        match message.get_message_type() {
+            Message_MessageType::TP_REGISTER_REQUEST => {
+                let request: TpRegisterRequest = protobuf::parse_from_bytes(message.get_content())?;
+                let family = TransactionFamily::new(
+                    request.get_family().into(),
+                    request.get_version().into(),
+                );
+                debug!(
+                    "Transaction processor registered {} {}",
+                    family.family_name(),
+                    family.family_version()
+                );
+                self.register(
+                    identity,
+                    Registration {
+                        family,
+                        raw_header: request.get_request_header_style()
+                            == TpRegisterRequest_TpProcessRequestHeaderStyle::RAW,
+                    },
+                );
+
+                let mut response = TpRegisterResponse::new();
+                response.set_status(TpRegisterResponse_Status::OK);
+                response.set_protocol_version(request.get_protocol_version());
+                self.send(
+                    identity,
+                    Message_MessageType::TP_REGISTER_RESPONSE,
+                    correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::TP_UNREGISTER_REQUEST => {
+                self.unregister(identity);
+
+                let mut response = TpUnregisterResponse::new();
+                response.set_status(TpUnregisterResponse_Status::OK);
+                self.send(
+                    identity,
+                    Message_MessageType::TP_UNREGISTER_RESPONSE,
+                    correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::TP_PROCESS_RESPONSE => {
+                let response: TpProcessResponse =
+                    protobuf::parse_from_bytes(message.get_content())?;
+                let is_sender = self
+                    .pending
+                    .get(&correlation_id)
+                    .map(|pending| pending.identity == identity)
+                    .unwrap_or(false);
+                match self.pending.remove(&correlation_id) {
+                    Some(pending) if is_sender => complete_transaction(pending, response),
+                    Some(pending) => {
+                        self.pending.insert(correlation_id, pending);
+                    }
+                    None => warn!("Received result for unknown request {}", correlation_id),
+                }
+                Ok(())
+            }
+            Message_MessageType::TP_STATE_GET_REQUEST => {
+                let request: TpStateGetRequest = protobuf::parse_from_bytes(message.get_content())?;
+                let mut response = TpStateGetResponse::new();
+                match self
+                    .authorize(
+                        identity,
+                        request.get_context_id(),
+                        request.get_addresses(),
+                        Access::Read,
+                    )
+                    .and_then(|context_id| {
+                        self.context_manager
+                            .get(&context_id, request.get_addresses())
+                            .map_err(|err| err.to_string())
+                    }) {
+                    Ok(entries) => {
+                        response.set_status(TpStateGetResponse_Status::OK);
+                        response.set_entries(RepeatedField::from_vec(
+                            entries
+                                .into_iter()
+                                .map(|(address, data)| {
+                                    let mut entry = TpStateEntry::new();
+                                    entry.set_address(address);
+                                    entry.set_data(data);
+                                    entry
+                                })
+                                .collect(),
+                        ));
+                    }
+                    Err(err) => {
+                        debug!("Rejecting state get request: {}", err);
+                        response.set_status(TpStateGetResponse_Status::AUTHORIZATION_ERROR);
+                    }
+                }
+                self.send(
+                    identity,
+                    Message_MessageType::TP_STATE_GET_RESPONSE,
+                    correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::TP_STATE_SET_REQUEST => {
+                let request: TpStateSetRequest = protobuf::parse_from_bytes(message.get_content())?;
+                let addresses = request
+                    .get_entries()
+                    .iter()
+                    .map(|entry| entry.get_address().to_string())
+                    .collect::<Vec<_>>();
+                let mut response = TpStateSetResponse::new();
+                match self
+                    .authorize(
+                        identity,
+                        request.get_context_id(),
+                        &addresses,
+                        Access::Write,
+                    )
+                    .and_then(|context_id| {
+                        request.get_entries().iter().try_for_each(|entry| {
+                            self.context_manager
+                                .set_state(
+                                    &context_id,
+                                    entry.get_address().into(),
+                                    entry.get_data().to_vec(),
+                                )
+                                .map_err(|err| err.to_string())
+                        })
+                    }) {
+                    Ok(()) => {
+                        response.set_status(TpStateSetResponse_Status::OK);
+                        response.set_addresses(RepeatedField::from_vec(addresses));
+                    }
+                    Err(err) => {
+                        debug!("Rejecting state set request: {}", err);
+                        response.set_status(TpStateSetResponse_Status::AUTHORIZATION_ERROR);
+                    }
+                }
+                self.send(
+                    identity,
+                    Message_MessageType::TP_STATE_SET_RESPONSE,
+                    correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::TP_STATE_DELETE_REQUEST => {
+                let request: TpStateDeleteRequest =
+                    protobuf::parse_from_bytes(message.get_content())?;
+                let mut response = TpStateDeleteResponse::new();
+                match self
+                    .authorize(
+                        identity,
+                        request.get_context_id(),
+                        request.get_addresses(),
+                        Access::Write,
+                    )
+                    .and_then(|context_id| {
+                        let mut deleted = vec![];
+                        for address in request.get_addresses() {
+                            if self
+                                .context_manager
+                                .delete_state(&context_id, address)
+                                .map_err(|err| err.to_string())?
+                                .is_some()
+                            {
+                                deleted.push(address.clone());
+                            }
+                        }
+                        Ok(deleted)
+                    }) {
+                    Ok(deleted) => {
+                        response.set_status(TpStateDeleteResponse_Status::OK);
+                        response.set_addresses(RepeatedField::from_vec(deleted));
+                    }
+                    Err(err) => {
+                        debug!("Rejecting state delete request: {}", err);
+                        response.set_status(TpStateDeleteResponse_Status::AUTHORIZATION_ERROR);
+                    }
+                }
+                self.send(
+                    identity,
+                    Message_MessageType::TP_STATE_DELETE_RESPONSE,
+                    correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::TP_RECEIPT_ADD_DATA_REQUEST => {
+                let request: TpReceiptAddDataRequest =
+                    protobuf::parse_from_bytes(message.get_content())?;
+                let mut response = TpReceiptAddDataResponse::new();
+                match self
+                    .authorize(identity, request.get_context_id(), &[], Access::Read)
+                    .and_then(|context_id| {
+                        self.context_manager
+                            .add_data(&context_id, request.get_data().to_vec())
+                            .map_err(|err| err.to_string())
+                    }) {
+                    Ok(()) => response.set_status(TpReceiptAddDataResponse_Status::OK),
+                    Err(err) => {
+                        debug!("Rejecting receipt data request: {}", err);
+                        response.set_status(TpReceiptAddDataResponse_Status::ERROR);
+                    }
+                }
+                self.send(
+                    identity,
+                    Message_MessageType::TP_RECEIPT_ADD_DATA_RESPONSE,
+                    correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::TP_EVENT_ADD_REQUEST => {
+                let request: TpEventAddRequest = protobuf::parse_from_bytes(message.get_content())?;
+                let mut response = TpEventAddResponse::new();
+                match self
+                    .authorize(identity, request.get_context_id(), &[], Access::Read)
+                    .and_then(|context_id| {
+                        self.context_manager
+                            .add_event(&context_id, from_sawtooth_event(request.get_event()))
+                            .map_err(|err| err.to_string())
+                    }) {
+                    Ok(()) => response.set_status(TpEventAddResponse_Status::OK),
+                    Err(err) => {
+                        debug!("Rejecting event request: {}", err);
+                        response.set_status(TpEventAddResponse_Status::ERROR);
+                    }
+                }
+                self.send(
+                    identity,
+                    Message_MessageType::TP_EVENT_ADD_RESPONSE,
+                    correlation_id,
+                    &response,
+                )
+            }
+            Message_MessageType::PING_REQUEST => self.send(
+                identity,
+                Message_MessageType::PING_RESPONSE,
+                correlation_id,
+                &PingResponse::new(),
+            ),
+            Message_MessageType::PING_RESPONSE => Ok(()),
+            message_type => Err(TpAdapterError(format!(
+                "unexpected message type {:?}",
+                message_type
+            ))),
+        }
+    }
+
+    fn register(&mut self, identity: &[u8], registration: Registration) {
+        let family_registered = self
+            .processors
+            .values()
+            .flatten()
+            .any(|existing| existing.family == registration.family);
+        if !family_registered {
+            if let Some(registry) = self.registry.as_mut() {
+                registry.register_transaction_family(registration.family.clone());
+            }
+        }
+
+        let registrations = self
+            .processors
+            .entry(identity.to_vec())
+            .or_insert_with(Vec::new);
+        registrations.retain(|existing| existing.family != registration.family);
+        registrations.push(registration);
+    }
+
+    /// Removes all of the processor's registrations, and fails its outstanding transactions, since
+    /// the processor may already have written to their contexts.
+    fn unregister(&mut self, identity: &[u8]) {
+        let registrations = self.processors.remove(identity).unwrap_or_default();
+        for registration in registrations {
+            let family_registered = self
+                .processors
+                .values()
+                .flatten()
+                .any(|existing| existing.family == registration.family);
+            if !family_registered {
+                if let Some(registry) = self.registry.as_mut() {
+                    registry.unregister_transaction_family(&registration.family);
+                }
+            }
+        }
+
+        let correlation_ids = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.identity == identity)
+            .map(|(correlation_id, _)| correlation_id.clone())
+            .collect::<Vec<_>>();
+        for correlation_id in correlation_ids {
+            if let Some(pending) = self.pending.remove(&correlation_id) {
+                (pending.on_done)(Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+                    TpAdapterError(format!(
+                        "processor disconnected while processing transaction {}",
+                        pending.transaction_pair.transaction().header_signature()
+                    )),
+                ))));
+            }
+        }
+    }
+
+    /// Checks that the context belongs to a transaction the processor is executing, and that the
+    /// transaction may access the given addresses; returns the context's ID.
+    fn authorize(
+        &self,
+        identity: &[u8],
+        context_id: &str,
+        addresses: &[String],
+        access: Access,
+    ) -> Result<ContextId, String> {
+        let pending = self
+            .pending
+            .values()
+            .find(|pending| {
+                pending.identity == identity && hex::encode(pending.context_id) == context_id
+            })
+            .ok_or_else(|| format!("context {} is not in use by this processor", context_id))?;
+
+        let header = pending.transaction_pair.header();
+        let allowed = match access {
+            Access::Read => header.inputs(),
+            Access::Write => header.outputs(),
+        };
+        for address in addresses {
+            if !allowed
+                .iter()
+                .any(|prefix| address.starts_with(&hex::encode(prefix)))
+            {
+                return Err(format!(
+                    "address {} is not in the transaction's {}",
+                    address,
+                    match access {
+                        Access::Read => "inputs",
+                        Access::Write => "outputs",
+                    }
+                ));
+            }
+        }
+
+        Ok(pending.context_id)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Access {
+    Read,
+    Write,
+}
+
+fn from_sawtooth_event(event: &SawtoothEvent) -> Event {
+    Event {
+        event_type: event.get_event_type().into(),
+        attributes: event
+            .get_attributes()
+            .iter()
+            .map(|attribute| (attribute.get_key().into(), attribute.get_value().into()))
+            .collect(),
+        data: event.get_data().to_vec(),
+    }
+}
+
+fn complete_transaction(pending: PendingTransaction, mut response: TpProcessResponse) {
+    let transaction_id = pending
+        .transaction_pair
+        .transaction()
+        .header_signature()
+        .to_string();
+    let result = match response.get_status() {
+        TpProcessResponse_Status::OK => Ok(ExecutionTaskCompletionNotification::Valid(
+            pending.context_id,
+            transaction_id,
+        )),
+        TpProcessResponse_Status::INVALID_TRANSACTION => {
+            Ok(ExecutionTaskCompletionNotification::Invalid(
+                pending.context_id,
+                InvalidTransactionResult {
+                    transaction_id,
+                    error_message: response.take_message(),
+                    error_data: response.take_extended_data(),
+                },
+            ))
+        }
+        TpProcessResponse_Status::INTERNAL_ERROR | TpProcessResponse_Status::STATUS_UNSET => {
+            Err(ExecutionAdapterError::GeneralExecutionError(Box::new(
+                ApplyError::InternalError(response.take_message()),
+            )))
+        }
+    };
+    (pending.on_done)(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use sawtooth_sdk::messages::processor::TpProcessRequest;
+
+    use crate::context::ContextLifecycle;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
+    use crate::state::hashmap::HashMapState;
+
+    /// Reports registration changes over a channel.
+    struct ChannelRegistry {
+        sender: Sender<(bool, TransactionFamily)>,
+    }
+
+    impl ExecutionRegistry for ChannelRegistry {
+        fn register_transaction_family(&mut self, family: TransactionFamily) {
+            self.sender
+                .send((true, family))
+                .expect("Unable to send registration");
+        }
+
+        fn unregister_transaction_family(&mut self, family: &TransactionFamily) {
+            self.sender
+                .send((false, family.clone()))
+                .expect("Unable to send unregistration");
+        }
+    }
+
+    /// A transaction processor which sends and receives raw Sawtooth messages.
+    struct TestProcessor {
+        socket: zmq::Socket,
+    }
+
+    impl TestProcessor {
+        fn connect(endpoint: &str) -> Self {
+            let socket = zmq::Context::new()
+                .socket(zmq::DEALER)
+                .expect("Unable to create socket");
+            socket.connect(endpoint).expect("Unable to connect");
+            socket
+                .set_rcvtimeo(5000)
+                .expect("Unable to set receive timeout");
+            TestProcessor { socket }
+        }
+
+        fn send(
+            &self,
+            message_type: Message_MessageType,
+            correlation_id: &str,
+            content: &dyn ProtobufMessage,
+        ) {
+            let mut message = Message::new();
+            message.set_message_type(message_type);
+            message.set_correlation_id(correlation_id.into());
+            message.set_content(content.write_to_bytes().expect("Unable to serialize"));
+            self.socket
+                .send(message.write_to_bytes().expect("Unable to serialize"), 0)
+                .expect("Unable to send message");
+        }
+
+        fn recv(&self, message_type: Message_MessageType) -> Message {
+            let bytes = self
+                .socket
+                .recv_bytes(0)
+                .expect("Unable to receive message");
+            let message: Message = protobuf::parse_from_bytes(&bytes).expect("Unable to parse");
+            assert_eq!(message.get_message_type(), message_type);
+            message
+        }
+
+        fn request<T: ProtobufMessage>(
+            &self,
+            message_type: Message_MessageType,
+            response_type: Message_MessageType,
+            content: &dyn ProtobufMessage,
+        ) -> T {
+            self.send(message_type, "request", content);
+            let response = self.recv(response_type);
+            assert_eq!(response.get_correlation_id(), "request");
+            protobuf::parse_from_bytes(response.get_content()).expect("Unable to parse response")
+        }
+    }
+
+    /// Tests that a Sawtooth transaction processor can register, process a transaction reading
+    /// and writing state, and is restricted to the transaction's inputs and outputs.
+    #[test]
+    fn test_sawtooth_tp_adapter() {
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let mut context_manager = ContextManager::new(Box::new(state));
+
+        let mut adapter =
+            SawtoothTpExecutionAdapter::new("tcp://127.0.0.1:*", context_manager.clone())
+                .expect("Unable to create adapter");
+        let (registry_tx, registry_rx) = channel();
+        adapter
+            .start(Box::new(ChannelRegistry {
+                sender: registry_tx,
+            }))
+            .expect("Unable to start adapter");
+
+        let processor = TestProcessor::connect(adapter.endpoint());
+        let mut register = TpRegisterRequest::new();
+        register.set_family("test".into());
+        register.set_version("1.0".into());
+        register.set_namespaces(RepeatedField::from_vec(vec!["ab".into()]));
+        let response: TpRegisterResponse = processor.request(
+            Message_MessageType::TP_REGISTER_REQUEST,
+            Message_MessageType::TP_REGISTER_RESPONSE,
+            &register,
+        );
+        assert_eq!(response.get_status(), TpRegisterResponse_Status::OK);
+        assert_eq!(
+            registry_rx
+                .recv_timeout(Duration::from_secs(5))
+                .expect("Family not registered"),
+            (true, TransactionFamily::new("test".into(), "1.0".into()))
+        );
+
+        let transaction_pair = TransactionBuilder::new()
+            .with_family_name("test".into())
+            .with_family_version("1.0".into())
+            .with_inputs(vec![vec![0xab]])
+            .with_outputs(vec![vec![0xab]])
+            .with_payload(b"payload".to_vec())
+            .with_payload_hash_method(HashMethod::SHA512)
+            .build_pair(&HashSigner::default())
+            .expect("Unable to build transaction");
+        let transaction_id = transaction_pair
+            .transaction()
+            .header_signature()
+            .to_string();
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let (result_tx, result_rx) = channel();
+        adapter
+            .execute(
+                transaction_pair,
+                context_id,
+                Box::new(move |result| result_tx.send(result).expect("Unable to send result")),
+            )
+            .expect("Unable to execute transaction");
+
+        let process_message = processor.recv(Message_MessageType::TP_PROCESS_REQUEST);
+        let process_request: TpProcessRequest =
+            protobuf::parse_from_bytes(process_message.get_content()).expect("Unable to parse");
+        assert_eq!(process_request.get_header().get_family_name(), "test");
+        assert_eq!(process_request.get_payload(), b"payload");
+        let sawtooth_context_id = process_request.get_context_id().to_string();
+
+        let mut set = TpStateSetRequest::new();
+        set.set_context_id(sawtooth_context_id.clone());
+        let mut entry = TpStateEntry::new();
+        entry.set_address("ab01".into());
+        entry.set_data(b"value".to_vec());
+        set.set_entries(RepeatedField::from_vec(vec![entry]));
+        let response: TpStateSetResponse = processor.request(
+            Message_MessageType::TP_STATE_SET_REQUEST,
+            Message_MessageType::TP_STATE_SET_RESPONSE,
+            &set,
+        );
+        assert_eq!(response.get_status(), TpStateSetResponse_Status::OK);
+
+        let mut get = TpStateGetRequest::new();
+        get.set_context_id(sawtooth_context_id.clone());
+        get.set_addresses(RepeatedField::from_vec(vec!["ab01".into()]));
+        let response: TpStateGetResponse = processor.request(
+            Message_MessageType::TP_STATE_GET_REQUEST,
+            Message_MessageType::TP_STATE_GET_RESPONSE,
+            &get,
+        );
+        assert_eq!(response.get_status(), TpStateGetResponse_Status::OK);
+        assert_eq!(response.get_entries()[0].get_data(), b"value");
+
+        // Addresses outside of the transaction's outputs may not be written
+        let mut set = TpStateSetRequest::new();
+        set.set_context_id(sawtooth_context_id);
+        let mut entry = TpStateEntry::new();
+        entry.set_address("cd01".into());
+        entry.set_data(b"value".to_vec());
+        set.set_entries(RepeatedField::from_vec(vec![entry]));
+        let response: TpStateSetResponse = processor.request(
+            Message_MessageType::TP_STATE_SET_REQUEST,
+            Message_MessageType::TP_STATE_SET_RESPONSE,
+            &set,
+        );
+        assert_eq!(
+            response.get_status(),
+            TpStateSetResponse_Status::AUTHORIZATION_ERROR
+        );
+
+        let mut process_response = TpProcessResponse::new();
+        process_response.set_status(TpProcessResponse_Status::OK);
+        processor.send(
+            Message_MessageType::TP_PROCESS_RESPONSE,
+            process_message.get_correlation_id(),
+            &process_response,
+        );
+
+        assert_eq!(
+            result_rx
+                .recv_timeout(Duration::from_secs(5))
+                .expect("Did not receive result")
+                .expect("Execution failed"),
+            ExecutionTaskCompletionNotification::Valid(context_id, transaction_id)
+        );
+        assert_eq!(
+            context_manager
+                .get(&context_id, &["ab01".into()])
+                .expect("Unable to get state"),
+            vec![("ab01".to_string(), b"value".to_vec())]
+        );
+
+        Box::new(adapter).stop().expect("Unable to stop adapter");
+    }
+}
@@ -0,0 +1,358 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Atomic commits of ordered lists of batches.
+//!
+//! A `BatchCommitter` executes an ordered list of batches on top of a state root, and then writes
+//! the state changes of the valid batches, along with their receipts, in a single
+//! `DatabaseWriter` transaction. If anything fails before that transaction is committed, nothing
+//! is written. The committer knows nothing of blocks; a block is just one such list of batches.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::context::manager::sync::ContextManager;
+use crate::database::{Database, DatabaseError};
+#[cfg(feature = "event-bus")]
+use crate::events::EventBus;
+use crate::execution::executor::Executor;
+use crate::protocol::batch::BatchPair;
+use crate::receipt_store::{self, ReceiptStore, ReceiptStoreError};
+use crate::scheduler::simulation::{is_valid, SchedulerKind, SimulationError, Simulator};
+use crate::scheduler::BatchExecutionResult;
+use crate::state::merkle::{
+    MerkleRadixTree, MerkleState, StateDatabaseError, CHANGE_LOG_INDEX, DUPLICATE_LOG_INDEX,
+};
+
+/// The indexes a database must have to be used by a `BatchCommitter`: those of merkle state and
+/// of a `ReceiptStore`.
+pub const INDEXES: [&str; 5] = [
+    CHANGE_LOG_INDEX,
+    DUPLICATE_LOG_INDEX,
+    receipt_store::RECEIPT_INDEX,
+    receipt_store::BATCH_RECEIPT_INDEX,
+    receipt_store::STATE_ROOT_RECEIPT_INDEX,
+];
+
+/// The result of committing a list of batches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommitResult {
+    /// The state root after the valid batches' state changes were committed.
+    pub state_root: String,
+    pub receipts: ReceiptSummary,
+}
+
+/// A summary of the receipts committed with a list of batches.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReceiptSummary {
+    /// The IDs of the batches whose state changes and receipts were committed, in order.
+    pub committed_batches: Vec<String>,
+    /// The IDs of the transactions whose receipts were committed, in order.
+    pub committed_transactions: Vec<String>,
+    /// The results of the invalid batches, none of which were committed.
+    pub invalid_batches: Vec<BatchExecutionResult>,
+}
+
+#[derive(Debug)]
+pub enum CommitError {
+    /// The batches could not be executed.
+    SimulationError(SimulationError),
+    /// The state changes of the batches could not be computed or written.
+    StateDatabaseError(StateDatabaseError),
+    /// The receipts could not be written.
+    ReceiptStoreError(ReceiptStoreError),
+    /// The database transaction could not be written or committed.
+    DatabaseError(DatabaseError),
+}
+
+impl Error for CommitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CommitError::SimulationError(err) => Some(err),
+            CommitError::StateDatabaseError(err) => Some(err),
+            CommitError::ReceiptStoreError(err) => Some(err),
+            CommitError::DatabaseError(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for CommitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommitError::SimulationError(err) => write!(f, "unable to execute batches: {}", err),
+            CommitError::StateDatabaseError(err) => {
+                write!(f, "unable to update state: {}", err)
+            }
+            CommitError::ReceiptStoreError(err) => write!(f, "unable to save receipts: {}", err),
+            CommitError::DatabaseError(err) => write!(f, "unable to commit batches: {}", err),
+        }
+    }
+}
+
+impl From<SimulationError> for CommitError {
+    fn from(err: SimulationError) -> Self {
+        CommitError::SimulationError(err)
+    }
+}
+
+impl From<StateDatabaseError> for CommitError {
+    fn from(err: StateDatabaseError) -> Self {
+        CommitError::StateDatabaseError(err)
+    }
+}
+
+impl From<ReceiptStoreError> for CommitError {
+    fn from(err: ReceiptStoreError) -> Self {
+        CommitError::ReceiptStoreError(err)
+    }
+}
+
+impl From<DatabaseError> for CommitError {
+    fn from(err: DatabaseError) -> Self {
+        CommitError::DatabaseError(err)
+    }
+}
+
+/// Executes ordered lists of batches and commits their results atomically.
+///
+/// The executor must already be started, and its adapters must use the given context manager,
+/// which must read merkle state from the given database. The database must have the `INDEXES`.
+pub struct BatchCommitter<'a> {
+    executor: &'a Executor,
+    context_manager: ContextManager,
+    database: Box<dyn Database>,
+    scheduler_kind: SchedulerKind,
+    #[cfg(feature = "event-bus")]
+    event_bus: Option<EventBus>,
+}
+
+impl<'a> BatchCommitter<'a> {
+    pub fn new(
+        executor: &'a Executor,
+        context_manager: ContextManager,
+        database: Box<dyn Database>,
+    ) -> Self {
+        BatchCommitter {
+            executor,
+            context_manager,
+            database,
+            scheduler_kind: SchedulerKind::default(),
+            #[cfg(feature = "event-bus")]
+            event_bus: None,
+        }
+    }
+
+    /// Executes batches with the given kind of scheduler, instead of a single `SerialScheduler`.
+    pub fn with_scheduler_kind(mut self, scheduler_kind: SchedulerKind) -> Self {
+        self.scheduler_kind = scheduler_kind;
+        self
+    }
+
+    /// Publishes the events of the valid batches to the bus after each commit; the bus retains
+    /// only as many commits as it was created to, `events::DEFAULT_RETENTION` by default.
+    #[cfg(feature = "event-bus")]
+    pub fn with_event_bus(mut self, event_bus: EventBus) -> Self {
+        self.event_bus = Some(event_bus);
+        self
+    }
+
+    /// Executes the batches on top of `state_root`, in order, and commits the state changes and
+    /// receipts of the valid batches together; invalid batches are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CommitError` if the batches cannot be executed, or if their results cannot be
+    /// written; in either case, nothing is committed.
+    pub fn commit_batches(
+        &self,
+        state_root: &str,
+        batches: Vec<BatchPair>,
+    ) -> Result<CommitResult, CommitError> {
+        let result = Simulator::new(
+            self.executor,
+            self.context_manager.clone(),
+            MerkleState::new(self.database.clone()),
+        )
+        .with_scheduler_kind(self.scheduler_kind)
+        .simulate_batches(state_root, batches)?;
+
+        // State is read before the writer is taken, since a database may not allow reads while a
+        // writer is open.
+        let update = MerkleRadixTree::new(self.database.clone(), Some(state_root))?
+            .prepare_update(&result.state_changes())?;
+
+        let mut summary = ReceiptSummary::default();
+        let mut committed = vec![];
+        for batch_result in result.batch_results {
+            if !batch_result.receipts.iter().all(is_valid) {
+                summary.invalid_batches.push(batch_result);
+                continue;
+            }
+
+            summary
+                .committed_batches
+                .push(batch_result.batch.batch().header_signature().into());
+            summary.committed_transactions.extend(
+                batch_result
+                    .receipts
+                    .iter()
+                    .map(|receipt| receipt.transaction_id.clone()),
+            );
+            committed.push(batch_result);
+        }
+
+        let mut db_writer = self.database.get_writer()?;
+        update.write(&mut *db_writer)?;
+        ReceiptStore::write_receipts(&mut *db_writer, update.state_root(), &committed)?;
+        db_writer.commit()?;
+
+        #[cfg(feature = "event-bus")]
+        {
+            if let Some(event_bus) = &self.event_bus {
+                let receipts = committed
+                    .iter()
+                    .flat_map(|batch_result| batch_result.receipts.iter().cloned())
+                    .collect::<Vec<_>>();
+                // The batches are already committed, so a failure to publish is only reported
+                if let Err(err) = event_bus.publish(update.state_root(), &receipts) {
+                    error!("Unable to publish events of committed batches: {}", err);
+                }
+            }
+        }
+
+        Ok(CommitResult {
+            state_root: update.state_root().into(),
+            receipts: summary,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::pipeline::{PipelineBuilder, StorageBackend};
+    use crate::state::merkle;
+    use crate::state::Read;
+    use crate::workload::command::{make_set_state_batch, CommandTransactionHandler};
+
+    /// Commits the batches on top of the empty state, with the committer of a pipeline storing
+    /// state in the given database.
+    fn commit(
+        database: Box<dyn Database>,
+        batches: Vec<BatchPair>,
+    ) -> Result<CommitResult, CommitError> {
+        let pipeline = PipelineBuilder::new()
+            .with_backend(StorageBackend::Database(database))
+            .with_handler(Box::new(CommandTransactionHandler::new()))
+            .build()
+            .expect("Unable to build pipeline");
+
+        let result = pipeline
+            .batch_committer()
+            .commit_batches(pipeline.empty_state_root(), batches);
+
+        pipeline.shutdown();
+        result
+    }
+
+    /// Commit a valid and an invalid batch, and verify that only the valid batch's state changes
+    /// and receipt are committed, and that the invalid batch is reported.
+    #[test]
+    fn commit_batches() {
+        let valid_address = "ab".repeat(35);
+        let invalid_address = "cd".repeat(35);
+        let valid = make_set_state_batch(&valid_address, b"valid", false);
+        let invalid = make_set_state_batch(&invalid_address, b"invalid", true);
+        let valid_txn_id = valid.batch().transactions()[0]
+            .header_signature()
+            .to_string();
+        let invalid_txn_id = invalid.batch().transactions()[0]
+            .header_signature()
+            .to_string();
+
+        let database: Box<dyn Database> = Box::new(BTreeDatabase::new(&INDEXES));
+        let result = commit(database.clone(), vec![valid.clone(), invalid.clone()])
+            .expect("Unable to commit batches");
+
+        assert_eq!(
+            result.receipts.committed_batches,
+            vec![valid.batch().header_signature().to_string()]
+        );
+        assert_eq!(
+            result.receipts.committed_transactions,
+            vec![valid_txn_id.clone()]
+        );
+        assert_eq!(result.receipts.invalid_batches.len(), 1);
+        assert_eq!(result.receipts.invalid_batches[0].batch, invalid);
+
+        let entries = MerkleState::new(database.clone())
+            .get(
+                &result.state_root,
+                &[valid_address.clone(), invalid_address.clone()],
+            )
+            .expect("Unable to read committed state");
+        assert_eq!(Some(&b"valid".to_vec()), entries.get(&valid_address));
+        assert!(!entries.contains_key(&invalid_address));
+
+        let receipt_store = ReceiptStore::new(database);
+        assert!(receipt_store
+            .get_receipt(&valid_txn_id)
+            .expect("Unable to get receipt")
+            .is_some());
+        assert!(receipt_store
+            .get_receipt(&invalid_txn_id)
+            .expect("Unable to get receipt")
+            .is_none());
+        assert_eq!(
+            receipt_store
+                .list_state_root_receipts(&result.state_root, 0, 10)
+                .expect("Unable to list receipts")
+                .total,
+            1
+        );
+    }
+
+    /// Commit a batch to a database without the receipt indexes, and verify that the failure to
+    /// write its receipt leaves its state changes uncommitted.
+    #[test]
+    fn commit_batches_rollback() {
+        let database: Box<dyn Database> = Box::new(BTreeDatabase::new(&merkle::INDEXES));
+        MerkleRadixTree::new(database.clone(), None).expect("Unable to create empty state");
+        let count = database
+            .get_reader()
+            .and_then(|reader| reader.count())
+            .expect("Unable to count entries");
+
+        match commit(
+            database.clone(),
+            vec![make_set_state_batch(&"ab".repeat(35), b"valid", false)],
+        ) {
+            Err(CommitError::ReceiptStoreError(_)) => (),
+            res => panic!("Expected ReceiptStoreError, got {:?}", res),
+        }
+
+        assert_eq!(
+            count,
+            database
+                .get_reader()
+                .and_then(|reader| reader.count())
+                .expect("Unable to count entries")
+        );
+    }
+}
@@ -28,11 +28,19 @@
 //! `ExecutionTaskCompletionNotification`s back to the `Scheduler` via the
 //! `SchedulerExecutionInterface`.
 
+#[cfg(feature = "batch-commit")]
+pub mod commit;
 pub mod multi;
 pub mod parallel;
+#[cfg(feature = "replay")]
+pub mod replay;
 pub mod serial;
+#[cfg(feature = "simulation")]
+pub mod simulation;
+pub mod validation;
 
 use std::error::Error;
+use std::time::Duration;
 
 use crate::context::ContextId;
 use crate::protocol::batch::BatchPair;
@@ -102,6 +110,46 @@ impl Into<TransactionReceipt> for InvalidTransactionResult {
     }
 }
 
+/// Determines what a scheduler does with a transaction whose execution deadline has expired.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeadlinePolicy {
+    /// Treat the transaction as invalid, which invalidates its containing batch.
+    InvalidateBatch,
+    /// Return the transaction to the task iterator to be executed again, at most the given number
+    /// of times; once the retries are exhausted, the containing batch is invalidated.
+    Retry(usize),
+}
+
+/// The amount of time a scheduler will wait for a single transaction to be executed, along with
+/// the policy that is applied when that time has elapsed.
+///
+/// Deadlines are enforced by the `SerialScheduler`, which is given one with
+/// `SerialSchedulerBuilder::with_execution_deadline`. A `MultiScheduler` has no deadline of its
+/// own; each of its sub-schedulers enforces its own, and the `ExecutionDeadlineExceeded` errors
+/// they report are passed to the `MultiScheduler`'s error callback unchanged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExecutionDeadline {
+    timeout: Duration,
+    policy: DeadlinePolicy,
+}
+
+impl ExecutionDeadline {
+    /// Create a new `ExecutionDeadline`.
+    pub fn new(timeout: Duration, policy: DeadlinePolicy) -> Self {
+        ExecutionDeadline { timeout, policy }
+    }
+
+    /// The maximum amount of time a transaction may take to execute.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// What to do with a transaction which did not execute within the timeout.
+    pub fn policy(&self) -> DeadlinePolicy {
+        self.policy
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ExecutionTaskCompletionNotification {
     /// The transation was invalid.
@@ -116,6 +164,9 @@ pub enum SchedulerError {
     /// The scheduler's `add_batch` method was called with a batch that the scheduler already has
     /// pending or in progress; the contained `String` is the batch ID.
     DuplicateBatch(String),
+    /// A transaction was not executed before its execution deadline expired; the contained
+    /// `String` is the transaction ID.
+    ExecutionDeadlineExceeded(String),
     /// An internal error occurred that the scheduler could not recover from.
     Internal(String),
     /// A scheduler only has one task iterator, so its `take_task_iterator` method can only be
@@ -123,6 +174,8 @@ pub enum SchedulerError {
     NoTaskIterator,
     /// The scheduler's `add_batch` method was called, but the scheduler was already finalized
     SchedulerFinalized,
+    /// The sub-schedulers of a `MultiScheduler` returned different results for a batch.
+    SubSchedulerResultsDiverged(Box<multi::DivergenceReport>),
     /// An `ExecutionTaskCompletionNotification` was received for a transaction that the scheduler
     /// was not expecting; the contained `String` is the transaction ID.
     UnexpectedNotification(String),
@@ -136,11 +189,19 @@ impl std::fmt::Display for SchedulerError {
             SchedulerError::DuplicateBatch(ref batch_id) => {
                 write!(f, "duplicate batch added to scheduler: {}", batch_id)
             }
+            SchedulerError::ExecutionDeadlineExceeded(ref txn_id) => write!(
+                f,
+                "transaction was not executed before its deadline: {}",
+                txn_id
+            ),
             SchedulerError::Internal(ref err) => {
                 write!(f, "scheduler encountered an internal error: {}", err)
             }
             SchedulerError::NoTaskIterator => write!(f, "task iterator already taken"),
             SchedulerError::SchedulerFinalized => write!(f, "batch added to finalized scheduler"),
+            SchedulerError::SubSchedulerResultsDiverged(ref report) => {
+                write!(f, "sub-scheduler results diverged: {}", report)
+            }
             SchedulerError::UnexpectedNotification(ref txn_id) => write!(
                 f,
                 "scheduler received an unexpected notification: {}",
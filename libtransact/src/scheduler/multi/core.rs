@@ -19,6 +19,8 @@
 
 use crate::scheduler::{BatchExecutionResult, SchedulerError};
 
+use super::divergence::{DivergencePolicy, DivergenceReport, SubSchedulerResult};
+
 use std::collections::{HashMap, HashSet};
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
@@ -43,17 +45,21 @@ pub struct MultiSchedulerCore {
     /// Tracks which sub-schedulers have returned a None result, indicating they have executed all
     /// of their batches.
     done_schedulers: HashSet<usize>,
+    /// Determines which result is returned when the sub-schedulers' results do not match.
+    divergence_policy: DivergencePolicy,
 }
 
 impl MultiSchedulerCore {
     pub fn new(
         shared_lock: Arc<Mutex<MultiSchedulerShared>>,
         rx: Receiver<MultiSchedulerCoreMessage>,
+        divergence_policy: DivergencePolicy,
     ) -> Self {
         MultiSchedulerCore {
             shared_lock,
             rx,
             done_schedulers: HashSet::new(),
+            divergence_policy,
         }
     }
 
@@ -120,7 +126,9 @@ impl MultiSchedulerCore {
 
                     // If all schedulers have now reported a result for the batch, remove the
                     // pending result and call the appropriate callback (result callback if all
-                    // results match, error callback if there's a mismatch)
+                    // results match, error callback with a divergence report if there's a
+                    // mismatch, followed by the result callback if the divergence policy selects
+                    // a result)
                     if batch_done {
                         let mut results = pending_results
                             .remove(&batch_result.batch)
@@ -153,19 +161,41 @@ impl MultiSchedulerCore {
                             let (result, _) = results.drain().next().unwrap();
                             shared.result_callback()(Some(result));
                         } else {
-                            shared.error_callback()(SchedulerError::Internal(format!(
-                                "mismatched results for batch {}: {:?}",
-                                batch_result.batch.batch().header_signature(),
-                                results,
-                            )));
+                            let report = DivergenceReport::new(
+                                batch_result.batch.batch().header_signature().into(),
+                                results
+                                    .drain()
+                                    .map(|(result, schedulers)| SubSchedulerResult {
+                                        schedulers: schedulers.into_iter().collect(),
+                                        result,
+                                    })
+                                    .collect(),
+                            );
+                            let selected_result =
+                                report.select_result(self.divergence_policy, num_schedulers);
+                            shared.error_callback()(SchedulerError::SubSchedulerResultsDiverged(
+                                Box::new(report),
+                            ));
+                            if let Some(result) = selected_result {
+                                shared.result_callback()(Some(result));
+                            }
                         }
                     }
                 }
                 Ok(MultiSchedulerCoreMessage::SubSchedulerError(scheduler_index, err)) => {
-                    self.shared_lock.lock()?.error_callback()(SchedulerError::Internal(format!(
-                        "scheduler {} encountered error: {}",
-                        scheduler_index, err,
-                    )));
+                    let err = match err {
+                        // Sub-schedulers enforce the execution deadlines, so their expiry is
+                        // reported as is rather than as an internal error
+                        SchedulerError::ExecutionDeadlineExceeded(_) => {
+                            debug!("scheduler {} encountered error: {}", scheduler_index, err);
+                            err
+                        }
+                        err => SchedulerError::Internal(format!(
+                            "scheduler {} encountered error: {}",
+                            scheduler_index, err,
+                        )),
+                    };
+                    self.shared_lock.lock()?.error_callback()(err);
                 }
                 Ok(MultiSchedulerCoreMessage::Shutdown) => {
                     break;
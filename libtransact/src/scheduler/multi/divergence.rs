@@ -0,0 +1,624 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Reporting of divergent results between the MultiScheduler's sub-schedulers.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::protocol::receipt::{Event, StateChange, TransactionReceipt, TransactionResult};
+use crate::scheduler::BatchExecutionResult;
+
+/// Determines which result, if any, the `MultiScheduler` returns when its sub-schedulers do not
+/// agree on the result of a batch. A `DivergenceReport` is sent to the error callback regardless
+/// of the policy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DivergencePolicy {
+    /// Do not return a result for the batch.
+    FailHard,
+    /// Return the result of the primary sub-scheduler (the first sub-scheduler, whose task
+    /// iterator and notifier are provided by the `MultiScheduler` itself).
+    Primary,
+    /// Return the result that a strict majority of the sub-schedulers agree on; if there is no
+    /// such result, do not return a result for the batch.
+    Majority,
+}
+
+impl Default for DivergencePolicy {
+    fn default() -> Self {
+        DivergencePolicy::FailHard
+    }
+}
+
+/// A single difference between two sets of transaction receipts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReceiptDifference {
+    /// The expected receipt for the transaction is not in the actual receipts.
+    MissingReceipt { transaction_id: String },
+    /// The actual receipts include a receipt for a transaction that was not expected.
+    UnexpectedReceipt { transaction_id: String },
+    /// Both lists include a receipt for the transaction, but at different positions among the
+    /// receipts that both lists include.
+    Order {
+        transaction_id: String,
+        expected_position: usize,
+        actual_position: usize,
+    },
+    /// One receipt is valid and the other is invalid.
+    Validity {
+        transaction_id: String,
+        expected_valid: bool,
+        actual_valid: bool,
+    },
+    /// Both receipts are valid, but have different state changes.
+    StateChanges {
+        transaction_id: String,
+        expected: Vec<StateChange>,
+        actual: Vec<StateChange>,
+    },
+    /// Both receipts are valid, but have different events.
+    Events {
+        transaction_id: String,
+        expected: Vec<Event>,
+        actual: Vec<Event>,
+    },
+    /// Both receipts are valid, but have different receipt data.
+    Data {
+        transaction_id: String,
+        expected: Vec<Vec<u8>>,
+        actual: Vec<Vec<u8>>,
+    },
+    /// Both receipts are invalid, but have different error messages.
+    ErrorMessage {
+        transaction_id: String,
+        expected: String,
+        actual: String,
+    },
+    /// Both receipts are invalid, but have different error data.
+    ErrorData {
+        transaction_id: String,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+}
+
+impl ReceiptDifference {
+    /// The ID of the transaction whose receipts differ.
+    pub fn transaction_id(&self) -> &str {
+        match self {
+            ReceiptDifference::MissingReceipt { transaction_id }
+            | ReceiptDifference::UnexpectedReceipt { transaction_id }
+            | ReceiptDifference::Order { transaction_id, .. }
+            | ReceiptDifference::Validity { transaction_id, .. }
+            | ReceiptDifference::StateChanges { transaction_id, .. }
+            | ReceiptDifference::Events { transaction_id, .. }
+            | ReceiptDifference::Data { transaction_id, .. }
+            | ReceiptDifference::ErrorMessage { transaction_id, .. }
+            | ReceiptDifference::ErrorData { transaction_id, .. } => transaction_id,
+        }
+    }
+}
+
+impl fmt::Display for ReceiptDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReceiptDifference::MissingReceipt { transaction_id } => {
+                write!(f, "missing receipt for transaction {}", transaction_id)
+            }
+            ReceiptDifference::UnexpectedReceipt { transaction_id } => {
+                write!(f, "unexpected receipt for transaction {}", transaction_id)
+            }
+            ReceiptDifference::Order {
+                transaction_id,
+                expected_position,
+                actual_position,
+            } => write!(
+                f,
+                "receipt for transaction {} is out of order: expected at position {}, got {}",
+                transaction_id, expected_position, actual_position
+            ),
+            ReceiptDifference::Validity {
+                transaction_id,
+                expected_valid,
+                actual_valid,
+            } => write!(
+                f,
+                "transaction {} validity differs: expected {}, got {}",
+                transaction_id,
+                validity_str(*expected_valid),
+                validity_str(*actual_valid)
+            ),
+            ReceiptDifference::StateChanges {
+                transaction_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "transaction {} state changes differ: expected {:?}, got {:?}",
+                transaction_id, expected, actual
+            ),
+            ReceiptDifference::Events {
+                transaction_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "transaction {} events differ: expected {:?}, got {:?}",
+                transaction_id, expected, actual
+            ),
+            ReceiptDifference::Data {
+                transaction_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "transaction {} receipt data differs: expected {:?}, got {:?}",
+                transaction_id, expected, actual
+            ),
+            ReceiptDifference::ErrorMessage {
+                transaction_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "transaction {} error message differs: expected {:?}, got {:?}",
+                transaction_id, expected, actual
+            ),
+            ReceiptDifference::ErrorData {
+                transaction_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "transaction {} error data differs: expected {:?}, got {:?}",
+                transaction_id, expected, actual
+            ),
+        }
+    }
+}
+
+fn validity_str(valid: bool) -> &'static str {
+    if valid {
+        "valid"
+    } else {
+        "invalid"
+    }
+}
+
+/// Compares two lists of transaction receipts field-by-field, returning all differences between
+/// them.
+///
+/// Receipts are matched by transaction ID. The receipts that both lists include must also be in
+/// the same order, since the order of a batch's receipts is the order its transactions were
+/// applied in; each receipt out of place is reported with an `Order` difference.
+pub fn compare_receipts(
+    expected: &[TransactionReceipt],
+    actual: &[TransactionReceipt],
+) -> Vec<ReceiptDifference> {
+    let mut differences = vec![];
+
+    for expected_receipt in expected {
+        let transaction_id = expected_receipt.transaction_id.clone();
+        let actual_receipt = match actual
+            .iter()
+            .find(|receipt| receipt.transaction_id == transaction_id)
+        {
+            Some(receipt) => receipt,
+            None => {
+                differences.push(ReceiptDifference::MissingReceipt { transaction_id });
+                continue;
+            }
+        };
+
+        match (
+            &expected_receipt.transaction_result,
+            &actual_receipt.transaction_result,
+        ) {
+            (
+                TransactionResult::Valid {
+                    state_changes: expected_state_changes,
+                    events: expected_events,
+                    data: expected_data,
+                },
+                TransactionResult::Valid {
+                    state_changes: actual_state_changes,
+                    events: actual_events,
+                    data: actual_data,
+                },
+            ) => {
+                if expected_state_changes != actual_state_changes {
+                    differences.push(ReceiptDifference::StateChanges {
+                        transaction_id: transaction_id.clone(),
+                        expected: expected_state_changes.clone(),
+                        actual: actual_state_changes.clone(),
+                    });
+                }
+                if expected_events != actual_events {
+                    differences.push(ReceiptDifference::Events {
+                        transaction_id: transaction_id.clone(),
+                        expected: expected_events.clone(),
+                        actual: actual_events.clone(),
+                    });
+                }
+                if expected_data != actual_data {
+                    differences.push(ReceiptDifference::Data {
+                        transaction_id,
+                        expected: expected_data.clone(),
+                        actual: actual_data.clone(),
+                    });
+                }
+            }
+            (
+                TransactionResult::Invalid {
+                    error_message: expected_message,
+                    error_data: expected_data,
+                },
+                TransactionResult::Invalid {
+                    error_message: actual_message,
+                    error_data: actual_data,
+                },
+            ) => {
+                if expected_message != actual_message {
+                    differences.push(ReceiptDifference::ErrorMessage {
+                        transaction_id: transaction_id.clone(),
+                        expected: expected_message.clone(),
+                        actual: actual_message.clone(),
+                    });
+                }
+                if expected_data != actual_data {
+                    differences.push(ReceiptDifference::ErrorData {
+                        transaction_id,
+                        expected: expected_data.clone(),
+                        actual: actual_data.clone(),
+                    });
+                }
+            }
+            (expected_result, actual_result) => {
+                differences.push(ReceiptDifference::Validity {
+                    transaction_id,
+                    expected_valid: is_valid(expected_result),
+                    actual_valid: is_valid(actual_result),
+                });
+            }
+        }
+    }
+
+    let expected_ids = expected
+        .iter()
+        .map(|receipt| receipt.transaction_id.as_str())
+        .collect::<HashSet<_>>();
+    let actual_ids = actual
+        .iter()
+        .map(|receipt| receipt.transaction_id.as_str())
+        .collect::<HashSet<_>>();
+    let expected_order = expected
+        .iter()
+        .map(|receipt| receipt.transaction_id.as_str())
+        .filter(|transaction_id| actual_ids.contains(transaction_id))
+        .collect::<Vec<_>>();
+    let actual_order = actual
+        .iter()
+        .map(|receipt| receipt.transaction_id.as_str())
+        .filter(|transaction_id| expected_ids.contains(transaction_id))
+        .collect::<Vec<_>>();
+    differences.extend(
+        expected_order
+            .iter()
+            .zip(actual_order.iter())
+            .enumerate()
+            .filter(|(_, (expected_id, actual_id))| expected_id != actual_id)
+            .filter_map(|(expected_position, (transaction_id, _))| {
+                actual_order
+                    .iter()
+                    .position(|actual_id| actual_id == transaction_id)
+                    .map(|actual_position| ReceiptDifference::Order {
+                        transaction_id: transaction_id.to_string(),
+                        expected_position,
+                        actual_position,
+                    })
+            }),
+    );
+
+    differences.extend(
+        actual
+            .iter()
+            .filter(|receipt| !expected_ids.contains(receipt.transaction_id.as_str()))
+            .map(|receipt| ReceiptDifference::UnexpectedReceipt {
+                transaction_id: receipt.transaction_id.clone(),
+            }),
+    );
+
+    differences
+}
+
+fn is_valid(result: &TransactionResult) -> bool {
+    match result {
+        TransactionResult::Valid { .. } => true,
+        TransactionResult::Invalid { .. } => false,
+    }
+}
+
+/// A result that was returned for a batch by one or more sub-schedulers.
+#[derive(Clone, Debug)]
+pub struct SubSchedulerResult {
+    /// The indexes of the sub-schedulers which returned this result, in ascending order.
+    pub schedulers: Vec<usize>,
+    /// The result returned by the sub-schedulers.
+    pub result: BatchExecutionResult,
+}
+
+/// The differences between one sub-scheduler result and the reference result.
+#[derive(Clone, Debug)]
+pub struct ResultComparison {
+    /// The sub-schedulers which returned the reference result.
+    pub expected_schedulers: Vec<usize>,
+    /// The sub-schedulers which returned a result that differs from the reference result.
+    pub actual_schedulers: Vec<usize>,
+    /// The field-by-field differences between the receipts of the two results.
+    pub differences: Vec<ReceiptDifference>,
+}
+
+/// A detailed report of the results returned by the `MultiScheduler`'s sub-schedulers for a batch
+/// when the results do not all match.
+///
+/// The reference result is the one returned by the lowest-indexed sub-scheduler (normally the
+/// primary sub-scheduler); every other result is compared against it.
+#[derive(Clone, Debug)]
+pub struct DivergenceReport {
+    batch_id: String,
+    results: Vec<SubSchedulerResult>,
+    comparisons: Vec<ResultComparison>,
+}
+
+impl DivergenceReport {
+    /// Builds a report from the distinct results returned for a batch.
+    pub fn new(batch_id: String, results: Vec<SubSchedulerResult>) -> Self {
+        let mut results = results;
+        for result in results.iter_mut() {
+            result.schedulers.sort_unstable();
+        }
+        results.sort_by_key(|result| result.schedulers.first().cloned());
+
+        let comparisons = match results.split_first() {
+            Some((reference, others)) => others
+                .iter()
+                .map(|other| ResultComparison {
+                    expected_schedulers: reference.schedulers.clone(),
+                    actual_schedulers: other.schedulers.clone(),
+                    differences: compare_receipts(
+                        &reference.result.receipts,
+                        &other.result.receipts,
+                    ),
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        DivergenceReport {
+            batch_id,
+            results,
+            comparisons,
+        }
+    }
+
+    /// The ID of the batch whose results diverged.
+    pub fn batch_id(&self) -> &str {
+        &self.batch_id
+    }
+
+    /// The distinct results for the batch, along with the sub-schedulers which returned them.
+    pub fn results(&self) -> &[SubSchedulerResult] {
+        &self.results
+    }
+
+    /// The comparisons of each divergent result against the reference result.
+    pub fn comparisons(&self) -> &[ResultComparison] {
+        &self.comparisons
+    }
+
+    /// Selects the result to return for the batch according to the given policy, if any.
+    pub fn select_result(
+        &self,
+        policy: DivergencePolicy,
+        num_schedulers: usize,
+    ) -> Option<BatchExecutionResult> {
+        match policy {
+            DivergencePolicy::FailHard => None,
+            DivergencePolicy::Primary => self
+                .results
+                .iter()
+                .find(|result| result.schedulers.contains(&0))
+                .map(|result| result.result.clone()),
+            DivergencePolicy::Majority => self
+                .results
+                .iter()
+                .find(|result| result.schedulers.len() * 2 > num_schedulers)
+                .map(|result| result.result.clone()),
+        }
+    }
+}
+
+impl fmt::Display for DivergenceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "sub-schedulers returned {} different results for batch {}",
+            self.results.len(),
+            self.batch_id
+        )?;
+        for comparison in &self.comparisons {
+            write!(
+                f,
+                "; schedulers {:?} differ from {:?}: [",
+                comparison.actual_schedulers, comparison.expected_schedulers
+            )?;
+            for (i, difference) in comparison.differences.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}", difference)?;
+            }
+            f.write_str("]")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TXN_ID: &str = "txn";
+
+    fn valid_receipt(state_changes: Vec<StateChange>, data: Vec<Vec<u8>>) -> TransactionReceipt {
+        TransactionReceipt {
+            transaction_id: TXN_ID.into(),
+            transaction_result: TransactionResult::Valid {
+                state_changes,
+                events: vec![],
+                data,
+            },
+        }
+    }
+
+    fn invalid_receipt(error_message: &str) -> TransactionReceipt {
+        TransactionReceipt {
+            transaction_id: TXN_ID.into(),
+            transaction_result: TransactionResult::Invalid {
+                error_message: error_message.into(),
+                error_data: vec![],
+            },
+        }
+    }
+
+    /// Verify that identical receipts have no differences.
+    #[test]
+    fn compare_identical_receipts() {
+        let receipts = vec![valid_receipt(vec![], vec![b"data".to_vec()])];
+        assert!(compare_receipts(&receipts, &receipts).is_empty());
+    }
+
+    /// Verify that each differing field of a pair of valid receipts is reported, and that fields
+    /// which match are not.
+    #[test]
+    fn compare_valid_receipts() {
+        let set = StateChange::Set {
+            key: "abc".into(),
+            value: b"abc".to_vec(),
+        };
+        let delete = StateChange::Delete { key: "abc".into() };
+
+        let differences = compare_receipts(
+            &[valid_receipt(vec![set.clone()], vec![b"data".to_vec()])],
+            &[valid_receipt(vec![delete.clone()], vec![b"other".to_vec()])],
+        );
+
+        assert_eq!(
+            differences,
+            vec![
+                ReceiptDifference::StateChanges {
+                    transaction_id: TXN_ID.into(),
+                    expected: vec![set],
+                    actual: vec![delete],
+                },
+                ReceiptDifference::Data {
+                    transaction_id: TXN_ID.into(),
+                    expected: vec![b"data".to_vec()],
+                    actual: vec![b"other".to_vec()],
+                },
+            ]
+        );
+    }
+
+    /// Verify that receipts which match but are in a different order are reported, with their
+    /// positions among the receipts both lists include.
+    #[test]
+    fn compare_reordered_receipts() {
+        let receipt = |transaction_id: &str| {
+            let mut receipt = valid_receipt(vec![], vec![]);
+            receipt.transaction_id = transaction_id.into();
+            receipt
+        };
+
+        assert_eq!(
+            compare_receipts(
+                &[receipt("a"), receipt("b"), receipt("c")],
+                &[receipt("a"), receipt("c"), receipt("b")],
+            ),
+            vec![
+                ReceiptDifference::Order {
+                    transaction_id: "b".into(),
+                    expected_position: 1,
+                    actual_position: 2,
+                },
+                ReceiptDifference::Order {
+                    transaction_id: "c".into(),
+                    expected_position: 2,
+                    actual_position: 1,
+                },
+            ]
+        );
+
+        // A missing receipt does not move the receipts after it out of order
+        assert_eq!(
+            compare_receipts(
+                &[receipt("a"), receipt("b"), receipt("c")],
+                &[receipt("a"), receipt("c")],
+            ),
+            vec![ReceiptDifference::MissingReceipt {
+                transaction_id: "b".into(),
+            }]
+        );
+    }
+
+    /// Verify that validity, error message, and missing/unexpected receipt differences are
+    /// reported.
+    #[test]
+    fn compare_validity_and_missing_receipts() {
+        assert_eq!(
+            compare_receipts(&[valid_receipt(vec![], vec![])], &[invalid_receipt("")]),
+            vec![ReceiptDifference::Validity {
+                transaction_id: TXN_ID.into(),
+                expected_valid: true,
+                actual_valid: false,
+            }]
+        );
+
+        assert_eq!(
+            compare_receipts(&[invalid_receipt("a")], &[invalid_receipt("b")]),
+            vec![ReceiptDifference::ErrorMessage {
+                transaction_id: TXN_ID.into(),
+                expected: "a".into(),
+                actual: "b".into(),
+            }]
+        );
+
+        let mut other = invalid_receipt("");
+        other.transaction_id = "other".into();
+        assert_eq!(
+            compare_receipts(&[invalid_receipt("")], &[other]),
+            vec![
+                ReceiptDifference::MissingReceipt {
+                    transaction_id: TXN_ID.into(),
+                },
+                ReceiptDifference::UnexpectedReceipt {
+                    transaction_id: "other".into(),
+                },
+            ]
+        );
+    }
+}
@@ -20,8 +20,14 @@
 //! produce the same results for a given workload.
 
 mod core;
+mod divergence;
 mod shared;
 
+pub use self::divergence::{
+    compare_receipts, DivergencePolicy, DivergenceReport, ReceiptDifference, ResultComparison,
+    SubSchedulerResult,
+};
+
 use crate::protocol::batch::BatchPair;
 use crate::scheduler::{
     BatchExecutionResult, ExecutionTask, ExecutionTaskCompletionNotifier, Scheduler, SchedulerError,
@@ -72,9 +78,27 @@ pub struct MultiScheduler {
 
 impl MultiScheduler {
     /// Returns a newly created `MultiScheduler` that runs the specified sub-schedulers.
+    ///
+    /// If the sub-schedulers do not agree on the result of a batch, a `DivergenceReport` is sent
+    /// to the error callback and no result is returned for the batch.
     pub fn new(
+        schedulers: Vec<Box<dyn Scheduler + Send>>,
+        sub_scheduler_handler: &mut dyn SubSchedulerHandler,
+    ) -> Result<MultiScheduler, SchedulerError> {
+        Self::new_with_divergence_policy(
+            schedulers,
+            sub_scheduler_handler,
+            DivergencePolicy::FailHard,
+        )
+    }
+
+    /// Returns a newly created `MultiScheduler` that runs the specified sub-schedulers, using the
+    /// given policy to decide which result to return when the sub-schedulers do not agree on the
+    /// result of a batch.
+    pub fn new_with_divergence_policy(
         mut schedulers: Vec<Box<dyn Scheduler + Send>>,
         sub_scheduler_handler: &mut dyn SubSchedulerHandler,
+        divergence_policy: DivergencePolicy,
     ) -> Result<MultiScheduler, SchedulerError> {
         let (core_tx, core_rx) = mpsc::channel();
 
@@ -145,7 +169,9 @@ impl MultiScheduler {
 
         let shared_lock = Arc::new(Mutex::new(shared::MultiSchedulerShared::new(schedulers)));
 
-        let core_handle = core::MultiSchedulerCore::new(shared_lock.clone(), core_rx).start()?;
+        let core_handle =
+            core::MultiSchedulerCore::new(shared_lock.clone(), core_rx, divergence_policy)
+                .start()?;
 
         Ok(MultiScheduler {
             shared_lock,
@@ -241,6 +267,7 @@ mod tests {
         received_batches: Arc<Mutex<Vec<BatchPair>>>,
         finalized: Arc<AtomicBool>,
         callback: Arc<Mutex<Box<dyn Fn(Option<BatchExecutionResult>) + Send>>>,
+        error_callback: Arc<Mutex<Box<dyn Fn(SchedulerError) + Send>>>,
         results: Vec<Option<BatchExecutionResult>>,
     }
 
@@ -252,6 +279,9 @@ mod tests {
                 callback: Arc::new(Mutex::new(Box::new(|_| {
                     panic!("callback not set for subscheduler")
                 }))),
+                error_callback: Arc::new(Mutex::new(Box::new(|_| {
+                    panic!("error callback not set for subscheduler")
+                }))),
                 results,
             }
         }
@@ -265,6 +295,12 @@ mod tests {
         fn finalized(&self) -> bool {
             self.finalized.load(Ordering::Relaxed)
         }
+
+        fn send_error(&self, error: SchedulerError) {
+            self.error_callback
+                .lock()
+                .expect("error callback lock poisoned")(error)
+        }
     }
 
     impl Scheduler for MockSubScheduler {
@@ -278,8 +314,12 @@ mod tests {
 
         fn set_error_callback(
             &mut self,
-            _callback: Box<dyn Fn(SchedulerError) + Send>,
+            callback: Box<dyn Fn(SchedulerError) + Send>,
         ) -> Result<(), SchedulerError> {
+            *self
+                .error_callback
+                .lock()
+                .expect("error callback lock poisoned") = callback;
             Ok(())
         }
 
@@ -609,11 +649,132 @@ mod tests {
 
         sub_scheduler_handler.next();
         match error_rx.recv().expect("Failed to receive error") {
-            SchedulerError::Internal(err_str) => {
-                assert!(err_str.contains(batches[2].batch().header_signature()))
+            SchedulerError::SubSchedulerResultsDiverged(report) => {
+                assert_eq!(report.batch_id(), batches[2].batch().header_signature());
+                assert_eq!(report.results().len(), 2);
+                assert_eq!(report.comparisons().len(), 1);
+                let comparison = &report.comparisons()[0];
+                assert_eq!(comparison.expected_schedulers, vec![0]);
+                assert_eq!(comparison.actual_schedulers, vec![1, 2]);
+                assert_eq!(
+                    comparison.differences,
+                    vec![ReceiptDifference::Validity {
+                        transaction_id: batches[2].batch().transactions()[0]
+                            .header_signature()
+                            .into(),
+                        expected_valid: false,
+                        actual_valid: true,
+                    }]
+                );
             }
             e => panic!("Wrong error type received: {:?}", e),
         }
+        // With the default policy, no result is returned for the divergent batch
+        assert!(result_rx.try_recv().is_err());
+
+        multi_scheduler.shutdown();
+    }
+
+    /// This test verifies that when the sub-schedulers do not agree on the result for a batch, the
+    /// MultiScheduler reports the divergence and then returns the result selected by its
+    /// divergence policy.
+    #[test]
+    pub fn test_multi_scheduler_divergence_policy() {
+        let batch = mock_batch_with_num_txns(1);
+        let valid_receipt = valid_receipt_from_batch(batch.clone());
+        let invalid_receipt = invalid_receipt_from_batch(batch.clone());
+
+        for (policy, expected_result) in vec![
+            (DivergencePolicy::Primary, invalid_receipt.clone()),
+            (DivergencePolicy::Majority, valid_receipt.clone()),
+        ] {
+            // The primary sub-scheduler disagrees with the other two
+            let sub_schedulers = vec![
+                Box::new(MockSubScheduler::new(vec![invalid_receipt.clone()]))
+                    as Box<dyn Scheduler + Send>,
+                Box::new(MockSubScheduler::new(vec![valid_receipt.clone()]))
+                    as Box<dyn Scheduler + Send>,
+                Box::new(MockSubScheduler::new(vec![valid_receipt.clone()]))
+                    as Box<dyn Scheduler + Send>,
+            ];
+
+            let mut sub_scheduler_handler = MockSubSchedulerHandler::new();
+            let mut multi_scheduler = MultiScheduler::new_with_divergence_policy(
+                sub_schedulers,
+                &mut sub_scheduler_handler,
+                policy,
+            )
+            .expect("Failed to create scheduler");
+            sub_scheduler_handler
+                .pass_scheduler(
+                    multi_scheduler
+                        .take_task_iterator()
+                        .expect("Failed to take task iterator"),
+                    multi_scheduler
+                        .new_notifier()
+                        .expect("Failed to get new notifier"),
+                )
+                .expect("Failed to pass first scheduler to handler");
+            multi_scheduler
+                .add_batch(batch.clone())
+                .expect("Failed to add batch");
+
+            let (result_tx, result_rx) = mpsc::channel();
+            multi_scheduler
+                .set_result_callback(Box::new(move |result| {
+                    result_tx.send(result).expect("Failed to send result");
+                }))
+                .expect("Failed to set result callback");
+            let (error_tx, error_rx) = mpsc::channel();
+            multi_scheduler
+                .set_error_callback(Box::new(move |err| {
+                    error_tx.send(err).expect("Failed to send error");
+                }))
+                .expect("Failed to set error callback");
+
+            sub_scheduler_handler.next();
+
+            match error_rx.recv().expect("Failed to receive error") {
+                SchedulerError::SubSchedulerResultsDiverged(report) => {
+                    assert_eq!(report.batch_id(), batch.batch().header_signature())
+                }
+                e => panic!("Wrong error type received: {:?}", e),
+            }
+            let result = result_rx.recv().expect("Failed to receive result");
+            assert_eq!(result, expected_result);
+
+            multi_scheduler.shutdown();
+        }
+    }
+
+    /// This test verifies that an expired execution deadline reported by a sub-scheduler is passed
+    /// to the MultiScheduler's error callback as is, while other sub-scheduler errors are reported
+    /// as internal errors.
+    #[test]
+    pub fn test_multi_scheduler_sub_scheduler_deadline() {
+        let sub_schedulers: Vec<_> = (0..2)
+            .map(|_| Box::new(MockSubScheduler::new(vec![])))
+            .collect();
+        let mut multi_scheduler = clone_mocksubschedulers_into_multischeduler(&sub_schedulers);
+
+        let (error_tx, error_rx) = mpsc::channel();
+        multi_scheduler
+            .set_error_callback(Box::new(move |err| {
+                error_tx.send(err).expect("Failed to send error");
+            }))
+            .expect("Failed to set error callback");
+
+        sub_schedulers[1].send_error(SchedulerError::ExecutionDeadlineExceeded("txn".into()));
+        match error_rx.recv().expect("Failed to receive error") {
+            SchedulerError::ExecutionDeadlineExceeded(txn_id) => assert_eq!(txn_id, "txn"),
+            err => panic!("Did not get ExecutionDeadlineExceeded; got {:?}", err),
+        }
+
+        sub_schedulers[0].send_error(SchedulerError::NoTaskIterator);
+        match error_rx.recv().expect("Failed to receive error") {
+            SchedulerError::Internal(_) => (),
+            err => panic!("Did not get Internal; got {:?}", err),
+        }
 
         multi_scheduler.shutdown();
     }
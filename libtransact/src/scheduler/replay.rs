@@ -0,0 +1,668 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Deterministic replay of previously executed batches.
+//!
+//! Replaying re-executes a list of batches on top of the state root they were originally executed
+//! against, using a `SerialScheduler` and the given `Executor`, and verifies that every
+//! transaction produces the same receipt as was originally recorded, and that the batches produce
+//! the state root they originally did. The first transaction whose receipt differs is reported,
+//! along with the differences in its state changes.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::context::manager::ContextManagerError;
+use crate::context::{ContextId, ContextLifecycle};
+use crate::execution::executor::{Executor, ExecutorError};
+use crate::protocol::batch::BatchPair;
+use crate::protocol::receipt::{StateChange, TransactionReceipt, TransactionResult};
+use crate::scheduler::multi::{compare_receipts, ReceiptDifference};
+use crate::scheduler::serial::SerialScheduler;
+use crate::scheduler::{BatchExecutionResult, Scheduler, SchedulerError};
+use crate::state::{self, StateWriteError, Write};
+
+/// The state changes that differ between an expected receipt and a replayed receipt.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StateChangeDiff {
+    /// State changes in the expected receipt which are not in the replayed receipt.
+    pub missing: Vec<StateChange>,
+    /// State changes in the replayed receipt which are not in the expected receipt.
+    pub unexpected: Vec<StateChange>,
+}
+
+impl StateChangeDiff {
+    fn new(expected: Option<&TransactionReceipt>, actual: Option<&TransactionReceipt>) -> Self {
+        let expected = expected.map(state_changes).unwrap_or(&[]);
+        let actual = actual.map(state_changes).unwrap_or(&[]);
+
+        StateChangeDiff {
+            missing: expected
+                .iter()
+                .filter(|change| !actual.contains(change))
+                .cloned()
+                .collect(),
+            unexpected: actual
+                .iter()
+                .filter(|change| !expected.contains(change))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Returns true if there are no differing state changes.
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+fn state_changes(receipt: &TransactionReceipt) -> &[StateChange] {
+    match receipt.transaction_result {
+        TransactionResult::Valid {
+            ref state_changes, ..
+        } => state_changes,
+        TransactionResult::Invalid { .. } => &[],
+    }
+}
+
+/// The first transaction whose replayed receipt did not match its expected receipt.
+#[derive(Clone, Debug)]
+pub struct ReplayMismatch {
+    /// The ID of the batch containing the transaction.
+    pub batch_id: String,
+    /// The ID of the mismatched transaction.
+    pub transaction_id: String,
+    /// The field-by-field differences between the expected and replayed receipts.
+    pub differences: Vec<ReceiptDifference>,
+    /// The differences between the expected and replayed state changes.
+    pub state_change_diff: StateChangeDiff,
+}
+
+impl fmt::Display for ReplayMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "transaction {} in batch {} did not match its expected receipt",
+            self.transaction_id, self.batch_id
+        )?;
+        for difference in &self.differences {
+            write!(f, "; {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of replaying a list of batches.
+#[derive(Clone, Debug)]
+pub enum ReplayOutcome {
+    /// Every replayed transaction produced its expected receipt, and the batches produced the
+    /// expected state root.
+    Verified,
+    /// A replayed transaction did not produce its expected receipt.
+    Mismatch(Box<ReplayMismatch>),
+    /// Every replayed transaction produced its expected receipt, but the batches' state changes
+    /// did not produce the expected state root.
+    StateRootMismatch { expected: String, actual: String },
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The executor could not execute the replayed batches.
+    ExecutorError(ExecutorError),
+    /// No expected result was provided for the batch with the contained ID.
+    MissingExpectedResult(String),
+    /// The scheduler returned an error while replaying the batches.
+    SchedulerError(SchedulerError),
+    /// The state root of the replayed batches' state changes could not be computed.
+    StateWriteError(StateWriteError),
+    /// The scheduler stopped returning results before all batches were replayed.
+    Incomplete(String),
+}
+
+impl Error for ReplayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReplayError::ExecutorError(err) => Some(err),
+            ReplayError::MissingExpectedResult(_) => None,
+            ReplayError::SchedulerError(err) => Some(err),
+            ReplayError::StateWriteError(err) => Some(err),
+            ReplayError::Incomplete(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::ExecutorError(err) => write!(f, "unable to execute batches: {}", err),
+            ReplayError::MissingExpectedResult(batch_id) => {
+                write!(f, "no expected result provided for batch {}", batch_id)
+            }
+            ReplayError::SchedulerError(err) => write!(f, "scheduler error: {}", err),
+            ReplayError::StateWriteError(err) => {
+                write!(f, "unable to compute state root: {}", err)
+            }
+            ReplayError::Incomplete(msg) => write!(f, "replay did not complete: {}", msg),
+        }
+    }
+}
+
+impl From<ExecutorError> for ReplayError {
+    fn from(err: ExecutorError) -> Self {
+        ReplayError::ExecutorError(err)
+    }
+}
+
+impl From<SchedulerError> for ReplayError {
+    fn from(err: SchedulerError) -> Self {
+        ReplayError::SchedulerError(err)
+    }
+}
+
+impl From<StateWriteError> for ReplayError {
+    fn from(err: StateWriteError) -> Self {
+        ReplayError::StateWriteError(err)
+    }
+}
+
+/// Replays the given batches on top of `state_root` and verifies the resulting receipts against
+/// `expected_results`, and the resulting state root against `expected_state_root`.
+///
+/// The batches are scheduled with a `SerialScheduler` created from `context_lifecycle`, and are
+/// executed by `executor`, which must already be started. Results are checked in the order they
+/// are returned; replay stops at the first transaction whose receipt does not match. Once every
+/// receipt matches, the state root of the valid batches' state changes is computed with `state`;
+/// nothing is committed. The contexts created for the replay are dropped before it returns.
+///
+/// # Errors
+///
+/// Returns a `ReplayError` if an expected result is missing for one of the batches, if the
+/// scheduler or executor fail before all of the batches have been replayed, or if the resulting
+/// state root cannot be computed.
+pub fn replay_batches<W>(
+    executor: &Executor,
+    context_lifecycle: Box<dyn ContextLifecycle>,
+    state: &W,
+    state_root: &str,
+    batches: Vec<BatchPair>,
+    expected_results: &[BatchExecutionResult],
+    expected_state_root: &str,
+) -> Result<ReplayOutcome, ReplayError>
+where
+    W: Write<StateId = String, Key = String, Value = Vec<u8>>,
+{
+    let expected_results = expected_results
+        .iter()
+        .map(|result| (result.batch.batch().header_signature(), result))
+        .collect::<HashMap<_, _>>();
+    if let Some(batch) = batches
+        .iter()
+        .find(|batch| !expected_results.contains_key(batch.batch().header_signature()))
+    {
+        return Err(ReplayError::MissingExpectedResult(
+            batch.batch().header_signature().into(),
+        ));
+    }
+
+    let context_lifecycle = TrackingContextLifecycle::new(context_lifecycle);
+    let mut scheduler =
+        SerialScheduler::new(Box::new(context_lifecycle.clone()), state_root.into())?;
+
+    let (result_tx, result_rx) = mpsc::channel();
+    let error_tx = result_tx.clone();
+    scheduler.set_result_callback(Box::new(move |result| {
+        result_tx
+            .send(Ok(result))
+            .unwrap_or_else(|err| error!("Unable to send replay result: {}", err));
+    }))?;
+    scheduler.set_error_callback(Box::new(move |err| {
+        error_tx
+            .send(Err(err))
+            .unwrap_or_else(|err| error!("Unable to send replay error: {}", err));
+    }))?;
+
+    let num_batches = batches.len();
+    let outcome = batches
+        .into_iter()
+        .try_for_each(|batch| scheduler.add_batch(batch))
+        .and_then(|_| scheduler.finalize())
+        .map_err(ReplayError::from)
+        .and_then(|_| {
+            run_replay(
+                executor,
+                &mut scheduler,
+                &result_rx,
+                num_batches,
+                &expected_results,
+            )
+        });
+
+    scheduler.shutdown();
+    context_lifecycle.drop_created_contexts();
+
+    match outcome? {
+        Ok(results) => {
+            let actual_state_root =
+                state.compute_state_id(&state_root.to_string(), &valid_state_changes(&results))?;
+            if actual_state_root == expected_state_root {
+                Ok(ReplayOutcome::Verified)
+            } else {
+                Ok(ReplayOutcome::StateRootMismatch {
+                    expected: expected_state_root.into(),
+                    actual: actual_state_root,
+                })
+            }
+        }
+        Err(mismatch) => Ok(ReplayOutcome::Mismatch(Box::new(mismatch))),
+    }
+}
+
+/// Executes the batches, returning their results in the order they were returned, or the first
+/// mismatched transaction.
+fn run_replay(
+    executor: &Executor,
+    scheduler: &mut SerialScheduler,
+    result_rx: &mpsc::Receiver<Result<Option<BatchExecutionResult>, SchedulerError>>,
+    num_batches: usize,
+    expected_results: &HashMap<&str, &BatchExecutionResult>,
+) -> Result<Result<Vec<BatchExecutionResult>, ReplayMismatch>, ReplayError> {
+    executor.execute(scheduler.take_task_iterator()?, scheduler.new_notifier()?)?;
+
+    let mut results = Vec::with_capacity(num_batches);
+    for _ in 0..num_batches {
+        let result = match result_rx.recv() {
+            Ok(Ok(Some(result))) => result,
+            Ok(Ok(None)) => {
+                return Err(ReplayError::Incomplete(
+                    "scheduler finished before all batches were executed".into(),
+                ))
+            }
+            Ok(Err(err)) => return Err(ReplayError::SchedulerError(err)),
+            Err(_) => return Err(ReplayError::Incomplete("scheduler disconnected".into())),
+        };
+
+        let batch_id = result.batch.batch().header_signature();
+        let expected = expected_results
+            .get(batch_id)
+            .ok_or_else(|| ReplayError::MissingExpectedResult(batch_id.into()))?;
+
+        if let Some(mismatch) = find_first_mismatch(expected, &result) {
+            return Ok(Err(mismatch));
+        }
+        results.push(result);
+    }
+
+    Ok(Ok(results))
+}
+
+/// The state changes of the batches whose transactions are all valid, in order.
+fn valid_state_changes(results: &[BatchExecutionResult]) -> Vec<state::StateChange> {
+    results
+        .iter()
+        .filter(|result| {
+            result
+                .receipts
+                .iter()
+                .all(|receipt| match receipt.transaction_result {
+                    TransactionResult::Valid { .. } => true,
+                    TransactionResult::Invalid { .. } => false,
+                })
+        })
+        .flat_map(|result| result.receipts.iter())
+        .flat_map(|receipt| state_changes(receipt).iter().cloned())
+        .map(|state_change| state_change.into())
+        .collect()
+}
+
+/// A `ContextLifecycle` which records the contexts created through it, so that they can be
+/// dropped once the replay is done.
+#[derive(Clone)]
+struct TrackingContextLifecycle {
+    inner: Arc<Mutex<TrackedContexts>>,
+}
+
+struct TrackedContexts {
+    context_lifecycle: Box<dyn ContextLifecycle>,
+    created_contexts: Vec<ContextId>,
+}
+
+impl TrackingContextLifecycle {
+    fn new(context_lifecycle: Box<dyn ContextLifecycle>) -> Self {
+        TrackingContextLifecycle {
+            inner: Arc::new(Mutex::new(TrackedContexts {
+                context_lifecycle,
+                created_contexts: vec![],
+            })),
+        }
+    }
+
+    // A panic while the lock is held cannot leave the contexts inconsistent, so a poisoned lock
+    // is used as is
+    fn lock(&self) -> MutexGuard<TrackedContexts> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Drops every context created through the lifecycle which has not already been dropped.
+    fn drop_created_contexts(&self) {
+        let mut tracked = self.lock();
+        for context_id in std::mem::replace(&mut tracked.created_contexts, vec![]) {
+            tracked.context_lifecycle.drop_context(context_id);
+        }
+    }
+}
+
+impl ContextLifecycle for TrackingContextLifecycle {
+    fn create_context(&mut self, dependent_contexts: &[ContextId], state_id: &str) -> ContextId {
+        let mut tracked = self.lock();
+        let context_id = tracked
+            .context_lifecycle
+            .create_context(dependent_contexts, state_id);
+        tracked.created_contexts.push(context_id);
+        context_id
+    }
+
+    fn drop_context(&mut self, context_id: ContextId) {
+        let mut tracked = self.lock();
+        tracked
+            .created_contexts
+            .retain(|created| *created != context_id);
+        tracked.context_lifecycle.drop_context(context_id);
+    }
+
+    fn get_transaction_receipt(
+        &self,
+        context_id: &ContextId,
+        transaction_id: &str,
+    ) -> Result<TransactionReceipt, ContextManagerError> {
+        self.lock()
+            .context_lifecycle
+            .get_transaction_receipt(context_id, transaction_id)
+    }
+}
+
+/// Compares the receipts of each transaction in the batch, in the order of the transactions in
+/// the batch, and returns the first mismatch.
+fn find_first_mismatch(
+    expected: &BatchExecutionResult,
+    actual: &BatchExecutionResult,
+) -> Option<ReplayMismatch> {
+    let find_receipt = |receipts: &[TransactionReceipt], txn_id: &str| {
+        receipts
+            .iter()
+            .find(|receipt| receipt.transaction_id == txn_id)
+            .cloned()
+    };
+
+    actual
+        .batch
+        .batch()
+        .transactions()
+        .iter()
+        .map(|txn| txn.header_signature())
+        .find_map(|txn_id| {
+            let expected_receipt = find_receipt(&expected.receipts, txn_id);
+            let actual_receipt = find_receipt(&actual.receipts, txn_id);
+
+            let differences = compare_receipts(
+                expected_receipt
+                    .as_ref()
+                    .map(std::slice::from_ref)
+                    .unwrap_or(&[]),
+                actual_receipt
+                    .as_ref()
+                    .map(std::slice::from_ref)
+                    .unwrap_or(&[]),
+            );
+
+            if differences.is_empty() {
+                None
+            } else {
+                Some(ReplayMismatch {
+                    batch_id: actual.batch.batch().header_signature().into(),
+                    transaction_id: txn_id.into(),
+                    differences,
+                    state_change_diff: StateChangeDiff::new(
+                        expected_receipt.as_ref(),
+                        actual_receipt.as_ref(),
+                    ),
+                })
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::context::manager::sync::ContextManager;
+    use crate::execution::adapter::static_adapter::StaticExecutionAdapter;
+    use crate::state::hashmap::HashMapState;
+    use crate::workload::command::{make_set_state_batch, CommandTransactionHandler};
+
+    fn set_result(batch: &BatchPair, key: &str, value: &[u8]) -> BatchExecutionResult {
+        BatchExecutionResult {
+            batch: batch.clone(),
+            receipts: vec![TransactionReceipt {
+                transaction_id: batch.batch().transactions()[0].header_signature().into(),
+                transaction_result: TransactionResult::Valid {
+                    state_changes: vec![StateChange::Set {
+                        key: key.into(),
+                        value: value.to_vec(),
+                    }],
+                    events: vec![],
+                    data: vec![],
+                },
+            }],
+        }
+    }
+
+    /// The state root produced by setting the given entries in empty state.
+    fn state_root_after(entries: &[(&str, &str)]) -> String {
+        HashMapState::new()
+            .compute_state_id(
+                &HashMapState::state_id(&HashMap::new()),
+                &entries
+                    .iter()
+                    .map(|(key, value)| state::StateChange::Set {
+                        key: key.to_string(),
+                        value: value.as_bytes().to_vec(),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .expect("Unable to compute state root")
+    }
+
+    /// Replays the batches on empty state, and checks that every context created for the replay
+    /// was dropped.
+    fn replay(
+        batches: Vec<BatchPair>,
+        expected_results: &[BatchExecutionResult],
+        expected_state_root: &str,
+    ) -> Result<ReplayOutcome, ReplayError> {
+        let state = HashMapState::new();
+        let state_root = HashMapState::state_id(&HashMap::new());
+        let context_manager = ContextManager::new(Box::new(state.clone()));
+        let live_contexts = Arc::new(AtomicUsize::new(0));
+
+        let mut executor = Executor::new(vec![Box::new(
+            StaticExecutionAdapter::new_adapter(
+                vec![Box::new(CommandTransactionHandler::new())],
+                context_manager.clone(),
+            )
+            .expect("Unable to create adapter"),
+        )]);
+        executor.start().expect("Unable to start executor");
+
+        let outcome = replay_batches(
+            &executor,
+            Box::new(CountingContextLifecycle {
+                context_manager,
+                live_contexts: Arc::clone(&live_contexts),
+            }),
+            &state,
+            &state_root,
+            batches,
+            expected_results,
+            expected_state_root,
+        );
+
+        executor.stop();
+        assert_eq!(live_contexts.load(Ordering::SeqCst), 0);
+
+        outcome
+    }
+
+    /// Counts the contexts which have been created, but not yet dropped.
+    struct CountingContextLifecycle {
+        context_manager: ContextManager,
+        live_contexts: Arc<AtomicUsize>,
+    }
+
+    impl ContextLifecycle for CountingContextLifecycle {
+        fn create_context(
+            &mut self,
+            dependent_contexts: &[ContextId],
+            state_id: &str,
+        ) -> ContextId {
+            self.live_contexts.fetch_add(1, Ordering::SeqCst);
+            self.context_manager
+                .create_context(dependent_contexts, state_id)
+        }
+
+        fn drop_context(&mut self, context_id: ContextId) {
+            self.live_contexts.fetch_sub(1, Ordering::SeqCst);
+            self.context_manager.drop_context(context_id)
+        }
+
+        fn get_transaction_receipt(
+            &self,
+            context_id: &ContextId,
+            transaction_id: &str,
+        ) -> Result<TransactionReceipt, ContextManagerError> {
+            self.context_manager
+                .get_transaction_receipt(context_id, transaction_id)
+        }
+    }
+
+    /// Verify that replaying batches which produce their expected receipts is verified.
+    #[test]
+    fn replay_matching_results() {
+        let batch1 = make_set_state_batch("abc", b"abc", false);
+        let batch2 = make_set_state_batch("def", b"def", false);
+        let expected = vec![
+            set_result(&batch1, "abc", b"abc"),
+            set_result(&batch2, "def", b"def"),
+        ];
+
+        match replay(
+            vec![batch1, batch2],
+            &expected,
+            &state_root_after(&[("abc", "abc"), ("def", "def")]),
+        )
+        .expect("Replay failed")
+        {
+            ReplayOutcome::Verified => (),
+            outcome => panic!("Replay should have been verified; got {:?}", outcome),
+        }
+    }
+
+    /// Verify that replaying batches which produce their expected receipts, but not the expected
+    /// state root, reports the differing state roots.
+    #[test]
+    fn replay_mismatched_state_root() {
+        let batch1 = make_set_state_batch("abc", b"abc", false);
+        let batch2 = make_set_state_batch("def", b"def", false);
+        let expected = vec![
+            set_result(&batch1, "abc", b"abc"),
+            set_result(&batch2, "def", b"def"),
+        ];
+        let expected_state_root = state_root_after(&[("abc", "abc")]);
+
+        match replay(vec![batch1, batch2], &expected, &expected_state_root).expect("Replay failed")
+        {
+            ReplayOutcome::StateRootMismatch { expected, actual } => {
+                assert_eq!(expected, expected_state_root);
+                assert_eq!(actual, state_root_after(&[("abc", "abc"), ("def", "def")]));
+            }
+            outcome => panic!("Did not get StateRootMismatch; got {:?}", outcome),
+        }
+    }
+
+    /// Verify that replaying a batch which does not produce its expected receipt reports the
+    /// mismatched transaction along with the differing state changes.
+    #[test]
+    fn replay_mismatched_results() {
+        let batch1 = make_set_state_batch("abc", b"abc", false);
+        let batch2 = make_set_state_batch("def", b"def", false);
+        let expected = vec![
+            set_result(&batch1, "abc", b"abc"),
+            set_result(&batch2, "def", b"xyz"),
+        ];
+
+        let mismatch = match replay(
+            vec![batch1, batch2.clone()],
+            &expected,
+            &state_root_after(&[("abc", "abc"), ("def", "xyz")]),
+        )
+        .expect("Replay failed")
+        {
+            ReplayOutcome::Mismatch(mismatch) => mismatch,
+            outcome => panic!("Did not get Mismatch; got {:?}", outcome),
+        };
+
+        assert_eq!(mismatch.batch_id, batch2.batch().header_signature());
+        assert_eq!(
+            mismatch.transaction_id,
+            batch2.batch().transactions()[0].header_signature()
+        );
+        assert_eq!(
+            mismatch.state_change_diff,
+            StateChangeDiff {
+                missing: vec![StateChange::Set {
+                    key: "def".into(),
+                    value: b"xyz".to_vec(),
+                }],
+                unexpected: vec![StateChange::Set {
+                    key: "def".into(),
+                    value: b"def".to_vec(),
+                }],
+            }
+        );
+    }
+
+    /// Verify that replay fails if an expected result is not provided for every batch.
+    #[test]
+    fn replay_missing_expected_result() {
+        let batch = make_set_state_batch("abc", b"abc", false);
+
+        match replay(
+            vec![batch.clone()],
+            &[],
+            &state_root_after(&[("abc", "abc")]),
+        ) {
+            Err(ReplayError::MissingExpectedResult(batch_id)) => {
+                assert_eq!(batch_id, batch.batch().header_signature())
+            }
+            res => panic!("Did not get MissingExpectedResult; got {:?}", res),
+        }
+    }
+}
@@ -23,17 +23,21 @@ use crate::protocol::batch::BatchPair;
 use crate::protocol::receipt::TransactionReceipt;
 use crate::protocol::receipt::TransactionResult;
 use crate::protocol::transaction::Transaction;
+use crate::scheduler::validation::rejected_batch_result;
 use crate::scheduler::BatchExecutionResult;
+use crate::scheduler::DeadlinePolicy;
+use crate::scheduler::ExecutionDeadline;
 use crate::scheduler::ExecutionTask;
 use crate::scheduler::ExecutionTaskCompletionNotification;
 use crate::scheduler::InvalidTransactionResult;
 use crate::scheduler::SchedulerError;
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
-use std::sync::mpsc::{Receiver, SendError, Sender};
+use std::sync::mpsc::{Receiver, RecvError, RecvTimeoutError, SendError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use super::shared::Shared;
 
@@ -157,6 +161,22 @@ pub struct SchedulerCore {
 
     /// The context from the previously run transaction.
     previous_context: Option<ContextId>,
+
+    /// The maximum amount of time to wait for a transaction to be executed, if any.
+    execution_deadline: Option<ExecutionDeadline>,
+
+    /// The context and start time of the current transaction, along with a copy of the
+    /// transaction itself so that it may be retried; only tracked when a deadline is set.
+    current_txn_deadline: Option<(ContextId, Instant, Transaction)>,
+
+    /// The context and transaction IDs of the current batch's execution tasks whose deadlines
+    /// expired; late notifications for these tasks are ignored. Cleared when the batch completes,
+    /// after which a late notification is reported as unexpected.
+    expired_tasks: HashSet<(ContextId, String)>,
+
+    /// The number of times each of the current batch's transactions has been retried after its
+    /// deadline expired.
+    txn_retries: HashMap<String, usize>,
 }
 
 impl SchedulerCore {
@@ -166,6 +186,7 @@ impl SchedulerCore {
         execution_tx: Sender<Option<ExecutionTask>>,
         context_lifecycle: Box<dyn ContextLifecycle>,
         state_id: String,
+        execution_deadline: Option<ExecutionDeadline>,
     ) -> Self {
         SchedulerCore {
             shared_lock,
@@ -179,10 +200,16 @@ impl SchedulerCore {
             context_lifecycle,
             state_id,
             previous_context: None,
+            execution_deadline,
+            current_txn_deadline: None,
+            expired_tasks: HashSet::new(),
+            txn_retries: HashMap::new(),
         }
     }
 
     fn try_schedule_next(&mut self) -> Result<(), CoreError> {
+        self.send_rejected_batch_results()?;
+
         if !self.next_ready {
             return Ok(());
         }
@@ -193,19 +220,35 @@ impl SchedulerCore {
 
         if self.current_batch.is_none() {
             let mut shared = self.shared_lock.lock()?;
-            match shared.pop_unscheduled_batch() {
-                Some(unscheduled_batch) => {
-                    self.txn_queue =
-                        VecDeque::from(unscheduled_batch.batch().transactions().to_vec());
-                    self.current_batch = Some(unscheduled_batch);
-                }
-                None => {
-                    // If the scheduler is finalized, no more batches will be added; send a `None`
-                    // result to let the calling code know that all results have been sent.
-                    if shared.finalized() {
-                        shared.result_callback()(None);
+            loop {
+                match shared.pop_unscheduled_batch() {
+                    Some((rejected_batch, Some(error))) => {
+                        shared.result_callback()(Some(rejected_batch_result(
+                            rejected_batch,
+                            &error,
+                        )));
+                    }
+                    Some((unscheduled_batch, None)) => {
+                        self.txn_queue =
+                            VecDeque::from(unscheduled_batch.batch().transactions().to_vec());
+                        self.current_batch = Some(unscheduled_batch);
+                        break;
+                    }
+                    None => {
+                        // If the scheduler is finalized, no more batches will be added; send a
+                        // `None` result to let the calling code know that all results have been
+                        // sent.
+                        if shared.finalized() {
+                            shared.result_callback()(None);
+                            // The task iterator is waiting for a task, which it may have asked for
+                            // before the scheduler was finalized or while a transaction that could
+                            // have been retried was executing; let it know there are no more
+                            // tasks.
+                            self.execution_tx.send(None)?;
+                            self.next_ready = false;
+                        }
+                        return Ok(());
                     }
-                    return Ok(());
                 }
             }
         }
@@ -220,6 +263,11 @@ impl SchedulerCore {
             ))
         })?;
         let transaction_id = transaction.header_signature().into();
+        let retry_copy = if self.execution_deadline.is_some() {
+            Some(transaction.clone())
+        } else {
+            None
+        };
         let transaction_pair = match transaction.into_pair() {
             Ok(pair) => pair,
             Err(err) => {
@@ -241,6 +289,8 @@ impl SchedulerCore {
         };
 
         self.current_txn = Some(transaction_pair.transaction().header_signature().into());
+        self.current_txn_deadline =
+            retry_copy.map(|transaction| (context_id, Instant::now(), transaction));
         self.execution_tx
             .send(Some(ExecutionTask::new(transaction_pair, context_id)))?;
         self.next_ready = false;
@@ -248,6 +298,88 @@ impl SchedulerCore {
         Ok(())
     }
 
+    /// Reports the results of the rejected batches at the front of the queue, unless a batch is
+    /// executing, so that each rejection is reported in the order its batch would have run.
+    fn send_rejected_batch_results(&mut self) -> Result<(), CoreError> {
+        if self.current_batch.is_some() {
+            return Ok(());
+        }
+        let mut shared = self.shared_lock.lock()?;
+        while let Some((batch, error)) = shared.pop_rejected_batch() {
+            shared.result_callback()(Some(rejected_batch_result(batch, &error)));
+        }
+        Ok(())
+    }
+
+    /// Returns how much time remains before the current transaction's deadline expires, if a
+    /// deadline is set and a transaction is executing.
+    fn time_until_deadline(&self) -> Option<Duration> {
+        let timeout = self.execution_deadline.as_ref()?.timeout();
+        let (_, started, _) = self.current_txn_deadline.as_ref()?;
+        Some(
+            timeout
+                .checked_sub(started.elapsed())
+                .unwrap_or_else(|| Duration::from_secs(0)),
+        )
+    }
+
+    /// Waits for the next message, giving up when the current transaction's deadline expires.
+    /// Returns `Ok(None)` if the deadline expired.
+    fn recv(&self) -> Result<Option<CoreMessage>, RecvError> {
+        match self.time_until_deadline() {
+            Some(timeout) => match self.rx.recv_timeout(timeout) {
+                Ok(message) => Ok(Some(message)),
+                Err(RecvTimeoutError::Timeout) => Ok(None),
+                Err(RecvTimeoutError::Disconnected) => Err(RecvError),
+            },
+            None => self.rx.recv().map(Some),
+        }
+    }
+
+    /// Apply the deadline policy to the current transaction, which has not been executed in time.
+    fn handle_expired_deadline(&mut self) -> Result<(), CoreError> {
+        let (timeout, policy) = match self.execution_deadline {
+            Some(ref deadline) => (deadline.timeout(), deadline.policy()),
+            None => return Ok(()),
+        };
+        let (transaction_id, (context_id, _, transaction)) =
+            match (self.current_txn.take(), self.current_txn_deadline.take()) {
+                (Some(txn_id), Some(deadline)) => (txn_id, deadline),
+                _ => return Ok(()),
+            };
+
+        self.expired_tasks
+            .insert((context_id, transaction_id.clone()));
+        // The execution of the task may still complete, but its context will not be used
+        self.context_lifecycle.drop_context(context_id);
+        self.send_scheduler_error(SchedulerError::ExecutionDeadlineExceeded(
+            transaction_id.clone(),
+        ))?;
+
+        let retries = self.txn_retries.entry(transaction_id.clone()).or_insert(0);
+        let retry = match policy {
+            DeadlinePolicy::Retry(max_retries) => *retries < max_retries,
+            DeadlinePolicy::InvalidateBatch => false,
+        };
+
+        if retry {
+            *retries += 1;
+            self.txn_queue.push_front(transaction);
+        } else {
+            self.invalidate_current_batch(InvalidTransactionResult {
+                transaction_id,
+                error_message: format!(
+                    "transaction was not executed within its deadline ({:?})",
+                    timeout
+                ),
+                error_data: vec![],
+            })?;
+            self.send_batch_result()?;
+        }
+
+        self.try_schedule_next()
+    }
+
     fn invalidate_current_batch(
         &mut self,
         invalid_result: InvalidTransactionResult,
@@ -324,6 +456,8 @@ impl SchedulerCore {
 
         let mut receipts = vec![];
         std::mem::swap(&mut receipts, &mut self.txn_receipts);
+        self.txn_retries.clear();
+        self.expired_tasks.clear();
 
         let batch_result = BatchExecutionResult { batch, receipts };
 
@@ -332,6 +466,21 @@ impl SchedulerCore {
         Ok(())
     }
 
+    /// Whether the current transaction could be returned to the queue if its deadline expires.
+    fn current_txn_may_be_retried(&self) -> bool {
+        match self.execution_deadline {
+            Some(ref deadline) => match deadline.policy() {
+                DeadlinePolicy::Retry(max_retries) => {
+                    self.current_txn.as_ref().map_or(false, |txn_id| {
+                        self.txn_retries.get(txn_id).cloned().unwrap_or(0) < max_retries
+                    })
+                }
+                DeadlinePolicy::InvalidateBatch => false,
+            },
+            None => false,
+        }
+    }
+
     fn send_scheduler_error(&mut self, error: SchedulerError) -> Result<(), CoreError> {
         self.shared_lock.lock()?.error_callback()(error);
         Ok(())
@@ -339,11 +488,30 @@ impl SchedulerCore {
 
     fn run(&mut self) -> Result<(), CoreError> {
         loop {
-            match self.rx.recv() {
-                Ok(CoreMessage::BatchAdded) => {
+            match self.recv() {
+                Ok(None) => {
+                    self.handle_expired_deadline()?;
+                }
+                Ok(Some(CoreMessage::BatchAdded)) => {
                     self.try_schedule_next()?;
                 }
-                Ok(CoreMessage::ExecutionResult(task_notification)) => {
+                Ok(Some(CoreMessage::ExecutionResult(task_notification))) => {
+                    let expired_task = match task_notification {
+                        ExecutionTaskCompletionNotification::Valid(context_id, ref txn_id) => {
+                            (context_id, txn_id.clone())
+                        }
+                        ExecutionTaskCompletionNotification::Invalid(context_id, ref result) => {
+                            (context_id, result.transaction_id.clone())
+                        }
+                    };
+                    if self.expired_tasks.remove(&expired_task) {
+                        debug!(
+                            "Ignoring notification for transaction whose deadline expired: {}",
+                            expired_task.1
+                        );
+                        continue;
+                    }
+
                     let current_txn_id = self.current_txn.clone().unwrap_or_else(|| "".into());
                     match task_notification {
                         ExecutionTaskCompletionNotification::Valid(context_id, transaction_id) => {
@@ -354,6 +522,7 @@ impl SchedulerCore {
                                 continue;
                             }
                             self.current_txn = None;
+                            self.current_txn_deadline = None;
                             self.previous_context = Some(context_id);
                             self.txn_receipts.push(
                                 self.context_lifecycle
@@ -368,6 +537,7 @@ impl SchedulerCore {
                                 continue;
                             }
                             self.current_txn = None;
+                            self.current_txn_deadline = None;
                             self.invalidate_current_batch(result)?;
                         }
                     };
@@ -378,15 +548,17 @@ impl SchedulerCore {
 
                     self.try_schedule_next()?;
                 }
-                Ok(CoreMessage::Next) => {
+                Ok(Some(CoreMessage::Next)) => {
                     // If the scheduler is finalized, there are no unscheduled batches, and there
                     // are no more transactions in the queue for the current batch: there are no
-                    // more execution tasks to return.
+                    // more execution tasks to return. If the current transaction may still be
+                    // retried, the answer has to wait until it completes or expires.
                     {
                         let shared = self.shared_lock.lock()?;
                         if shared.finalized()
                             && shared.unscheduled_batches_is_empty()
                             && self.txn_queue.is_empty()
+                            && !self.current_txn_may_be_retried()
                         {
                             self.execution_tx.send(None)?;
                             continue;
@@ -396,7 +568,8 @@ impl SchedulerCore {
                     self.next_ready = true;
                     self.try_schedule_next()?;
                 }
-                Ok(CoreMessage::Finalized) => {
+                Ok(Some(CoreMessage::Finalized)) => {
+                    self.send_rejected_batch_results()?;
                     // If there are no unscheduled batches and no batch is currently executing, the
                     // scheduler is done; send a `None` result to let the calling code know that
                     // all results have been sent.
@@ -406,7 +579,7 @@ impl SchedulerCore {
                         break;
                     }
                 }
-                Ok(CoreMessage::Shutdown) => {
+                Ok(Some(CoreMessage::Shutdown)) => {
                     break;
                 }
                 Err(err) => {
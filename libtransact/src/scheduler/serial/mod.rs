@@ -23,7 +23,9 @@ mod shared;
 
 use crate::context::ContextLifecycle;
 use crate::protocol::batch::BatchPair;
+use crate::scheduler::validation::BatchValidator;
 use crate::scheduler::BatchExecutionResult;
+use crate::scheduler::ExecutionDeadline;
 use crate::scheduler::ExecutionTask;
 use crate::scheduler::ExecutionTaskCompletionNotifier;
 use crate::scheduler::Scheduler;
@@ -59,24 +61,86 @@ pub struct SerialScheduler {
     task_iterator: Option<Box<dyn Iterator<Item = ExecutionTask> + Send>>,
 }
 
-impl SerialScheduler {
-    /// Returns a newly created `SerialScheduler`.
-    pub fn new(
-        context_lifecycle: Box<dyn ContextLifecycle>,
-        state_id: String,
-    ) -> Result<SerialScheduler, SchedulerError> {
+/// Determines the priority of a batch when it is added to a `SerialScheduler`; batches with a
+/// higher priority are scheduled before batches with a lower priority, and batches with equal
+/// priority are scheduled in the order they were added.
+pub type BatchPriorityFn = Box<dyn Fn(&BatchPair) -> i32 + Send>;
+
+/// Builds a `SerialScheduler`, optionally configuring an execution deadline and a batch priority
+/// function.
+pub struct SerialSchedulerBuilder {
+    context_lifecycle: Box<dyn ContextLifecycle>,
+    state_id: String,
+    execution_deadline: Option<ExecutionDeadline>,
+    batch_priority: Option<BatchPriorityFn>,
+    batch_validator: Option<BatchValidator>,
+}
+
+impl SerialSchedulerBuilder {
+    /// Creates a builder for a scheduler which executes transactions on top of the given state
+    /// ID, using the given `ContextLifecycle`.
+    pub fn new(context_lifecycle: Box<dyn ContextLifecycle>, state_id: String) -> Self {
+        SerialSchedulerBuilder {
+            context_lifecycle,
+            state_id,
+            execution_deadline: None,
+            batch_priority: None,
+            batch_validator: None,
+        }
+    }
+
+    /// Applies the given deadline to the execution of each transaction.
+    ///
+    /// When a transaction's deadline expires, an `ExecutionDeadlineExceeded` error is sent to the
+    /// error callback and the deadline's policy is applied; the scheduler then continues with the
+    /// remaining batches.
+    pub fn with_execution_deadline(
+        mut self,
+        execution_deadline: ExecutionDeadline,
+    ) -> SerialSchedulerBuilder {
+        self.execution_deadline = Some(execution_deadline);
+        self
+    }
+
+    /// Orders pending batches using the given priority function, rather than strictly in the
+    /// order they were added.
+    pub fn with_batch_priority(
+        mut self,
+        batch_priority: BatchPriorityFn,
+    ) -> SerialSchedulerBuilder {
+        self.batch_priority = Some(batch_priority);
+        self
+    }
+
+    /// Checks each batch with the given validator when it is added; batches which fail validation
+    /// are not executed, and an invalid result is produced for them in the order they would have
+    /// been executed.
+    pub fn with_batch_validator(
+        mut self,
+        batch_validator: BatchValidator,
+    ) -> SerialSchedulerBuilder {
+        self.batch_validator = Some(batch_validator);
+        self
+    }
+
+    /// Builds the `SerialScheduler`, starting its internal thread.
+    pub fn build(self) -> Result<SerialScheduler, SchedulerError> {
         let (execution_tx, execution_rx) = mpsc::channel();
         let (core_tx, core_rx) = mpsc::channel();
 
-        let shared_lock = Arc::new(Mutex::new(shared::Shared::new()));
+        let shared_lock = Arc::new(Mutex::new(shared::Shared::new(
+            self.batch_priority,
+            self.batch_validator,
+        )));
 
         // Start the thread to accept and process CoreMessage messages
         let core_handle = core::SchedulerCore::new(
             shared_lock.clone(),
             core_rx,
             execution_tx,
-            context_lifecycle,
-            state_id,
+            self.context_lifecycle,
+            self.state_id,
+            self.execution_deadline,
         )
         .start()?;
 
@@ -90,6 +154,41 @@ impl SerialScheduler {
             ))),
         })
     }
+}
+
+impl SerialScheduler {
+    /// Returns a newly created `SerialScheduler`.
+    pub fn new(
+        context_lifecycle: Box<dyn ContextLifecycle>,
+        state_id: String,
+    ) -> Result<SerialScheduler, SchedulerError> {
+        SerialSchedulerBuilder::new(context_lifecycle, state_id).build()
+    }
+
+    /// Changes the priority of the pending batch with the given ID, moving it to its new place in
+    /// the queue of unscheduled batches.
+    ///
+    /// Returns `false` if the batch is not pending; a batch which has already started executing
+    /// cannot be reprioritized.
+    pub fn set_batch_priority(
+        &mut self,
+        batch_id: &str,
+        priority: i32,
+    ) -> Result<bool, SchedulerError> {
+        Ok(self
+            .shared_lock
+            .lock()?
+            .set_unscheduled_batch_priority(batch_id, priority))
+    }
+
+    /// Removes the pending batch with the given ID from the scheduler, without affecting any
+    /// other batches.
+    ///
+    /// Returns the withdrawn `BatchPair`, or `None` if the batch is not pending; a batch which has
+    /// already started executing cannot be withdrawn.
+    pub fn withdraw_batch(&mut self, batch_id: &str) -> Result<Option<BatchPair>, SchedulerError> {
+        Ok(self.shared_lock.lock()?.remove_unscheduled_batch(batch_id))
+    }
 
     pub fn shutdown(mut self) {
         match self.core_tx.send(core::CoreMessage::Shutdown) {
@@ -141,7 +240,19 @@ impl Scheduler for SerialScheduler {
             ));
         }
 
-        shared.add_unscheduled_batch(batch);
+        match shared.validate_batch(&batch) {
+            Ok(()) => shared.add_unscheduled_batch(batch),
+            Err(err) => {
+                debug!(
+                    "rejecting batch {}: {}",
+                    batch.batch().header_signature(),
+                    err
+                );
+                // The rejection is queued like any other batch, so that the core reports its
+                // result in order.
+                shared.add_rejected_batch(batch, err);
+            }
+        }
 
         // Notify the core that a batch has been added. Note that the batch is
         // not sent across the channel because the batch has already been added
@@ -180,8 +291,12 @@ impl Scheduler for SerialScheduler {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::context::manager::ContextManagerError;
+    use crate::context::ContextId;
+    use crate::protocol::receipt::{TransactionReceipt, TransactionResult};
     use crate::scheduler::tests::*;
-    use crate::scheduler::ExecutionTaskCompletionNotification;
+    use crate::scheduler::validation::{DuplicateTransactionRule, MaxTransactionsRule};
+    use crate::scheduler::{DeadlinePolicy, ExecutionTaskCompletionNotification};
 
     // General Scheduler tests
 
@@ -406,4 +521,467 @@ mod tests {
 
         scheduler.shutdown();
     }
+
+    /// This test verifies that when a transaction is not executed before its deadline and the
+    /// deadline policy is `InvalidateBatch`, the SerialScheduler reports the expired deadline via
+    /// the error callback, invalidates the transaction's batch, and continues on to the next batch.
+    #[test]
+    fn test_serial_scheduler_deadline_invalidates_batch() {
+        let state_id = String::from("state0");
+        let context_lifecycle = DropRecordingContextLifecycle::default();
+        let mut scheduler =
+            SerialSchedulerBuilder::new(Box::new(context_lifecycle.clone()), state_id)
+                .with_execution_deadline(ExecutionDeadline::new(
+                    std::time::Duration::from_millis(100),
+                    DeadlinePolicy::InvalidateBatch,
+                ))
+                .build()
+                .expect("Failed to create scheduler");
+
+        let (result_tx, result_rx) = mpsc::channel();
+        scheduler
+            .set_result_callback(Box::new(move |result| {
+                result_tx.send(result).expect("Failed to send result");
+            }))
+            .expect("Failed to set result callback");
+        let (error_tx, error_rx) = mpsc::channel();
+        scheduler
+            .set_error_callback(Box::new(move |err| {
+                error_tx.send(err).expect("Failed to send error");
+            }))
+            .expect("Failed to set error callback");
+
+        let batches = mock_batches_with_one_transaction(2);
+        for batch in &batches {
+            scheduler
+                .add_batch(batch.clone())
+                .expect("Failed to add batch");
+        }
+
+        let mut task_iterator = scheduler
+            .take_task_iterator()
+            .expect("Failed to get task iterator");
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+
+        // Take the first task but never report a result for it
+        let expired_task = task_iterator.next().expect("Failed to get 1st task");
+        let expired_txn_id = expired_task
+            .pair()
+            .transaction()
+            .header_signature()
+            .to_string();
+
+        match error_rx.recv().expect("Failed to receive error") {
+            SchedulerError::ExecutionDeadlineExceeded(txn_id) => assert_eq!(txn_id, expired_txn_id),
+            err => panic!("Received unexpected error: {}", err),
+        }
+
+        let BatchExecutionResult { batch, receipts } = result_rx
+            .recv()
+            .expect("Failed to receive 1st result")
+            .expect("Got None result");
+        assert_eq!(batch, batches[0]);
+        match receipts[0].transaction_result {
+            TransactionResult::Invalid { .. } => (),
+            ref res => panic!("Did not get invalid receipt; got {:?}", res),
+        }
+        // The expired task's context is dropped
+        assert_eq!(
+            *context_lifecycle.dropped.lock().unwrap(),
+            vec![*expired_task.context_id()]
+        );
+
+        // The second batch is still executed
+        let txn_id = task_iterator
+            .next()
+            .expect("Failed to get 2nd task")
+            .pair()
+            .transaction()
+            .header_signature()
+            .to_string();
+        notifier.notify(ExecutionTaskCompletionNotification::Valid(
+            mock_context_id(),
+            txn_id,
+        ));
+        let result = result_rx.recv().expect("Failed to receive 2nd result");
+        assert_eq!(result, valid_receipt_from_batch(batches[1].clone()));
+
+        scheduler.shutdown();
+    }
+
+    /// This test verifies that, without a deadline, a task requested before the scheduler is
+    /// finalized, while the last transaction is executing, is answered with `None` once that
+    /// transaction completes.
+    #[test]
+    fn test_serial_scheduler_next_before_finalize() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler =
+            SerialScheduler::new(context_lifecycle, state_id).expect("Failed to create scheduler");
+
+        let batch = mock_batch_with_num_txns(1);
+        scheduler
+            .add_batch(batch.clone())
+            .expect("Failed to add batch");
+
+        let mut task_iterator = scheduler
+            .take_task_iterator()
+            .expect("Failed to get task iterator");
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+        let txn_id = task_iterator
+            .next()
+            .expect("Failed to get task")
+            .pair()
+            .transaction()
+            .header_signature()
+            .to_string();
+
+        // Ask for the next task while the transaction is executing and before finalizing
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("Thread-test_serial_scheduler_next_before_finalize".into())
+            .spawn(move || {
+                tx.send(task_iterator.next().is_none())
+                    .expect("Failed to send");
+            })
+            .expect("Failed to spawn thread");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        scheduler.finalize().expect("Failed to finalize");
+        notifier.notify(ExecutionTaskCompletionNotification::Valid(
+            mock_context_id(),
+            txn_id,
+        ));
+        assert!(rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .expect("Task iterator was not answered"));
+
+        scheduler.shutdown();
+    }
+
+    /// This test verifies that when a transaction is not executed before its deadline and the
+    /// deadline policy is `Retry`, the SerialScheduler returns the same transaction from its task
+    /// iterator again.
+    #[test]
+    fn test_serial_scheduler_deadline_retries_transaction() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = SerialSchedulerBuilder::new(context_lifecycle, state_id)
+            .with_execution_deadline(ExecutionDeadline::new(
+                std::time::Duration::from_millis(100),
+                DeadlinePolicy::Retry(1),
+            ))
+            .build()
+            .expect("Failed to create scheduler");
+
+        let (result_tx, result_rx) = mpsc::channel();
+        scheduler
+            .set_result_callback(Box::new(move |result| {
+                result_tx.send(result).expect("Failed to send result");
+            }))
+            .expect("Failed to set result callback");
+        let (error_tx, error_rx) = mpsc::channel();
+        scheduler
+            .set_error_callback(Box::new(move |err| {
+                error_tx.send(err).expect("Failed to send error");
+            }))
+            .expect("Failed to set error callback");
+
+        let batch = mock_batch_with_num_txns(1);
+        scheduler
+            .add_batch(batch.clone())
+            .expect("Failed to add batch");
+        scheduler.finalize().expect("Failed to finalize");
+
+        let mut task_iterator = scheduler
+            .take_task_iterator()
+            .expect("Failed to get task iterator");
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+
+        let first_txn_id = task_iterator
+            .next()
+            .expect("Failed to get 1st task")
+            .pair()
+            .transaction()
+            .header_signature()
+            .to_string();
+
+        // Once the deadline expires, the same transaction is returned again
+        let retried_txn_id = task_iterator
+            .next()
+            .expect("Failed to get retried task")
+            .pair()
+            .transaction()
+            .header_signature()
+            .to_string();
+        assert_eq!(first_txn_id, retried_txn_id);
+
+        match error_rx.recv().expect("Failed to receive error") {
+            SchedulerError::ExecutionDeadlineExceeded(txn_id) => assert_eq!(txn_id, first_txn_id),
+            err => panic!("Received unexpected error: {}", err),
+        }
+
+        notifier.notify(ExecutionTaskCompletionNotification::Valid(
+            mock_context_id(),
+            retried_txn_id,
+        ));
+        let result = result_rx.recv().expect("Failed to receive result");
+        assert_eq!(result, valid_receipt_from_batch(batch));
+        assert!(task_iterator.next().is_none());
+
+        scheduler.shutdown();
+    }
+
+    /// Tests that batches are queued according to the configured priority function, with batches
+    /// of equal priority kept in the order they were added.
+    #[test]
+    fn test_serial_scheduler_batch_priority() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = SerialSchedulerBuilder::new(context_lifecycle, state_id)
+            .with_batch_priority(Box::new(|batch| batch.batch().transactions().len() as i32))
+            .build()
+            .expect("Failed to create scheduler");
+
+        let batch1 = mock_batch_with_num_txns(1);
+        let batch2 = mock_batch_with_num_txns(3);
+        let batch3 = mock_batch_with_num_txns(2);
+        let batch4 = mock_batch(mock_transactions(4).split_off(1));
+        for batch in &[&batch1, &batch2, &batch3, &batch4] {
+            scheduler
+                .add_batch((*batch).clone())
+                .expect("Failed to add batch");
+        }
+
+        let mut shared = scheduler
+            .shared_lock
+            .lock()
+            .expect("shared lock is poisoned");
+        assert_eq!(shared.pop_unscheduled_batch(), Some((batch2, None)));
+        assert_eq!(shared.pop_unscheduled_batch(), Some((batch4, None)));
+        assert_eq!(shared.pop_unscheduled_batch(), Some((batch3, None)));
+        assert_eq!(shared.pop_unscheduled_batch(), Some((batch1, None)));
+        drop(shared);
+
+        scheduler.shutdown();
+    }
+
+    /// Tests that pending batches can be reprioritized and withdrawn by ID, and that batches which
+    /// are not pending are reported as such.
+    #[test]
+    fn test_serial_scheduler_reprioritize_and_withdraw() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler =
+            SerialScheduler::new(context_lifecycle, state_id).expect("Failed to create scheduler");
+
+        let batches = mock_batches_with_one_transaction(3);
+        for batch in &batches {
+            scheduler
+                .add_batch(batch.clone())
+                .expect("Failed to add batch");
+        }
+
+        assert!(scheduler
+            .set_batch_priority(batches[2].batch().header_signature(), 1)
+            .expect("Failed to set batch priority"));
+        assert_eq!(
+            scheduler
+                .withdraw_batch(batches[1].batch().header_signature())
+                .expect("Failed to withdraw batch"),
+            Some(batches[1].clone())
+        );
+        assert!(!scheduler
+            .set_batch_priority(batches[1].batch().header_signature(), 1)
+            .expect("Failed to set batch priority"));
+        assert_eq!(
+            scheduler
+                .withdraw_batch(batches[1].batch().header_signature())
+                .expect("Failed to withdraw batch"),
+            None
+        );
+
+        let mut shared = scheduler
+            .shared_lock
+            .lock()
+            .expect("shared lock is poisoned");
+        assert_eq!(
+            shared.pop_unscheduled_batch(),
+            Some((batches[2].clone(), None))
+        );
+        assert_eq!(
+            shared.pop_unscheduled_batch(),
+            Some((batches[0].clone(), None))
+        );
+        assert_eq!(shared.pop_unscheduled_batch(), None);
+        drop(shared);
+
+        scheduler.shutdown();
+    }
+
+    /// Tests that batches withdrawn or cancelled before they were scheduled are released from the
+    /// batch validator, so that resubmitting them is not rejected as a duplicate.
+    #[test]
+    fn test_serial_scheduler_resubmit_withdrawn_batch() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = SerialSchedulerBuilder::new(context_lifecycle, state_id)
+            .with_batch_validator(
+                BatchValidator::new().with_rule(Box::new(DuplicateTransactionRule::new())),
+            )
+            .build()
+            .expect("Failed to create scheduler");
+
+        let batches = mock_batches_with_one_transaction(2);
+        for batch in &batches {
+            scheduler
+                .add_batch(batch.clone())
+                .expect("Failed to add batch");
+        }
+        assert_eq!(
+            scheduler
+                .withdraw_batch(batches[0].batch().header_signature())
+                .expect("Failed to withdraw batch"),
+            Some(batches[0].clone())
+        );
+        assert_eq!(
+            scheduler.cancel().expect("Failed to cancel"),
+            vec![batches[1].clone()]
+        );
+
+        for batch in &batches {
+            scheduler
+                .add_batch(batch.clone())
+                .expect("Failed to add batch");
+        }
+
+        let mut shared = scheduler
+            .shared_lock
+            .lock()
+            .expect("shared lock is poisoned");
+        assert_eq!(
+            shared.pop_unscheduled_batch(),
+            Some((batches[0].clone(), None))
+        );
+        assert_eq!(
+            shared.pop_unscheduled_batch(),
+            Some((batches[1].clone(), None))
+        );
+        drop(shared);
+
+        scheduler.shutdown();
+    }
+
+    /// Tests that a batch which fails validation is not executed, and that an invalid result
+    /// naming the failed rule is produced in order, after the results of the batches added before
+    /// it.
+    #[test]
+    fn test_serial_scheduler_rejects_invalid_batch() {
+        let state_id = String::from("state0");
+        let context_lifecycle = Box::new(MockContextLifecycle::new());
+        let mut scheduler = SerialSchedulerBuilder::new(context_lifecycle, state_id)
+            .with_batch_validator(
+                BatchValidator::new().with_rule(Box::new(MaxTransactionsRule::new(1))),
+            )
+            .build()
+            .expect("Failed to create scheduler");
+
+        let (result_tx, result_rx) = mpsc::channel();
+        scheduler
+            .set_result_callback(Box::new(move |result| {
+                result_tx.send(result).expect("Failed to send result");
+            }))
+            .expect("Failed to set result callback");
+
+        let valid_batch = mock_batch_with_num_txns(1);
+        scheduler
+            .add_batch(valid_batch.clone())
+            .expect("Failed to add batch");
+        let invalid_batch = mock_batch_with_num_txns(2);
+        scheduler
+            .add_batch(invalid_batch.clone())
+            .expect("Failed to add batch");
+        scheduler.finalize().expect("Failed to finalize");
+
+        let mut task_iterator = scheduler
+            .take_task_iterator()
+            .expect("Failed to get task iterator");
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+        let task = task_iterator.next().expect("Failed to get task");
+        assert_eq!(
+            task.pair().transaction(),
+            &valid_batch.batch().transactions()[0]
+        );
+        // The rejected batch's result must wait for the valid batch's
+        assert!(result_rx.try_recv().is_err());
+        notifier.notify(ExecutionTaskCompletionNotification::Valid(
+            mock_context_id(),
+            task.pair().transaction().header_signature().into(),
+        ));
+        assert!(task_iterator.next().is_none());
+
+        let result = result_rx
+            .recv()
+            .expect("Failed to receive result")
+            .expect("Received None result");
+        assert_eq!(result.batch, valid_batch);
+
+        let result = result_rx
+            .recv()
+            .expect("Failed to receive result")
+            .expect("Received None result");
+        assert_eq!(result.batch, invalid_batch);
+        assert_eq!(result.receipts.len(), 2);
+        for receipt in result.receipts {
+            match receipt.transaction_result {
+                TransactionResult::Invalid { error_message, .. } => {
+                    assert!(error_message.contains("max_transactions"))
+                }
+                res => panic!("Unexpected result: {:?}", res),
+            }
+        }
+
+        assert!(result_rx
+            .recv()
+            .expect("Failed to receive result")
+            .is_none());
+
+        scheduler.shutdown();
+    }
+
+    /// A `MockContextLifecycle` which records the contexts dropped.
+    #[derive(Clone, Default)]
+    struct DropRecordingContextLifecycle {
+        dropped: Arc<Mutex<Vec<ContextId>>>,
+    }
+
+    impl ContextLifecycle for DropRecordingContextLifecycle {
+        fn create_context(
+            &mut self,
+            dependent_contexts: &[ContextId],
+            state_id: &str,
+        ) -> ContextId {
+            MockContextLifecycle::new().create_context(dependent_contexts, state_id)
+        }
+
+        fn get_transaction_receipt(
+            &self,
+            context_id: &ContextId,
+            transaction_id: &str,
+        ) -> Result<TransactionReceipt, ContextManagerError> {
+            MockContextLifecycle::new().get_transaction_receipt(context_id, transaction_id)
+        }
+
+        fn drop_context(&mut self, context_id: ContextId) {
+            self.dropped.lock().unwrap().push(context_id);
+        }
+    }
 }
@@ -24,27 +24,48 @@ use crate::scheduler::{default_error_callback, default_result_callback};
 
 use std::collections::VecDeque;
 
+use super::BatchPriorityFn;
+use crate::scheduler::validation::{BatchValidationError, BatchValidator};
+
+/// A batch waiting to be scheduled, along with its priority and, if it failed validation, the
+/// error to report in its place.
+struct UnscheduledBatch {
+    batch: BatchPair,
+    priority: i32,
+    rejection: Option<BatchValidationError>,
+}
+
 /// Stores all serial scheduler data which is shared between threads.
 pub struct Shared {
     finalized: bool,
     result_callback: Box<dyn Fn(Option<BatchExecutionResult>) + Send>,
     error_callback: Box<dyn Fn(SchedulerError) + Send>,
-    unscheduled_batches: VecDeque<BatchPair>,
+    /// Batches waiting to be scheduled, ordered from highest to lowest priority; batches with
+    /// equal priority are kept in the order they were added. Rejected batches are queued too, so
+    /// that their results are reported in order.
+    unscheduled_batches: VecDeque<UnscheduledBatch>,
+    batch_priority: Option<BatchPriorityFn>,
+    batch_validator: Option<BatchValidator>,
 }
 
 impl Default for Shared {
     fn default() -> Self {
-        Self::new()
+        Self::new(None, None)
     }
 }
 
 impl Shared {
-    pub fn new() -> Self {
+    pub fn new(
+        batch_priority: Option<BatchPriorityFn>,
+        batch_validator: Option<BatchValidator>,
+    ) -> Self {
         Shared {
             finalized: false,
             result_callback: Box::new(default_result_callback),
             error_callback: Box::new(default_error_callback),
             unscheduled_batches: VecDeque::new(),
+            batch_priority,
+            batch_validator,
         }
     }
 
@@ -76,22 +97,123 @@ impl Shared {
     }
 
     pub fn batch_already_queued(&self, batch: &BatchPair) -> bool {
-        self.unscheduled_batches.contains(batch)
+        self.unscheduled_batches
+            .iter()
+            .any(|unscheduled| &unscheduled.batch == batch)
     }
 
     pub fn unscheduled_batches_is_empty(&self) -> bool {
         self.unscheduled_batches.is_empty()
     }
 
+    pub fn validate_batch(&mut self, batch: &BatchPair) -> Result<(), BatchValidationError> {
+        match self.batch_validator {
+            Some(ref mut validator) => validator.validate(batch),
+            None => Ok(()),
+        }
+    }
+
     pub fn add_unscheduled_batch(&mut self, batch: BatchPair) {
-        self.unscheduled_batches.push_back(batch);
+        self.queue_batch(batch, None);
+    }
+
+    /// Queues a batch which failed validation; its result is reported when it reaches the front
+    /// of the queue, instead of it being executed.
+    pub fn add_rejected_batch(&mut self, batch: BatchPair, error: BatchValidationError) {
+        self.queue_batch(batch, Some(error));
+    }
+
+    fn queue_batch(&mut self, batch: BatchPair, rejection: Option<BatchValidationError>) {
+        let priority = self
+            .batch_priority
+            .as_ref()
+            .map(|batch_priority| batch_priority(&batch))
+            .unwrap_or(0);
+        self.insert_unscheduled_batch(UnscheduledBatch {
+            batch,
+            priority,
+            rejection,
+        });
     }
 
+    /// Removes and returns the batches waiting to be executed, releasing them from the batch
+    /// validator; rejected batches are kept, so that their results are still reported.
     pub fn drain_unscheduled_batches(&mut self) -> Vec<BatchPair> {
-        self.unscheduled_batches.drain(0..).collect()
+        let (rejected, unscheduled) = self
+            .unscheduled_batches
+            .drain(0..)
+            .partition::<VecDeque<_>, _>(|unscheduled| unscheduled.rejection.is_some());
+        self.unscheduled_batches = rejected;
+        unscheduled
+            .into_iter()
+            .map(|unscheduled| {
+                self.release_batch(&unscheduled);
+                unscheduled.batch
+            })
+            .collect()
+    }
+
+    /// Removes and returns the batch at the front of the queue, along with the error it was
+    /// rejected with, if any.
+    pub fn pop_unscheduled_batch(&mut self) -> Option<(BatchPair, Option<BatchValidationError>)> {
+        self.unscheduled_batches
+            .pop_front()
+            .map(|unscheduled| (unscheduled.batch, unscheduled.rejection))
+    }
+
+    /// Removes and returns the batch at the front of the queue if it was rejected.
+    pub fn pop_rejected_batch(&mut self) -> Option<(BatchPair, BatchValidationError)> {
+        match self.unscheduled_batches.front() {
+            Some(unscheduled) if unscheduled.rejection.is_some() => self
+                .pop_unscheduled_batch()
+                .and_then(|(batch, rejection)| rejection.map(|error| (batch, error))),
+            _ => None,
+        }
+    }
+
+    pub fn set_unscheduled_batch_priority(&mut self, batch_id: &str, priority: i32) -> bool {
+        match self.take_unscheduled_batch(batch_id) {
+            Some(mut unscheduled) => {
+                unscheduled.priority = priority;
+                self.insert_unscheduled_batch(unscheduled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes and returns the batch waiting to be executed with the given ID, releasing it from
+    /// the batch validator.
+    pub fn remove_unscheduled_batch(&mut self, batch_id: &str) -> Option<BatchPair> {
+        self.take_unscheduled_batch(batch_id).map(|unscheduled| {
+            self.release_batch(&unscheduled);
+            unscheduled.batch
+        })
+    }
+
+    /// Notifies the batch validator that a batch it accepted will not be executed; batches it
+    /// rejected were never accepted.
+    fn release_batch(&mut self, unscheduled: &UnscheduledBatch) {
+        if let (Some(validator), None) = (&mut self.batch_validator, &unscheduled.rejection) {
+            validator.release(&unscheduled.batch);
+        }
+    }
+
+    fn take_unscheduled_batch(&mut self, batch_id: &str) -> Option<UnscheduledBatch> {
+        let index = self
+            .unscheduled_batches
+            .iter()
+            .position(|unscheduled| unscheduled.batch.batch().header_signature() == batch_id)?;
+        self.unscheduled_batches.remove(index)
     }
 
-    pub fn pop_unscheduled_batch(&mut self) -> Option<BatchPair> {
-        self.unscheduled_batches.pop_front()
+    /// Inserts the batch after all batches with the same or a higher priority.
+    fn insert_unscheduled_batch(&mut self, unscheduled: UnscheduledBatch) {
+        let index = self
+            .unscheduled_batches
+            .iter()
+            .position(|queued| queued.priority < unscheduled.priority)
+            .unwrap_or_else(|| self.unscheduled_batches.len());
+        self.unscheduled_batches.insert(index, unscheduled);
     }
 }
@@ -0,0 +1,464 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Dry-run execution of transactions and batches.
+//!
+//! A `Simulator` executes batches on top of a state root, using a `SerialScheduler` (or several of
+//! them, through a `MultiScheduler`) and the given `Executor`, and returns their results along
+//! with the state root that committing them would produce. Nothing is committed to state, and
+//! every context created while executing the batches is dropped once they have been executed.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::context::manager::sync::ContextManager;
+use crate::context::manager::ContextManagerError;
+use crate::context::{ContextId, ContextLifecycle};
+use crate::execution::executor::{Executor, ExecutorError};
+use crate::protocol::batch::{BatchBuildError, BatchBuilder, BatchPair};
+use crate::protocol::receipt::{TransactionReceipt, TransactionResult};
+use crate::protocol::transaction::TransactionPair;
+use crate::scheduler::multi::{MultiScheduler, SubSchedulerHandler};
+use crate::scheduler::serial::SerialScheduler;
+use crate::scheduler::{
+    BatchExecutionResult, ExecutionTask, ExecutionTaskCompletionNotifier, Scheduler, SchedulerError,
+};
+use crate::signing::hash::HashSigner;
+use crate::state::{self, StateWriteError, Write};
+
+/// The results of simulating a list of batches.
+#[derive(Clone, Debug)]
+pub struct SimulationResult {
+    /// The result of each batch, in the order the batches were given.
+    pub batch_results: Vec<BatchExecutionResult>,
+    /// The state root that committing the state changes of the valid batches would produce.
+    pub state_root: String,
+}
+
+impl SimulationResult {
+    /// Returns true if every transaction of every batch was valid.
+    pub fn is_valid(&self) -> bool {
+        self.batch_results
+            .iter()
+            .all(|result| result.receipts.iter().all(is_valid))
+    }
+
+    /// Returns the state changes of the valid batches, in order; these are the changes which
+    /// produce `state_root`.
+    pub fn state_changes(&self) -> Vec<state::StateChange> {
+        self.batch_results
+            .iter()
+            .filter(|result| result.receipts.iter().all(is_valid))
+            .flat_map(|result| result.receipts.iter())
+            .flat_map(|receipt| match &receipt.transaction_result {
+                TransactionResult::Valid { state_changes, .. } => state_changes.clone(),
+                TransactionResult::Invalid { .. } => vec![],
+            })
+            .map(|state_change| state_change.into())
+            .collect()
+    }
+}
+
+/// Returns true if the receipt is of a valid transaction.
+pub(crate) fn is_valid(receipt: &TransactionReceipt) -> bool {
+    match receipt.transaction_result {
+        TransactionResult::Valid { .. } => true,
+        TransactionResult::Invalid { .. } => false,
+    }
+}
+
+#[derive(Debug)]
+pub enum SimulationError {
+    /// A transaction could not be wrapped in a batch.
+    BatchBuildError(BatchBuildError),
+    /// The executor could not execute the batches.
+    ExecutorError(ExecutorError),
+    /// The scheduler returned an error while executing the batches.
+    SchedulerError(SchedulerError),
+    /// The state root of the batches' state changes could not be computed.
+    StateWriteError(StateWriteError),
+    /// The scheduler stopped returning results before all batches were executed.
+    Incomplete(String),
+}
+
+impl Error for SimulationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SimulationError::BatchBuildError(err) => Some(err),
+            SimulationError::ExecutorError(err) => Some(err),
+            SimulationError::SchedulerError(err) => Some(err),
+            SimulationError::StateWriteError(err) => Some(err),
+            SimulationError::Incomplete(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulationError::BatchBuildError(err) => {
+                write!(f, "unable to build simulation batch: {}", err)
+            }
+            SimulationError::ExecutorError(err) => write!(f, "unable to execute batches: {}", err),
+            SimulationError::SchedulerError(err) => write!(f, "scheduler error: {}", err),
+            SimulationError::StateWriteError(err) => {
+                write!(f, "unable to compute state root: {}", err)
+            }
+            SimulationError::Incomplete(msg) => write!(f, "simulation did not complete: {}", msg),
+        }
+    }
+}
+
+impl From<BatchBuildError> for SimulationError {
+    fn from(err: BatchBuildError) -> Self {
+        SimulationError::BatchBuildError(err)
+    }
+}
+
+impl From<ExecutorError> for SimulationError {
+    fn from(err: ExecutorError) -> Self {
+        SimulationError::ExecutorError(err)
+    }
+}
+
+impl From<SchedulerError> for SimulationError {
+    fn from(err: SchedulerError) -> Self {
+        SimulationError::SchedulerError(err)
+    }
+}
+
+impl From<StateWriteError> for SimulationError {
+    fn from(err: StateWriteError) -> Self {
+        SimulationError::StateWriteError(err)
+    }
+}
+
+/// The scheduler a `Simulator` executes batches with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchedulerKind {
+    /// A single `SerialScheduler`.
+    Serial,
+    /// A `MultiScheduler` running the given number of `SerialScheduler`s, whose results must
+    /// agree; at least one is always run.
+    Multi(usize),
+}
+
+impl Default for SchedulerKind {
+    fn default() -> Self {
+        SchedulerKind::Serial
+    }
+}
+
+/// Executes batches without committing their results.
+///
+/// The executor must already be started, and its adapters must use the given context manager.
+pub struct Simulator<'a, W> {
+    executor: &'a Executor,
+    context_manager: ContextManager,
+    state: W,
+    scheduler_kind: SchedulerKind,
+}
+
+impl<'a, W> Simulator<'a, W>
+where
+    W: Write<StateId = String, Key = String, Value = Vec<u8>>,
+{
+    pub fn new(executor: &'a Executor, context_manager: ContextManager, state: W) -> Self {
+        Simulator {
+            executor,
+            context_manager,
+            state,
+            scheduler_kind: SchedulerKind::default(),
+        }
+    }
+
+    /// Executes batches with the given kind of scheduler, instead of a single `SerialScheduler`.
+    pub fn with_scheduler_kind(mut self, scheduler_kind: SchedulerKind) -> Self {
+        self.scheduler_kind = scheduler_kind;
+        self
+    }
+
+    /// Simulates the given transactions on top of `state_root`, each in a batch of its own, so
+    /// that an invalid transaction does not invalidate the others.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SimulationError` if the scheduler or executor fail before all of the
+    /// transactions have been executed, or if the resulting state root cannot be computed.
+    pub fn simulate_transactions(
+        &self,
+        state_root: &str,
+        transactions: Vec<TransactionPair>,
+    ) -> Result<SimulationResult, SimulationError> {
+        let signer = HashSigner::default();
+        let batches = transactions
+            .into_iter()
+            .map(|transaction| {
+                BatchBuilder::new()
+                    .with_transactions(vec![transaction.take().0])
+                    .build_pair(&signer)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.simulate_batches(state_root, batches)
+    }
+
+    /// Simulates the given batches on top of `state_root`, in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SimulationError` if the scheduler or executor fail before all of the batches
+    /// have been executed, or if the resulting state root cannot be computed.
+    pub fn simulate_batches(
+        &self,
+        state_root: &str,
+        batches: Vec<BatchPair>,
+    ) -> Result<SimulationResult, SimulationError> {
+        let created_contexts = Arc::new(Mutex::new(vec![]));
+        let context_lifecycle = TrackingContextLifecycle {
+            context_manager: self.context_manager.clone(),
+            created_contexts: Arc::clone(&created_contexts),
+        };
+
+        let batch_results = self.execute(state_root, &batches, context_lifecycle);
+
+        let mut context_manager = self.context_manager.clone();
+        for context_id in created_contexts
+            .lock()
+            .map(|mut contexts| std::mem::replace(&mut *contexts, vec![]))
+            .unwrap_or_default()
+        {
+            context_manager.drop_context(context_id);
+        }
+
+        let mut result = SimulationResult {
+            batch_results: batch_results?,
+            state_root: state_root.into(),
+        };
+        result.state_root = self
+            .state
+            .compute_state_id(&result.state_root, &result.state_changes())?;
+
+        Ok(result)
+    }
+
+    /// Executes the batches, returning their results in the order the batches were given.
+    fn execute(
+        &self,
+        state_root: &str,
+        batches: &[BatchPair],
+        context_lifecycle: TrackingContextLifecycle,
+    ) -> Result<Vec<BatchExecutionResult>, SimulationError> {
+        let results = match self.scheduler_kind {
+            SchedulerKind::Serial => {
+                let mut scheduler =
+                    SerialScheduler::new(Box::new(context_lifecycle), state_root.into())?;
+                let results = self.run(&mut scheduler, batches);
+                scheduler.shutdown();
+                results
+            }
+            SchedulerKind::Multi(count) => {
+                let schedulers = (0..count.max(1))
+                    .map(|_| {
+                        SerialScheduler::new(Box::new(context_lifecycle.clone()), state_root.into())
+                            .map(|scheduler| Box::new(scheduler) as Box<dyn Scheduler + Send>)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut scheduler =
+                    MultiScheduler::new(schedulers, &mut ExecutorHandler(self.executor))?;
+                let results = self.run(&mut scheduler, batches);
+                scheduler.shutdown();
+                results
+            }
+        };
+
+        let mut results = results?;
+        results.sort_by_key(|result| {
+            batches.iter().position(|batch| {
+                batch.batch().header_signature() == result.batch.batch().header_signature()
+            })
+        });
+        Ok(results)
+    }
+
+    fn run(
+        &self,
+        scheduler: &mut dyn Scheduler,
+        batches: &[BatchPair],
+    ) -> Result<Vec<BatchExecutionResult>, SimulationError> {
+        let (result_tx, result_rx) = mpsc::channel();
+        let error_tx = result_tx.clone();
+        scheduler.set_result_callback(Box::new(move |result| {
+            result_tx
+                .send(Ok(result))
+                .unwrap_or_else(|err| error!("Unable to send simulation result: {}", err));
+        }))?;
+        scheduler.set_error_callback(Box::new(move |err| {
+            error_tx
+                .send(Err(err))
+                .unwrap_or_else(|err| error!("Unable to send simulation error: {}", err));
+        }))?;
+
+        for batch in batches {
+            scheduler.add_batch(batch.clone())?;
+        }
+        scheduler.finalize()?;
+
+        self.executor
+            .execute(scheduler.take_task_iterator()?, scheduler.new_notifier()?)?;
+
+        let mut results = Vec::with_capacity(batches.len());
+        while results.len() < batches.len() {
+            match result_rx.recv() {
+                Ok(Ok(Some(result))) => results.push(result),
+                Ok(Ok(None)) => {
+                    return Err(SimulationError::Incomplete(
+                        "scheduler finished before all batches were executed".into(),
+                    ))
+                }
+                Ok(Err(err)) => return Err(SimulationError::SchedulerError(err)),
+                Err(_) => return Err(SimulationError::Incomplete("scheduler disconnected".into())),
+            }
+        }
+        Ok(results)
+    }
+}
+
+/// Passes the sub-schedulers of a `MultiScheduler` to the executor.
+struct ExecutorHandler<'a>(&'a Executor);
+
+impl<'a> SubSchedulerHandler for ExecutorHandler<'a> {
+    fn pass_scheduler(
+        &mut self,
+        task_iterator: Box<dyn Iterator<Item = ExecutionTask> + Send>,
+        notifier: Box<dyn ExecutionTaskCompletionNotifier>,
+    ) -> Result<(), String> {
+        self.0
+            .execute(task_iterator, notifier)
+            .map_err(|err| format!("{}", err))
+    }
+}
+
+/// A `ContextLifecycle` which records the contexts it creates, so they can be dropped once the
+/// simulation is done.
+#[derive(Clone)]
+struct TrackingContextLifecycle {
+    context_manager: ContextManager,
+    created_contexts: Arc<Mutex<Vec<ContextId>>>,
+}
+
+impl ContextLifecycle for TrackingContextLifecycle {
+    fn create_context(&mut self, dependent_contexts: &[ContextId], state_id: &str) -> ContextId {
+        let context_id = self
+            .context_manager
+            .create_context(dependent_contexts, state_id);
+        match self.created_contexts.lock() {
+            Ok(mut created_contexts) => created_contexts.push(context_id),
+            Err(_) => error!("Unable to record simulation context; lock poisoned"),
+        }
+        context_id
+    }
+
+    fn drop_context(&mut self, context_id: ContextId) {
+        self.context_manager.drop_context(context_id)
+    }
+
+    fn get_transaction_receipt(
+        &self,
+        context_id: &ContextId,
+        transaction_id: &str,
+    ) -> Result<TransactionReceipt, ContextManagerError> {
+        self.context_manager
+            .get_transaction_receipt(context_id, transaction_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::execution::adapter::static_adapter::StaticExecutionAdapter;
+    use crate::protocol::command::{BytesEntry, Command, ReturnInvalid, SetState};
+    use crate::state::hashmap::HashMapState;
+    use crate::state::Read;
+    use crate::workload::command::{make_command_transaction, CommandTransactionHandler};
+
+    fn set_transaction(key: &str, value: &[u8]) -> TransactionPair {
+        make_command_transaction(&[Command::SetState(SetState::new(vec![BytesEntry::new(
+            key.into(),
+            value.to_vec(),
+        )]))])
+    }
+
+    /// Simulate a valid and an invalid transaction, and verify that their receipts are returned
+    /// in order with the state root of the valid transaction's changes, and that state was not
+    /// committed.
+    #[test]
+    fn simulate_transactions() {
+        let state = HashMapState::new();
+        let state_root = HashMapState::state_id(&HashMap::new());
+        let context_manager = ContextManager::new(Box::new(state.clone()));
+
+        let mut executor = Executor::new(vec![Box::new(
+            StaticExecutionAdapter::new_adapter(
+                vec![Box::new(CommandTransactionHandler::new())],
+                context_manager.clone(),
+            )
+            .expect("Unable to create adapter"),
+        )]);
+        executor.start().expect("Unable to start executor");
+
+        let valid = set_transaction("abc", b"abc");
+        let invalid = make_command_transaction(&[
+            Command::SetState(SetState::new(vec![BytesEntry::new(
+                "def".into(),
+                b"def".to_vec(),
+            )])),
+            Command::ReturnInvalid(ReturnInvalid::new("invalid".into())),
+        ]);
+
+        let simulator = Simulator::new(&executor, context_manager.clone(), state.clone());
+        let result = simulator
+            .simulate_transactions(&state_root, vec![invalid.clone(), valid.clone()])
+            .expect("Simulation failed");
+
+        executor.stop();
+
+        assert!(!result.is_valid());
+        assert_eq!(result.batch_results.len(), 2);
+        assert_eq!(
+            result.batch_results[0].receipts[0].transaction_id,
+            invalid.transaction().header_signature()
+        );
+        assert!(!is_valid(&result.batch_results[0].receipts[0]));
+        assert_eq!(
+            result.batch_results[1].receipts[0].transaction_id,
+            valid.transaction().header_signature()
+        );
+        assert!(is_valid(&result.batch_results[1].receipts[0]));
+
+        let mut expected_state = HashMap::new();
+        expected_state.insert("abc".to_string(), b"abc".to_vec());
+        assert_eq!(result.state_root, HashMapState::state_id(&expected_state));
+
+        // Nothing was committed
+        assert!(state.get(&result.state_root, &["abc".to_string()]).is_err());
+    }
+}
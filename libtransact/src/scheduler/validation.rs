@@ -0,0 +1,568 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Validation of batches before they are scheduled.
+//!
+//! A `BatchValidator` combines any number of `BatchValidationRule`s; schedulers which are
+//! configured with a validator check each batch as it is added, and produce an invalid result,
+//! in place of executing it, for any batch which fails a rule.
+
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+
+use crate::execution::TransactionFamily;
+use crate::protocol::batch::BatchPair;
+use crate::protocol::receipt::{TransactionReceipt, TransactionResult};
+use crate::protocol::transaction::TransactionHeader;
+use crate::protos::FromBytes;
+use crate::scheduler::BatchExecutionResult;
+
+/// A check which a batch must pass before it is scheduled.
+pub trait BatchValidationRule: Send {
+    /// The name of the rule, which is included in the results of rejected batches.
+    fn name(&self) -> &str;
+
+    /// Checks the batch, returning the reason it was rejected if the batch does not satisfy the
+    /// rule.
+    fn validate(&self, batch: &BatchPair) -> Result<(), String>;
+
+    /// Called once the batch has passed all of the validator's rules and will be scheduled.
+    fn batch_accepted(&mut self, _batch: &BatchPair) {}
+
+    /// Called when an accepted batch is withdrawn or cancelled before it was scheduled, and so
+    /// will not be executed.
+    fn batch_released(&mut self, _batch: &BatchPair) {}
+}
+
+/// The error returned when a batch fails one of a `BatchValidator`'s rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchValidationError {
+    rule: String,
+    reason: String,
+}
+
+impl BatchValidationError {
+    pub fn new(rule: String, reason: String) -> Self {
+        BatchValidationError { rule, reason }
+    }
+
+    /// The name of the rule which the batch failed.
+    pub fn rule(&self) -> &str {
+        &self.rule
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl Error for BatchValidationError {}
+
+impl std::fmt::Display for BatchValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "batch failed validation rule '{}': {}",
+            self.rule, self.reason
+        )
+    }
+}
+
+/// Checks batches against a list of rules, in the order the rules were added.
+#[derive(Default)]
+pub struct BatchValidator {
+    rules: Vec<Box<dyn BatchValidationRule>>,
+}
+
+impl BatchValidator {
+    pub fn new() -> Self {
+        BatchValidator::default()
+    }
+
+    /// Adds a rule which will be checked after all previously added rules.
+    pub fn with_rule(mut self, rule: Box<dyn BatchValidationRule>) -> BatchValidator {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Checks the batch against each rule, stopping at the first rule the batch fails. If the
+    /// batch passes every rule, all rules are notified that it has been accepted.
+    pub fn validate(&mut self, batch: &BatchPair) -> Result<(), BatchValidationError> {
+        for rule in &self.rules {
+            rule.validate(batch)
+                .map_err(|reason| BatchValidationError::new(rule.name().into(), reason))?;
+        }
+        for rule in &mut self.rules {
+            rule.batch_accepted(batch);
+        }
+        Ok(())
+    }
+
+    /// Notifies all rules that an accepted batch was withdrawn or cancelled before it was
+    /// scheduled.
+    pub fn release(&mut self, batch: &BatchPair) {
+        for rule in &mut self.rules {
+            rule.batch_released(batch);
+        }
+    }
+}
+
+/// Builds the result for a batch which was rejected by a `BatchValidator`; every transaction in
+/// the batch is marked invalid with the validation error.
+pub(crate) fn rejected_batch_result(
+    batch: BatchPair,
+    error: &BatchValidationError,
+) -> BatchExecutionResult {
+    let receipts = batch
+        .batch()
+        .transactions()
+        .iter()
+        .map(|transaction| TransactionReceipt {
+            transaction_id: transaction.header_signature().into(),
+            transaction_result: TransactionResult::Invalid {
+                error_message: error.to_string(),
+                error_data: vec![],
+            },
+        })
+        .collect();
+
+    BatchExecutionResult { batch, receipts }
+}
+
+fn transaction_headers(batch: &BatchPair) -> Result<Vec<TransactionHeader>, String> {
+    batch
+        .batch()
+        .transactions()
+        .iter()
+        .map(|transaction| {
+            TransactionHeader::from_bytes(transaction.header()).map_err(|err| {
+                format!(
+                    "header of transaction {} is ill-formed: {}",
+                    transaction.header_signature(),
+                    err
+                )
+            })
+        })
+        .collect()
+}
+
+/// Requires that the batch header lists exactly the IDs of the batch's transactions, in order,
+/// and that each transaction's header can be parsed.
+#[derive(Default)]
+pub struct TransactionIdConsistencyRule;
+
+impl TransactionIdConsistencyRule {
+    pub fn new() -> Self {
+        TransactionIdConsistencyRule
+    }
+}
+
+impl BatchValidationRule for TransactionIdConsistencyRule {
+    fn name(&self) -> &str {
+        "transaction_id_consistency"
+    }
+
+    fn validate(&self, batch: &BatchPair) -> Result<(), String> {
+        let header_ids = batch.header().transaction_ids();
+        let transactions = batch.batch().transactions();
+
+        if header_ids.len() != transactions.len() {
+            return Err(format!(
+                "batch header lists {} transaction IDs but batch contains {} transactions",
+                header_ids.len(),
+                transactions.len()
+            ));
+        }
+
+        for (header_id, transaction) in header_ids.iter().zip(transactions) {
+            if hex::encode(header_id) != transaction.header_signature() {
+                return Err(format!(
+                    "transaction {} does not match the batch header's transaction ID {}",
+                    transaction.header_signature(),
+                    hex::encode(header_id)
+                ));
+            }
+        }
+
+        transaction_headers(batch).map(|_| ())
+    }
+}
+
+/// The number of accepted transaction IDs a `DuplicateTransactionRule` retains by default.
+pub const DEFAULT_DUPLICATE_WINDOW: usize = 65_536;
+
+/// Rejects batches containing the same transaction more than once, or containing a transaction
+/// which was part of a recently accepted batch.
+///
+/// Only the IDs of the most recently accepted transactions, up to the rule's window, are retained;
+/// once a transaction's ID has been evicted from the window, a batch repeating it is no longer
+/// rejected.
+pub struct DuplicateTransactionRule {
+    window: usize,
+    accepted_ids: HashSet<String>,
+    // The accepted IDs, oldest first, so that the oldest can be evicted
+    accepted_order: VecDeque<String>,
+}
+
+impl Default for DuplicateTransactionRule {
+    fn default() -> Self {
+        Self::with_window(DEFAULT_DUPLICATE_WINDOW)
+    }
+}
+
+impl DuplicateTransactionRule {
+    /// Creates a rule which retains the IDs of the last `DEFAULT_DUPLICATE_WINDOW` accepted
+    /// transactions.
+    pub fn new() -> Self {
+        DuplicateTransactionRule::default()
+    }
+
+    /// Creates a rule which retains the IDs of the last `window` accepted transactions.
+    pub fn with_window(window: usize) -> Self {
+        DuplicateTransactionRule {
+            window,
+            accepted_ids: HashSet::new(),
+            accepted_order: VecDeque::new(),
+        }
+    }
+}
+
+impl BatchValidationRule for DuplicateTransactionRule {
+    fn name(&self) -> &str {
+        "duplicate_transactions"
+    }
+
+    fn validate(&self, batch: &BatchPair) -> Result<(), String> {
+        let mut batch_ids = HashSet::new();
+        for transaction in batch.batch().transactions() {
+            let id = transaction.header_signature();
+            if !batch_ids.insert(id) {
+                return Err(format!("transaction {} appears more than once", id));
+            }
+            if self.accepted_ids.contains(id) {
+                return Err(format!(
+                    "transaction {} was already part of another batch",
+                    id
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn batch_accepted(&mut self, batch: &BatchPair) {
+        for transaction in batch.batch().transactions() {
+            let id = transaction.header_signature().to_string();
+            if self.accepted_ids.insert(id.clone()) {
+                self.accepted_order.push_back(id);
+            }
+        }
+        while self.accepted_order.len() > self.window {
+            if let Some(id) = self.accepted_order.pop_front() {
+                self.accepted_ids.remove(&id);
+            }
+        }
+    }
+
+    /// Forgets the batch's transactions, so that they may be submitted again.
+    fn batch_released(&mut self, batch: &BatchPair) {
+        for transaction in batch.batch().transactions() {
+            self.accepted_ids.remove(transaction.header_signature());
+        }
+        let accepted_ids = &self.accepted_ids;
+        self.accepted_order.retain(|id| accepted_ids.contains(id));
+    }
+}
+
+/// Limits the number of transactions in a batch.
+pub struct MaxTransactionsRule {
+    max_transactions: usize,
+}
+
+impl MaxTransactionsRule {
+    pub fn new(max_transactions: usize) -> Self {
+        MaxTransactionsRule { max_transactions }
+    }
+}
+
+impl BatchValidationRule for MaxTransactionsRule {
+    fn name(&self) -> &str {
+        "max_transactions"
+    }
+
+    fn validate(&self, batch: &BatchPair) -> Result<(), String> {
+        let num_transactions = batch.batch().transactions().len();
+        if num_transactions > self.max_transactions {
+            return Err(format!(
+                "batch contains {} transactions; the maximum is {}",
+                num_transactions, self.max_transactions
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Limits the size, in bytes, of each transaction's payload.
+pub struct MaxPayloadSizeRule {
+    max_payload_size: usize,
+}
+
+impl MaxPayloadSizeRule {
+    pub fn new(max_payload_size: usize) -> Self {
+        MaxPayloadSizeRule { max_payload_size }
+    }
+}
+
+impl BatchValidationRule for MaxPayloadSizeRule {
+    fn name(&self) -> &str {
+        "max_payload_size"
+    }
+
+    fn validate(&self, batch: &BatchPair) -> Result<(), String> {
+        for transaction in batch.batch().transactions() {
+            if transaction.payload().len() > self.max_payload_size {
+                return Err(format!(
+                    "payload of transaction {} is {} bytes; the maximum is {}",
+                    transaction.header_signature(),
+                    transaction.payload().len(),
+                    self.max_payload_size
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Only allows transactions of the given families and versions.
+pub struct AllowedFamiliesRule {
+    families: HashSet<TransactionFamily>,
+}
+
+impl AllowedFamiliesRule {
+    pub fn new(families: Vec<TransactionFamily>) -> Self {
+        AllowedFamiliesRule {
+            families: families.into_iter().collect(),
+        }
+    }
+}
+
+impl BatchValidationRule for AllowedFamiliesRule {
+    fn name(&self) -> &str {
+        "allowed_families"
+    }
+
+    fn validate(&self, batch: &BatchPair) -> Result<(), String> {
+        let headers = transaction_headers(batch)?;
+        for (header, transaction) in headers.iter().zip(batch.batch().transactions()) {
+            let family =
+                TransactionFamily::new(header.family_name().into(), header.family_version().into());
+            if !self.families.contains(&family) {
+                return Err(format!(
+                    "transaction {} has family {} {}, which is not allowed",
+                    transaction.header_signature(),
+                    family.family_name(),
+                    family.family_version()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Only allows batches signed by one of the given public keys, containing transactions which are
+/// also signed by one of those keys.
+pub struct SignerAllowlistRule {
+    public_keys: HashSet<Vec<u8>>,
+}
+
+impl SignerAllowlistRule {
+    pub fn new(public_keys: Vec<Vec<u8>>) -> Self {
+        SignerAllowlistRule {
+            public_keys: public_keys.into_iter().collect(),
+        }
+    }
+}
+
+impl BatchValidationRule for SignerAllowlistRule {
+    fn name(&self) -> &str {
+        "signer_allowlist"
+    }
+
+    fn validate(&self, batch: &BatchPair) -> Result<(), String> {
+        if !self
+            .public_keys
+            .contains(batch.header().signer_public_key())
+        {
+            return Err(format!(
+                "batch signer {} is not allowed",
+                hex::encode(batch.header().signer_public_key())
+            ));
+        }
+
+        let headers = transaction_headers(batch)?;
+        for (header, transaction) in headers.iter().zip(batch.batch().transactions()) {
+            if !self.public_keys.contains(header.signer_public_key()) {
+                return Err(format!(
+                    "signer {} of transaction {} is not allowed",
+                    hex::encode(header.signer_public_key()),
+                    transaction.header_signature()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::protos::{self, IntoNative, IntoProto};
+    use crate::scheduler::tests::*;
+    use crate::signing::hash::HashSigner;
+
+    /// Tests that the rule a batch fails is reported, and that a batch rejected by a later rule is
+    /// not recorded as accepted by earlier rules.
+    #[test]
+    fn test_validator_reports_failed_rule() {
+        let mut validator = BatchValidator::new()
+            .with_rule(Box::new(DuplicateTransactionRule::new()))
+            .with_rule(Box::new(MaxTransactionsRule::new(2)));
+
+        let transactions = mock_transactions(3);
+        let too_large = mock_batch(transactions.clone());
+        let err = validator
+            .validate(&too_large)
+            .expect_err("Batch with too many transactions was accepted");
+        assert_eq!(err.rule(), "max_transactions");
+
+        let batch = mock_batch(transactions[..2].to_vec());
+        validator.validate(&batch).expect("Valid batch rejected");
+
+        let duplicate = mock_batch(transactions[1..].to_vec());
+        let err = validator
+            .validate(&duplicate)
+            .expect_err("Batch with a previously accepted transaction was accepted");
+        assert_eq!(err.rule(), "duplicate_transactions");
+
+        let result = rejected_batch_result(duplicate.clone(), &err);
+        assert_eq!(result.batch, duplicate);
+        assert_eq!(result.receipts.len(), 2);
+        match &result.receipts[0].transaction_result {
+            TransactionResult::Invalid { error_message, .. } => {
+                assert!(error_message.contains("duplicate_transactions"))
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    /// Tests that the duplicate transaction rule only retains the IDs of the transactions most
+    /// recently accepted, up to its window, and forgets those of released batches.
+    #[test]
+    fn test_duplicate_transaction_window() {
+        let mut rule = DuplicateTransactionRule::with_window(2);
+        let transactions = mock_transactions(3);
+        let batches = transactions
+            .iter()
+            .map(|transaction| mock_batch(vec![transaction.clone()]))
+            .collect::<Vec<_>>();
+
+        rule.batch_accepted(&batches[0]);
+        rule.batch_accepted(&batches[1]);
+        assert!(rule.validate(&batches[0]).is_err());
+        assert!(rule.validate(&batches[1]).is_err());
+
+        rule.batch_accepted(&batches[2]);
+        assert!(rule.validate(&batches[0]).is_ok());
+        assert!(rule.validate(&batches[1]).is_err());
+        assert!(rule.validate(&batches[2]).is_err());
+
+        rule.batch_released(&batches[1]);
+        assert!(rule.validate(&batches[1]).is_ok());
+        assert!(rule.validate(&batches[2]).is_err());
+
+        // The released batch no longer counts towards the window
+        rule.batch_accepted(&batches[0]);
+        assert!(rule.validate(&batches[2]).is_err());
+    }
+
+    /// Tests the rules which check the contents of a batch's transactions.
+    #[test]
+    fn test_transaction_content_rules() {
+        let transaction = TransactionBuilder::new()
+            .with_family_name("mock".into())
+            .with_family_version("0.1".into())
+            .with_inputs(vec![])
+            .with_outputs(vec![])
+            .with_payload(vec![0; 8])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .build(&HashSigner::new(b"txn_signer".to_vec()))
+            .expect("Failed to build transaction");
+        let batch = BatchBuilder::new()
+            .with_transactions(vec![transaction.clone(), transaction])
+            .build_pair(&HashSigner::default())
+            .expect("Failed to build batch");
+
+        assert!(TransactionIdConsistencyRule::new().validate(&batch).is_ok());
+        assert!(DuplicateTransactionRule::new().validate(&batch).is_err());
+        assert!(MaxPayloadSizeRule::new(8).validate(&batch).is_ok());
+        assert!(MaxPayloadSizeRule::new(7).validate(&batch).is_err());
+        assert!(AllowedFamiliesRule::new(vec![TransactionFamily::new(
+            "mock".into(),
+            "0.1".into()
+        )])
+        .validate(&batch)
+        .is_ok());
+        assert!(AllowedFamiliesRule::new(vec![TransactionFamily::new(
+            "mock".into(),
+            "0.2".into()
+        )])
+        .validate(&batch)
+        .is_err());
+        assert!(
+            SignerAllowlistRule::new(vec![b"hash_signer".to_vec(), b"txn_signer".to_vec()])
+                .validate(&batch)
+                .is_ok()
+        );
+        assert!(SignerAllowlistRule::new(vec![b"hash_signer".to_vec()])
+            .validate(&batch)
+            .is_err());
+    }
+
+    /// Tests that a batch whose header does not match its transactions is rejected.
+    #[test]
+    fn test_transaction_id_consistency_rule() {
+        let batch = mock_batch_with_num_txns(2);
+        let other = mock_batch(mock_transactions(3).split_off(1));
+
+        let mut proto: protos::batch::Batch = batch
+            .into_proto()
+            .expect("Failed to convert batch to proto");
+        let mut other_proto: protos::batch::Batch = other
+            .into_proto()
+            .expect("Failed to convert batch to proto");
+        proto.set_transactions(other_proto.take_transactions());
+        let batch: BatchPair = proto
+            .into_native()
+            .expect("Failed to convert proto to batch");
+
+        assert!(TransactionIdConsistencyRule::new()
+            .validate(&batch)
+            .is_err());
+    }
+}
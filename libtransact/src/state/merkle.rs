@@ -306,6 +306,33 @@ impl MerkleRadixTree {
         state_changes: &[StateChange],
         is_virtual: bool,
     ) -> Result<String, StateDatabaseError> {
+        let update = self.prepare_update(state_changes)?;
+        if !is_virtual {
+            let mut db_writer = self.db.get_writer()?;
+            update.write(&mut *db_writer)?;
+            db_writer.commit()?;
+        }
+        Ok(update.state_root)
+    }
+
+    /// Computes the nodes added and removed by applying the given state changes to the tree,
+    /// without writing them, so that they can be written along with other data by
+    /// `MerkleUpdate::write`.
+    ///
+    /// If there are no changes, the update leaves the root unchanged.
+    pub fn prepare_update(
+        &self,
+        state_changes: &[StateChange],
+    ) -> Result<MerkleUpdate, StateDatabaseError> {
+        if state_changes.is_empty() {
+            return Ok(MerkleUpdate {
+                parent_root: self.root_hash.clone(),
+                state_root: self.root_hash.clone(),
+                batch: vec![],
+                deletions: vec![],
+            });
+        }
+
         let mut path_map = HashMap::new();
 
         let mut deletions = HashSet::new();
@@ -409,65 +436,18 @@ impl MerkleRadixTree {
             batch.push((hash_key, packed));
         }
 
-        if !is_virtual {
-            let deletions: Vec<Vec<u8>> = deletions
-                .iter()
-                // We expect this to be hex, since we generated it
-                .map(|s| ::hex::decode(s).expect("Improper hex"))
-                .collect();
-            self.store_changes(&key_hash, &batch, &deletions)?;
-        }
-
-        Ok(::hex::encode(key_hash))
-    }
-
-    /// Puts all the items into the database.
-    fn store_changes(
-        &self,
-        successor_root_hash: &[u8],
-        batch: &[(Vec<u8>, Vec<u8>)],
-        deletions: &[Vec<u8>],
-    ) -> Result<(), StateDatabaseError> {
-        let mut db_writer = self.db.get_writer()?;
-
-        // We expect this to be hex, since we generated it
-        let root_hash_bytes = ::hex::decode(&self.root_hash).expect("Improper hex");
-
-        for &(ref key, ref value) in batch {
-            match db_writer.put(::hex::encode(key).as_bytes(), &value) {
-                Ok(_) => continue,
-                Err(DatabaseError::DuplicateEntry) => {
-                    increment_ref_count(&mut *db_writer, key)?;
-                }
-                Err(err) => return Err(StateDatabaseError::from(err)),
-            }
-        }
-
-        let mut current_change_log = get_change_log(db_writer.as_reader(), &root_hash_bytes)?;
-        if let Some(change_log) = current_change_log.as_mut() {
-            let successor = Successor {
-                successor: Vec::from(successor_root_hash),
-                deletions: deletions.to_vec(),
-            };
-            change_log.successors.push(successor);
-        }
-
-        let next_change_log = ChangeLogEntry {
-            parent: root_hash_bytes.clone(),
-            additions: batch
-                .iter()
-                .map(|&(ref hash, _)| hash.clone())
-                .collect::<Vec<Vec<u8>>>(),
-            successors: vec![],
-        };
-
-        if let Some(current_change_log) = current_change_log {
-            write_change_log(&mut *db_writer, &root_hash_bytes, &current_change_log)?;
-        }
-        write_change_log(&mut *db_writer, successor_root_hash, &next_change_log)?;
+        let deletions: Vec<Vec<u8>> = deletions
+            .iter()
+            // We expect this to be hex, since we generated it
+            .map(|s| ::hex::decode(s).expect("Improper hex"))
+            .collect();
 
-        db_writer.commit()?;
-        Ok(())
+        Ok(MerkleUpdate {
+            parent_root: self.root_hash.clone(),
+            state_root: ::hex::encode(key_hash),
+            batch,
+            deletions,
+        })
     }
 
     pub fn get_value(&self, address: &str) -> Result<Option<Vec<u8>>, StateDatabaseError> {
@@ -602,6 +582,76 @@ impl Iterator for MerkleLeafIterator {
     }
 }
 
+/// The nodes added to and removed from a `MerkleRadixTree` by a set of state changes, computed by
+/// `MerkleRadixTree::prepare_update`.
+#[derive(Clone, Debug)]
+pub struct MerkleUpdate {
+    parent_root: String,
+    state_root: String,
+    batch: Vec<(Vec<u8>, Vec<u8>)>,
+    deletions: Vec<Vec<u8>>,
+}
+
+impl MerkleUpdate {
+    /// The state root the update is applied to.
+    pub fn parent_root(&self) -> &str {
+        &self.parent_root
+    }
+
+    /// The state root the update produces.
+    pub fn state_root(&self) -> &str {
+        &self.state_root
+    }
+
+    /// Writes the added nodes and the change log of the update with the given writer, without
+    /// committing it.
+    pub fn write(&self, db_writer: &mut dyn DatabaseWriter) -> Result<(), StateDatabaseError> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        // We expect these to be hex, since we generated them
+        let root_hash_bytes = ::hex::decode(&self.parent_root).expect("Improper hex");
+        let successor_root_hash = ::hex::decode(&self.state_root).expect("Improper hex");
+
+        for &(ref key, ref value) in &self.batch {
+            match db_writer.put(::hex::encode(key).as_bytes(), &value) {
+                Ok(_) => continue,
+                Err(DatabaseError::DuplicateEntry) => {
+                    increment_ref_count(db_writer, key)?;
+                }
+                Err(err) => return Err(StateDatabaseError::from(err)),
+            }
+        }
+
+        let mut current_change_log = get_change_log(db_writer.as_reader(), &root_hash_bytes)?;
+        if let Some(change_log) = current_change_log.as_mut() {
+            let successor = Successor {
+                successor: successor_root_hash.clone(),
+                deletions: self.deletions.clone(),
+            };
+            change_log.successors.push(successor);
+        }
+
+        let next_change_log = ChangeLogEntry {
+            parent: root_hash_bytes.clone(),
+            additions: self
+                .batch
+                .iter()
+                .map(|&(ref hash, _)| hash.clone())
+                .collect::<Vec<Vec<u8>>>(),
+            successors: vec![],
+        };
+
+        if let Some(current_change_log) = current_change_log {
+            write_change_log(db_writer, &root_hash_bytes, &current_change_log)?;
+        }
+        write_change_log(db_writer, &successor_root_hash, &next_change_log)?;
+
+        Ok(())
+    }
+}
+
 /// Initializes a database with an empty Trie
 fn initialize_db(db: &dyn Database) -> Result<String, StateDatabaseError> {
     let (hash, packed) = encode_and_hash(Node::default())?;
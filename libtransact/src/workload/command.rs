@@ -19,7 +19,10 @@ use std::{thread, time};
 
 use crate::handler::{ApplyError, TransactionContext, TransactionHandler};
 use crate::protocol;
-use crate::protocol::command::{Command, CommandPayload, SleepType};
+use crate::protocol::batch::{BatchBuilder, BatchPair};
+use crate::protocol::command::{
+    BytesEntry, Command, CommandPayload, ReturnInvalid, SetState, SleepType,
+};
 use crate::protocol::transaction::{HashMethod, TransactionBuilder, TransactionPair};
 use crate::protos::{FromBytes, IntoBytes};
 use crate::signing::hash::HashSigner;
@@ -186,6 +189,27 @@ pub fn make_command_transaction(commands: &[Command]) -> TransactionPair {
         .unwrap()
 }
 
+/// Builds a batch holding a single command transaction of the given commands.
+pub fn make_command_batch(commands: &[Command]) -> BatchPair {
+    BatchBuilder::new()
+        .with_transactions(vec![make_command_transaction(commands).take().0])
+        .build_pair(&HashSigner::default())
+        .expect("Unable to build batch")
+}
+
+/// Builds a batch holding a single command transaction which sets `key` to `value`; if `invalid`
+/// is true, the transaction then returns an invalid transaction error.
+pub fn make_set_state_batch(key: &str, value: &[u8], invalid: bool) -> BatchPair {
+    let mut commands = vec![Command::SetState(SetState::new(vec![BytesEntry::new(
+        key.into(),
+        value.to_vec(),
+    )]))];
+    if invalid {
+        commands.push(Command::ReturnInvalid(ReturnInvalid::new("invalid".into())));
+    }
+    make_command_batch(&commands)
+}
+
 fn sleep(sleep_type: SleepType, duration: u32) {
     let duration_millis = time::Duration::from_millis(duration.into());
     match sleep_type {